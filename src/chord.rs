@@ -0,0 +1,327 @@
+// chord.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::pattern::PatternSet;
+
+/// Named chord types and their intervals (in semitones above the root)
+const NAMED_CHORD_TYPES: [(&str, &[u8]); 10] = [
+    ("5", &[0, 7]),
+    ("maj", &[0, 4, 7]),
+    ("min", &[0, 3, 7]),
+    ("dim", &[0, 3, 6]),
+    ("aug", &[0, 4, 8]),
+    ("sus2", &[0, 2, 7]),
+    ("sus4", &[0, 5, 7]),
+    ("7", &[0, 4, 7, 10]),
+    ("maj7", &[0, 4, 7, 11]),
+    ("min7", &[0, 3, 7, 10]),
+];
+
+/// Separator between the notes of a chord, on the command line and in hashes
+pub const CHORD_SEPARATOR: char = '+';
+
+/**********************
+***** Chord Types *****
+**********************/
+
+/// Error type for parsing [ChordType](struct.ChordType.html) from `&str`
+#[derive(Debug, thiserror::Error)]
+pub enum ParseChordTypeError {
+    #[error("Invalid chord type {input} (expected name, i.e., 'maj', or intervals in semitones, i.e., '0+4+7')")]
+    InvalidFormat { input: String },
+    #[error("Chord type {input} must include the root (interval 0)")]
+    MissingRoot { input: String },
+}
+
+/// Chord type, as intervals in semitones above the root (always including the root itself).
+/// A single note is a chord type with only the root (`0`).
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ChordType {
+    /// Intervals in ascending order, starting with 0
+    pub intervals: Vec<u8>,
+}
+
+impl ChordType {
+    /// Chord type of a single note
+    pub fn unison() -> Self {
+        Self { intervals: vec![0] }
+    }
+
+    /// Generate notes of chord with root `root`, or `None` if the root is a rest
+    /// or any note is above MIDI note number 127
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// let major = "maj".parse::<atm::chord::ChordType>().unwrap();
+    /// let root = libatm::MIDINote::new(libatm::MIDINoteType::C, 4);
+    /// let chord = major.gen_chord(&root).unwrap();
+    /// assert_eq!("C:4+E:4+G:4", atm::chord::format_chord(&chord));
+    /// ```
+    pub fn gen_chord(&self, root: &libatm::MIDINote) -> Option<Vec<libatm::MIDINote>> {
+        if root.note_type == libatm::MIDINoteType::Rest {
+            return None;
+        }
+        let root_number = root.convert();
+        self.intervals
+            .iter()
+            .map(|interval| {
+                let number = root_number.checked_add(*interval as u32).filter(|number| *number <= 127)?;
+                crate::midi::note_from_number(number as u8)
+            })
+            .collect()
+    }
+}
+
+impl std::str::FromStr for ChordType {
+    type Err = ParseChordTypeError;
+
+    /// Parse chord type from name (i.e., 'maj', 'min7') or intervals
+    /// in semitones joined by '+' (i.e., '0+4+7')
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim();
+        if let Some((_, intervals)) = NAMED_CHORD_TYPES.iter().find(|(name, _)| *name == input) {
+            return Ok(Self { intervals: intervals.to_vec() });
+        }
+        let mut intervals = input
+            .split(CHORD_SEPARATOR)
+            .map(|interval| interval.trim().parse::<u8>())
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| ParseChordTypeError::InvalidFormat { input: input.to_string() })?;
+        intervals.sort_unstable();
+        intervals.dedup();
+        if intervals[0] != 0 {
+            return Err(ParseChordTypeError::MissingRoot { input: input.to_string() });
+        }
+        Ok(Self { intervals })
+    }
+}
+
+impl std::fmt::Display for ChordType {
+    /// Format chord type as intervals joined by '+' (i.e., '0+4+7')
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let intervals = self.intervals.iter().map(|interval| interval.to_string()).collect::<Vec<String>>();
+        write!(f, "{}", intervals.join(&CHORD_SEPARATOR.to_string()))
+    }
+}
+
+/*****************
+***** Chords *****
+*****************/
+
+/// Error type for [parse_chord](fn.parse_chord.html)
+#[derive(Debug, thiserror::Error)]
+pub enum ParseChordError {
+    #[error(transparent)]
+    ParseNote(#[from] libatm::ParseMIDINoteError),
+    #[error("Rests can't be played together with other notes (found {input})")]
+    RestInChord { input: String },
+}
+
+/// Format chord as NOTE:OCTAVE pairs joined by '+' (i.e., 'C:4+E:4+G:4')
+pub fn format_chord(chord: &[libatm::MIDINote]) -> String {
+    chord
+        .iter()
+        .map(crate::utils::format_note)
+        .collect::<Vec<String>>()
+        .join(&CHORD_SEPARATOR.to_string())
+}
+
+/// Generate hash of chord: MIDI note numbers joined by '+' (i.e., `60+64+67`)
+pub fn gen_chord_hash(chord: &[libatm::MIDINote]) -> String {
+    chord
+        .iter()
+        .map(|note| crate::utils::gen_melody_hash(std::slice::from_ref(note)))
+        .collect::<Vec<String>>()
+        .join(&CHORD_SEPARATOR.to_string())
+}
+
+/// Parse chord from its hash (see: [gen_chord_hash](fn.gen_chord_hash.html)), as its root
+/// (the first note) and chord type, or `None` if it isn't a chord hash
+///
+/// # Examples
+///
+/// ```rust
+/// let (root, chord_type) = atm::chord::parse_chord_hash("60+64+67").unwrap();
+/// assert_eq!(libatm::MIDINote::new(libatm::MIDINoteType::C, 4), root);
+/// assert_eq!("maj".parse::<atm::chord::ChordType>().unwrap(), chord_type);
+/// assert!(atm::chord::parse_chord_hash("67+60").is_none());
+/// ```
+pub fn parse_chord_hash(hash: &str) -> Option<(libatm::MIDINote, ChordType)> {
+    let numbers = hash
+        .split(CHORD_SEPARATOR)
+        .map(|number| number.parse::<u8>().ok())
+        .collect::<Option<Vec<u8>>>()?;
+    let root = crate::midi::note_from_number(numbers[0]).filter(|root| root.note_type != libatm::MIDINoteType::Rest)?;
+    let intervals = numbers
+        .iter()
+        .map(|number| number.checked_sub(numbers[0]))
+        .collect::<Option<Vec<u8>>>()?;
+    // Intervals of a chord type are in ascending order
+    if intervals.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return None;
+    }
+    Some((root, ChordType { intervals }))
+}
+
+/// Parse chord from NOTE:OCTAVE pairs joined by '+' (i.e., 'C:4+E:4+G:4'), as its root
+/// (the lowest note) and chord type. A single note (or rest) is a chord of type
+/// [unison](struct.ChordType.html#method.unison).
+///
+/// # Examples
+///
+/// ```rust
+/// let (root, chord_type) = atm::chord::parse_chord("G:4+C:4+E:4").unwrap();
+/// assert_eq!(libatm::MIDINote::new(libatm::MIDINoteType::C, 4), root);
+/// assert_eq!("maj".parse::<atm::chord::ChordType>().unwrap(), chord_type);
+/// ```
+pub fn parse_chord(input: &str) -> Result<(libatm::MIDINote, ChordType), ParseChordError> {
+    let mut notes = input
+        .split(CHORD_SEPARATOR)
+        .map(crate::utils::parse_note)
+        .collect::<Result<Vec<libatm::MIDINote>, _>>()?;
+    if notes.len() > 1 && notes.iter().any(|note| note.note_type == libatm::MIDINoteType::Rest) {
+        return Err(ParseChordError::RestInChord { input: input.to_string() });
+    }
+    notes.sort_by_key(|note| note.convert());
+    notes.dedup();
+    let root = notes[0];
+    let intervals = notes.iter().map(|note| (note.convert() - root.convert()) as u8).collect();
+    Ok((root, ChordType { intervals }))
+}
+
+/// Error type for [parse_chord_vec](fn.parse_chord_vec.html)
+#[derive(Debug, thiserror::Error)]
+pub enum ParseChordVecError {
+    #[error("Failed to parse chord at index {index} ({source})")]
+    ParseChord { index: usize, source: ParseChordError },
+}
+
+/// Sequence of chords, as the sequence of roots and the type of each chord
+/// (or `None` if every chord is a single note)
+#[derive(Clone, Debug)]
+pub struct ChordVec {
+    pub roots: libatm::MIDINoteVec,
+    pub chord_types: Option<Vec<ChordType>>,
+}
+
+/// Parse sequence of chords from comma-separated chords (see: [parse_chord](fn.parse_chord.html)),
+/// i.e., 'C:4+E:4+G:4,R,G:3'
+pub fn parse_chord_vec(input: &str) -> Result<ChordVec, ParseChordVecError> {
+    let (roots, chord_types): (Vec<libatm::MIDINote>, Vec<ChordType>) = input
+        .split(',')
+        .enumerate()
+        .map(|(index, chord)| parse_chord(chord).map_err(|source| ParseChordVecError::ParseChord { index, source }))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .unzip();
+    let is_melody = chord_types.iter().all(|chord_type| *chord_type == ChordType::unison());
+    Ok(ChordVec {
+        roots: roots.into_iter().collect(),
+        chord_types: if is_melody { None } else { Some(chord_types) },
+    })
+}
+
+/***********************
+***** ChordTypeSet *****
+***********************/
+
+/// Error type for [ChordTypeSet](struct.ChordTypeSet.html)
+#[derive(Debug, thiserror::Error)]
+pub enum ChordTypeSetError {
+    #[error("Must provide at least one chord type")]
+    Empty,
+    #[error("Number of chord type patterns of {length} chords overflows a 64-bit index")]
+    TooManyPatterns { length: u32 },
+    #[error("Chord type {chord_type} over root {root} is above MIDI note number 127")]
+    OutOfRange { chord_type: ChordType, root: String },
+    #[error("Rests can't be generated with chord types (remove 'R' from note set)")]
+    RestInNoteSet,
+}
+
+/// Set of chord type patterns melodies are generated with: every sequence of `length`
+/// chord types from a set of chord types, so every note of a melody is the root of a
+/// chord. Patterns are generated in lexicographic order of intervals (see:
+/// [PatternSet](../pattern/struct.PatternSet.html)).
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChordTypeSet {
+    /// Chord type patterns
+    patterns: PatternSet<ChordType>,
+}
+
+impl ChordTypeSet {
+    /// Create new `ChordTypeSet` instance for patterns of `length` chords.
+    /// Chord types are sorted, and duplicates removed.
+    pub fn new(chord_types: &[ChordType], length: u32) -> Result<Self, ChordTypeSetError> {
+        let mut chord_types = chord_types.to_vec();
+        chord_types.sort();
+        chord_types.dedup();
+        if chord_types.is_empty() {
+            return Err(ChordTypeSetError::Empty);
+        }
+        let patterns = PatternSet::new(chord_types, length).ok_or(ChordTypeSetError::TooManyPatterns { length })?;
+        Ok(Self { patterns })
+    }
+
+    /// Chord type patterns in set
+    pub fn patterns(&self) -> &PatternSet<ChordType> {
+        &self.patterns
+    }
+
+    /// Chord types patterns are generated from (in generation order)
+    pub fn chord_types(&self) -> &[ChordType] {
+        self.patterns.symbols()
+    }
+
+    /// Ensure every chord type can be played over every note in `notes`
+    pub fn check_roots(&self, notes: &[libatm::MIDINote]) -> Result<(), ChordTypeSetError> {
+        for root in notes.iter() {
+            if root.note_type == libatm::MIDINoteType::Rest {
+                return Err(ChordTypeSetError::RestInNoteSet);
+            }
+            if let Some(chord_type) = self.chord_types().iter().find(|chord_type| chord_type.gen_chord(root).is_none()) {
+                return Err(ChordTypeSetError::OutOfRange {
+                    chord_type: chord_type.clone(),
+                    root: crate::utils::format_note(root),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for ChordTypeSet {
+    /// Format chord type set as `chords=<chord types>` (separated by `;`)
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "chords={}",
+            self.chord_types().iter().map(|t| t.to_string()).collect::<Vec<String>>().join(";"),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_roots() {
+        let chord_types = ["min7", "maj", "0+4+7", "5"]
+            .iter()
+            .map(|chord_type| chord_type.parse::<ChordType>().unwrap())
+            .collect::<Vec<ChordType>>();
+        let chords = ChordTypeSet::new(&chord_types, 3).unwrap();
+        // Duplicate chord types (maj and 0+4+7) are removed
+        assert_eq!(3, chords.chord_types().len());
+        let notes = crate::utils::parse_note_vec("C:4,G:9").unwrap();
+        assert!(chords.check_roots(&notes[..1]).is_ok());
+        // G:9 + 10 semitones is above MIDI note number 127
+        assert!(chords.check_roots(&notes).is_err());
+    }
+}
@@ -33,15 +33,97 @@ pub enum ParseNumberArgError {
     NotInteger(#[from] std::num::ParseIntError),
     #[error("{arg_name} must be greater than 0")]
     LessThanZero { arg_name: String },
+    #[error("{arg_name} must be greater than 0, found {input}")]
+    NotPositive { arg_name: String, input: String },
     #[error("{arg_name} must be between {min} and {max}, found {input}")]
     OutOfRange { arg_name: String, min: String, max: String, input: String },
 }
 
+/// Error type for parsing dataset ID from `&str`
+#[derive(Debug, thiserror::Error)]
+pub enum ParseDatasetIdError {
+    #[error("Dataset ID must not be empty")]
+    Empty,
+    #[error("Dataset ID must not contain control characters, found {input:?}")]
+    ControlCharacter { input: String },
+}
+
+/// Error type for parsing render manifests (see:
+/// [RenderManifestArgs](struct.RenderManifestArgs.html))
+#[derive(Debug, thiserror::Error)]
+pub enum ParseManifestError {
+    #[error("Render manifest line must have format <option>=<value>, found {line:?}")]
+    InvalidLine { line: String },
+    #[error("Unknown option {name:?} in render manifest")]
+    UnknownOption { name: String },
+    #[error("Invalid rendering options in render manifest ({message})")]
+    InvalidOptions { message: String },
+}
+
+/// Error type for parsing shard arguments from `&str`
+#[derive(Debug, thiserror::Error)]
+pub enum ParseShardArgError {
+    #[error(transparent)]
+    NotInteger(#[from] std::num::ParseIntError),
+    #[error("Shard must have format K/N (i.e., '2/8'), found {input}")]
+    InvalidFormat { input: String },
+    #[error("Shard must be between 1 and {num_shards}, found {shard}")]
+    OutOfRange { shard: u32, num_shards: u32 },
+}
+
+/**********************
+***** BackendArgs *****
+**********************/
+
+fn try_backend_level_from_str(arg: &str) -> Result<u32, ParseNumberArgError> {
+    let level = arg.parse::<u32>()?;
+    if level > 22 {
+        return Err(ParseNumberArgError::OutOfRange {
+            arg_name: "Compression level".to_string(),
+            min: "0".to_string(),
+            max: "22".to_string(),
+            input: arg.to_string(),
+        });
+    }
+    Ok(level)
+}
+
+/// Options of the storage backend chosen by the extension of the target, and of the run
+/// writing to it (see: [dispatch_backend](../directives/gen/fn.dispatch_backend.html))
+#[derive(Debug, structopt::StructOpt)]
+pub struct BackendArgs {
+    /// Compression level: [0-9, default: 6] for Gzip-compressed Tar and ZIP files,
+    /// or [1-22, default: 3] for Zstandard-compressed Tar files.
+    #[structopt(
+        short="C",
+        long="compress",
+        value_name="level",
+        parse(try_from_str = try_backend_level_from_str))]
+    pub compression_level: Option<u32>,
+    /// Store ZIP entries without compression (instead of deflating them).
+    #[structopt(long, conflicts_with="compression-level")]
+    pub store: bool,
+    /// Enable long-distance matching with a window of 2^N bytes [10-31] (Zstandard-compressed
+    /// Tar files only).
+    #[structopt(long="long", value_name="N", parse(try_from_str = try_window_log_from_str))]
+    pub window_log: Option<u32>,
+    /// Number of compression worker threads (Zstandard-compressed Tar files only, 0 compresses
+    /// on the writing thread).
+    #[structopt(long="workers", default_value="0")]
+    pub num_workers: u32,
+    #[structopt(flatten)]
+    pub num_threads: ThreadsArg,
+    #[structopt(flatten)]
+    pub shard_args: ShardArgs,
+    #[structopt(flatten)]
+    pub checkpoint_args: CheckpointArgs,
+}
+
 /********************
 ***** BatchSize *****
 ********************/
 
-fn try_batch_from_str(arg: &str) -> Result<u32, ParseNumberArgError> {
+pub(crate) fn try_batch_from_str(arg: &str) -> Result<u32, ParseNumberArgError> {
     let batch_size = arg.parse::<u32>()?;
     if batch_size == 0 {
         return Err(ParseNumberArgError::LessThanZero { arg_name: "Batch size".to_string() });
@@ -62,11 +144,101 @@ pub struct BatchSize {
 
 impl_into! { BatchSize, batch_size, u32 }
 
+/*************************
+***** CheckpointArgs *****
+*************************/
+
+fn try_interval_from_str(arg: &str) -> Result<u64, ParseNumberArgError> {
+    let interval = arg.parse::<u64>()?;
+    if interval == 0 {
+        return Err(ParseNumberArgError::LessThanZero { arg_name: "Checkpoint interval".to_string() });
+    }
+    Ok(interval)
+}
+
+#[derive(Debug, structopt::StructOpt)]
+pub struct CheckpointArgs {
+    /// Write checkpoint to <target>.checkpoint every N melodies, so that
+    /// an interrupted run can be resumed (see: --resume).
+    #[structopt(
+        long="checkpoint",
+        value_name="N",
+        parse(try_from_str = try_interval_from_str))]
+    pub checkpoint_interval: Option<u64>,
+    /// Resume interrupted run from <target>.checkpoint, using the same arguments as the
+    /// interrupted run. Target file is truncated to the last checkpoint.
+    #[structopt(long, requires="checkpoint-interval")]
+    pub resume: bool,
+}
+
+/***************************
+***** ConvertInputArgs *****
+***************************/
+
+#[derive(Debug, structopt::StructOpt)]
+pub struct ConvertInputArgs {
+    /// Input archive generated by one of the `gen` directives (Tar, Gzip- or Zstandard-compressed
+    /// Tar, or batch) or melody pack, or '-' to read from stdin.
+    #[structopt(parse(from_os_str))]
+    pub input: std::path::PathBuf,
+    /// Note set melodies in input archive were generated from. Required (with --length) to
+    /// partition output, or to write batches or melody packs. Read from header of melody packs.
+    #[structopt(
+        long="notes",
+        value_name="notes",
+        requires="melody-length",
+        parse(try_from_str = crate::utils::parse_note_set))]
+    pub note_set: Option<libatm::MIDINoteSet>,
+    /// Length of melodies in input archive (see: --notes).
+    #[structopt(
+        long="length",
+        value_name="length",
+        requires="note-set",
+        parse(try_from_str = try_length_from_str))]
+    pub melody_length: Option<u32>,
+    /// Options melodies in input archive were rendered with, used to write melody packs and
+    /// train zstd dictionaries. Read from header of melody packs.
+    #[structopt(flatten)]
+    pub render_args: MIDIRenderArgs,
+    /// Chord types, durations and velocities melodies in input archive were generated with,
+    /// used to partition output.
+    #[structopt(flatten)]
+    pub variation_args: VariationArgs,
+}
+
+/*******************
+***** DrumArgs *****
+*******************/
+
+#[derive(Debug, structopt::StructOpt)]
+pub struct DrumArgs {
+    /// Number of steps of drum patterns to generate [1-32].
+    #[structopt(parse(try_from_str = try_length_from_str))]
+    pub steps: u32,
+    /// Percussion instruments to generate drum patterns for, as names (kick, rim, snare, clap,
+    /// hat, pedal-hat, low-tom, open-hat, mid-tom, crash, high-tom, ride, tambourine, cowbell)
+    /// or General MIDI percussion note numbers [35-81], each optionally followed by the maximum
+    /// number of steps it's hit on (i.e., 'kick:4,snare:2,hat').
+    #[structopt(long, value_name="instruments", require_delimiter=true, default_value="kick,snare,hat")]
+    pub instruments: Vec<crate::drums::DrumPart>,
+    /// Duration of each step, as fraction of a whole note. If steps aren't a whole number of
+    /// ticks, the division is raised to its smallest multiple that renders them (see: --division).
+    #[structopt(long, value_name="duration", default_value="1/16")]
+    pub step: crate::rhythm::NoteDuration,
+}
+
+impl DrumArgs {
+    /// Create set of drum patterns from arguments
+    pub fn gen_drum_pattern_set(&self) -> Result<crate::drums::DrumPatternSet, crate::drums::DrumPatternSetError> {
+        crate::drums::DrumPatternSet::new(&self.instruments, self.steps, self.step)
+    }
+}
+
 /***********************
 ***** MelodyLength *****
 ***********************/
 
-fn try_length_from_str(arg: &str) -> Result<u32, ParseNumberArgError> {
+pub(crate) fn try_length_from_str(arg: &str) -> Result<u32, ParseNumberArgError> {
     let length = arg.parse::<u32>()?;
     if length == 0 {
         return Err(ParseNumberArgError::LessThanZero { arg_name: "Length".to_string() });
@@ -83,6 +255,137 @@ pub struct MelodyLengthArg {
 
 impl_into! { MelodyLengthArg, melody_length, u32 }
 
+/*************************
+***** MelodyIndexArg *****
+*************************/
+
+#[derive(Debug, structopt::StructOpt)]
+pub struct MelodyIndexArg {
+    /// Index of melody in the sequence of generated melodies (starting from 0).
+    #[structopt(parse(try_from_str = u64::from_str))]
+    pub melody_index: u64,
+}
+
+impl_into! { MelodyIndexArg, melody_index, u64 }
+
+/*************************
+***** MIDIRenderArgs *****
+*************************/
+
+/// Parse integer argument from `&str` and ensure it's between `min` and `max` (inclusive)
+fn try_in_range_from_str(arg: &str, arg_name: &str, min: u32, max: u32) -> Result<u32, ParseNumberArgError> {
+    let value = arg.parse::<u32>()?;
+    if !(min..=max).contains(&value) {
+        return Err(ParseNumberArgError::OutOfRange {
+            arg_name: arg_name.to_string(),
+            min: min.to_string(),
+            max: max.to_string(),
+            input: arg.to_string(),
+        });
+    }
+    Ok(value)
+}
+
+fn try_midi_format_from_str(arg: &str) -> Result<libatm::MIDIFormat, ParseNumberArgError> {
+    match try_in_range_from_str(arg, "MIDI format", 0, 2)? {
+        0 => Ok(libatm::MIDIFormat::Format0),
+        1 => Ok(libatm::MIDIFormat::Format1),
+        _ => Ok(libatm::MIDIFormat::Format2),
+    }
+}
+
+fn try_division_from_str(arg: &str) -> Result<u16, ParseNumberArgError> {
+    // Division with the top bit set is in SMPTE frames, which isn't supported
+    Ok(try_in_range_from_str(arg, "Division", 1, 0x7FFF)? as u16)
+}
+
+fn try_tempo_from_str(arg: &str) -> Result<u32, ParseNumberArgError> {
+    // Tempo is written in microseconds per quarter note, which must fit in 24 bits
+    try_in_range_from_str(arg, "Tempo", 4, 1000)
+}
+
+fn try_duration_from_str(arg: &str) -> Result<u32, ParseNumberArgError> {
+    // Largest delta time that can be written as a variable-length quantity
+    try_in_range_from_str(arg, "Duration", 1, 0x0FFF_FFFF)
+}
+
+fn try_velocity_from_str(arg: &str) -> Result<u8, ParseNumberArgError> {
+    Ok(try_in_range_from_str(arg, "Velocity", 1, 127)? as u8)
+}
+
+fn try_channel_from_str(arg: &str) -> Result<u8, ParseNumberArgError> {
+    Ok(try_in_range_from_str(arg, "Channel", 1, 16)? as u8)
+}
+
+fn try_program_from_str(arg: &str) -> Result<u8, ParseNumberArgError> {
+    Ok(try_in_range_from_str(arg, "Program", 1, 128)? as u8)
+}
+
+fn try_dataset_id_from_str(arg: &str) -> Result<String, ParseDatasetIdError> {
+    if arg.is_empty() {
+        return Err(ParseDatasetIdError::Empty);
+    }
+    // Dataset ID is written to checkpoint files, one option per line
+    if arg.chars().any(char::is_control) {
+        return Err(ParseDatasetIdError::ControlCharacter { input: arg.to_string() });
+    }
+    Ok(arg.to_string())
+}
+
+#[derive(Clone, Debug, structopt::StructOpt)]
+pub struct MIDIRenderArgs {
+    /// MIDI file format [0-2] (melodies are always written to a single track).
+    #[structopt(
+        long="midi-format",
+        value_name="format",
+        default_value="0",
+        parse(try_from_str = try_midi_format_from_str))]
+    pub format: libatm::MIDIFormat,
+    /// Number of ticks per quarter note in MIDI files [1-32767].
+    #[structopt(long, value_name="ticks", default_value="1", parse(try_from_str = try_division_from_str))]
+    pub division: u16,
+    /// Tempo in beats per minute [4-1000]. If not set, no tempo is written (players default to 120).
+    #[structopt(long, value_name="bpm", parse(try_from_str = try_tempo_from_str))]
+    pub tempo: Option<u32>,
+    /// Duration of each note in ticks [default: one quarter note].
+    #[structopt(long, value_name="ticks", parse(try_from_str = try_duration_from_str))]
+    pub duration: Option<u32>,
+    /// Velocity of each note [1-127].
+    #[structopt(long, default_value="100", parse(try_from_str = try_velocity_from_str))]
+    pub velocity: u8,
+    /// MIDI channel [1-16].
+    #[structopt(long, default_value="1", parse(try_from_str = try_channel_from_str))]
+    pub channel: u8,
+    /// General MIDI program (instrument) [1-128]. If not set, no program change is written.
+    #[structopt(long, parse(try_from_str = try_program_from_str))]
+    pub program: Option<u8>,
+    /// Write meta events with dataset ID to every MIDI file: melody as track name, CC0 (public
+    /// domain) copyright notice, and text with dataset ID and melody index (i.e., 'C4_D4_E4-8').
+    #[structopt(long, value_name="id", parse(try_from_str = try_dataset_id_from_str))]
+    pub dataset_id: Option<String>,
+}
+
+impl MIDIRenderArgs {
+    /// Create rendering options for dataset of melodies generated from `notes`
+    /// (in generation order), used to calculate the melody index in meta events
+    pub fn gen_render_options(self, notes: &libatm::MIDINoteVec) -> crate::midi::MIDIRenderOptions {
+        crate::midi::MIDIRenderOptions {
+            format: self.format,
+            division: self.division,
+            tempo: self.tempo,
+            duration: self.duration,
+            velocity: self.velocity,
+            channel: self.channel - 1,
+            program: self.program.map(|program| program - 1),
+            meta: self.dataset_id.map(|dataset_id| crate::midi::MIDIMetaEvents {
+                dataset_id,
+                notes: notes.clone(),
+            }),
+            variations: crate::variation::VariationSet::default(),
+        }
+    }
+}
+
 /**************************
 ***** NoteSet/NoteVec *****
 **************************/
@@ -90,7 +393,8 @@ impl_into! { MelodyLengthArg, melody_length, u32 }
 #[derive(Debug, structopt::StructOpt)]
 pub struct NoteSetArg {
     /// Comma-separated set of NOTE:OCTAVE pairs (i.e., 'C:4,D:4,E:4,F:4,G:4,A:4,B:4,C:5').
-    #[structopt(value_name="notes", parse(try_from_str = libatm::MIDINoteSet::from_str))]
+    /// Include 'R' to generate melodies with rests.
+    #[structopt(value_name="notes", parse(try_from_str = crate::utils::parse_note_set))]
     pub note_set: libatm::MIDINoteSet,
 }
 
@@ -98,12 +402,13 @@ impl_into! { NoteSetArg, note_set, libatm::MIDINoteSet }
 
 #[derive(Debug, structopt::StructOpt)]
 pub struct NoteVecArg {
-    /// Comma-separated set of NOTE:OCTAVE pairs (i.e., 'C:4,D:4,E:4,F:4,G:4,A:4,B:4,C:5').
-    #[structopt(value_name="notes", parse(try_from_str = libatm::MIDINoteVec::from_str))]
-    pub note_vec: libatm::MIDINoteVec,
+    /// Comma-separated list of NOTE:OCTAVE pairs, and 'R' for rests (i.e., 'C:4,D:4,R,C:5').
+    /// Notes played together (chords) are joined by '+' (i.e., 'C:4+E:4+G:4,R,G:3+B:3+D:4').
+    #[structopt(value_name="notes", parse(try_from_str = crate::chord::parse_chord_vec))]
+    pub note_vec: crate::chord::ChordVec,
 }
 
-impl_into! { NoteVecArg, note_vec, libatm::MIDINoteVec }
+impl_into! { NoteVecArg, note_vec, crate::chord::ChordVec }
 
 /**********************
 ***** NumNotesArg *****
@@ -164,19 +469,304 @@ pub struct PartitionArgs {
     pub partition_depth: Option<u32>, 
 }
 
+/********************
+***** ShardArgs *****
+********************/
+
+/// Shard K of N (1 <= K <= N)
+#[derive(Debug)]
+pub struct Shard {
+    /// Shard number (starting from 1)
+    pub shard: u32,
+    /// Total number of shards
+    pub num_shards: u32,
+}
+
+fn try_shard_from_str(arg: &str) -> Result<Shard, ParseShardArgError> {
+    let split_pair: Vec<&str> = arg.split('/').collect();
+    if split_pair.len() != 2 {
+        return Err(ParseShardArgError::InvalidFormat { input: arg.to_string() });
+    }
+    let shard = split_pair[0].parse::<u32>()?;
+    let num_shards = split_pair[1].parse::<u32>()?;
+    if shard == 0 || shard > num_shards {
+        return Err(ParseShardArgError::OutOfRange { shard, num_shards });
+    }
+    Ok(Shard { shard, num_shards })
+}
+
+#[derive(Debug, structopt::StructOpt)]
+pub struct ShardArgs {
+    /// Only generate shard K of N (i.e., '2/8'). Shards are aligned to partition boundaries,
+    /// and together contain every melody exactly once.
+    #[structopt(
+        long,
+        conflicts_with_all=&["start-index", "end-index"],
+        parse(try_from_str = try_shard_from_str))]
+    pub shard: Option<Shard>,
+    /// Index of first melody to generate (must be aligned to partition boundary).
+    #[structopt(long, parse(try_from_str = u64::from_str))]
+    pub start_index: Option<u64>,
+    /// Index after last melody to generate (must be aligned to partition boundary).
+    #[structopt(long, parse(try_from_str = u64::from_str))]
+    pub end_index: Option<u64>,
+}
+
 /*****************
 ***** Target *****
 *****************/
 
 #[derive(Debug, structopt::StructOpt)]
 pub struct TargetArg {
-    /// File output path (directory/directories must exist).
+    /// File output path (directory/directories must exist), or '-' to write to stdout
+    /// (Tar-based backends only).
     #[structopt(parse(from_str))]
     pub target: std::path::PathBuf,
 }
 
+impl TargetArg {
+    /// Whether output should be written to stdout (target is '-')
+    pub fn is_stdout(&self) -> bool {
+        self.target.as_os_str() == "-"
+    }
+}
+
 impl_into! { TargetArg, target, std::path::PathBuf }
 
+/*********************
+***** ThreadsArg *****
+*********************/
+
+fn try_threads_from_str(arg: &str) -> Result<u32, ParseNumberArgError> {
+    // Parse as signed integer so that negative numbers are reported as such
+    let num_threads = arg.parse::<i64>()?;
+    if num_threads <= 0 {
+        return Err(ParseNumberArgError::NotPositive {
+            arg_name: "Number of threads".to_string(),
+            input: arg.to_string(),
+        });
+    }
+    if num_threads > u32::MAX as i64 {
+        return Err(ParseNumberArgError::OutOfRange {
+            arg_name: "Number of threads".to_string(),
+            min: "1".to_string(),
+            max: u32::MAX.to_string(),
+            input: arg.to_string(),
+        });
+    }
+    Ok(num_threads as u32)
+}
+
+#[derive(Debug, structopt::StructOpt)]
+pub struct ThreadsArg {
+    /// Number of worker threads used to generate melodies. Melodies are always
+    /// written in the same order, so output is identical for any number of threads.
+    #[structopt(
+        short="t",
+        long="threads",
+        default_value="1",
+        parse(try_from_str = try_threads_from_str))]
+    pub num_threads: u32,
+}
+
+impl_into! { ThreadsArg, num_threads, u32 }
+
+/************************
+***** VariationArgs *****
+************************/
+
+#[derive(Debug, structopt::StructOpt)]
+pub struct VariationArgs {
+    /// Chord types to play over every note, as names (5, maj, min, dim, aug, sus2, sus4, 7,
+    /// maj7, min7) or intervals in semitones above the root (i.e., 'maj,min,0+4+7+11').
+    /// Every melody is generated once per chord type pattern.
+    #[structopt(long, value_name="chord-types", require_delimiter=true)]
+    pub chord_types: Vec<crate::chord::ChordType>,
+    /// Note durations to generate rhythm patterns from, as fractions of a whole note
+    /// (i.e., '1/8,1/4,1/2'). Every melody is generated once per rhythm pattern.
+    #[structopt(long, value_name="durations", require_delimiter=true, conflicts_with="duration")]
+    pub durations: Vec<crate::rhythm::NoteDuration>,
+    /// Only generate rhythm patterns that fill this many bars (of 4/4) exactly.
+    #[structopt(long, requires="durations")]
+    pub bars: Option<u32>,
+    /// Velocities to generate dynamics patterns from, as numbers [1-127] or dynamic markings
+    /// ppp-fff (i.e., 'pp,mf,ff'). Every melody is generated once per dynamics pattern.
+    #[structopt(
+        long,
+        value_name="velocities",
+        require_delimiter=true,
+        conflicts_with="velocity",
+        parse(try_from_str = crate::dynamics::parse_velocity))]
+    pub velocities: Vec<u8>,
+}
+
+impl VariationArgs {
+    /// Create set of chord type, rhythm and dynamics patterns for melodies of length
+    /// `melody_length` (empty if no chord types, durations or velocities were provided)
+    pub fn gen_variation_set(
+        &self,
+        melody_length: u32,
+    ) -> Result<crate::variation::VariationSet, crate::variation::VariationSetError> {
+        let chords = if self.chord_types.is_empty() {
+            None
+        } else {
+            Some(crate::chord::ChordTypeSet::new(&self.chord_types, melody_length)?)
+        };
+        let rhythms = if self.durations.is_empty() {
+            None
+        } else {
+            Some(crate::rhythm::RhythmSet::new(&self.durations, melody_length, self.bars)?)
+        };
+        let dynamics = if self.velocities.is_empty() {
+            None
+        } else {
+            Some(crate::dynamics::DynamicsSet::new(&self.velocities, melody_length)?)
+        };
+        crate::variation::VariationSet::new(chords, rhythms, dynamics)
+    }
+}
+
+/*****************************
+***** RenderManifestArgs *****
+*****************************/
+
+/// Rendering and variation arguments recorded in the render manifest of a dataset (see:
+/// [MIDIRenderOptions::gen_manifest](../midi/struct.MIDIRenderOptions.html#method.gen_manifest))
+#[derive(Debug, structopt::StructOpt)]
+pub struct RenderManifestArgs {
+    #[structopt(flatten)]
+    pub render_args: MIDIRenderArgs,
+    #[structopt(flatten)]
+    pub variation_args: VariationArgs,
+}
+
+impl RenderManifestArgs {
+    /// Parse arguments from render manifest. Options are validated as if they were
+    /// passed on the command line.
+    pub fn from_manifest(manifest: &str) -> Result<Self, ParseManifestError> {
+        let mut pairs = Vec::new();
+        for line in manifest.lines().filter(|line| !line.is_empty()) {
+            match line.split_once('=') {
+                Some(pair) => pairs.push(pair),
+                None => return Err(ParseManifestError::InvalidLine { line: line.to_string() }),
+            }
+        }
+        let has_velocities = pairs.iter().any(|(name, _)| *name == "velocities");
+        let mut args = vec!["atm-render".to_string()];
+        for (name, value) in pairs {
+            let flag = match name {
+                "format" => "--midi-format",
+                "division" => "--division",
+                "tempo" => "--tempo",
+                "duration" => "--duration",
+                // Velocity conflicts with velocities, which replace it
+                "velocity" if has_velocities => continue,
+                "velocity" => "--velocity",
+                "channel" => "--channel",
+                "program" => "--program",
+                "dataset" => "--dataset-id",
+                "chords" => "--chord-types",
+                "durations" => "--durations",
+                "bars" => "--bars",
+                "velocities" => "--velocities",
+                _ => return Err(ParseManifestError::UnknownOption { name: name.to_string() }),
+            };
+            // Options that aren't set are written as '-'
+            if value != "-" {
+                args.push(format!("{}={}", flag, value.replace(';', ",")));
+            }
+        }
+        <Self as structopt::StructOpt>::from_iter_safe(args).map_err(|err| ParseManifestError::InvalidOptions {
+            message: err.message.lines().next().unwrap_or_default().to_string(),
+        })
+    }
+}
+
+/******************
+***** ZipArgs *****
+******************/
+
+#[derive(Debug, structopt::StructOpt)]
+pub struct ZipArgs {
+    #[structopt(
+        short="C",
+        long="compress",
+        help="Compression level [0-9, default: 6]",
+        parse(try_from_str = crate::directives::gen::try_compression_from_str))]
+    pub compression_level: Option<flate2::Compression>,
+    /// Store entries without compression (instead of deflating them).
+    #[structopt(long, conflicts_with="compression-level")]
+    pub store: bool,
+}
+
+impl From<ZipArgs> for crate::storage::ZipMethod {
+    fn from(args: ZipArgs) -> Self {
+        if args.store {
+            Self::Store
+        } else {
+            Self::Deflate(args.compression_level.map(|level| level.level() as i32))
+        }
+    }
+}
+
+/*******************
+***** ZstdArgs *****
+*******************/
+
+pub(crate) fn try_zstd_level_from_str(arg: &str) -> Result<i32, ParseNumberArgError> {
+    let level = arg.parse::<i32>()?;
+    if !(1..=22).contains(&level) {
+        return Err(ParseNumberArgError::OutOfRange {
+            arg_name: "Compression level".to_string(),
+            min: "1".to_string(),
+            max: "22".to_string(),
+            input: arg.to_string(),
+        });
+    }
+    Ok(level)
+}
+
+fn try_window_log_from_str(arg: &str) -> Result<u32, ParseNumberArgError> {
+    let window_log = arg.parse::<u32>()?;
+    if !(10..=31).contains(&window_log) {
+        return Err(ParseNumberArgError::OutOfRange {
+            arg_name: "Window log".to_string(),
+            min: "10".to_string(),
+            max: "31".to_string(),
+            input: arg.to_string(),
+        });
+    }
+    Ok(window_log)
+}
+
+#[derive(Debug, structopt::StructOpt)]
+pub struct ZstdArgs {
+    /// Compression level [1-22].
+    #[structopt(
+        short="C",
+        long="compress",
+        default_value="3",
+        parse(try_from_str = try_zstd_level_from_str))]
+    pub level: i32,
+    /// Enable long-distance matching with a window of 2^N bytes [10-31]. Decompressing
+    /// with the zstd CLI requires passing '--long=N' if N is greater than 27.
+    #[structopt(long="long", value_name="N", parse(try_from_str = try_window_log_from_str))]
+    pub window_log: Option<u32>,
+    /// Number of compression worker threads (0 compresses on the writing thread).
+    #[structopt(long="workers", default_value="0")]
+    pub num_workers: u32,
+}
+
+impl From<ZstdArgs> for crate::storage::ZstdOptions {
+    fn from(args: ZstdArgs) -> Self {
+        Self {
+            level: args.level,
+            window_log: args.window_log,
+            num_workers: args.num_workers,
+        }
+    }
+}
+
 /******************************
 ***** CLI Directive Trait *****
 ******************************/
@@ -201,17 +791,91 @@ pub trait CliDirective {
     version = env!("CARGO_PKG_VERSION"),
     setting=structopt::clap::AppSettings::ArgRequiredElseHelp)]
 pub enum Cli {
+    At(crate::directives::AtDirective),
+    Convert(crate::directives::ConvertDirective),
     Estimate(crate::directives::EstimateDirective),
+    Extract(crate::directives::ExtractDirective),
     Gen(crate::directives::GenDirective),
+    IndexOf(crate::directives::IndexOfDirective),
+    Ls(crate::directives::LsDirective),
     Partition(crate::directives::PartitionDirective),
+    Verify(crate::directives::VerifyDirective),
 }
 
 impl CliDirective for Cli {
     fn run(self) {
         match self {
+            Self::At(d) => d.run(),
+            Self::Convert(d) => d.run(),
             Self::Gen(d) => d.run(),
             Self::Estimate(d) => d.run(),
+            Self::Extract(d) => d.run(),
+            Self::IndexOf(d) => d.run(),
+            Self::Ls(d) => d.run(),
             Self::Partition(d) => d.run(),
+            Self::Verify(d) => d.run(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threads_from_str() {
+        assert_eq!(1, try_threads_from_str("1").unwrap());
+        assert_eq!(16, try_threads_from_str("16").unwrap());
+        assert!(matches!(try_threads_from_str("0"), Err(ParseNumberArgError::NotPositive { .. })));
+        assert!(matches!(try_threads_from_str("-4"), Err(ParseNumberArgError::NotPositive { .. })));
+        assert!(matches!(try_threads_from_str("4294967296"), Err(ParseNumberArgError::OutOfRange { .. })));
+        assert!(matches!(try_threads_from_str("four"), Err(ParseNumberArgError::NotInteger(_))));
+    }
+
+    #[test]
+    fn test_drum_args_defaults() {
+        let directive = <crate::directives::GenDrumsDirective as structopt::StructOpt>::from_iter_safe(&[
+            "drums", "4", "d.tar",
+        ]).unwrap();
+        let (patterns, render) = crate::directives::gen::resolve_drum_options(
+            &directive.drum_args,
+            directive.render_args,
+        );
+        // Sixteenth note steps are rendered with the smallest division that fits them
+        assert_eq!(4, render.division);
+        assert_eq!(16 * 16 * 16, patterns.num_patterns());
+    }
+
+    #[test]
+    fn test_render_manifest_round_trip() {
+        let gen_render_options = |args: RenderManifestArgs| crate::midi::MIDIRenderOptions {
+            variations: args.variation_args.gen_variation_set(4).unwrap(),
+            ..args.render_args.gen_render_options(&crate::utils::parse_note_vec("C:4,E:4").unwrap())
+        };
+        let args = <RenderManifestArgs as structopt::StructOpt>::from_iter_safe(&[
+            "atm",
+            "--division", "480",
+            "--tempo", "90",
+            "--program", "41",
+            "--dataset-id=-C4,E4",
+            "--chord-types", "maj,min7",
+            "--durations", "1/8,1/4",
+            "--bars", "1",
+            "--velocities", "pp,ff",
+        ]).unwrap();
+        let render = gen_render_options(args);
+        let manifest = render.gen_manifest();
+        assert!(manifest.contains("dataset=-C4,E4\nchords=0+3+7+10;0+4+7\ndurations=1/8;1/4\nbars=1\n"));
+        let parsed = gen_render_options(RenderManifestArgs::from_manifest(&manifest).unwrap());
+        assert_eq!(render, parsed);
+
+        assert!(matches!(
+            RenderManifestArgs::from_manifest("format=0\nspeed=2\n"),
+            Err(ParseManifestError::UnknownOption { .. })
+        ));
+        assert!(matches!(
+            RenderManifestArgs::from_manifest("division=0\n"),
+            Err(ParseManifestError::InvalidOptions { .. })
+        ));
+    }
+}
@@ -0,0 +1,263 @@
+// counterpoint.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+/// Separator between the voices of a pair, in hashes and partition names
+pub const VOICE_SEPARATOR: char = '_';
+
+/// Number of voices (and thus tracks of MIDI files) of pairs
+pub const NUM_VOICES: u16 = 2;
+
+/****************************
+***** VoiceLeadingRules *****
+****************************/
+
+/// Voice-leading rules pairs of voices are filtered by. Positions where either voice
+/// rests are ignored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VoiceLeadingRules {
+    /// Reject parallel fifths and octaves: two consecutive perfect fifths, or two consecutive
+    /// octaves (or unisons), between voices that both move (compound intervals included)
+    pub no_parallels: bool,
+    /// Reject voice crossing: bass above the melody
+    pub no_crossing: bool,
+}
+
+impl VoiceLeadingRules {
+    /// Interval in semitones from bass to melody, or `None` if either voice rests
+    fn gen_interval(melody_note: &libatm::MIDINote, bass_note: &libatm::MIDINote) -> Option<i32> {
+        match (melody_note.note_type, bass_note.note_type) {
+            (libatm::MIDINoteType::Rest, _) | (_, libatm::MIDINoteType::Rest) => None,
+            _ => Some(melody_note.convert() as i32 - bass_note.convert() as i32),
+        }
+    }
+
+    /// Whether `interval` is a perfect fifth or octave (or unison), as semitones modulo 12
+    fn gen_perfect_interval(interval: i32) -> Option<i32> {
+        match interval.abs() % 12 {
+            0 => Some(0),
+            7 => Some(7),
+            _ => None,
+        }
+    }
+
+    /// Check whether voices `melody` and `bass` (of the same length) follow rules
+    pub fn check(&self, melody: &[libatm::MIDINote], bass: &[libatm::MIDINote]) -> bool {
+        let intervals = melody
+            .iter()
+            .zip(bass.iter())
+            .map(|(melody_note, bass_note)| Self::gen_interval(melody_note, bass_note))
+            .collect::<Vec<Option<i32>>>();
+        if self.no_crossing && intervals.iter().any(|interval| interval.is_some_and(|interval| interval < 0)) {
+            return false;
+        }
+        if self.no_parallels {
+            let steps = intervals.windows(2).zip(melody.windows(2).zip(bass.windows(2)));
+            for (intervals, (melody, bass)) in steps {
+                let (previous, current) = match (intervals[0], intervals[1]) {
+                    (Some(previous), Some(current)) => (previous, current),
+                    _ => continue,
+                };
+                // Voices moving in oblique motion (one voice held) aren't parallel
+                if melody[0] == melody[1] || bass[0] == bass[1] {
+                    continue;
+                }
+                let previous = Self::gen_perfect_interval(previous);
+                if previous.is_some() && previous == Self::gen_perfect_interval(current) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Generate hash of pair of voices: the hash of the melody and of the bass (see:
+/// [gen_melody_hash](../utils/fn.gen_melody_hash.html)), joined by `_` (i.e., `606467_485543`)
+///
+/// # Examples
+///
+/// ```rust
+/// let melody = atm::utils::parse_note_vec("C:4,R,G:4").unwrap();
+/// let bass = atm::utils::parse_note_vec("C:3,F:3,G:3").unwrap();
+/// assert_eq!("60R67_485355", atm::counterpoint::gen_counterpoint_hash(&melody, &bass));
+/// ```
+pub fn gen_counterpoint_hash(melody: &[libatm::MIDINote], bass: &[libatm::MIDINote]) -> String {
+    format!(
+        "{}{}{}",
+        crate::utils::gen_melody_hash(melody),
+        VOICE_SEPARATOR,
+        crate::utils::gen_melody_hash(bass),
+    )
+}
+
+/**************************
+***** CounterpointSet *****
+**************************/
+
+/// Error type for [CounterpointSet](struct.CounterpointSet.html)
+#[derive(Debug, thiserror::Error)]
+pub enum CounterpointSetError {
+    #[error("Must provide at least one note for the {voice}")]
+    Empty { voice: &'static str },
+    #[error("Number of pairs of voices of length {length} overflows a 64-bit index")]
+    TooManyPairs { length: u32 },
+}
+
+/// Set of pairs of voices: every melody of `length` notes generated from a set of melody
+/// notes, against every bass line of `length` notes generated from a set of bass notes.
+/// Pairs are generated in lexicographic order of the notes played together at every
+/// position (melody note first, then bass note), so every pair has a stable index, and
+/// pairs that start with the same notes are generated together.
+///
+/// Pairs that break the voice-leading rules of the set (see:
+/// [VoiceLeadingRules](struct.VoiceLeadingRules.html)) keep their index, but aren't rendered.
+#[derive(Clone, Debug)]
+pub struct CounterpointSet {
+    /// Notes melodies are generated from (in generation order)
+    melody_notes: Vec<libatm::MIDINote>,
+    /// Notes bass lines are generated from (in generation order)
+    bass_notes: Vec<libatm::MIDINote>,
+    /// Number of notes per voice
+    length: u32,
+    /// Voice-leading rules pairs must follow
+    rules: VoiceLeadingRules,
+    /// Total number of pairs (including those that break rules)
+    num_pairs: u64,
+}
+
+impl CounterpointSet {
+    /// Create new `CounterpointSet` instance for pairs of voices of `length` notes
+    pub fn new(
+        melody_notes: &[libatm::MIDINote],
+        bass_notes: &[libatm::MIDINote],
+        length: u32,
+        rules: VoiceLeadingRules,
+    ) -> Result<Self, CounterpointSetError> {
+        if melody_notes.is_empty() {
+            return Err(CounterpointSetError::Empty { voice: "melody" });
+        }
+        if bass_notes.is_empty() {
+            return Err(CounterpointSetError::Empty { voice: "bass" });
+        }
+        let num_pairs = (melody_notes.len() as u64)
+            .checked_mul(bass_notes.len() as u64)
+            .and_then(|num_positions| num_positions.checked_pow(length))
+            .ok_or(CounterpointSetError::TooManyPairs { length })?;
+        Ok(Self {
+            melody_notes: melody_notes.to_vec(),
+            bass_notes: bass_notes.to_vec(),
+            length,
+            rules,
+            num_pairs,
+        })
+    }
+
+    /// Number of pairs in set (including those that break voice-leading rules)
+    pub fn num_pairs(&self) -> u64 {
+        self.num_pairs
+    }
+
+    /// Generate the pair of voices (melody, bass) at index `index`, or `None` if out of range
+    pub fn gen_pair_at(&self, index: u64) -> Option<(libatm::MIDINoteVec, libatm::MIDINoteVec)> {
+        if index >= self.num_pairs {
+            return None;
+        }
+        let num_bass_notes = self.bass_notes.len() as u64;
+        let base = self.melody_notes.len() as u64 * num_bass_notes;
+        let mut melody = Vec::with_capacity(self.length as usize);
+        let mut bass = Vec::with_capacity(self.length as usize);
+        let mut remainder = index;
+        for _ in 0..self.length {
+            let position = remainder % base;
+            melody.push(self.melody_notes[(position / num_bass_notes) as usize]);
+            bass.push(self.bass_notes[(position % num_bass_notes) as usize]);
+            remainder /= base;
+        }
+        melody.reverse();
+        bass.reverse();
+        Some((libatm::MIDINoteVec(melody), libatm::MIDINoteVec(bass)))
+    }
+
+    /// Render pair at index `index` as format 1 MIDI file with `render`, with one track per
+    /// voice (melody first), and generate its hash (see:
+    /// [gen_counterpoint_hash](fn.gen_counterpoint_hash.html)). The sequence of the MIDI file
+    /// is the melody followed by the bass. Returns `None` if out of range, or if the pair breaks
+    /// the voice-leading rules of the set.
+    pub fn prepare(&self, render: &crate::midi::MIDIRenderOptions, index: u64) -> Option<crate::storage::PreparedMIDIFile> {
+        let (melody, bass) = self.gen_pair_at(index)?;
+        if !self.rules.check(&melody, &bass) {
+            return None;
+        }
+        let hash = gen_counterpoint_hash(&melody, &bass);
+        // Track name is only written with meta events
+        let name = match render.meta {
+            Some(_) => format!("{} | {}", crate::utils::format_melody(&melody), crate::utils::format_melody(&bass)),
+            None => String::new(),
+        };
+        let data = render.render_voices(&[&melody[..], &bass[..]], &name, Some(index));
+        let mut sequence = melody.0;
+        sequence.extend(bass.0);
+        let mfile = libatm::MIDIFile::new(
+            libatm::MIDINoteVec(sequence),
+            libatm::MIDIFormat::Format1,
+            NUM_VOICES,
+            render.division,
+        );
+        Some(crate::storage::PreparedMIDIFile { mfile, hash, data })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voice_leading_rules() {
+        let melody = crate::utils::parse_note_vec("G:4,A:4,C:5").unwrap();
+        let rules = VoiceLeadingRules { no_parallels: true, no_crossing: true };
+        // Parallel fifths (C-G to D-A), and octaves in contrary motion across a compound interval
+        assert!(!rules.check(&melody, &crate::utils::parse_note_vec("C:4,D:4,E:4").unwrap()));
+        assert!(!rules.check(&melody, &crate::utils::parse_note_vec("G:3,A:2,E:4").unwrap()));
+        // Fifths that aren't consecutive, or separated by a rest
+        assert!(rules.check(&melody, &crate::utils::parse_note_vec("C:4,F:4,F:4").unwrap()));
+        assert!(rules.check(&melody, &crate::utils::parse_note_vec("C:4,R,F:4").unwrap()));
+        // Voice crossing
+        assert!(!rules.check(&melody, &crate::utils::parse_note_vec("C:4,B:4,A:4").unwrap()));
+        assert!(VoiceLeadingRules::default().check(&melody, &crate::utils::parse_note_vec("C:4,D:4,D:5").unwrap()));
+    }
+
+    #[test]
+    fn test_counterpoint_pairs() {
+        let melody_notes = crate::utils::parse_note_vec("C:4,E:4,G:4").unwrap();
+        let bass_notes = crate::utils::parse_note_vec("C:3,G:2").unwrap();
+        let pairs = CounterpointSet::new(&melody_notes, &bass_notes, 2, VoiceLeadingRules::default()).unwrap();
+        assert_eq!(36, pairs.num_pairs());
+        // Bass changes fastest at every position, then melody
+        let (melody, bass) = pairs.gen_pair_at(8).unwrap();
+        assert_eq!("C:4,E:4", crate::utils::format_melody(&melody));
+        assert_eq!("G:2,C:3", crate::utils::format_melody(&bass));
+        assert_eq!(None, pairs.gen_pair_at(36));
+
+        // Pairs are written with one track per voice, and decoded as melody followed by bass
+        let render = crate::midi::MIDIRenderOptions::default();
+        let pfile = pairs.prepare(&render, 8).unwrap();
+        assert_eq!("6064_4348", pfile.hash);
+        let decoded = crate::midi::decode_midi_file(&pfile.data).unwrap();
+        assert_eq!((1, 2), (decoded.format, decoded.tracks));
+        assert_eq!(pfile.mfile.sequence, decoded.sequence);
+
+        // Pairs that break rules aren't rendered
+        let rules = VoiceLeadingRules { no_parallels: true, no_crossing: false };
+        let pairs = CounterpointSet::new(&melody_notes, &bass_notes, 2, rules).unwrap();
+        let (melody, bass) = pairs.gen_pair_at(5).unwrap();
+        assert_eq!("C:4,G:4", crate::utils::format_melody(&melody));
+        assert_eq!("C:3,G:2", crate::utils::format_melody(&bass));
+        assert!(pairs.prepare(&render, 5).is_none());
+        assert!(pairs.prepare(&render, 3).is_some());
+    }
+}
@@ -6,22 +6,72 @@
 // To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
 // a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
 
+pub mod at;
+pub mod convert;
 pub mod estimate;
+pub mod extract;
 pub mod gen;
+pub mod index_of;
+pub mod ls;
 pub mod partition;
+pub mod verify;
+mod convert_batch;
+mod convert_dir;
+mod convert_pack;
+mod convert_tar;
+mod convert_tar_gz;
+mod convert_tar_zst;
+mod convert_zip;
+mod estimate_dir;
+mod estimate_drums;
+mod estimate_pack;
 mod estimate_tar;
 mod estimate_tar_gz;
+mod estimate_tar_zst;
+mod estimate_zip;
+mod gen_counterpoint;
+mod gen_dir;
+mod gen_drums;
+mod gen_pack;
+mod gen_progressions;
 mod gen_single;
 mod gen_tar;
 mod gen_tar_gz;
+mod gen_tar_zst;
+mod gen_zip;
 mod gen_batch;
 
+pub use at::AtDirective;
+pub use convert::ConvertDirective;
+pub use convert_batch::ConvertBatchDirective;
+pub use convert_dir::ConvertDirDirective;
+pub use convert_pack::ConvertPackDirective;
+pub use convert_tar::ConvertTarDirective;
+pub use convert_tar_gz::ConvertTarGzDirective;
+pub use convert_tar_zst::ConvertTarZstDirective;
+pub use convert_zip::ConvertZipDirective;
 pub use estimate::EstimateDirective;
+pub use estimate_dir::EstimateDirDirective;
+pub use estimate_drums::EstimateDrumsDirective;
+pub use estimate_pack::EstimatePackDirective;
 pub use estimate_tar::EstimateTarDirective;
 pub use estimate_tar_gz::EstimateTarGzDirective;
+pub use estimate_tar_zst::EstimateTarZstDirective;
+pub use estimate_zip::EstimateZipDirective;
+pub use extract::ExtractDirective;
 pub use gen::GenDirective;
+pub use gen_counterpoint::GenCounterpointDirective;
+pub use gen_dir::GenDirDirective;
+pub use gen_drums::GenDrumsDirective;
+pub use gen_pack::GenPackDirective;
+pub use gen_progressions::GenProgressionsDirective;
 pub use gen_single::GenSingleDirective;
 pub use gen_tar::GenTarDirective;
 pub use gen_tar_gz::GenTarGzDirective;
+pub use gen_tar_zst::GenTarZstDirective;
+pub use gen_zip::GenZipDirective;
 pub use gen_batch::GenBatchDirective;
+pub use index_of::IndexOfDirective;
+pub use ls::LsDirective;
 pub use partition::PartitionDirective;
+pub use verify::VerifyDirective;
@@ -0,0 +1,41 @@
+// at.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::cli::CliDirective;
+
+/**********************
+***** AtDirective *****
+**********************/
+
+/// Generate the melody at an index in the sequence of melodies generated from a set of notes.
+/// Inverse of the `index-of` directive.
+#[derive(structopt::StructOpt)]
+pub struct AtDirective {
+    #[structopt(flatten)]
+    pub note_set: crate::cli::NoteSetArg,
+    #[structopt(flatten)]
+    pub melody_length: crate::cli::MelodyLengthArg,
+    #[structopt(flatten)]
+    pub melody_index: crate::cli::MelodyIndexArg,
+}
+
+impl CliDirective for AtDirective {
+    fn run(self) {
+        let notes = libatm::MIDINoteVec::from(self.note_set.note_set);
+        let melody_length = self.melody_length.into();
+        let melody_index = self.melody_index.into();
+
+        match crate::utils::gen_melody_at(&notes, melody_length, melody_index) {
+            Ok(melody) => println!("{}", crate::utils::format_melody(&melody)),
+            Err(err) => {
+                println!("::: ERROR: Failed to generate melody at index {} ({})", melody_index, err);
+                std::process::exit(1);
+            },
+        }
+    }
+}
@@ -0,0 +1,352 @@
+// convert.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use std::io::BufRead;
+
+use crate::{
+    cli::{CliDirective, ConvertInputArgs, PartitionArgs},
+    directives::{
+        gen::resolve_render_options,
+        ConvertBatchDirective,
+        ConvertDirDirective,
+        ConvertPackDirective,
+        ConvertTarDirective,
+        ConvertTarGzDirective,
+        ConvertTarZstDirective,
+        ConvertZipDirective,
+    },
+    midi::MIDIRenderOptions,
+    storage::{
+        pack_file::PACK_MAGIC,
+        ArchiveReader,
+        PackReader,
+        PartitionPathGenerator,
+        PreparedMIDIFile,
+        StorageBackend,
+    },
+};
+
+/***********************
+***** ConvertInput *****
+***********************/
+
+/// Notes, melody length and rendering options of the dataset in a `convert` input
+#[derive(Clone)]
+pub(crate) struct InputDataset {
+    /// Notes melodies were generated from (in generation order)
+    pub notes: libatm::MIDINoteVec,
+    /// Length of melodies
+    pub melody_length: u32,
+    /// Options melodies were rendered as MIDI files with
+    pub render: MIDIRenderOptions,
+}
+
+/// Input of `convert` directives, read in a single pass
+pub(crate) enum ConvertInput {
+    /// Tar-based archive (see: [ArchiveReader](../storage/archive_reader/struct.ArchiveReader.html)),
+    /// and its dataset if provided (see: --notes, --length)
+    Archive(Box<ArchiveReader>, Option<InputDataset>),
+    /// Melody pack (see: [PackReader](../storage/pack_file/struct.PackReader.html))
+    Pack(PackReader<Box<dyn std::io::Read>>),
+}
+
+impl ConvertInput {
+    /// Open input file (or stdin if `-`), and detect whether it's an archive or a melody pack
+    pub(crate) fn open(args: &ConvertInputArgs) -> Self {
+        let path = &args.input;
+        let reader: Box<dyn std::io::Read> = if path.as_os_str() == "-" {
+            Box::new(std::io::stdin())
+        } else {
+            match std::fs::File::open(path) {
+                Ok(file) => Box::new(file),
+                Err(err) => {
+                    eprintln!("::: ERROR: Failed to open input {:?} ({})", path, err);
+                    std::process::exit(1);
+                },
+            }
+        };
+        let mut reader = std::io::BufReader::new(reader);
+        let is_pack = match reader.fill_buf() {
+            Ok(data) => data.starts_with(PACK_MAGIC),
+            Err(err) => {
+                eprintln!("::: ERROR: Failed to read input {:?} ({})", path, err);
+                std::process::exit(1);
+            },
+        };
+
+        if is_pack {
+            match PackReader::from_reader(Box::new(reader) as Box<dyn std::io::Read>) {
+                Ok(pack_reader) => Self::Pack(pack_reader),
+                Err(err) => {
+                    eprintln!("::: ERROR: Failed to read melody pack {:?} ({})", path, err);
+                    std::process::exit(1);
+                },
+            }
+        } else {
+            match ArchiveReader::from_reader(Box::new(reader)) {
+                Ok(archive_reader) => Self::Archive(Box::new(archive_reader), Self::gen_archive_dataset(args)),
+                Err(err) => {
+                    eprintln!("::: ERROR: Failed to read archive {:?} ({})", path, err);
+                    std::process::exit(1);
+                },
+            }
+        }
+    }
+
+    /// Generate dataset of input archive from the `--notes`, `--length` (and rendering)
+    /// arguments, or `None` if notes and melody length weren't provided
+    fn gen_archive_dataset(args: &ConvertInputArgs) -> Option<InputDataset> {
+        let note_set = args.note_set.as_ref()?;
+        let melody_length = args.melody_length?;
+        let notes = libatm::MIDINoteVec::from(note_set);
+        let render = resolve_render_options(args.render_args.clone(), &args.variation_args, &notes, melody_length);
+        Some(InputDataset { notes, melody_length, render })
+    }
+
+    /// Resolve notes, melody length and rendering options of input dataset, from the header of
+    /// melody packs or the `--notes`, `--length` (and rendering) arguments for archives
+    /// (see: [open](#method.open)).
+    /// Exits if notes or melody length aren't known.
+    pub(crate) fn resolve_dataset(&self) -> InputDataset {
+        match self {
+            Self::Pack(pack_reader) => InputDataset {
+                notes: pack_reader.header().notes.clone(),
+                melody_length: pack_reader.header().melody_length,
+                render: pack_reader.header().render.clone(),
+            },
+            Self::Archive(_, Some(dataset)) => dataset.clone(),
+            Self::Archive(_, None) => {
+                eprintln!("::: ERROR: Must provide note set and melody length of input archive (see: --notes, --length)");
+                std::process::exit(1);
+            },
+        }
+    }
+}
+
+/**************************
+***** Utility Methods *****
+**************************/
+
+/// Create path generator for partitioning scheme of output
+pub(crate) fn gen_partition_path_generator(
+    partition_args: &PartitionArgs,
+    partition_depth: u32,
+    dataset: &InputDataset,
+) -> PartitionPathGenerator {
+    PartitionPathGenerator::new_with_variations(
+        dataset.notes.len() as u32,
+        dataset.melody_length,
+        dataset.render.num_variations(),
+        partition_args.max_files,
+        partition_depth,
+    ).unwrap_or_else(|err| {
+        eprintln!("::: ERROR: Failed to initialize partitioning scheme ({:?})", err);
+        std::process::exit(1);
+    })
+}
+
+/// Create progress bar for reading entries of input archive, out of the number of files
+/// in its dataset if known (or just counting entries otherwise)
+fn gen_archive_progress_bar(dataset: Option<&InputDataset>) -> pbr::ProgressBar<std::io::Stderr> {
+    let num_melodies = dataset.map(|dataset| dataset.render.gen_num_files(&dataset.notes, dataset.melody_length));
+    let mut pb = pbr::ProgressBar::on(std::io::stderr(), num_melodies.unwrap_or(u64::MAX));
+    if num_melodies.is_none() {
+        pb.show_bar = false;
+        pb.show_percent = false;
+        pb.show_time_left = false;
+        pb.show_counter = false;
+    }
+    pb.set_max_refresh_rate(Some(std::time::Duration::from_millis(500)));
+    pb
+}
+
+/// Write render manifest to storage backend, and exit if it couldn't be written
+fn append_manifest<B: StorageBackend>(backend: &mut B, manifest: &str) {
+    if let Err(err) = backend.append_manifest(manifest) {
+        eprintln!("::: ERROR: Failed to write render manifest to storage backend ({:?})", err);
+        std::process::exit(1);
+    }
+}
+
+/// Write every melody in input to storage backend with permissions `mode`, and finish backend.
+/// MIDI files read from archives are written as-is, and stored at the path given by the
+/// backend's path generator. The melody of each entry is recovered from the hash in its
+/// filename (see: [recover_annotated_melody](../midi/fn.recover_annotated_melody.html)).
+/// Entries that can't be decoded, don't match the hash in their filename, or have a variation
+/// that isn't generated with the dataset (if provided), aren't written, and exits with status 1
+/// if any were found (or if any melody couldn't be added to the backend).
+///
+/// The render manifest of the input is copied to the backend (or generated from the
+/// rendering options of the dataset, if the input doesn't have one). Exits if the input
+/// was rendered with different options than those provided.
+pub(crate) fn convert_to_backend<B: StorageBackend>(input: ConvertInput, mode: Option<u32>, mut backend: B) {
+    let mut num_converted = 0u64;
+    let mut num_skipped = 0u64;
+    let mut num_failed = 0u64;
+    match input {
+        ConvertInput::Archive(archive_reader, dataset) => {
+            let mut pb = gen_archive_progress_bar(dataset.as_ref());
+            let mut has_manifest = false;
+            let result = archive_reader.for_each_entry(
+                |_| true,
+                |entry| {
+                    if entry.is_render_manifest() {
+                        let manifest = String::from_utf8_lossy(&entry.data);
+                        if let Some(ref dataset) = dataset {
+                            let expected = dataset.render.gen_manifest();
+                            if manifest != expected {
+                                eprintln!(
+                                    "::: ERROR: Input was rendered with options {} (see: its render manifest), not {}",
+                                    manifest.trim_end().replace('\n', ","),
+                                    dataset.render,
+                                );
+                                std::process::exit(1);
+                            }
+                        }
+                        // Shards concatenated with `cat` have one render manifest each
+                        if !has_manifest {
+                            append_manifest(&mut backend, &manifest);
+                            has_manifest = true;
+                        }
+                        return Ok(std::ops::ControlFlow::Continue(()));
+                    }
+                    // Render manifest is the first entry of archives that have one
+                    if !has_manifest {
+                        match dataset {
+                            Some(ref dataset) => append_manifest(&mut backend, &dataset.render.gen_manifest()),
+                            None => eprintln!(
+                                "::: WARNING: Input has no render manifest, and output won't have one either \
+                                (see: --notes, --length)"
+                            ),
+                        }
+                        has_manifest = true;
+                    }
+                    if dataset.is_none() {
+                        pb.message(&format!("{} entries ", num_converted + num_skipped + num_failed + 1));
+                    }
+                    pb.inc();
+                    let recovered = match dataset {
+                        Some(ref dataset) => dataset.render.recover_melody(entry.hash(), &entry.data).map(|(melody, _)| melody),
+                        None => crate::midi::recover_annotated_melody(entry.hash(), &entry.data).map(|parsed| parsed.melody),
+                    };
+                    let melody = match recovered {
+                        Ok(melody) => melody,
+                        Err(err) => {
+                            eprintln!("::: WARNING: Skipping {} ({})", entry.path, err);
+                            num_skipped += 1;
+                            return Ok(std::ops::ControlFlow::Continue(()));
+                        },
+                    };
+                    let mfile = libatm::MIDIFile::new(melody, libatm::MIDIFormat::Format0, 1, 1);
+                    let hash = entry.hash().to_string();
+                    let pfile = PreparedMIDIFile { mfile, hash, data: entry.data };
+                    // Show error if adding melody to backend failed
+                    match backend.append_prepared(pfile, mode) {
+                        Ok(_) => num_converted += 1,
+                        Err(err) => {
+                            eprintln!("::: WARNING: Failed to add {} to storage backend ({:?})", entry.path, err);
+                            num_failed += 1;
+                        },
+                    }
+                    Ok(std::ops::ControlFlow::Continue(()))
+                },
+            );
+            pb.finish_println("");
+            if let Err(err) = result {
+                eprintln!("::: ERROR: Failed to read archive ({})", err);
+                std::process::exit(1);
+            }
+        },
+        ConvertInput::Pack(pack_reader) => {
+            let mut pb = pbr::ProgressBar::on(std::io::stderr(), pack_reader.header().num_melodies);
+            pb.set_max_refresh_rate(Some(std::time::Duration::from_millis(500)));
+            let melodies = pack_reader.into_melodies();
+            let header = melodies.header().clone();
+            append_manifest(&mut backend, &header.render.gen_manifest());
+            for melody in melodies {
+                let melody = melody.unwrap_or_else(|err| {
+                    eprintln!("::: ERROR: Failed to read melody from pack ({})", err);
+                    std::process::exit(1);
+                });
+                // Show error if adding melody to backend failed
+                match backend.append_prepared(header.gen_file(melody), mode) {
+                    Ok(_) => num_converted += 1,
+                    Err(err) => {
+                        eprintln!("::: WARNING: Failed to add melody to storage backend ({:?})", err);
+                        num_failed += 1;
+                    },
+                }
+                pb.inc();
+            }
+            pb.finish_println("");
+        },
+    }
+
+    if let Err(err) = backend.finish() {
+        eprintln!("::: ERROR: Failed to finish writing to storage backend ({:?})", err);
+        std::process::exit(1);
+    }
+    eprintln!(
+        "::: INFO: Converted {} melodies ({} skipped, {} failed)",
+        num_converted,
+        num_skipped,
+        num_failed,
+    );
+    if num_skipped + num_failed > 0 {
+        eprintln!("::: ERROR: Output is missing {} entries of input", num_skipped + num_failed);
+        std::process::exit(1);
+    }
+}
+
+/***************************
+***** ConvertDirective *****
+***************************/
+
+/// Convert an archive generated by one of the `gen` directives (Tar, Gzip- or
+/// Zstandard-compressed Tar, or batch) or a melody pack to another storage backend.
+/// Input is streamed, so nothing is extracted to disk. Melodies are stored at
+/// the paths given by the output partitioning scheme (see: -p/--partitions).
+#[derive(structopt::StructOpt)]
+pub enum ConvertDirective {
+    /// Convert input to Tar file, where each entry is a Gzip- or Zstandard-compressed
+    /// Tar file containing a batch of melodies.
+    #[structopt(name="batch")]
+    ConvertBatch(ConvertBatchDirective),
+    /// Convert input to files in a directory tree, where partitions are directories.
+    #[structopt(name="dir")]
+    ConvertDir(ConvertDirDirective),
+    /// Convert input to melody pack.
+    #[structopt(name="pack")]
+    ConvertPack(ConvertPackDirective),
+    /// Convert input to Tar file.
+    #[structopt(name="tar")]
+    ConvertTar(ConvertTarDirective),
+    /// Convert input to Gzip-compressed Tar file.
+    #[structopt(name="tar-gz")]
+    ConvertTarGz(ConvertTarGzDirective),
+    /// Convert input to Zstandard-compressed Tar file.
+    #[structopt(name="tar-zst")]
+    ConvertTarZst(ConvertTarZstDirective),
+    /// Convert input to ZIP file.
+    #[structopt(name="zip")]
+    ConvertZip(ConvertZipDirective),
+}
+
+impl CliDirective for ConvertDirective {
+    fn run(self) {
+        match self {
+            Self::ConvertBatch(d) => d.run(),
+            Self::ConvertDir(d) => d.run(),
+            Self::ConvertPack(d) => d.run(),
+            Self::ConvertTar(d) => d.run(),
+            Self::ConvertTarGz(d) => d.run(),
+            Self::ConvertTarZst(d) => d.run(),
+            Self::ConvertZip(d) => d.run(),
+        }
+    }
+}
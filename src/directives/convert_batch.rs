@@ -0,0 +1,117 @@
+// convert_batch.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use flate2::Compression;
+
+use crate::{
+    cli::{CliDirective, try_zstd_level_from_str},
+    directives::{
+        convert::{convert_to_backend, ConvertInput},
+        gen::{gen_stdout_writer, try_compression_from_str},
+        gen_batch::resolve_batch_compression,
+    },
+    storage::BatchOptions,
+};
+
+/********************************
+***** ConvertBatchDirective *****
+********************************/
+
+/// Convert archive or melody pack to nested Gzip- or Zstandard-compressed Tar files
+/// (see: [BatchTarFile](../storage/batch_tar_file/struct.BatchTarFile.html)).
+/// Melodies in each partition must be contiguous in the input, as they are in
+/// the output of every `gen` directive.
+#[derive(structopt::StructOpt)]
+pub struct ConvertBatchDirective {
+    #[structopt(flatten)]
+    pub input_args: crate::cli::ConvertInputArgs,
+    #[structopt(flatten)]
+    pub target: crate::cli::TargetArg,
+    #[structopt(flatten)]
+    pub partition_args: crate::cli::PartitionArgs,
+    /// Permissions to use for entries in top-level Tar file [default: 644]
+    #[structopt(long="mode")]
+    pub batch_mode: Option<u32>,
+    #[structopt(
+        short="C",
+        long="compress",
+        help="Compression level [0-9, default: 6]",
+        parse(try_from_str = try_compression_from_str))]
+    pub batch_compression: Option<Compression>,
+    /// Compress batches with Zstandard at level [1-22] instead of Gzip, using a dictionary
+    /// trained from a sample of melodies. The dictionary is stored as the first entry
+    /// of the top-level Tar file (batch.dict).
+    #[structopt(
+        long="zstd",
+        value_name="level",
+        conflicts_with="batch-compression",
+        parse(try_from_str = try_zstd_level_from_str))]
+    pub batch_zstd_level: Option<i32>,
+    /// Maximum size of zstd dictionary (in bytes) [default: 16384]
+    #[structopt(long="dict-size", requires="batch-zstd-level")]
+    pub dictionary_size: Option<usize>,
+    #[structopt(flatten)]
+    pub batch_size: crate::cli::BatchSize,
+}
+
+impl CliDirective for ConvertBatchDirective {
+    fn run(self) {
+        let partition_depth = match self.partition_args.partition_depth {
+            Some(partition_depth) => partition_depth,
+            None => {
+                eprintln!("::: ERROR: Must provide partition depth");
+                std::process::exit(1);
+            },
+        };
+        let input = ConvertInput::open(&self.input_args);
+        let dataset = input.resolve_dataset();
+
+        let path_generator = crate::storage::PartitionPathGenerator::new_with_variations(
+            dataset.notes.len() as u32,
+            dataset.melody_length,
+            dataset.render.num_variations(),
+            self.partition_args.max_files,
+            partition_depth,
+        ).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to initialize partitioning scheme ({:?})", err);
+            std::process::exit(1);
+        });
+
+        // Resolve batch compression, training zstd dictionary if necessary
+        let batch_options = BatchOptions {
+            batch_size: self.batch_size.into(),
+            batch_compression: resolve_batch_compression(
+                self.batch_compression,
+                self.batch_zstd_level,
+                self.dictionary_size,
+                &dataset.notes,
+                dataset.melody_length,
+                &dataset.render,
+            ),
+            batch_mode: self.batch_mode,
+        };
+
+        // Write batches to stdout
+        if self.target.is_stdout() {
+            let backend = crate::storage::BatchTarFile::from_writer(gen_stdout_writer(), path_generator, &batch_options)
+                .unwrap_or_else(|err| {
+                    eprintln!("::: ERROR: Failed to create storage backend ({:?})", err);
+                    std::process::exit(1);
+                });
+            convert_to_backend(input, None, backend);
+            return;
+        }
+
+        let backend = crate::storage::BatchTarFile::new(&self.target.target, path_generator, &batch_options)
+            .unwrap_or_else(|err| {
+                eprintln!("::: ERROR: Failed to create storage backend ({:?})", err);
+                std::process::exit(1);
+            });
+        convert_to_backend(input, None, backend);
+    }
+}
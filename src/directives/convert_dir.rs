@@ -0,0 +1,70 @@
+// convert_dir.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::{
+    cli::CliDirective,
+    directives::{
+        convert::{convert_to_backend, gen_partition_path_generator, ConvertInput},
+        gen_dir::{try_mode_from_str, try_sync_from_str},
+    },
+    storage::PathGenerator,
+};
+
+/******************************
+***** ConvertDirDirective *****
+******************************/
+
+/// Convert archive or melody pack to files in a directory tree
+/// (see: [DirectoryBackend](../storage/directory/struct.DirectoryBackend.html))
+#[derive(structopt::StructOpt)]
+pub struct ConvertDirDirective {
+    #[structopt(flatten)]
+    pub input_args: crate::cli::ConvertInputArgs,
+    /// Root directory of output directory tree (created if it doesn't exist).
+    #[structopt(parse(from_str))]
+    pub target: std::path::PathBuf,
+    /// Permissions (octal) to use for files [default: 666 minus umask]
+    #[structopt(long="mode", parse(try_from_str = try_mode_from_str))]
+    pub mode: Option<u32>,
+    /// Sync files to disk (fsync) after every N files written
+    #[structopt(long="sync", value_name="N", parse(try_from_str = try_sync_from_str))]
+    pub sync_interval: Option<u32>,
+    #[structopt(flatten)]
+    pub partition_args: crate::cli::PartitionArgs,
+}
+
+impl ConvertDirDirective {
+    /// Write melodies from input to directory tree using provided path generator
+    fn write_melodies<G: PathGenerator>(self, input: ConvertInput, path_generator: G) {
+        let backend = crate::storage::DirectoryBackend::new(
+            &self.target,
+            path_generator,
+            self.sync_interval,
+        ).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to create storage backend ({:?})", err);
+            std::process::exit(1);
+        });
+        convert_to_backend(input, self.mode, backend);
+    }
+}
+
+impl CliDirective for ConvertDirDirective {
+    fn run(self) {
+        let input = ConvertInput::open(&self.input_args);
+        match self.partition_args.partition_depth {
+            // Use partitioning scheme
+            Some(partition_depth) => {
+                let dataset = input.resolve_dataset();
+                let path_generator = gen_partition_path_generator(&self.partition_args, partition_depth, &dataset);
+                self.write_melodies(input, path_generator);
+            },
+            // Don't use partitioning scheme
+            None => self.write_melodies(input, crate::storage::MIDIHashPathGenerator),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+// convert_pack.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::{
+    cli::CliDirective,
+    directives::{
+        convert::{convert_to_backend, gen_partition_path_generator, ConvertInput},
+        gen::resolve_file_target,
+    },
+    storage::{PackHeader, PackPartition},
+};
+
+/*******************************
+***** ConvertPackDirective *****
+*******************************/
+
+/// Convert archive (or melody pack) to melody pack
+/// (see: [PackFile](../storage/pack_file/struct.PackFile.html)).
+/// Melodies are packed in the order they are read from the input.
+#[derive(structopt::StructOpt)]
+pub struct ConvertPackDirective {
+    #[structopt(flatten)]
+    pub input_args: crate::cli::ConvertInputArgs,
+    #[structopt(flatten)]
+    pub target: crate::cli::TargetArg,
+    #[structopt(flatten)]
+    pub partition_args: crate::cli::PartitionArgs,
+}
+
+impl CliDirective for ConvertPackDirective {
+    fn run(self) {
+        let target = resolve_file_target(self.target);
+        let input = ConvertInput::open(&self.input_args);
+        let dataset = input.resolve_dataset();
+        // Packs only store pitch sequences
+        if !dataset.render.variations.is_empty() {
+            eprintln!("::: ERROR: Melody packs don't support chord types, durations or velocities");
+            std::process::exit(1);
+        }
+
+        // Partitioning scheme is only recorded in the pack header
+        let partition_args = self.partition_args;
+        let partition = partition_args.partition_depth.map(|partition_depth| {
+            // Ensure partitioning scheme is valid for dataset
+            gen_partition_path_generator(&partition_args, partition_depth, &dataset);
+            PackPartition {
+                max_files: partition_args.max_files,
+                partition_depth,
+            }
+        });
+        let header = PackHeader::new(dataset.notes, dataset.melody_length, dataset.render, partition);
+        let backend = crate::storage::PackFile::new(&target, header).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to create storage backend ({:?})", err);
+            std::process::exit(1);
+        });
+        convert_to_backend(input, None, backend);
+    }
+}
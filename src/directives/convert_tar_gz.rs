@@ -0,0 +1,77 @@
+// convert_tar_gz.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use flate2::Compression;
+
+use crate::{
+    cli::CliDirective,
+    directives::{
+        convert::{convert_to_backend, gen_partition_path_generator, ConvertInput},
+        gen::{gen_stdout_writer, try_compression_from_str},
+    },
+    storage::PathGenerator,
+};
+
+/********************************
+***** ConvertTarGzDirective *****
+********************************/
+
+/// Convert archive or melody pack to Gzip-compressed Tar file
+/// (see: [TarGzFile](../storage/tar_gz_file/struct.TarGzFile.html))
+#[derive(structopt::StructOpt)]
+pub struct ConvertTarGzDirective {
+    #[structopt(flatten)]
+    pub input_args: crate::cli::ConvertInputArgs,
+    #[structopt(flatten)]
+    pub target: crate::cli::TargetArg,
+    #[structopt(
+        short="C",
+        long="compress",
+        help="Compression level [0-9, default: 6]",
+        parse(try_from_str = try_compression_from_str))]
+    pub compression_level: Option<Compression>,
+    #[structopt(flatten)]
+    pub partition_args: crate::cli::PartitionArgs,
+}
+
+impl ConvertTarGzDirective {
+    /// Write melodies from input to Gzip-compressed Tar file (or stdout) using provided path generator
+    fn write_melodies<G: PathGenerator>(self, input: ConvertInput, path_generator: G) {
+        if self.target.is_stdout() {
+            let writer = gen_stdout_writer();
+            let backend = crate::storage::TarGzFile::from_writer(writer, path_generator, self.compression_level);
+            convert_to_backend(input, None, backend);
+            return;
+        }
+        let backend = crate::storage::TarGzFile::new(
+            &self.target.target,
+            path_generator,
+            self.compression_level,
+        ).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to create storage backend ({:?})", err);
+            std::process::exit(1);
+        });
+        convert_to_backend(input, None, backend);
+    }
+}
+
+impl CliDirective for ConvertTarGzDirective {
+    fn run(self) {
+        let input = ConvertInput::open(&self.input_args);
+        match self.partition_args.partition_depth {
+            // Use partitioning scheme
+            Some(partition_depth) => {
+                let dataset = input.resolve_dataset();
+                let path_generator = gen_partition_path_generator(&self.partition_args, partition_depth, &dataset);
+                self.write_melodies(input, path_generator);
+            },
+            // Don't use partitioning scheme
+            None => self.write_melodies(input, crate::storage::MIDIHashPathGenerator),
+        }
+    }
+}
@@ -0,0 +1,66 @@
+// convert_tar_zst.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::{
+    cli::CliDirective,
+    directives::{
+        convert::{convert_to_backend, gen_partition_path_generator, ConvertInput},
+        gen::resolve_file_target,
+    },
+    storage::PathGenerator,
+};
+
+/*********************************
+***** ConvertTarZstDirective *****
+*********************************/
+
+/// Convert archive or melody pack to Zstandard-compressed Tar file
+/// (see: [TarZstFile](../storage/tar_zst_file/struct.TarZstFile.html))
+#[derive(structopt::StructOpt)]
+pub struct ConvertTarZstDirective {
+    #[structopt(flatten)]
+    pub input_args: crate::cli::ConvertInputArgs,
+    #[structopt(flatten)]
+    pub target: crate::cli::TargetArg,
+    #[structopt(flatten)]
+    pub zstd_args: crate::cli::ZstdArgs,
+    #[structopt(flatten)]
+    pub partition_args: crate::cli::PartitionArgs,
+}
+
+impl ConvertTarZstDirective {
+    /// Write melodies from input to Zstandard-compressed Tar file using provided path generator
+    fn write_melodies<G: PathGenerator>(self, input: ConvertInput, path_generator: G) {
+        let target = resolve_file_target(self.target);
+        let backend = crate::storage::TarZstFile::new(
+            &target,
+            path_generator,
+            &self.zstd_args.into(),
+        ).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to create storage backend ({:?})", err);
+            std::process::exit(1);
+        });
+        convert_to_backend(input, None, backend);
+    }
+}
+
+impl CliDirective for ConvertTarZstDirective {
+    fn run(self) {
+        let input = ConvertInput::open(&self.input_args);
+        match self.partition_args.partition_depth {
+            // Use partitioning scheme
+            Some(partition_depth) => {
+                let dataset = input.resolve_dataset();
+                let path_generator = gen_partition_path_generator(&self.partition_args, partition_depth, &dataset);
+                self.write_melodies(input, path_generator);
+            },
+            // Don't use partitioning scheme
+            None => self.write_melodies(input, crate::storage::MIDIHashPathGenerator),
+        }
+    }
+}
@@ -9,8 +9,13 @@
 use crate::{
     cli::CliDirective,
     directives::{
+        EstimateDirDirective,
+        EstimateDrumsDirective,
+        EstimatePackDirective,
         EstimateTarDirective,
         EstimateTarGzDirective,
+        EstimateTarZstDirective,
+        EstimateZipDirective,
     },
 };
 
@@ -19,7 +24,7 @@ use crate::{
 **************************/
 
 /// Maximum number of melodies to use for size estimatation.
-/// Only used for tar_gz and batch backends.
+/// Only used for tar_gz, tar_zst, zip and batch backends.
 pub(crate) const MAX_SIM_NUM_MELODIES: u64 = 200000;
 
 /// Given total number of melodies, the simulated number of melodies,
@@ -60,19 +65,41 @@ pub(crate) fn pad_value_to_block(value: u64, block_size: Option<u64>) -> u64 {
 /// Estimate output size of storage backends to help make informed decisions about which to use.
 #[derive(structopt::StructOpt)]
 pub enum EstimateDirective {
+    /// Estimate number of files, directories and inodes, and disk usage
+    /// of directory tree storage backend.
+    #[structopt(name="dir")]
+    EstimateDir(EstimateDirDirective),
+    /// Count drum patterns, and estimate output size of Tar file storage backend
+    /// (see: `gen drums`).
+    #[structopt(name="drums")]
+    EstimateDrums(EstimateDrumsDirective),
+    /// Calculate output size of melody pack storage backend.
+    #[structopt(name="pack")]
+    EstimatePack(EstimatePackDirective),
     /// Estimate output size of Tar file storage backend.
     #[structopt(name="tar")]
     EstimateTar(EstimateTarDirective),
     /// Estimate output size of Gzip-compressed Tar file storage backend.
     #[structopt(name="tar-gz")]
     EstimateTarGz(EstimateTarGzDirective),
+    /// Estimate output size of Zstandard-compressed Tar file storage backend.
+    #[structopt(name="tar-zst")]
+    EstimateTarZst(EstimateTarZstDirective),
+    /// Estimate output size of ZIP file storage backend.
+    #[structopt(name="zip")]
+    EstimateZip(EstimateZipDirective),
 }
 
 impl CliDirective for EstimateDirective {
     fn run(self) {
         match self {
+            Self::EstimateDir(d) => d.run(),
+            Self::EstimateDrums(d) => d.run(),
+            Self::EstimatePack(d) => d.run(),
             Self::EstimateTar(d) => d.run(),
             Self::EstimateTarGz(d) => d.run(),
+            Self::EstimateTarZst(d) => d.run(),
+            Self::EstimateZip(d) => d.run(),
         }
     }
 }
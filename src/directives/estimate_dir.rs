@@ -0,0 +1,97 @@
+// estimate_dir.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use humansize::{FileSize, file_size_opts as options};
+
+use crate::{cli::CliDirective, directives::gen::resolve_render_options};
+
+/*******************************
+***** EstimateDirDirective *****
+*******************************/
+
+const CAVEATS: &str = "\
+Estimate assumes each file and directory takes up one inode and a whole number of blocks, \
+and that directories fit in a single block. Directories with many entries can take up \
+multiple blocks, and some file systems store small files inline (i.e., in the inode), \
+which could affect disk usage.";
+
+#[derive(structopt::StructOpt)]
+pub struct EstimateDirDirective {
+    #[structopt(flatten)]
+    pub num_notes: crate::cli::NumNotesArg,
+    #[structopt(flatten)]
+    pub melody_length: crate::cli::MelodyLengthArg,
+    #[structopt(flatten)]
+    pub partition_args: crate::cli::PartitionArgs,
+    #[structopt(flatten)]
+    pub render_args: crate::cli::MIDIRenderArgs,
+    #[structopt(flatten)]
+    pub variation_args: crate::cli::VariationArgs,
+    /// Block size of underlying file system (in bytes).
+    #[structopt(short, long, default_value="4096")]
+    pub block_size: u64,
+}
+
+impl CliDirective for EstimateDirDirective {
+    fn run(self) {
+        let num_notes: u32 = self.num_notes.into();
+        let melody_length: u32 = self.melody_length.into();
+        // Notes are only needed to calculate melody indices, not the size of the largest file
+        let render = resolve_render_options(
+            self.render_args,
+            &self.variation_args,
+            &libatm::MIDINoteVec(Vec::new()),
+            melody_length,
+        );
+
+        // Generate total number of melodies (files)
+        let num_melodies = render.gen_num_melodies(num_notes, melody_length);
+        // Generate number of partition directories
+        let num_directories = match self.partition_args.partition_depth {
+            Some(partition_depth) => crate::storage::PartitionPathGenerator::new_with_variations(
+                num_notes,
+                melody_length,
+                render.num_variations(),
+                self.partition_args.max_files,
+                partition_depth,
+            ).unwrap_or_else(|err| {
+                println!("::: ERROR: Failed to initialize partitioning scheme ({:?})", err);
+                std::process::exit(1);
+            }).gen_num_directories(),
+            None => 0,
+        };
+        // Every file and directory (including root directory) takes one inode
+        let num_inodes = num_melodies + num_directories + 1;
+
+        // Every file takes up a whole number of blocks, and every directory at least one block
+        let midi_file_size = render.gen_max_file_size(num_notes, melody_length);
+        let blocks_per_file = midi_file_size.div_ceil(self.block_size);
+        let disk_usage = (num_melodies * blocks_per_file + num_directories + 1) * self.block_size;
+
+        println!(
+            concat!("Number of distinct notes:               {num_notes}\n",
+                    "Length of melodies (notes):             {melody_length}\n",
+                    "Total number of melodies (files):       {num_melodies}\n",
+                    "Number of directories:                  {num_directories}\n",
+                    "Number of inodes:                       {num_inodes}\n",
+                    "Size of each file:                      {midi_file_size}\n",
+                    "Block size:                             {block_size}\n",
+                    "Estimated approximate disk usage:       {disk_usage}\n",
+                    "Caveats: {caveats}"),
+            num_notes=num_notes,
+            melody_length=melody_length,
+            num_melodies=num_melodies,
+            num_directories=num_directories,
+            num_inodes=num_inodes,
+            midi_file_size=midi_file_size.file_size(options::CONVENTIONAL).unwrap(),
+            block_size=self.block_size.file_size(options::CONVENTIONAL).unwrap(),
+            disk_usage=disk_usage.file_size(options::CONVENTIONAL).unwrap(),
+            caveats=CAVEATS,
+        );
+    }
+}
@@ -0,0 +1,61 @@
+// estimate_drums.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use humansize::{FileSize, file_size_opts as options};
+
+use crate::{cli::CliDirective, directives::gen::resolve_drum_options};
+
+/*********************************
+***** EstimateDrumsDirective *****
+*********************************/
+
+/// Size of Tar entry header, and of the blocks entry data is padded to
+const BLOCK_SIZE: u64 = 512;
+
+const CAVEATS: &str = "\
+Estimate is for an uncompressed Tar file ('.tar'), and assumes every drum pattern is as large \
+as the densest one. Compressed Tar files ('.tar.gz' or '.tar.zst') are much smaller, as drum \
+patterns are very similar to each other.";
+
+#[derive(structopt::StructOpt)]
+pub struct EstimateDrumsDirective {
+    #[structopt(flatten)]
+    pub drum_args: crate::cli::DrumArgs,
+    #[structopt(flatten)]
+    pub render_args: crate::cli::MIDIRenderArgs,
+}
+
+impl CliDirective for EstimateDrumsDirective {
+    fn run(self) {
+        let (patterns, render) = resolve_drum_options(&self.drum_args, self.render_args);
+
+        let num_patterns = patterns.num_patterns();
+        // Every entry has a header block, followed by the MIDI file padded to whole blocks
+        let midi_file_size = patterns.gen_max_file_size(&render);
+        let entry_size = BLOCK_SIZE + midi_file_size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+        let instruments = patterns
+            .parts()
+            .iter()
+            .map(|part| part.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+
+        println!(
+            concat!("Instruments:                            {instruments}\n",
+                    "Length of patterns (steps):             {steps}\n",
+                    "Total number of drum patterns:          {num_patterns}\n",
+                    "Estimated approximate output file size: {file_size}\n",
+                    "Caveats: {caveats}"),
+            instruments=instruments,
+            steps=self.drum_args.steps,
+            num_patterns=num_patterns,
+            file_size=num_patterns.saturating_mul(entry_size).file_size(options::CONVENTIONAL).unwrap(),
+            caveats=CAVEATS,
+        );
+    }
+}
@@ -0,0 +1,56 @@
+// estimate_pack.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use humansize::{FileSize, file_size_opts as options};
+
+use crate::{
+    cli::CliDirective,
+    storage::PackHeader,
+};
+
+/********************************
+***** EstimatePackDirective *****
+********************************/
+
+#[derive(structopt::StructOpt)]
+pub struct EstimatePackDirective {
+    #[structopt(flatten)]
+    pub note_set: crate::cli::NoteSetArg,
+    #[structopt(flatten)]
+    pub melody_length: crate::cli::MelodyLengthArg,
+    #[structopt(flatten)]
+    pub render_args: crate::cli::MIDIRenderArgs,
+}
+
+impl CliDirective for EstimatePackDirective {
+    fn run(self) {
+        let notes = libatm::MIDINoteVec::from(self.note_set.note_set);
+        let num_notes = notes.len() as u32;
+        let melody_length = self.melody_length.into();
+        let render = self.render_args.gen_render_options(&notes);
+
+        // Generate total number of melodies
+        let num_melodies = crate::utils::gen_num_melodies(num_notes, melody_length);
+        // Melody packs have a fixed layout, so the size can be calculated exactly
+        let header = PackHeader::new(notes, melody_length, render, None);
+        let file_size = header.gen_size(num_melodies);
+
+        println!(
+            concat!("Number of distinct notes:               {num_notes}\n",
+                    "Length of melodies (notes):             {melody_length}\n",
+                    "Total number of melodies:               {num_melodies}\n",
+                    "Bits per melody:                        {bits_per_melody}\n",
+                    "Output file size:                       {file_size}"),
+            num_notes=num_notes,
+            melody_length=melody_length,
+            num_melodies=num_melodies,
+            bits_per_melody=header.bits_per_melody(),
+            file_size=file_size.file_size(options::CONVENTIONAL).unwrap(),
+        );
+    }
+}
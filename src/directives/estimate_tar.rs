@@ -8,13 +8,14 @@
 
 use humansize::{FileSize, file_size_opts as options};
 
-use crate::cli::CliDirective;
+use crate::{cli::CliDirective, directives::gen::resolve_render_options};
 
 /*******************************
 ***** EstimateTarDirective *****
 *******************************/
 
-const ENTRY_SIZE: u64 = 1024;
+/// Size of Tar entry header, and of the blocks entry data is padded to
+const BLOCK_SIZE: u64 = 512;
 
 const CAVEATS: &'static str = "\
 Estimate assumes underlying drive has block size of 512 bytes. Some RAID arrays \
@@ -28,6 +29,10 @@ pub struct EstimateTarDirective {
     pub num_notes: crate::cli::NumNotesArg,
     #[structopt(flatten)]
     pub melody_length: crate::cli::MelodyLengthArg,
+    #[structopt(flatten)]
+    pub render_args: crate::cli::MIDIRenderArgs,
+    #[structopt(flatten)]
+    pub variation_args: crate::cli::VariationArgs,
 }
 
 impl CliDirective for EstimateTarDirective {
@@ -35,8 +40,19 @@ impl CliDirective for EstimateTarDirective {
         let num_notes: u32 = self.num_notes.into();
         let melody_length: u32 = self.melody_length.into();
 
+        // Notes are only needed to calculate melody indices, not the size of the largest file
+        let render = resolve_render_options(
+            self.render_args,
+            &self.variation_args,
+            &libatm::MIDINoteVec(Vec::new()),
+            melody_length,
+        );
+
         // Generate total number of melodies
-        let num_melodies = crate::utils::gen_num_melodies(num_notes, melody_length);
+        let num_melodies = render.gen_num_melodies(num_notes, melody_length);
+        // Every entry has a header block, followed by the MIDI file padded to whole blocks
+        let midi_file_size = render.gen_max_file_size(num_notes, melody_length);
+        let entry_size = BLOCK_SIZE + midi_file_size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
 
         println!(
             concat!("Number of distinct notes:               {num_notes}\n",
@@ -47,7 +63,7 @@ impl CliDirective for EstimateTarDirective {
             num_notes=num_notes,
             melody_length=melody_length,
             num_melodies=num_melodies,
-            file_size=(num_melodies * ENTRY_SIZE).file_size(options::CONVENTIONAL).unwrap(),
+            file_size=(num_melodies * entry_size).file_size(options::CONVENTIONAL).unwrap(),
             caveats=CAVEATS,
         );
     }
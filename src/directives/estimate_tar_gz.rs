@@ -20,7 +20,7 @@ use crate::{
             gen_sim_num_melodies,
             pad_value_to_block,
         },
-        gen::try_compression_from_str,
+        gen::{gen_melodies_range, resolve_render_options, try_compression_from_str},
     },
     storage::{
         IntoInner,
@@ -28,10 +28,6 @@ use crate::{
         StorageBackend,
         tar_archive::TarArchive,
     },
-    utils::{
-        gen_num_melodies,
-        gen_sequences,
-    },
 };
 
 /********************************
@@ -42,6 +38,7 @@ fn estimate_tar_gz_size(
     notes: &libatm::MIDINoteVec,
     melody_length: u32,
     num_melodies: u64,
+    render: &crate::midi::MIDIRenderOptions,
     compression_level: Compression,
 ) -> u64 {
     // Create gzip-compressed tar archive
@@ -50,13 +47,16 @@ fn estimate_tar_gz_size(
         MIDIHashPathGenerator
     );
 
-    // For each melody
-    for (idx, melody_ref) in gen_sequences(notes, melody_length).enumerate() {
-        if idx as u64 == num_melodies { break; }
-        // Copy notes into owned melody
-        let melody = melody_ref.iter().map(|n| *n.clone()).collect::<libatm::MIDINoteVec>();
+    // For each of the first `num_melodies` files, skipping melodies that aren't
+    // generated with their variation (range always valid)
+    let num_indices = render.gen_num_melodies(notes.len() as u32, melody_length);
+    let pfiles = gen_melodies_range(notes, melody_length, render.num_variations(), 0..num_indices)
+        .unwrap()
+        .filter_map(|(melody, variation_index)| render.prepare_variation(melody, variation_index))
+        .take(num_melodies as usize);
+    for pfile in pfiles {
         // Append melody to archive
-        archive.append_melody(melody, None).unwrap();
+        archive.append_prepared(pfile, None).unwrap();
     }
 
     archive
@@ -75,6 +75,10 @@ pub struct EstimateTarGzDirective {
     pub note_set: crate::cli::NoteSetArg,
     #[structopt(flatten)]
     pub melody_length: crate::cli::MelodyLengthArg,
+    #[structopt(flatten)]
+    pub render_args: crate::cli::MIDIRenderArgs,
+    #[structopt(flatten)]
+    pub variation_args: crate::cli::VariationArgs,
     #[structopt(
         short="C",
         long="compress",
@@ -88,12 +92,13 @@ impl CliDirective for EstimateTarGzDirective {
         let notes = libatm::MIDINoteVec::from(self.note_set.note_set);
         let num_notes = notes.len() as u32;
         let melody_length = self.melody_length.into();
+        let render = resolve_render_options(self.render_args, &self.variation_args, &notes, melody_length);
         let compression_level = self.compression_level.unwrap_or(Compression::new(6));
 
-        let num_melodies = gen_num_melodies(num_notes, melody_length);
+        let num_melodies = render.gen_num_files(&notes, melody_length);
         let sim_num_melodies = gen_sim_num_melodies(num_melodies);
 
-        let sim_size_estimate = estimate_tar_gz_size(&notes, melody_length, sim_num_melodies, compression_level);
+        let sim_size_estimate = estimate_tar_gz_size(&notes, melody_length, sim_num_melodies, &render, compression_level);
         let sim_size_estimate = pad_value_to_block(sim_size_estimate, None);
         let file_size = gen_sim_file_size(sim_num_melodies, num_melodies, sim_size_estimate);
 
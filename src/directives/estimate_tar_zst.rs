@@ -0,0 +1,123 @@
+// estimate_tar_zst.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use humansize::{FileSize, file_size_opts as options};
+
+use crate::{
+    cli::CliDirective,
+    directives::{
+        estimate::{
+            gen_sim_file_size,
+            gen_sim_num_melodies,
+            pad_value_to_block,
+        },
+        gen::{gen_melodies_range, resolve_render_options},
+    },
+    storage::{
+        IntoInner,
+        MIDIHashPathGenerator,
+        StorageBackend,
+        ZstdOptions,
+        tar_archive::TarArchive,
+    },
+};
+
+/**********************************
+***** EstimateTarZstDirective *****
+**********************************/
+
+fn estimate_tar_zst_size(
+    notes: &libatm::MIDINoteVec,
+    melody_length: u32,
+    num_melodies: u64,
+    render: &crate::midi::MIDIRenderOptions,
+    zstd_options: &ZstdOptions,
+) -> u64 {
+    // Create zstd-compressed tar archive
+    let mut archive = TarArchive::new(
+        zstd_options.gen_encoder(std::io::BufWriter::new(Vec::new())).unwrap(),
+        MIDIHashPathGenerator
+    );
+
+    // For each of the first `num_melodies` files, skipping melodies that aren't
+    // generated with their variation (range always valid)
+    let num_indices = render.gen_num_melodies(notes.len() as u32, melody_length);
+    let pfiles = gen_melodies_range(notes, melody_length, render.num_variations(), 0..num_indices)
+        .unwrap()
+        .filter_map(|(melody, variation_index)| render.prepare_variation(melody, variation_index))
+        .take(num_melodies as usize);
+    for pfile in pfiles {
+        // Append melody to archive
+        archive.append_prepared(pfile, None).unwrap();
+    }
+
+    archive
+        .into_inner()   // Finish archive
+        .unwrap()       // zstd Encoder
+        .finish()       // Finish zstd Encoder
+        .unwrap()       // std::io::BufWriter
+        .into_inner()   // Finish writing to buffer
+        .unwrap()       // Vec<u8>
+        .len() as u64   // Number of bytes in vector as u64
+}
+
+#[derive(structopt::StructOpt)]
+pub struct EstimateTarZstDirective {
+    #[structopt(flatten)]
+    pub note_set: crate::cli::NoteSetArg,
+    #[structopt(flatten)]
+    pub melody_length: crate::cli::MelodyLengthArg,
+    #[structopt(flatten)]
+    pub render_args: crate::cli::MIDIRenderArgs,
+    #[structopt(flatten)]
+    pub variation_args: crate::cli::VariationArgs,
+    #[structopt(flatten)]
+    pub zstd_args: crate::cli::ZstdArgs,
+}
+
+impl CliDirective for EstimateTarZstDirective {
+    fn run(self) {
+        let notes = libatm::MIDINoteVec::from(self.note_set.note_set);
+        let num_notes = notes.len() as u32;
+        let melody_length = self.melody_length.into();
+        let render = resolve_render_options(self.render_args, &self.variation_args, &notes, melody_length);
+        let zstd_options: ZstdOptions = self.zstd_args.into();
+
+        let num_melodies = render.gen_num_files(&notes, melody_length);
+        let sim_num_melodies = gen_sim_num_melodies(num_melodies);
+
+        let sim_size_estimate = estimate_tar_zst_size(&notes, melody_length, sim_num_melodies, &render, &zstd_options);
+        let sim_size_estimate = pad_value_to_block(sim_size_estimate, None);
+        let file_size = gen_sim_file_size(sim_num_melodies, num_melodies, sim_size_estimate);
+
+        println!(
+            concat!("Number of distinct notes:               {num_notes}\n",
+                    "Length of melodies (notes):             {melody_length}\n",
+                    "Compression level:                      {compression_level}\n",
+                    "Long-distance matching window:          {window}\n",
+                    "Total number of melodies:               {num_melodies}\n",
+                    "Number of melodies used in simulation:  {sim_num_melodies}\n",
+                    "Simulated output size:                  {sim_size_estimate}\n",
+                    "Estimated approximate output file size: {file_size}\n",
+                    "Caveats: Estimate calculated by creating a zstd-compressed tar file in memory \
+                    containing {sim_num_melodies} melodies, and extrapolating from that size. Assumes underlying \
+                    drive has block size of 512 bytes (see: 'estimate tar')."),
+            num_notes=num_notes,
+            melody_length=melody_length,
+            compression_level=zstd_options.level,
+            window=match zstd_options.window_log {
+                Some(window_log) => (1u64 << window_log).file_size(options::CONVENTIONAL).unwrap(),
+                None => "disabled".to_string(),
+            },
+            num_melodies=num_melodies,
+            sim_num_melodies=sim_num_melodies,
+            sim_size_estimate=sim_size_estimate.file_size(options::CONVENTIONAL).unwrap(),
+            file_size=file_size.file_size(options::CONVENTIONAL).unwrap(),
+        );
+    }
+}
@@ -0,0 +1,206 @@
+// extract.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use std::io::BufRead;
+
+use crate::{
+    cli::CliDirective,
+    storage::{
+        archive_reader::gen_parent_path,
+        ArchiveReader,
+        PathGenerator,
+        PreparedMIDIFile,
+    },
+    variation::{AnnotatedMelody, MelodyVariation},
+};
+
+/**************************
+***** Utility Methods *****
+**************************/
+
+/// Read newline-delimited melodies from file (or stdin if `input` is `-`), as NOTE:OCTAVE
+/// pairs or MIDI note numbers (see: [parse_melody](../utils/fn.parse_melody.html)).
+/// Lines are read lazily and returned with their line number. Empty lines are skipped.
+pub(crate) fn read_melodies(
+    input: &std::path::Path,
+) -> impl Iterator<Item = (usize, Result<libatm::MIDINoteVec, crate::utils::ParseMelodyError>)> {
+    let reader: Box<dyn std::io::BufRead> = if input.as_os_str() == "-" {
+        Box::new(std::io::BufReader::new(std::io::stdin()))
+    } else {
+        match std::fs::File::open(input) {
+            Ok(file) => Box::new(std::io::BufReader::new(file)),
+            Err(err) => {
+                eprintln!("::: ERROR: Failed to open input file {:?} ({})", input, err);
+                std::process::exit(1);
+            },
+        }
+    };
+
+    let input = input.to_path_buf();
+    reader.lines().enumerate().filter_map(move |(line_idx, line)| {
+        let line = line.unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to read input file {:?} ({})", input, err);
+            std::process::exit(1);
+        });
+        let line = line.trim();
+        if line.is_empty() {
+            None
+        } else {
+            Some((line_idx + 1, crate::utils::parse_melody(line)))
+        }
+    })
+}
+
+/***************************
+***** ExtractDirective *****
+***************************/
+
+/// Extract melodies (MIDI files) from an archive generated by one of the `gen` directives
+/// (Tar, Gzip- or Zstandard-compressed Tar, or batch). Each melody is looked up at the path
+/// it was written to, so the note set, partitioning scheme, and chord types, durations and
+/// velocities must be the same as when the archive was generated.
+#[derive(structopt::StructOpt)]
+pub struct ExtractDirective {
+    /// Archive input path, or '-' to read from stdin.
+    #[structopt(parse(from_os_str))]
+    pub archive: std::path::PathBuf,
+    #[structopt(flatten)]
+    pub note_set: crate::cli::NoteSetArg,
+    /// Melodies to extract, as comma-separated NOTE:OCTAVE pairs and 'R' for rests (i.e., 'C:4,R,D:4').
+    /// With --durations, each note is followed by '@' and its duration (i.e., 'C:4@1/8,R@1/8,D:4@1/4'),
+    /// and with --velocities, by '~' and its velocity, except rests (i.e., 'C:4~mf,R,D:4~ff'). With --chord-types,
+    /// notes played together are joined by '+' (i.e., 'C:4+E:4+G:4,A:3+C:4+E:4').
+    #[structopt(value_name="melody", parse(try_from_str = crate::variation::parse_annotated_melody))]
+    pub melodies: Vec<crate::variation::AnnotatedMelody>,
+    /// Read melodies to extract from file (or stdin if '-'), one per line (as NOTE:OCTAVE
+    /// pairs or MIDI note numbers).
+    #[structopt(short="i", long, conflicts_with_all=&["chord-types", "durations", "velocities"], parse(from_os_str))]
+    pub input: Option<std::path::PathBuf>,
+    /// Directory to write extracted MIDI files to (must exist).
+    #[structopt(short="o", long="output", default_value=".", parse(from_os_str))]
+    pub output_dir: std::path::PathBuf,
+    #[structopt(flatten)]
+    pub partition_args: crate::cli::PartitionArgs,
+    #[structopt(flatten)]
+    pub variation_args: crate::cli::VariationArgs,
+}
+
+impl ExtractDirective {
+    /// Create path generator for melodies of length `melody_length`, generated with
+    /// `num_variations` variations each (`None` if archive isn't partitioned)
+    fn gen_path_generator(&self, melody_length: u32, num_variations: u64) -> Option<crate::storage::PartitionPathGenerator> {
+        let partition_depth = self.partition_args.partition_depth?;
+        let path_generator = crate::storage::PartitionPathGenerator::new_with_variations(
+            self.note_set.note_set.len() as u32,
+            melody_length,
+            num_variations,
+            self.partition_args.max_files,
+            partition_depth,
+        ).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to initialize partitioning scheme ({:?})", err);
+            std::process::exit(1);
+        });
+        Some(path_generator)
+    }
+}
+
+/// Generate path melody was written to in archive, using `path_generator`
+/// (or the melody hash if not partitioned)
+fn gen_path(path_generator: &Option<crate::storage::PartitionPathGenerator>, pfile: &PreparedMIDIFile) -> String {
+    let result = match path_generator {
+        Some(path_generator) => path_generator.gen_path_for_prepared(pfile),
+        None => crate::storage::MIDIHashPathGenerator.gen_path_for_prepared(pfile),
+    };
+    result.unwrap_or_else(|err| {
+        eprintln!("::: ERROR: Failed to generate path for melody ({:?})", err);
+        std::process::exit(1);
+    })
+}
+
+impl CliDirective for ExtractDirective {
+    fn run(self) {
+        let mut melodies = self.melodies.clone();
+        if let Some(ref input) = self.input {
+            for (line_number, melody) in read_melodies(input) {
+                match melody {
+                    Ok(melody) => melodies.push(AnnotatedMelody { melody, variation: MelodyVariation::default() }),
+                    Err(err) => {
+                        eprintln!("::: ERROR: Invalid melody on line {} ({})", line_number, err);
+                        std::process::exit(1);
+                    },
+                }
+            }
+        }
+        if melodies.is_empty() {
+            eprintln!("::: ERROR: Must provide at least one melody to extract");
+            std::process::exit(1);
+        }
+
+        // Map path of each melody in archive to melody (all melodies in an archive have
+        // the same length, so melodies of any other length fail to generate a path)
+        let melody_length = melodies[0].melody.len() as u32;
+        let variations = self.variation_args.gen_variation_set(melody_length).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to generate chord type, rhythm or dynamics patterns ({})", err);
+            std::process::exit(1);
+        });
+        let path_generator = self.gen_path_generator(melody_length, variations.num_variations());
+        let mut remaining = std::collections::HashMap::new();
+        for AnnotatedMelody { melody, mut variation } in melodies {
+            // Melodies without chords are extracted as chords of one note
+            if variations.chords.is_some() && variation.chord_types.is_none() {
+                variation.chord_types = Some(vec![crate::chord::ChordType::unison(); melody.len()]);
+            }
+            let melody_str = crate::variation::format_annotated_melody(&melody, &variation);
+            if let Err(err) = variations.gen_variation_index(&variation, &melody) {
+                eprintln!("::: ERROR: Invalid melody {} ({})", melody_str, err);
+                std::process::exit(1);
+            }
+            let hash = variation.gen_hash(&melody);
+            let mfile = libatm::MIDIFile::new(melody, libatm::MIDIFormat::Format0, 1, 1);
+            let pfile = PreparedMIDIFile { mfile, hash, data: Vec::new() };
+            remaining.insert(gen_path(&path_generator, &pfile), melody_str);
+        }
+        // Only batches in the partitions of the melodies need to be read
+        let partitions = remaining
+            .keys()
+            .map(|path| gen_parent_path(path).to_string())
+            .collect::<std::collections::HashSet<String>>();
+
+        let reader = ArchiveReader::open(&self.archive).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to open archive {:?} ({})", &self.archive, err);
+            std::process::exit(1);
+        });
+        let result = reader.for_each_entry(
+            |batch_path| partitions.contains(gen_parent_path(batch_path)),
+            |entry| {
+                if let Some(melody_str) = remaining.remove(&entry.path) {
+                    let output_path = self.output_dir.join(format!("{}.mid", entry.hash()));
+                    std::fs::write(&output_path, &entry.data)?;
+                    println!("::: INFO: Extracted {} to {:?}", melody_str, output_path);
+                }
+                // Stop reading once all melodies have been found
+                if remaining.is_empty() {
+                    Ok(std::ops::ControlFlow::Break(()))
+                } else {
+                    Ok(std::ops::ControlFlow::Continue(()))
+                }
+            },
+        );
+        if let Err(err) = result {
+            eprintln!("::: ERROR: Failed to read archive {:?} ({})", &self.archive, err);
+            std::process::exit(1);
+        }
+
+        if !remaining.is_empty() {
+            for melody_str in remaining.values() {
+                eprintln!("::: WARNING: Melody {} not found in archive", melody_str);
+            }
+            std::process::exit(1);
+        }
+    }
+}
@@ -9,14 +9,39 @@
 use flate2::Compression;
 
 use crate::{
-    cli::CliDirective,
+    cli::{
+        BackendArgs,
+        CheckpointArgs,
+        CliDirective,
+        DrumArgs,
+        MIDIRenderArgs,
+        PartitionArgs,
+        VariationArgs,
+        ShardArgs,
+        TargetArg,
+    },
     directives::{
         GenBatchDirective,
+        GenCounterpointDirective,
+        GenDirDirective,
+        GenDrumsDirective,
+        GenPackDirective,
+        GenProgressionsDirective,
         GenSingleDirective,
         GenTarDirective,
         GenTarGzDirective,
+        GenTarZstDirective,
+        GenZipDirective,
+    },
+    drums::DrumPatternSet,
+    midi::MIDIRenderOptions,
+    storage::{
+        BackendCheckpoint,
+        Checkpoint,
+        PathGenerator,
+        PreparedMIDIFile,
+        StorageBackend,
     },
-    storage::StorageBackend,
 };
 
 /*************************
@@ -46,39 +71,832 @@ pub(crate) fn try_compression_from_str(arg: &str) -> Result<Compression, Compres
     Ok(Compression::new(compression_level))
 }
 
-/// Generate melodies and write them to provided backend
+/// Resolve options to render melodies of length `melody_length` generated from `notes` with,
+/// from rendering and variation arguments. Exits if the chord type, rhythm or dynamics patterns
+/// can't be generated, their chords can't be played over `notes`, or their durations can't be
+/// rendered with the division of the rendering options.
+pub(crate) fn resolve_render_options(
+    render_args: MIDIRenderArgs,
+    variation_args: &VariationArgs,
+    notes: &libatm::MIDINoteVec,
+    melody_length: u32,
+) -> MIDIRenderOptions {
+    let division = render_args.division;
+    let variations = variation_args
+        .gen_variation_set(melody_length)
+        .and_then(|variations| match variations.chords {
+            Some(ref chords) => chords.check_roots(notes).map(|_| variations).map_err(Into::into),
+            None => Ok(variations),
+        })
+        .and_then(|variations| match variations.rhythms {
+            Some(ref rhythms) => rhythms.check_division(division).map(|_| variations).map_err(Into::into),
+            None => Ok(variations),
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to generate chord type, rhythm or dynamics patterns ({})", err);
+            std::process::exit(1);
+        });
+    MIDIRenderOptions { variations, ..render_args.gen_render_options(notes) }
+}
+
+/// Resolve drum patterns to generate, and options to render them with, from drum and
+/// rendering arguments. Patterns are always rendered on the percussion channel, with the
+/// smallest multiple of the division of the rendering arguments that renders steps as a whole
+/// number of ticks. Exits if the patterns can't be generated, or their steps can't be rendered
+/// with any multiple of the division.
+pub(crate) fn resolve_drum_options(
+    drum_args: &DrumArgs,
+    mut render_args: MIDIRenderArgs,
+) -> (DrumPatternSet, MIDIRenderOptions) {
+    let (patterns, division) = drum_args
+        .gen_drum_pattern_set()
+        .and_then(|patterns| patterns.gen_division(render_args.division).map(|division| (patterns, division)))
+        .unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to generate drum patterns ({})", err);
+            std::process::exit(1);
+        });
+    if division != render_args.division {
+        eprintln!(
+            "::: INFO: Rendering with division {} (steps of {} aren't a whole number of ticks with division {})",
+            division,
+            drum_args.step,
+            render_args.division,
+        );
+        render_args.division = division;
+    }
+    // Meta events use the index of the pattern instead of a melody index
+    let render = MIDIRenderOptions {
+        channel: crate::drums::DRUM_CHANNEL,
+        ..render_args.gen_render_options(&std::iter::empty().collect())
+    };
+    (patterns, render)
+}
+
+/// Resolve range of melody indices to generate from shard arguments, out of `num_melodies`.
+/// Shard boundaries must be aligned to `block_size` melodies (i.e., the number of melodies
+/// per partition), such that no partition is split between shards.
+pub(crate) fn resolve_melody_range(
+    shard_args: &ShardArgs,
+    num_melodies: u64,
+    block_size: u64,
+) -> std::ops::Range<u64> {
+    let melody_range = match shard_args.shard {
+        Some(ref shard) => crate::utils::gen_shard_range(
+            num_melodies,
+            block_size,
+            shard.shard - 1,
+            shard.num_shards,
+        ),
+        None => {
+            let start_index = shard_args.start_index.unwrap_or(0);
+            let end_index = shard_args.end_index.unwrap_or(num_melodies);
+            if start_index > end_index || end_index > num_melodies {
+                eprintln!(
+                    "::: ERROR: Invalid melody range {}..{} for {} melodies",
+                    start_index,
+                    end_index,
+                    num_melodies,
+                );
+                std::process::exit(1);
+            }
+            // End index doesn't need to be aligned if it's the last melody
+            if !start_index.is_multiple_of(block_size) || (!end_index.is_multiple_of(block_size) && end_index != num_melodies) {
+                eprintln!(
+                    "::: ERROR: Melody range {}..{} must be aligned to partition size ({} melodies)",
+                    start_index,
+                    end_index,
+                    block_size,
+                );
+                std::process::exit(1);
+            }
+            start_index..end_index
+        },
+    };
+    if melody_range.end - melody_range.start != num_melodies {
+        eprintln!(
+            "::: INFO: Generating melodies {} to {} (of {})",
+            melody_range.start,
+            melody_range.end,
+            num_melodies,
+        );
+    }
+    melody_range
+}
+
+/// Resolve target file path for backends that can't write to stdout
+pub(crate) fn resolve_file_target(target: TargetArg) -> std::path::PathBuf {
+    if target.is_stdout() {
+        eprintln!("::: ERROR: Storage backend can't write to stdout, must provide file path");
+        std::process::exit(1);
+    }
+    target.into()
+}
+
+/// Writer for stdout that exits when the reading end of the pipe is closed (i.e., when piped
+/// to `head`), like most command line tools do on `SIGPIPE`. Rust programs ignore `SIGPIPE`,
+/// so otherwise every following write would fail.
+pub(crate) struct StdoutWriter(std::io::Stdout);
+
+impl std::io::Write for StdoutWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf).map_err(exit_on_broken_pipe)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush().map_err(exit_on_broken_pipe)
+    }
+}
+
+/// Exit (with the same status as if killed by `SIGPIPE`) if error is broken pipe
+fn exit_on_broken_pipe(err: std::io::Error) -> std::io::Error {
+    if err.kind() == std::io::ErrorKind::BrokenPipe {
+        std::process::exit(141);
+    }
+    err
+}
+
+/// Create buffered writer for writing output to stdout
+pub(crate) fn gen_stdout_writer() -> std::io::BufWriter<StdoutWriter> {
+    std::io::BufWriter::new(StdoutWriter(std::io::stdout()))
+}
+
+/// Exit if storage backend couldn't be created
+pub(crate) fn exit_on_backend_error<E: std::fmt::Debug, B>(err: E) -> B {
+    eprintln!("::: ERROR: Failed to create storage backend ({:?})", err);
+    std::process::exit(1);
+}
+
+/// Record options MIDI files are rendered with in new storage backend (see:
+/// [StorageBackend::append_manifest](../../storage/trait.StorageBackend.html#tymethod.append_manifest)).
+/// Exits if the render manifest couldn't be written.
+pub(crate) fn append_render_manifest<B: StorageBackend>(backend: &mut B, render: &MIDIRenderOptions) {
+    if let Err(err) = backend.append_manifest(&render.gen_manifest()) {
+        eprintln!("::: ERROR: Failed to write render manifest to storage backend ({:?})", err);
+        std::process::exit(1);
+    }
+}
+
+/// MIDI files generated by index (i.e., progressions, drum patterns and pairs of voices),
+/// written in index order by [dispatch_backend](fn.dispatch_backend.html)
+pub(crate) struct IndexedGenerator<F> {
+    /// Number of indices files are generated at
+    pub num_indices: u64,
+    /// Length of generated files (i.e., number of chords of progressions)
+    pub length: u32,
+    /// Options files are generated with (name and value pairs), recorded in the layout
+    /// of checkpoints so that a run is only resumed with the same options (i.e., `key=C:4`)
+    pub options: Vec<(&'static str, String)>,
+    /// Render file at an index with rendering options, or `None` if no file is generated
+    /// at the index
+    pub prepare: F,
+}
+
+/// Exit with error message if backend option doesn't apply to target
+fn exit_on_invalid_backend_option(message: &str) {
+    eprintln!("::: ERROR: {}", message);
+    std::process::exit(1);
+}
+
+/// Write MIDI files generated by `generator` (rendered with `render`) to the storage backend
+/// chosen by the extension of `target`, using provided path generator: Tar file ('.tar', or
+/// '-' to write to stdout), Gzip-compressed Tar file ('.tar.gz' or '.tgz'), Zstandard-compressed
+/// Tar file ('.tar.zst'), ZIP file ('.zip'), or directory tree otherwise. Batches and melody
+/// packs index melodies by note set, so they aren't supported. Files are generated on as many
+/// threads, and within the shard, given by `backend_args` (shards aren't aligned to partitions).
+/// Checkpoints are only supported by Tar and Gzip-compressed Tar files.
+pub(crate) fn dispatch_backend<G, F>(
+    target: std::path::PathBuf,
+    backend_args: BackendArgs,
+    path_generator: G,
+    generator: IndexedGenerator<F>,
+    render: MIDIRenderOptions,
+) where
+    G: PathGenerator,
+    F: Fn(&MIDIRenderOptions, u64) -> Option<PreparedMIDIFile> + Sync,
+{
+    let target = TargetArg { target };
+    let path = target.target.to_string_lossy().to_string();
+    let is_tar = target.is_stdout() || path.ends_with(".tar");
+    let is_tar_gz = path.ends_with(".tar.gz") || path.ends_with(".tgz");
+    let is_tar_zst = path.ends_with(".tar.zst");
+    let is_zip = path.ends_with(".zip");
+
+    // Ensure backend options apply to target
+    let compression_level = backend_args.compression_level;
+    match compression_level {
+        Some(level) if (is_tar_gz || is_zip) && level > 9 => exit_on_invalid_backend_option(
+            "Compression level must be between 0 and 9 for Gzip-compressed Tar and ZIP files",
+        ),
+        Some(0) if is_tar_zst => exit_on_invalid_backend_option(
+            "Compression level must be between 1 and 22 for Zstandard-compressed Tar files",
+        ),
+        Some(_) if !(is_tar_gz || is_tar_zst || is_zip) => exit_on_invalid_backend_option(
+            "Compression level is only supported for compressed targets ('.tar.gz', '.tgz', '.tar.zst' or '.zip')",
+        ),
+        _ => (),
+    }
+    if backend_args.store && !is_zip {
+        exit_on_invalid_backend_option("--store is only supported for ZIP files ('.zip')");
+    }
+    if (backend_args.window_log.is_some() || backend_args.num_workers > 0) && !is_tar_zst {
+        exit_on_invalid_backend_option("--long and --workers are only supported for Zstandard-compressed Tar files ('.tar.zst')");
+    }
+    let checkpoint_args = &backend_args.checkpoint_args;
+    if (checkpoint_args.checkpoint_interval.is_some() || checkpoint_args.resume) && !(is_tar || is_tar_gz) {
+        exit_on_invalid_backend_option("Checkpoints are only supported for Tar and Gzip-compressed Tar files");
+    }
+
+    let num_threads = backend_args.num_threads.into();
+    let index_range = resolve_melody_range(&backend_args.shard_args, generator.num_indices, 1);
+    // Layout of target file is the options of the generator, and the compression of the backend
+    let mut options = generator.options.clone();
+    if is_tar_gz {
+        options.push(("compress", compression_level.unwrap_or(6).to_string()));
+    }
+    let layout = options
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<String>>()
+        .join(",");
+    let checkpoint_options = resolve_checkpoint_options(&backend_args.checkpoint_args, &target, layout.clone());
+
+    if target.is_stdout() {
+        let mut backend = crate::storage::TarFile::from_writer(gen_stdout_writer(), path_generator);
+        append_render_manifest(&mut backend, &render);
+        write_indexed_to_backend(&generator, index_range, num_threads, render, None, backend);
+    } else if is_tar || is_tar_gz {
+        // Files generated by index aren't generated from a note set
+        let (resume_checkpoint, index_range) = resolve_resume(
+            &backend_args.checkpoint_args,
+            &target.target,
+            &libatm::MIDINoteSet(Default::default()),
+            generator.length,
+            &render,
+            &layout,
+            index_range,
+        );
+        if is_tar {
+            let mut backend = match resume_checkpoint {
+                Some(ref checkpoint) => crate::storage::TarFile::resume(&target.target, path_generator, checkpoint),
+                None => crate::storage::TarFile::new(&target.target, path_generator),
+            }.unwrap_or_else(exit_on_backend_error);
+            // Render manifest was already written if resuming
+            if resume_checkpoint.is_none() {
+                append_render_manifest(&mut backend, &render);
+            }
+            write_indexed_to_backend(&generator, index_range, num_threads, render, checkpoint_options, backend);
+        } else {
+            let compression_level = compression_level.map(Compression::new);
+            let mut backend = match resume_checkpoint {
+                Some(ref checkpoint) => crate::storage::TarGzFile::resume(
+                    &target.target,
+                    path_generator,
+                    compression_level,
+                    checkpoint,
+                ),
+                None => crate::storage::TarGzFile::new(&target.target, path_generator, compression_level),
+            }.unwrap_or_else(exit_on_backend_error);
+            // Render manifest was already written if resuming
+            if resume_checkpoint.is_none() {
+                append_render_manifest(&mut backend, &render);
+            }
+            write_indexed_to_backend(&generator, index_range, num_threads, render, checkpoint_options, backend);
+        }
+    } else if is_tar_zst {
+        let options = crate::storage::ZstdOptions {
+            level: compression_level.map_or(3, |level| level as i32),
+            window_log: backend_args.window_log,
+            num_workers: backend_args.num_workers,
+        };
+        let mut backend = crate::storage::TarZstFile::new(&target.target, path_generator, &options)
+            .unwrap_or_else(exit_on_backend_error);
+        append_render_manifest(&mut backend, &render);
+        write_indexed_to_backend(&generator, index_range, num_threads, render, None, backend);
+    } else if is_zip {
+        let method = if backend_args.store {
+            crate::storage::ZipMethod::Store
+        } else {
+            crate::storage::ZipMethod::Deflate(compression_level.map(|level| level as i32))
+        };
+        let mut backend = crate::storage::ZipFile::new(&target.target, path_generator, method)
+            .unwrap_or_else(exit_on_backend_error);
+        append_render_manifest(&mut backend, &render);
+        write_indexed_to_backend(&generator, index_range, num_threads, render, None, backend);
+    } else {
+        let mut backend = crate::storage::DirectoryBackend::new(&target.target, path_generator, None)
+            .unwrap_or_else(exit_on_backend_error);
+        append_render_manifest(&mut backend, &render);
+        write_indexed_to_backend(&generator, index_range, num_threads, render, None, backend);
+    }
+}
+
+/// Write MIDI files generated by `generator` at indices in `index_range` to provided backend,
+/// on `num_threads` worker threads (see: [write_melodies_to_backend](fn.write_melodies_to_backend.html)).
+/// Indices `generator` doesn't generate a file at are skipped.
+fn write_indexed_to_backend<B, F>(
+    generator: &IndexedGenerator<F>,
+    index_range: std::ops::Range<u64>,
+    num_threads: u32,
+    render: MIDIRenderOptions,
+    checkpoint_options: Option<CheckpointOptions>,
+    backend: B,
+) where
+    B: StorageBackend,
+    F: Fn(&MIDIRenderOptions, u64) -> Option<PreparedMIDIFile> + Sync,
+{
+    // Files generated by index aren't generated from a note set, so checkpoints record an
+    // empty note set (and the options of the generator in their layout)
+    let mut writer = MelodyWriter::new(
+        &libatm::MIDINoteVec(Vec::new()),
+        generator.length,
+        index_range.clone(),
+        WriteOptions { render, mode: None, checkpoint_options },
+        backend,
+    );
+
+    if num_threads > 1 {
+        // Rendering options shared with workers
+        let render = writer.render.clone();
+        let prepare = |chunk_range: std::ops::Range<u64>| {
+            chunk_range
+                .map(|index| (generator.prepare)(&render, index))
+                .collect::<Vec<Option<PreparedMIDIFile>>>()
+        };
+        write_chunks_multi_threaded(index_range, num_threads, prepare, &mut writer);
+    } else {
+        for index in index_range {
+            if writer.is_stopped() {
+                break;
+            }
+            let pfile = (generator.prepare)(&writer.render, index);
+            writer.append_prepared(pfile);
+        }
+    }
+
+    // Stop progress bar and finish writing to backend (even if interrupted)
+    writer.finish();
+}
+
+/// Options for writing generated melodies to a storage backend
+/// (see: [write_melodies_to_backend](fn.write_melodies_to_backend.html))
+pub(crate) struct WriteOptions {
+    /// Options to render melodies as MIDI files with
+    pub render: MIDIRenderOptions,
+    /// Permissions to use for files written to backend (backend default if not set)
+    pub mode: Option<u32>,
+    /// Options for writing checkpoints (if enabled)
+    pub checkpoint_options: Option<CheckpointOptions>,
+}
+
+/// Options for writing checkpoints while generating melodies
+pub(crate) struct CheckpointOptions {
+    /// Path to checkpoint file
+    pub path: std::path::PathBuf,
+    /// Number of melodies between checkpoints
+    pub interval: u64,
+    /// Layout of target file (see: [gen_layout](fn.gen_layout.html))
+    pub layout: String,
+}
+
+/// Generate layout of target file from partitioning arguments and backend `options`
+/// (name and value pairs) that determine where and how melodies are written
+/// (i.e., `partitions=2,max_files=4096,compress=6`). Written to checkpoints,
+/// so that a run is only resumed with the same layout.
+pub(crate) fn gen_layout(partition_args: &PartitionArgs, options: &[(&str, String)]) -> String {
+    let partitions = match partition_args.partition_depth {
+        Some(partition_depth) => partition_depth.to_string(),
+        None => "none".to_string(),
+    };
+    let mut layout = vec![
+        format!("partitions={}", partitions),
+        format!("max_files={}", partition_args.max_files),
+    ];
+    layout.extend(options.iter().map(|(name, value)| format!("{}={}", name, value)));
+    layout.join(",")
+}
+
+/// Resolve checkpoint options from checkpoint arguments (if checkpoints enabled), for target
+/// file with `layout` (see: [gen_layout](fn.gen_layout.html)). Checkpoints can't be used
+/// when writing to stdout, as the target can't be resumed.
+pub(crate) fn resolve_checkpoint_options(
+    checkpoint_args: &CheckpointArgs,
+    target: &TargetArg,
+    layout: String,
+) -> Option<CheckpointOptions> {
+    if checkpoint_args.checkpoint_interval.is_some() && target.is_stdout() {
+        eprintln!("::: ERROR: Checkpoints aren't supported when writing to stdout");
+        std::process::exit(1);
+    }
+    let target = &target.target;
+    checkpoint_args.checkpoint_interval.map(|interval| CheckpointOptions {
+        path: Checkpoint::gen_path(target),
+        interval,
+        layout,
+    })
+}
+
+/// If resuming, read checkpoint for target file and ensure it was written by a run
+/// with the same arguments (including the layout of the target file, see:
+/// [gen_layout](fn.gen_layout.html)). Returns the backend state to resume from and
+/// the range of melodies left to generate.
+pub(crate) fn resolve_resume(
+    checkpoint_args: &CheckpointArgs,
+    target: &std::path::Path,
+    note_set: &libatm::MIDINoteSet,
+    melody_length: u32,
+    render: &MIDIRenderOptions,
+    layout: &str,
+    melody_range: std::ops::Range<u64>,
+) -> (Option<BackendCheckpoint>, std::ops::Range<u64>) {
+    if !checkpoint_args.resume {
+        return (None, melody_range);
+    }
+
+    let checkpoint_path = Checkpoint::gen_path(target);
+    let checkpoint = Checkpoint::read(&checkpoint_path).unwrap_or_else(|err| {
+        eprintln!("::: ERROR: Failed to read checkpoint file {:?} ({})", &checkpoint_path, err);
+        std::process::exit(1);
+    });
+    let template = Checkpoint::new(
+        &libatm::MIDINoteVec::from(note_set),
+        melody_length,
+        render,
+        layout.to_string(),
+        melody_range.end,
+    );
+    let melody_range = checkpoint.gen_resume_range(&template, melody_range).unwrap_or_else(|err| {
+        eprintln!("::: ERROR: Can't resume from checkpoint file {:?} ({})", &checkpoint_path, err);
+        std::process::exit(1);
+    });
+    eprintln!(
+        "::: INFO: Resuming from melody {} (truncating target to {} bytes)",
+        checkpoint.melody_index,
+        checkpoint.backend.archive_offset,
+    );
+    (Some(checkpoint.backend), melody_range)
+}
+
+/// Install handler for Ctrl-C (SIGINT) and SIGTERM that sets the returned flag, so that
+/// generation can stop cleanly and leave behind a complete archive. A second signal
+/// exits immediately.
+fn install_stop_handler() -> std::sync::Arc<std::sync::atomic::AtomicBool> {
+    let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    let result = ctrlc::set_handler(move || {
+        if handler_stop.swap(true, std::sync::atomic::Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        eprintln!("\n::: INFO: Received interrupt, finishing archive (interrupt again to exit immediately)");
+    });
+    if let Err(err) = result {
+        eprintln!("::: WARNING: Failed to install interrupt handler ({})", err);
+    }
+    stop
+}
+
+/// Writes generated melodies to a storage backend in order, tracking progress
+/// and writing checkpoints (if enabled)
+struct MelodyWriter<B: StorageBackend> {
+    /// Storage backend
+    backend: B,
+    /// Options to render melodies as MIDI files with
+    render: MIDIRenderOptions,
+    /// Permissions to use for files written to backend
+    mode: Option<u32>,
+    /// Progress bar (written to stderr, so stdout can be used for output)
+    pb: pbr::ProgressBar<std::io::Stderr>,
+    /// Index of next melody to write
+    melody_index: u64,
+    /// Indices of melodies appended to backend since the last checkpoint (only tracked with
+    /// checkpoints), since melodies with rests aren't generated with every dynamics pattern
+    /// and the backend only reports how many appended melodies are still pending
+    appended_indices: std::collections::VecDeque<u64>,
+    /// Checkpoint options (if enabled)
+    checkpoint_options: Option<CheckpointOptions>,
+    /// Checkpoint template, updated with melody index and backend state
+    /// each time a checkpoint is written
+    checkpoint: Checkpoint,
+    /// Set when interrupted, to stop generating melodies
+    stop: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl<B: StorageBackend> MelodyWriter<B> {
+    /// Create new `MelodyWriter` instance
+    fn new(
+        notes: &libatm::MIDINoteVec,
+        melody_length: u32,
+        melody_range: std::ops::Range<u64>,
+        options: WriteOptions,
+        backend: B,
+    ) -> Self {
+        let WriteOptions { render, mode, checkpoint_options } = options;
+        // Initialize progress bar
+        let mut pb = pbr::ProgressBar::on(std::io::stderr(), melody_range.end - melody_range.start);
+        pb.set_max_refresh_rate(Some(std::time::Duration::from_millis(500)));
+        let layout = match checkpoint_options {
+            Some(ref options) => options.layout.clone(),
+            None => String::new(),
+        };
+        let checkpoint = Checkpoint::new(notes, melody_length, &render, layout, melody_range.end);
+        Self {
+            backend,
+            render,
+            mode,
+            pb,
+            melody_index: melody_range.start,
+            appended_indices: std::collections::VecDeque::new(),
+            checkpoint_options,
+            checkpoint,
+            stop: install_stop_handler(),
+        }
+    }
+
+    /// Whether generation was interrupted and should stop
+    fn is_stopped(&self) -> bool {
+        self.stop.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Render melody as MIDI file (with variation at `variation_index`, if rendering
+    /// with chord types, rhythms or dynamics) and append to backend. Melodies that
+    /// aren't generated with the variation are skipped.
+    fn append_melody(&mut self, melody: libatm::MIDINoteVec, variation_index: u64) {
+        if self.render.variations.is_empty() {
+            let result = self.backend.append_melody(melody, &self.render, self.mode);
+            self.record_append(result);
+        } else {
+            let pfile = self.render.prepare_variation(melody, variation_index);
+            self.append_prepared(pfile);
+        }
+    }
+
+    /// Append prepared MIDI file to backend (`None` if the melody was skipped)
+    fn append_prepared(&mut self, pfile: Option<PreparedMIDIFile>) {
+        match pfile {
+            Some(pfile) => {
+                let result = self.backend.append_prepared(pfile, self.mode);
+                self.record_append(result);
+            },
+            None => self.advance(),
+        }
+    }
+
+    /// Record result of appending melody to backend, and move to next melody
+    fn record_append(&mut self, result: Result<(), B::Error>) {
+        match result {
+            Ok(_) if self.checkpoint_options.is_some() => self.appended_indices.push_back(self.melody_index),
+            Ok(_) => (),
+            // Show error if adding melody to backend failed
+            Err(err) => eprintln!("::: WARNING: Failed to add melody to storage backend ({:?})", err),
+        }
+        self.advance();
+    }
+
+    /// Move to next melody, and write checkpoint if at checkpoint interval
+    fn advance(&mut self) {
+        // Increment progress bar even if write failed
+        self.pb.inc();
+        self.melody_index += 1;
+        let at_interval = match self.checkpoint_options {
+            Some(ref options) => self.melody_index.is_multiple_of(options.interval),
+            None => false,
+        };
+        if at_interval {
+            self.write_checkpoint();
+        }
+    }
+
+    /// Write checkpoint with current backend state
+    fn write_checkpoint(&mut self) {
+        let backend_checkpoint = match self.backend.checkpoint() {
+            Ok(Some(backend_checkpoint)) => backend_checkpoint,
+            Ok(None) => {
+                eprintln!("::: WARNING: Storage backend doesn't support checkpoints, disabling checkpoints");
+                self.checkpoint_options = None;
+                return;
+            },
+            Err(err) => {
+                eprintln!("::: WARNING: Failed to write checkpoint ({:?})", err);
+                return;
+            },
+        };
+        // Pending files haven't been written to the target yet, so resume from the first of them
+        let num_written = self.appended_indices.len().saturating_sub(backend_checkpoint.num_pending as usize);
+        self.appended_indices.drain(..num_written);
+        self.checkpoint.melody_index = self.appended_indices.front().copied().unwrap_or(self.melody_index);
+        self.checkpoint.backend = backend_checkpoint;
+        if let Some(ref options) = self.checkpoint_options {
+            if let Err(err) = self.checkpoint.write(&options.path) {
+                eprintln!("::: WARNING: Failed to write checkpoint ({})", err);
+            }
+        }
+    }
+
+    /// Finish writing to backend, and remove checkpoint file as
+    /// it's no longer needed. If interrupted, the backend is still finished
+    /// (so the target is a complete archive of the melodies written so far),
+    /// and a final checkpoint is written instead.
+    fn finish(mut self) {
+        let interrupted = self.melody_index < self.checkpoint.end_index;
+        // Write checkpoint before finishing backend, so resuming
+        // overwrites the end of the archive
+        if interrupted && self.checkpoint_options.is_some() {
+            self.write_checkpoint();
+        }
+        // Stop progress bar
+        self.pb.finish_println("");
+        // Finish writing to backend
+        if let Err(err) = self.backend.finish() {
+            eprintln!("::: ERROR: Failed to finish writing to storage backend ({:?})", err);
+            std::process::exit(1);
+        }
+        if interrupted {
+            eprintln!(
+                "::: INFO: Stopped at melody {} (of {}), target contains all melodies before it",
+                self.melody_index,
+                self.checkpoint.end_index,
+            );
+            if self.checkpoint_options.is_some() {
+                eprintln!("::: INFO: Rerun with --resume to continue");
+            }
+            std::process::exit(130);
+        }
+        if let Some(ref options) = self.checkpoint_options {
+            if options.path.exists() {
+                if let Err(err) = std::fs::remove_file(&options.path) {
+                    eprintln!("::: WARNING: Failed to remove checkpoint file {:?} ({})", &options.path, err);
+                }
+            }
+        }
+    }
+}
+
+/// Number of melodies per unit of work when generating melodies
+/// on multiple threads
+const MELODIES_PER_CHUNK: u64 = 4096;
+
+/// Generate melodies with indices in `melody_range`, each as the (owned) pitch sequence and
+/// the index of its variation (always 0 without rhythms or dynamics). Each pitch sequence is
+/// generated once per variation, in consecutive order, so the melody at index `i` is
+/// pitch sequence `i / num_variations` with variation `i % num_variations`.
+pub(crate) fn gen_melodies_range(
+    notes: &libatm::MIDINoteVec,
+    melody_length: u32,
+    num_variations: u64,
+    melody_range: std::ops::Range<u64>,
+) -> Result<impl Iterator<Item = (libatm::MIDINoteVec, u64)> + '_, crate::utils::MelodyIndexError> {
+    let pitch_range = melody_range.start / num_variations..melody_range.end.div_ceil(num_variations);
+    let sequences = crate::utils::gen_sequences_range(notes, melody_length, pitch_range.start, pitch_range.end)?;
+    Ok(sequences.zip(pitch_range).flat_map(move |(melody_ref, pitch_index)| {
+        // Copy notes into owned melody
+        let melody = melody_ref.iter().map(|n| **n).collect::<libatm::MIDINoteVec>();
+        let start = std::cmp::max(melody_range.start, pitch_index * num_variations);
+        let end = std::cmp::min(melody_range.end, (pitch_index + 1) * num_variations);
+        (start..end).map(move |index| (melody.clone(), index % num_variations))
+    }))
+}
+
+/// Generate (prepare) all melodies in `chunk_range` (`None` for melodies that aren't
+/// generated with their variation, see: [prepare_variation](../midi/struct.MIDIRenderOptions.html#method.prepare_variation))
+fn prepare_chunk(
+    notes: &libatm::MIDINoteVec,
+    melody_length: u32,
+    render: &MIDIRenderOptions,
+    chunk_range: std::ops::Range<u64>,
+) -> Vec<Option<PreparedMIDIFile>> {
+    gen_melodies_range(notes, melody_length, render.num_variations(), chunk_range)
+        // Range validated in write_melodies_to_backend
+        .unwrap()
+        .map(|(melody, variation_index)| render.prepare_variation(melody, variation_index))
+        .collect::<Vec<Option<PreparedMIDIFile>>>()
+}
+
+/// Generate melodies on current thread and write them to provided writer
+fn write_melodies_single_threaded<B: StorageBackend>(
+    melodies: impl Iterator<Item = (libatm::MIDINoteVec, u64)>,
+    writer: &mut MelodyWriter<B>,
+) {
+    // For each melody
+    for (melody, variation_index) in melodies {
+        if writer.is_stopped() {
+            break;
+        }
+        writer.append_melody(melody, variation_index);
+    }
+}
+
+/// Generate MIDI files with indices in `index_range` on `num_threads` worker threads and
+/// write them to provided writer. Workers generate chunks of `MELODIES_PER_CHUNK` files
+/// with `prepare_chunk` and send them back to the current thread, which writes the chunks
+/// in the same order as they would be generated on a single thread.
+fn write_chunks_multi_threaded<B, F>(
+    index_range: std::ops::Range<u64>,
+    num_threads: u32,
+    prepare_chunk: F,
+    writer: &mut MelodyWriter<B>,
+) where
+    B: StorageBackend,
+    F: Fn(std::ops::Range<u64>) -> Vec<Option<PreparedMIDIFile>> + Sync,
+{
+    // Interrupt flag, shared with workers
+    let stop = writer.stop.clone();
+    let num_chunks = (index_range.end - index_range.start).div_ceil(MELODIES_PER_CHUNK);
+    // Index of next chunk to generate, from which workers pull units of work
+    let next_chunk_idx = std::sync::atomic::AtomicU64::new(0);
+    // Bound number of chunks in flight to limit memory usage
+    let (sender, receiver) = std::sync::mpsc::sync_channel(num_threads as usize * 2);
+
+    std::thread::scope(|scope| {
+        for _ in 0..num_threads {
+            let sender = sender.clone();
+            let next_chunk_idx = &next_chunk_idx;
+            let stop = &stop;
+            let prepare_chunk = &prepare_chunk;
+            let index_range = index_range.clone();
+            scope.spawn(move || loop {
+                // Interrupted, stop generating
+                if stop.load(std::sync::atomic::Ordering::SeqCst) {
+                    break;
+                }
+                let chunk_idx = next_chunk_idx.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                if chunk_idx >= num_chunks {
+                    break;
+                }
+                let chunk_start = index_range.start + chunk_idx * MELODIES_PER_CHUNK;
+                let chunk_end = std::cmp::min(chunk_start + MELODIES_PER_CHUNK, index_range.end);
+                let chunk = prepare_chunk(chunk_start..chunk_end);
+                // Writer hung up, stop generating
+                if sender.send((chunk_idx, chunk)).is_err() {
+                    break;
+                }
+            });
+        }
+        // Drop original sender so receiver finishes once all workers have exited
+        drop(sender);
+
+        // Chunks received out of order, keyed by chunk index
+        let mut pending = std::collections::BTreeMap::new();
+        let mut next_write_idx = 0;
+        // NOTE: Dropping the receiver (on interrupt) stops workers waiting to send chunks
+        'receive: for (chunk_idx, chunk) in receiver {
+            pending.insert(chunk_idx, chunk);
+            // Write all chunks that are next in order
+            while let Some(chunk) = pending.remove(&next_write_idx) {
+                for pfile in chunk {
+                    if writer.is_stopped() {
+                        break 'receive;
+                    }
+                    writer.append_prepared(pfile);
+                }
+                next_write_idx += 1;
+            }
+        }
+    });
+}
+
+/// Generate melodies with indices in `melody_range` and write them to provided backend.
+/// If `num_threads` is greater than 1, melodies are generated on that many worker threads,
+/// but are still written to the backend in the same order (and thus produce the same output).
+/// Melodies are rendered and written with `options` (see: [WriteOptions](struct.WriteOptions.html)).
+/// If checkpoint options are provided, writes a checkpoint every `interval` melodies.
 pub(crate) fn write_melodies_to_backend<B: StorageBackend>(
     note_set: libatm::MIDINoteSet,
     melody_length: u32,
-    mut backend: B,
+    melody_range: std::ops::Range<u64>,
+    num_threads: u32,
+    options: WriteOptions,
+    backend: B,
 ) {
     // Convert set of notes to vec
     let notes = libatm::MIDINoteVec::from(note_set); 
-    // Generate total number of melodies
-    let num_melodies = crate::utils::gen_num_melodies(notes.len() as u32, melody_length);
-    // Initialize progress bar
-    let mut pb = pbr::ProgressBar::new(num_melodies);
-    pb.set_max_refresh_rate(Some(std::time::Duration::from_millis(500)));
+    // Validate range and seek to first melody
+    let melodies = gen_melodies_range(
+        &notes,
+        melody_length,
+        options.render.num_variations(),
+        melody_range.clone(),
+    ).unwrap_or_else(|err| {
+        eprintln!("::: ERROR: Failed to generate melodies ({})", err);
+        std::process::exit(1);
+    });
+    let mut writer = MelodyWriter::new(
+        &notes,
+        melody_length,
+        melody_range.clone(),
+        options,
+        backend,
+    );
 
-    // For each melody
-    for melody_ref in crate::utils::gen_sequences(&notes, melody_length) {
-        // Copy notes into owned melody
-        let melody = melody_ref.iter().map(|n| *n.clone()).collect::<libatm::MIDINoteVec>();
-        // Show error if adding melody to backend failed
-        if let Err(err) = backend.append_melody(melody, None) {
-            println!("::: WARNING: Failed to add melody to storage backend ({:?})", err);
-        }
-        // Increment progress bar even if write failed
-        pb.inc();
+    if num_threads > 1 {
+        // Rendering options shared with workers
+        let render = writer.render.clone();
+        let prepare = |chunk_range| prepare_chunk(&notes, melody_length, &render, chunk_range);
+        write_chunks_multi_threaded(melody_range, num_threads, prepare, &mut writer);
+    } else {
+        write_melodies_single_threaded(melodies, &mut writer);
     }
     
-    // Stop progress bar
-    pb.finish_println("");
-    // Finish writing to backend
-    if let Err(err) = backend.finish() {
-        println!("::: ERROR: Failed to finish writing to storage backend ({:?})", err);
-        std::process::exit(1);
-    }
+    // Stop progress bar and finish writing to backend (even if interrupted)
+    writer.finish();
 }
 
 /***********************
@@ -94,6 +912,32 @@ pub enum GenDirective {
     /// though in general using the `tar-gz` backend will provide better compression.
     #[structopt(name="batch")]
     GenBatch(GenBatchDirective),
+    /// Generate pairs of voices (a melody and a bass line over separate note sets) as format 1
+    /// MIDI files with one track per voice, optionally following voice-leading rules, and store
+    /// them in the backend matching the target extension (i.e., '.tar.gz', or a directory).
+    /// Files are named by both voices (i.e., 606467_485543.mid).
+    #[structopt(name="counterpoint")]
+    GenCounterpoint(GenCounterpointDirective),
+    /// Generate melodies and store them as files in a directory tree,
+    /// where partitions are directories (i.e., <root>/<partition>/<hash>.mid).
+    /// Use when the files are needed on disk as-is.
+    #[structopt(name="dir")]
+    GenDir(GenDirDirective),
+    /// Generate drum patterns (on/off steps of percussion instruments) on MIDI channel 10,
+    /// and store them in the backend matching the target extension (i.e., '.tar.zst', or a
+    /// directory). Files are named by the steps of every instrument (i.e., kick-8888_snare-0808_hat-aaaa.mid).
+    #[structopt(name="drums")]
+    GenDrums(GenDrumsDirective),
+    /// Generate melodies and store them bit-packed in a melody pack, which takes
+    /// a fraction of the space of any other backend. MIDI files can be regenerated
+    /// from the pack with `convert`.
+    #[structopt(name="pack")]
+    GenPack(GenPackDirective),
+    /// Generate chord progressions (Roman numerals in a key) rendered as block chords, and store
+    /// them in the backend matching the target extension (i.e., '.tar.gz', or a directory).
+    /// Files are named by their numeral sequence (i.e., I-vi-IV-V7.mid).
+    #[structopt(name="progressions")]
+    GenProgressions(GenProgressionsDirective),
     /// Generate single melody (MIDI file).
     #[structopt(name="single")]
     GenSingle(GenSingleDirective),
@@ -106,15 +950,31 @@ pub enum GenDirective {
     /// See `estimate` directives to simulate output file sizes.
     #[structopt(name="tar-gz")]
     GenTarGz(GenTarGzDirective),
+    /// Generate melodies and store them in Zstandard-compressed Tar file.
+    /// Compresses faster than `tar-gz` at similar or better compression ratios,
+    /// especially with a long-distance matching window (see: --long).
+    #[structopt(name="tar-zst")]
+    GenTarZst(GenTarZstDirective),
+    /// Generate melodies and store them in ZIP file (ZIP64 if more than 65,535 melodies).
+    /// Use for datasets shared with users who can't easily open Tar files (i.e., on Windows).
+    #[structopt(name="zip")]
+    GenZip(GenZipDirective),
 }
 
 impl CliDirective for GenDirective {
     fn run(self) {
         match self {
             Self::GenBatch(d) => d.run(),
+            Self::GenCounterpoint(d) => d.run(),
+            Self::GenDir(d) => d.run(),
+            Self::GenDrums(d) => d.run(),
+            Self::GenPack(d) => d.run(),
+            Self::GenProgressions(d) => d.run(),
             Self::GenSingle(d) => d.run(),
             Self::GenTar(d) => d.run(),
             Self::GenTarGz(d) => d.run(),
+            Self::GenTarZst(d) => d.run(),
+            Self::GenZip(d) => d.run(),
         }
     }
 }
@@ -11,13 +11,86 @@ use std::str::FromStr;
 use flate2::Compression;
 
 use crate::{
-    cli::CliDirective,
+    cli::{CliDirective, try_zstd_level_from_str},
     directives::gen::{
+        append_render_manifest,
+        gen_layout,
+        gen_stdout_writer,
+        resolve_checkpoint_options,
+        resolve_melody_range,
+        resolve_render_options,
+        resolve_resume,
         try_compression_from_str,
         write_melodies_to_backend,
+        WriteOptions,
+    },
+    midi::MIDIRenderOptions,
+    storage::{
+        BatchCompression,
+        BatchOptions,
+        PreparedMIDIFile,
     },
 };
 
+/// Default maximum size of zstd dictionary (in bytes)
+const DEFAULT_DICTIONARY_SIZE: usize = 16384;
+
+/// Number of melodies sampled to train zstd dictionary
+const NUM_DICTIONARY_SAMPLES: u64 = 4096;
+
+/// Generate sample of melodies to train zstd dictionary, evenly spaced across all melodies.
+/// Samples are drawn from all melodies (not just the generated range), so that every shard
+/// of a dataset uses the same dictionary.
+fn gen_dictionary_samples(
+    notes: &libatm::MIDINoteVec,
+    melody_length: u32,
+    render: &MIDIRenderOptions,
+) -> Vec<PreparedMIDIFile> {
+    let num_melodies = render.gen_num_melodies(notes.len() as u32, melody_length);
+    let num_samples = std::cmp::min(num_melodies, NUM_DICTIONARY_SAMPLES);
+    let step = num_melodies / num_samples;
+    let num_variations = render.num_variations();
+    // NOTE: Melodies that aren't generated with their variation are left out of the sample
+    (0..num_samples)
+        .filter_map(|sample_idx| {
+            // Index always in range
+            let index = sample_idx * step;
+            let melody = crate::utils::gen_melody_at(notes, melody_length, index / num_variations).unwrap();
+            render.prepare_variation(melody, index % num_variations)
+        })
+        .collect()
+}
+
+/// Resolve compression of batches from Gzip compression level or Zstandard compression level
+/// (training a dictionary from a sample of melodies with `notes` and `melody_length`,
+/// rendered with `render`)
+pub(crate) fn resolve_batch_compression(
+    gzip_compression: Option<Compression>,
+    zstd_level: Option<i32>,
+    dictionary_size: Option<usize>,
+    notes: &libatm::MIDINoteVec,
+    melody_length: u32,
+    render: &MIDIRenderOptions,
+) -> BatchCompression {
+    let batch_compression = match zstd_level {
+        Some(level) => BatchCompression::zstd_from_samples(
+            level,
+            gen_dictionary_samples(notes, melody_length, render),
+            dictionary_size.unwrap_or(DEFAULT_DICTIONARY_SIZE),
+        ).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to train zstd dictionary ({:?})", err);
+            std::process::exit(1);
+        }),
+        None => BatchCompression::Gzip(gzip_compression.unwrap_or_default()),
+    };
+    if let BatchCompression::Zstd { ref dictionary, .. } = batch_compression {
+        if dictionary.is_empty() {
+            eprintln!("::: WARNING: Not enough melodies to train zstd dictionary, compressing batches without one");
+        }
+    }
+    batch_compression
+}
+
 /****************************
 ***** GenBatchDirective *****
 ****************************/
@@ -34,6 +107,10 @@ pub struct GenBatchDirective {
     pub target: crate::cli::TargetArg,
     #[structopt(flatten)]
     pub partition_args: crate::cli::PartitionArgs,
+    #[structopt(flatten)]
+    pub render_args: crate::cli::MIDIRenderArgs,
+    #[structopt(flatten)]
+    pub variation_args: crate::cli::VariationArgs,
     #[structopt(
         short="m",
         long="mode",
@@ -46,8 +123,26 @@ pub struct GenBatchDirective {
         help="Compression level [0-9, default: 6]",
         parse(try_from_str = try_compression_from_str))]
     pub batch_compression: Option<Compression>,
+    /// Compress batches with Zstandard at level [1-22] instead of Gzip, using a dictionary
+    /// trained from a sample of melodies. The dictionary is stored as the first entry
+    /// of the top-level Tar file (batch.dict).
+    #[structopt(
+        long="zstd",
+        value_name="level",
+        conflicts_with="batch-compression",
+        parse(try_from_str = try_zstd_level_from_str))]
+    pub batch_zstd_level: Option<i32>,
+    /// Maximum size of zstd dictionary (in bytes) [default: 16384]
+    #[structopt(long="dict-size", requires="batch-zstd-level")]
+    pub dictionary_size: Option<usize>,
     #[structopt(flatten)]
     pub batch_size: crate::cli::BatchSize,
+    #[structopt(flatten)]
+    pub num_threads: crate::cli::ThreadsArg,
+    #[structopt(flatten)]
+    pub shard_args: crate::cli::ShardArgs,
+    #[structopt(flatten)]
+    pub checkpoint_args: crate::cli::CheckpointArgs,
 }
 
 impl CliDirective for GenBatchDirective {
@@ -55,30 +150,116 @@ impl CliDirective for GenBatchDirective {
         let partition_depth = match self.partition_args.partition_depth {
             Some(partition_depth) => partition_depth,
             None => {
-                println!("::: ERROR: Must provide partition depth");
+                eprintln!("::: ERROR: Must provide partition depth");
                 std::process::exit(1);
             },
         };
         let note_set: libatm::MIDINoteSet = self.note_set.into();
         let melody_length = self.melody_length.into();
-        let target: std::path::PathBuf = self.target.into();
+        let render = resolve_render_options(
+            self.render_args,
+            &self.variation_args,
+            &libatm::MIDINoteVec::from(&note_set),
+            melody_length,
+        );
+        let num_threads = self.num_threads.into();
 
-        // Create storage backend
-        let backend = crate::storage::BatchTarFile::new(
-            target,
-            self.batch_size.into(),
+        // Resolve range of melodies to generate (aligned to partitions)
+        let path_generator = crate::storage::PartitionPathGenerator::new_with_variations(
             note_set.len() as u32,
             melody_length,
+            render.num_variations(),
             self.partition_args.max_files,
             partition_depth,
-            self.batch_compression,
-            self.batch_mode,
-        ).unwrap_or_else(|err| { 
-            println!("::: ERROR: Failed to create storage backend ({:?})", err);
+        ).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to initialize partitioning scheme ({:?})", err);
+            std::process::exit(1);
+        });
+        let num_melodies_per_partition = path_generator.gen_num_melodies_per_partition();
+        let melody_range = resolve_melody_range(
+            &self.shard_args,
+            render.gen_num_melodies(note_set.len() as u32, melody_length),
+            num_melodies_per_partition,
+        );
+        let batch_size: u32 = self.batch_size.into();
+        let compress = match self.batch_zstd_level {
+            Some(level) => format!("zstd:{}:{}", level, self.dictionary_size.unwrap_or(DEFAULT_DICTIONARY_SIZE)),
+            None => format!("gzip:{}", self.batch_compression.unwrap_or_default().level()),
+        };
+        let layout = gen_layout(&self.partition_args, &[
+            ("batch_size", batch_size.to_string()),
+            ("compress", compress),
+            ("mode", self.batch_mode.unwrap_or(644).to_string()),
+        ]);
+        let checkpoint_options = resolve_checkpoint_options(&self.checkpoint_args, &self.target, layout.clone());
+
+        // Resolve batch compression, training zstd dictionary if necessary
+        let batch_options = BatchOptions {
+            batch_size,
+            batch_compression: resolve_batch_compression(
+                self.batch_compression,
+                self.batch_zstd_level,
+                self.dictionary_size,
+                &libatm::MIDINoteVec::from(&note_set),
+                melody_length,
+                &render,
+            ),
+            batch_mode: self.batch_mode,
+        };
+
+        // Write batches to stdout
+        if self.target.is_stdout() {
+            let mut backend = crate::storage::BatchTarFile::from_writer(
+                gen_stdout_writer(),
+                path_generator,
+                &batch_options,
+            ).unwrap_or_else(|err| {
+                eprintln!("::: ERROR: Failed to create storage backend ({:?})", err);
+                std::process::exit(1);
+            });
+            append_render_manifest(&mut backend, &render);
+            let options = WriteOptions { render, mode: None, checkpoint_options: None };
+            write_melodies_to_backend(note_set, melody_length, melody_range, num_threads, options, backend);
+            return;
+        }
+
+        let target: std::path::PathBuf = self.target.into();
+        // Resolve checkpoint to resume from (if resuming)
+        let (resume_checkpoint, melody_range) = resolve_resume(
+            &self.checkpoint_args,
+            &target,
+            &note_set,
+            melody_length,
+            &render,
+            &layout,
+            melody_range,
+        );
+        // Create storage backend (render manifest was already written if resuming)
+        let mut backend = match resume_checkpoint {
+            Some(ref checkpoint) => crate::storage::BatchTarFile::resume(
+                &target,
+                path_generator,
+                &batch_options,
+                checkpoint,
+            ),
+            None => crate::storage::BatchTarFile::new(&target, path_generator, &batch_options),
+        }.unwrap_or_else(|err| { 
+            eprintln!("::: ERROR: Failed to create storage backend ({:?})", err);
             std::process::exit(1);
         });
+        if resume_checkpoint.is_none() {
+            append_render_manifest(&mut backend, &render);
+        }
 
         // Write generated melodies to backend
-        write_melodies_to_backend(note_set, melody_length, backend);
+        let options = WriteOptions { render, mode: None, checkpoint_options };
+        write_melodies_to_backend(
+            note_set,
+            melody_length,
+            melody_range,
+            num_threads,
+            options,
+            backend,
+        );
     }
 }
@@ -0,0 +1,103 @@
+// gen_counterpoint.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::{
+    cli::CliDirective,
+    counterpoint::{CounterpointSet, VoiceLeadingRules},
+    directives::gen::{dispatch_backend, IndexedGenerator},
+    midi::MIDIRenderOptions,
+    storage::CounterpointPathGenerator,
+};
+
+/***********************************
+***** GenCounterpointDirective *****
+***********************************/
+
+/// Generate pairs of voices (a melody and a bass line) and store them as format 1 MIDI files
+/// with one track per voice (regardless of `--midi-format`), where the storage backend is
+/// chosen by the extension of the target (see:
+/// [CounterpointSet](../counterpoint/struct.CounterpointSet.html))
+#[derive(structopt::StructOpt)]
+pub struct GenCounterpointDirective {
+    /// Comma-separated set of NOTE:OCTAVE pairs to generate melodies from
+    /// (i.e., 'C:4,D:4,E:4,F:4,G:4'). Include 'R' to generate melodies with rests.
+    #[structopt(value_name="melody-notes", parse(try_from_str = crate::utils::parse_note_set))]
+    pub melody_notes: libatm::MIDINoteSet,
+    /// Comma-separated set of NOTE:OCTAVE pairs to generate bass lines from
+    /// (i.e., 'C:3,F:3,G:3'). Include 'R' to generate bass lines with rests.
+    #[structopt(value_name="bass-notes", parse(try_from_str = crate::utils::parse_note_set))]
+    pub bass_notes: libatm::MIDINoteSet,
+    /// Length of voices (number of notes) to generate.
+    #[structopt(parse(try_from_str = crate::cli::try_length_from_str))]
+    pub length: u32,
+    /// Output path: Tar file ('.tar', or '-' to write to stdout), Gzip-compressed Tar file
+    /// ('.tar.gz' or '.tgz'), Zstandard-compressed Tar file ('.tar.zst') or ZIP file ('.zip'),
+    /// or root directory of output directory tree otherwise.
+    #[structopt(parse(from_str))]
+    pub target: std::path::PathBuf,
+    /// Skip pairs with parallel fifths or octaves (consecutive perfect fifths, or octaves, between
+    /// voices that both move).
+    #[structopt(long)]
+    pub no_parallels: bool,
+    /// Skip pairs where the bass crosses above the melody.
+    #[structopt(long)]
+    pub no_crossing: bool,
+    /// Partition depth: files are partitioned into directories by the notes of both voices at
+    /// their first N positions (i.e., <root>/60_48/606467_485543.mid with depth 1).
+    #[structopt(short="p", long="partitions", value_name="N")]
+    pub partition_depth: Option<u32>,
+    #[structopt(flatten)]
+    pub backend_args: crate::cli::BackendArgs,
+    #[structopt(flatten)]
+    pub render_args: crate::cli::MIDIRenderArgs,
+}
+
+impl CliDirective for GenCounterpointDirective {
+    fn run(self) {
+        let length = self.length;
+        let melody_notes = libatm::MIDINoteVec::from(&self.melody_notes);
+        let bass_notes = libatm::MIDINoteVec::from(&self.bass_notes);
+        let rules = VoiceLeadingRules { no_parallels: self.no_parallels, no_crossing: self.no_crossing };
+        let pairs = CounterpointSet::new(
+            &melody_notes,
+            &bass_notes,
+            length,
+            rules,
+        ).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to generate pairs of voices ({})", err);
+            std::process::exit(1);
+        });
+        let path_generator = CounterpointPathGenerator::new(
+            length,
+            self.partition_depth.unwrap_or(0),
+        ).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to initialize partitioning scheme ({})", err);
+            std::process::exit(1);
+        });
+        let format_notes = |notes: &libatm::MIDINoteVec| notes
+            .iter()
+            .map(crate::utils::format_note)
+            .collect::<Vec<String>>()
+            .join("+");
+        let generator = IndexedGenerator {
+            num_indices: pairs.num_pairs(),
+            length,
+            options: vec![
+                ("melody_notes", format_notes(&melody_notes)),
+                ("bass_notes", format_notes(&bass_notes)),
+                ("no_parallels", self.no_parallels.to_string()),
+                ("no_crossing", self.no_crossing.to_string()),
+                ("partitions", self.partition_depth.unwrap_or(0).to_string()),
+            ],
+            prepare: |render: &MIDIRenderOptions, index| pairs.prepare(render, index),
+        };
+        // Meta events use the index of the pair instead of a melody index
+        let render = self.render_args.gen_render_options(&std::iter::empty().collect());
+        dispatch_backend(self.target, self.backend_args, path_generator, generator, render);
+    }
+}
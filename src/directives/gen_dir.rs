@@ -0,0 +1,141 @@
+// gen_dir.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::{
+    cli::{CliDirective, ParseNumberArgError},
+    directives::gen::{
+        append_render_manifest,
+        resolve_melody_range,
+        resolve_render_options,
+        write_melodies_to_backend,
+        WriteOptions,
+    },
+    storage::PathGenerator,
+};
+
+/**************************
+***** GenDirDirective *****
+**************************/
+
+/// Parse file permissions from octal `&str` (i.e., '644')
+pub(crate) fn try_mode_from_str(arg: &str) -> Result<u32, std::num::ParseIntError> {
+    u32::from_str_radix(arg, 8)
+}
+
+/// Parse sync interval from `&str` (must be greater than 0)
+pub(crate) fn try_sync_from_str(arg: &str) -> Result<u32, ParseNumberArgError> {
+    let sync_interval = arg.parse::<u32>()?;
+    if sync_interval == 0 {
+        return Err(ParseNumberArgError::LessThanZero { arg_name: "Sync interval".to_string() });
+    }
+    Ok(sync_interval)
+}
+
+/// Generate melodies and store them as files in a directory tree
+/// (see: [DirectoryBackend](../storage/directory/struct.DirectoryBackend.html))
+#[derive(structopt::StructOpt)]
+pub struct GenDirDirective {
+    #[structopt(flatten)]
+    pub note_set: crate::cli::NoteSetArg,
+    #[structopt(flatten)]
+    pub melody_length: crate::cli::MelodyLengthArg,
+    /// Root directory of output directory tree (created if it doesn't exist).
+    #[structopt(parse(from_str))]
+    pub target: std::path::PathBuf,
+    #[structopt(flatten)]
+    pub partition_args: crate::cli::PartitionArgs,
+    #[structopt(flatten)]
+    pub render_args: crate::cli::MIDIRenderArgs,
+    #[structopt(flatten)]
+    pub variation_args: crate::cli::VariationArgs,
+    /// Permissions (octal) to use for files [default: 666 minus umask]
+    #[structopt(long="mode", parse(try_from_str = try_mode_from_str))]
+    pub mode: Option<u32>,
+    /// Sync files to disk (fsync) as they're written, and their directories after every N files written
+    #[structopt(long="sync", value_name="N", parse(try_from_str = try_sync_from_str))]
+    pub sync_interval: Option<u32>,
+    #[structopt(flatten)]
+    pub num_threads: crate::cli::ThreadsArg,
+    #[structopt(flatten)]
+    pub shard_args: crate::cli::ShardArgs,
+}
+
+impl GenDirDirective {
+    /// Write generated melodies to directory tree using provided path generator.
+    /// Melody range is aligned to `num_melodies_per_partition` melodies.
+    /// Melodies are rendered with `render`.
+    fn write_melodies<G: PathGenerator>(
+        self,
+        render: crate::midi::MIDIRenderOptions,
+        path_generator: G,
+        num_melodies_per_partition: u64,
+    ) {
+        let note_set: libatm::MIDINoteSet = self.note_set.into();
+        let melody_length = self.melody_length.into();
+        let num_threads = self.num_threads.into();
+
+        // Resolve range of melodies to generate
+        let melody_range = resolve_melody_range(
+            &self.shard_args,
+            render.gen_num_melodies(note_set.len() as u32, melody_length),
+            num_melodies_per_partition,
+        );
+        // Create storage backend
+        let mut backend = crate::storage::DirectoryBackend::new(
+            &self.target,
+            path_generator,
+            self.sync_interval,
+        ).unwrap_or_else(|err| { 
+            eprintln!("::: ERROR: Failed to create storage backend ({:?})", err);
+            std::process::exit(1);
+        });
+        append_render_manifest(&mut backend, &render);
+        // Write generated melodies to backend
+        let options = WriteOptions { render, mode: self.mode, checkpoint_options: None };
+        write_melodies_to_backend(
+            note_set,
+            melody_length,
+            melody_range,
+            num_threads,
+            options,
+            backend,
+        );
+    }
+}
+
+impl CliDirective for GenDirDirective {
+    fn run(self) {
+        let render = resolve_render_options(
+            self.render_args.clone(),
+            &self.variation_args,
+            &libatm::MIDINoteVec::from(&self.note_set.note_set),
+            self.melody_length.melody_length,
+        );
+        match self.partition_args.partition_depth {
+            // Use partitioning scheme
+            Some(partition_depth) => {
+                // Create path generator
+                let path_generator = crate::storage::PartitionPathGenerator::new_with_variations(
+                    self.note_set.note_set.len() as u32,
+                    self.melody_length.melody_length,
+                    render.num_variations(),
+                    self.partition_args.max_files,
+                    partition_depth,
+                ).unwrap_or_else(|err| {
+                    eprintln!("::: ERROR: Failed to initialize partitioning scheme ({:?})", err);
+                    std::process::exit(1);
+                });
+                // Melody range must be aligned to partitions
+                let num_melodies_per_partition = path_generator.gen_num_melodies_per_partition();
+                self.write_melodies(render, path_generator, num_melodies_per_partition);
+            },
+            // Don't use partitioning scheme
+            None => self.write_melodies(render, crate::storage::MIDIHashPathGenerator, 1),
+        }
+    }
+}
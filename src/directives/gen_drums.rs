@@ -0,0 +1,70 @@
+// gen_drums.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::{
+    cli::CliDirective,
+    directives::gen::{dispatch_backend, resolve_drum_options, IndexedGenerator},
+    midi::MIDIRenderOptions,
+    storage::DrumPathGenerator,
+};
+
+/****************************
+***** GenDrumsDirective *****
+****************************/
+
+/// Generate drum patterns (on/off steps of percussion instruments) and store them in a
+/// file/files, where the storage backend is chosen by the extension of the target. Patterns
+/// are always rendered on MIDI channel 10 (see: [DrumPatternSet](../drums/struct.DrumPatternSet.html))
+#[derive(structopt::StructOpt)]
+pub struct GenDrumsDirective {
+    #[structopt(flatten)]
+    pub drum_args: crate::cli::DrumArgs,
+    /// Output path: Tar file ('.tar', or '-' to write to stdout), Gzip-compressed Tar file
+    /// ('.tar.gz' or '.tgz'), Zstandard-compressed Tar file ('.tar.zst') or ZIP file ('.zip'),
+    /// or root directory of output directory tree otherwise.
+    #[structopt(parse(from_str))]
+    pub target: std::path::PathBuf,
+    /// Partition depth: files are partitioned into directories by the steps of their first
+    /// N instruments (i.e., <root>/kick-8888/kick-8888_snare-0808_hat-aaaa.mid with depth 1).
+    #[structopt(short="p", long="partitions", value_name="N")]
+    pub partition_depth: Option<u32>,
+    #[structopt(flatten)]
+    pub backend_args: crate::cli::BackendArgs,
+    #[structopt(flatten)]
+    pub render_args: crate::cli::MIDIRenderArgs,
+}
+
+impl CliDirective for GenDrumsDirective {
+    fn run(self) {
+        let (patterns, render) = resolve_drum_options(&self.drum_args, self.render_args);
+        let path_generator = DrumPathGenerator::new(
+            patterns.parts().len() as u32,
+            self.partition_depth.unwrap_or(0),
+        ).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to initialize partitioning scheme ({})", err);
+            std::process::exit(1);
+        });
+
+        let instruments = self.drum_args.instruments
+            .iter()
+            .map(|part| part.to_string())
+            .collect::<Vec<String>>()
+            .join("+");
+        let generator = IndexedGenerator {
+            num_indices: patterns.num_patterns(),
+            length: self.drum_args.steps,
+            options: vec![
+                ("instruments", instruments),
+                ("step", self.drum_args.step.to_string()),
+                ("partitions", self.partition_depth.unwrap_or(0).to_string()),
+            ],
+            prepare: |render: &MIDIRenderOptions, index| patterns.prepare(render, index),
+        };
+        dispatch_backend(self.target, self.backend_args, path_generator, generator, render);
+    }
+}
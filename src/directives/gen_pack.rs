@@ -0,0 +1,94 @@
+// gen_pack.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::{
+    cli::CliDirective,
+    directives::gen::{
+        resolve_file_target,
+        resolve_melody_range,
+        write_melodies_to_backend,
+        WriteOptions,
+    },
+    storage::{PackHeader, PackPartition},
+};
+
+/***************************
+***** GenPackDirective *****
+***************************/
+
+/// Generate melodies and store them in melody pack
+/// (see: [PackFile](../storage/pack_file/struct.PackFile.html))
+#[derive(structopt::StructOpt)]
+pub struct GenPackDirective {
+    #[structopt(flatten)]
+    pub note_set: crate::cli::NoteSetArg,
+    #[structopt(flatten)]
+    pub melody_length: crate::cli::MelodyLengthArg,
+    #[structopt(flatten)]
+    pub target: crate::cli::TargetArg,
+    #[structopt(flatten)]
+    pub partition_args: crate::cli::PartitionArgs,
+    #[structopt(flatten)]
+    pub render_args: crate::cli::MIDIRenderArgs,
+    #[structopt(flatten)]
+    pub shard_args: crate::cli::ShardArgs,
+}
+
+impl CliDirective for GenPackDirective {
+    fn run(self) {
+        let note_set: libatm::MIDINoteSet = self.note_set.into();
+        let melody_length = self.melody_length.into();
+        let render = self.render_args.gen_render_options(&libatm::MIDINoteVec::from(&note_set));
+        let target = resolve_file_target(self.target);
+
+        // Partitioning scheme is only recorded in the pack header, but the
+        // melody range must still be aligned to partitions
+        let (partition, num_melodies_per_partition) = match self.partition_args.partition_depth {
+            Some(partition_depth) => {
+                let path_generator = crate::storage::PartitionPathGenerator::new(
+                    note_set.len() as u32,
+                    melody_length,
+                    self.partition_args.max_files,
+                    partition_depth,
+                ).unwrap_or_else(|err| {
+                    eprintln!("::: ERROR: Failed to initialize partitioning scheme ({:?})", err);
+                    std::process::exit(1);
+                });
+                let partition = PackPartition {
+                    max_files: self.partition_args.max_files,
+                    partition_depth,
+                };
+                (Some(partition), path_generator.gen_num_melodies_per_partition())
+            },
+            None => (None, 1),
+        };
+
+        // Resolve range of melodies to generate
+        let melody_range = resolve_melody_range(
+            &self.shard_args,
+            render.gen_num_melodies(note_set.len() as u32, melody_length),
+            num_melodies_per_partition,
+        );
+        // Create storage backend
+        let header = PackHeader::new(libatm::MIDINoteVec::from(&note_set), melody_length, render.clone(), partition);
+        let backend = crate::storage::PackFile::new(&target, header).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to create storage backend ({:?})", err);
+            std::process::exit(1);
+        });
+        // Write generated melodies to backend
+        let options = WriteOptions { render, mode: None, checkpoint_options: None };
+        write_melodies_to_backend(
+            note_set,
+            melody_length,
+            melody_range,
+            1,
+            options,
+            backend,
+        );
+    }
+}
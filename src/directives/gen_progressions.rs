@@ -0,0 +1,101 @@
+// gen_progressions.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::{
+    cli::CliDirective,
+    directives::gen::{dispatch_backend, IndexedGenerator},
+    midi::MIDIRenderOptions,
+    progression::{ProgressionSet, RomanNumeral, Voicing},
+    storage::ProgressionPathGenerator,
+};
+
+/***********************************
+***** GenProgressionsDirective *****
+***********************************/
+
+/// Generate chord progressions (as Roman numerals in a key) and store them in a file/files,
+/// where the storage backend is chosen by the extension of the target
+/// (see: [ProgressionSet](../progression/struct.ProgressionSet.html))
+#[derive(structopt::StructOpt)]
+pub struct GenProgressionsDirective {
+    /// Tonic of key, as NOTE:OCTAVE pair (i.e., 'C:4'). Chords are built upward from the
+    /// scale degrees of the key above the tonic.
+    #[structopt(value_name="key", parse(try_from_str = crate::utils::parse_note))]
+    pub key: libatm::MIDINote,
+    /// Length of progressions (number of chords) to generate.
+    #[structopt(parse(try_from_str = crate::cli::try_length_from_str))]
+    pub progression_length: u32,
+    /// Output path: Tar file ('.tar', or '-' to write to stdout), Gzip-compressed Tar file
+    /// ('.tar.gz' or '.tgz'), Zstandard-compressed Tar file ('.tar.zst') or ZIP file ('.zip'),
+    /// or root directory of output directory tree otherwise.
+    #[structopt(parse(from_str))]
+    pub target: std::path::PathBuf,
+    /// Roman numerals to generate progressions from: scale degree (uppercase for major,
+    /// lowercase for minor triads), optionally preceded by 'b' or '#' and followed by 'o'
+    /// (diminished) or '+' (augmented), and '7' or 'maj7' (i.e., 'I,ii,V7,viio,bVII').
+    #[structopt(long, value_name="numerals", require_delimiter=true, default_value="I,ii,iii,IV,V,vi,viio")]
+    pub numerals: Vec<RomanNumeral>,
+    /// Build chords on the natural minor scale instead of the major scale.
+    #[structopt(long)]
+    pub minor: bool,
+    /// Voicing of chords: 'close' (root position), 'open' (root position, with every other
+    /// note raised an octave), 'first' or 'second' (inversion).
+    #[structopt(long, default_value="close")]
+    pub voicing: Voicing,
+    /// Partition depth: files are partitioned into directories by their first N numerals
+    /// (i.e., <root>/I/vi/I-vi-IV-V.mid with depth 2).
+    #[structopt(short="p", long="partitions", value_name="N")]
+    pub partition_depth: Option<u32>,
+    #[structopt(flatten)]
+    pub backend_args: crate::cli::BackendArgs,
+    #[structopt(flatten)]
+    pub render_args: crate::cli::MIDIRenderArgs,
+}
+
+impl CliDirective for GenProgressionsDirective {
+    fn run(self) {
+        let progression_length = self.progression_length;
+        let progressions = ProgressionSet::new(
+            &self.numerals,
+            progression_length,
+            self.key,
+            self.minor,
+            self.voicing,
+        ).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to generate progressions ({})", err);
+            std::process::exit(1);
+        });
+        let path_generator = ProgressionPathGenerator::new(
+            progression_length,
+            self.partition_depth.unwrap_or(0),
+        ).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to initialize partitioning scheme ({})", err);
+            std::process::exit(1);
+        });
+        let numerals = self.numerals
+            .iter()
+            .map(|numeral| numeral.to_string())
+            .collect::<Vec<String>>()
+            .join("+");
+        let generator = IndexedGenerator {
+            num_indices: progressions.patterns().num_patterns(),
+            length: progression_length,
+            options: vec![
+                ("key", crate::utils::format_note(&self.key)),
+                ("numerals", numerals),
+                ("minor", self.minor.to_string()),
+                ("voicing", self.voicing.to_string()),
+                ("partitions", self.partition_depth.unwrap_or(0).to_string()),
+            ],
+            prepare: |render: &MIDIRenderOptions, index| progressions.prepare(render, index),
+        };
+        // Meta events use the index of the progression instead of a melody index
+        let render = self.render_args.gen_render_options(&std::iter::empty().collect());
+        dispatch_backend(self.target, self.backend_args, path_generator, generator, render);
+    }
+}
@@ -18,23 +18,36 @@ pub struct GenSingleDirective {
     pub note_vec: crate::cli::NoteVecArg,
     #[structopt(flatten)]
     pub target: crate::cli::TargetArg,
+    #[structopt(flatten)]
+    pub render_args: crate::cli::MIDIRenderArgs,
 }
 
 impl CliDirective for GenSingleDirective {
     fn run(self) {
         // Get values from args
-        let note_vec = self.note_vec.into();
+        let chord_vec: crate::chord::ChordVec = self.note_vec.into();
+        let note_vec = chord_vec.roots;
         let target: std::path::PathBuf = self.target.into();
-        // Generate MIDIFile from input melody
-        println!("::: INFO: Generating MIDI file from pitch sequence");
-        let mfile = libatm::MIDIFile::new(note_vec, libatm::MIDIFormat::Format0, 1, 1);
+        // Melody index (in meta events) is relative to the notes of the melody
+        let notes = libatm::MIDINoteVec::from(libatm::MIDINoteSet(note_vec.iter().cloned().collect()));
+        let render = self.render_args.gen_render_options(&notes);
+        // Render MIDI file from input melody
+        eprintln!("::: INFO: Generating MIDI file from pitch sequence");
+        let data = match chord_vec.chord_types {
+            // Chord sequences aren't in the sequence of melodies generated from their roots,
+            // so are written without index
+            Some(chord_types) => {
+                let variation = crate::variation::MelodyVariation { chord_types: Some(chord_types), ..Default::default() };
+                render.render(&note_vec, &variation, None)
+            },
+            None => render.gen_file(&note_vec),
+        };
 
         // Write MIDI file to target file path
-        println!("::: INFO: Attempting to write MIDI file to {:?}", target);
-        match mfile.write_file(&target) {
-            Err(err) => println!("::: ERROR: Failed to write MIDI file to path {:?} ({})", &target, err),
-            _ => println!("::: INFO: Successfully wrote MIDI file"),
+        eprintln!("::: INFO: Attempting to write MIDI file to {:?}", target);
+        match std::fs::write(&target, data) {
+            Err(err) => eprintln!("::: ERROR: Failed to write MIDI file to path {:?} ({})", &target, err),
+            _ => eprintln!("::: INFO: Successfully wrote MIDI file"),
         }
     }
 }
-
@@ -8,7 +8,18 @@
 
 use crate::{
     cli::CliDirective,
-    directives::gen::write_melodies_to_backend,
+    directives::gen::{
+        append_render_manifest,
+        gen_layout,
+        gen_stdout_writer,
+        resolve_checkpoint_options,
+        resolve_melody_range,
+        resolve_render_options,
+        resolve_resume,
+        write_melodies_to_backend,
+        WriteOptions,
+    },
+    storage::PathGenerator,
 };
 
 /**************************
@@ -27,51 +38,114 @@ pub struct GenTarDirective {
     pub target: crate::cli::TargetArg,
     #[structopt(flatten)]
     pub partition_args: crate::cli::PartitionArgs,
+    #[structopt(flatten)]
+    pub render_args: crate::cli::MIDIRenderArgs,
+    #[structopt(flatten)]
+    pub variation_args: crate::cli::VariationArgs,
+    #[structopt(flatten)]
+    pub num_threads: crate::cli::ThreadsArg,
+    #[structopt(flatten)]
+    pub shard_args: crate::cli::ShardArgs,
+    #[structopt(flatten)]
+    pub checkpoint_args: crate::cli::CheckpointArgs,
 }
 
-impl CliDirective for GenTarDirective {
-    fn run(self) {
+impl GenTarDirective {
+    /// Write generated melodies to Tar file using provided path generator.
+    /// Melody range is aligned to `num_melodies_per_partition` melodies.
+    /// Melodies are rendered with `render`.
+    fn write_melodies<G: PathGenerator>(
+        self,
+        render: crate::midi::MIDIRenderOptions,
+        path_generator: G,
+        num_melodies_per_partition: u64,
+    ) {
         let note_set: libatm::MIDINoteSet = self.note_set.into();
         let melody_length = self.melody_length.into();
+        let num_threads = self.num_threads.into();
+
+        // Resolve range of melodies to generate
+        let melody_range = resolve_melody_range(
+            &self.shard_args,
+            render.gen_num_melodies(note_set.len() as u32, melody_length),
+            num_melodies_per_partition,
+        );
+        let layout = gen_layout(&self.partition_args, &[]);
+        let checkpoint_options = resolve_checkpoint_options(&self.checkpoint_args, &self.target, layout.clone());
+
+        // Write Tar file to stdout
+        if self.target.is_stdout() {
+            let writer = gen_stdout_writer();
+            let mut backend = crate::storage::TarFile::from_writer(writer, path_generator);
+            append_render_manifest(&mut backend, &render);
+            let options = WriteOptions { render, mode: None, checkpoint_options: None };
+            write_melodies_to_backend(note_set, melody_length, melody_range, num_threads, options, backend);
+            return;
+        }
+
         let target: std::path::PathBuf = self.target.into();
+        // Resolve checkpoint to resume from (if resuming)
+        let (resume_checkpoint, melody_range) = resolve_resume(
+            &self.checkpoint_args,
+            &target,
+            &note_set,
+            melody_length,
+            &render,
+            &layout,
+            melody_range,
+        );
+        // Create storage backend (render manifest was already written if resuming)
+        let mut backend = match resume_checkpoint {
+            Some(ref checkpoint) => crate::storage::TarFile::resume(&target, path_generator, checkpoint),
+            None => crate::storage::TarFile::new(&target, path_generator),
+        }.unwrap_or_else(|err| { 
+            eprintln!("::: ERROR: Failed to create storage backend ({:?})", err);
+            std::process::exit(1);
+        });
+        if resume_checkpoint.is_none() {
+            append_render_manifest(&mut backend, &render);
+        }
+        // Write generated melodies to backend
+        let options = WriteOptions { render, mode: None, checkpoint_options };
+        write_melodies_to_backend(
+            note_set,
+            melody_length,
+            melody_range,
+            num_threads,
+            options,
+            backend,
+        );
+    }
+}
 
+impl CliDirective for GenTarDirective {
+    fn run(self) {
+        let render = resolve_render_options(
+            self.render_args.clone(),
+            &self.variation_args,
+            &libatm::MIDINoteVec::from(&self.note_set.note_set),
+            self.melody_length.melody_length,
+        );
         match self.partition_args.partition_depth {
             // Use partitioning scheme
             Some(partition_depth) => {
                 // Create path generator
-                let path_generator = crate::storage::PartitionPathGenerator::new(  
-                    note_set.len() as u32,
-                    melody_length,
+                let path_generator = crate::storage::PartitionPathGenerator::new_with_variations(
+                    self.note_set.note_set.len() as u32,
+                    self.melody_length.melody_length,
+                    render.num_variations(),
                     self.partition_args.max_files.into(),
                     partition_depth,
                 ).unwrap_or_else(|err| {
-                    println!("::: ERROR: Failed to initialize partitioning scheme ({:?})", err);
+                    eprintln!("::: ERROR: Failed to initialize partitioning scheme ({:?})", err);
                     std::process::exit(1);
                 });
-                // Create storage backend
-                let backend = crate::storage::TarFile::new(
-                    target,
-                    path_generator,
-                ).unwrap_or_else(|err| { 
-                    println!("::: ERROR: Failed to create storage backend ({:?})", err);
-                    std::process::exit(1);
-                });
-                // Write generated melodies to backend
-                write_melodies_to_backend(note_set, melody_length, backend);
+                // Melody range must be aligned to partitions
+                let num_melodies_per_partition = path_generator.gen_num_melodies_per_partition();
+                self.write_melodies(render, path_generator, num_melodies_per_partition);
             },
             // Don't use partitioning scheme
-            None => {
-                // Create storage backend
-                let backend = crate::storage::TarFile::new(
-                    target,
-                    crate::storage::MIDIHashPathGenerator,
-                ).unwrap_or_else(|err| { 
-                    println!("::: ERROR: Failed to create storage backend ({:?})", err);
-                    std::process::exit(1);
-                });
-                // Write generated melodies to backend
-                write_melodies_to_backend(note_set, melody_length, backend);
-            },
+            None => self.write_melodies(render, crate::storage::MIDIHashPathGenerator, 1),
         }
     }
 }
@@ -0,0 +1,124 @@
+// gen_zip.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::{
+    cli::CliDirective,
+    directives::gen::{
+        append_render_manifest,
+        resolve_file_target,
+        resolve_melody_range,
+        resolve_render_options,
+        write_melodies_to_backend,
+        WriteOptions,
+    },
+    storage::PathGenerator,
+};
+
+/**************************
+***** GenZipDirective *****
+**************************/
+
+/// Generate melodies and store them in ZIP file
+/// (see: [ZipFile](../storage/zip_file/struct.ZipFile.html))
+#[derive(structopt::StructOpt)]
+pub struct GenZipDirective {
+    #[structopt(flatten)]
+    pub note_set: crate::cli::NoteSetArg,
+    #[structopt(flatten)]
+    pub melody_length: crate::cli::MelodyLengthArg,
+    #[structopt(flatten)]
+    pub target: crate::cli::TargetArg,
+    #[structopt(flatten)]
+    pub zip_args: crate::cli::ZipArgs,
+    #[structopt(flatten)]
+    pub partition_args: crate::cli::PartitionArgs,
+    #[structopt(flatten)]
+    pub render_args: crate::cli::MIDIRenderArgs,
+    #[structopt(flatten)]
+    pub variation_args: crate::cli::VariationArgs,
+    #[structopt(flatten)]
+    pub num_threads: crate::cli::ThreadsArg,
+    #[structopt(flatten)]
+    pub shard_args: crate::cli::ShardArgs,
+}
+
+impl GenZipDirective {
+    /// Write generated melodies to ZIP file using provided path generator.
+    /// Melody range is aligned to `num_melodies_per_partition` melodies.
+    /// Melodies are rendered with `render`.
+    fn write_melodies<G: PathGenerator>(
+        self,
+        render: crate::midi::MIDIRenderOptions,
+        path_generator: G,
+        num_melodies_per_partition: u64,
+    ) {
+        let note_set: libatm::MIDINoteSet = self.note_set.into();
+        let melody_length = self.melody_length.into();
+        let target = resolve_file_target(self.target);
+        let num_threads = self.num_threads.into();
+
+        // Resolve range of melodies to generate
+        let melody_range = resolve_melody_range(
+            &self.shard_args,
+            render.gen_num_melodies(note_set.len() as u32, melody_length),
+            num_melodies_per_partition,
+        );
+        // Create storage backend
+        let mut backend = crate::storage::ZipFile::new(
+            &target,
+            path_generator,
+            self.zip_args.into(),
+        ).unwrap_or_else(|err| { 
+            eprintln!("::: ERROR: Failed to create storage backend ({:?})", err);
+            std::process::exit(1);
+        });
+        append_render_manifest(&mut backend, &render);
+        // Write generated melodies to backend
+        let options = WriteOptions { render, mode: None, checkpoint_options: None };
+        write_melodies_to_backend(
+            note_set,
+            melody_length,
+            melody_range,
+            num_threads,
+            options,
+            backend,
+        );
+    }
+}
+
+impl CliDirective for GenZipDirective {
+    fn run(self) {
+        let render = resolve_render_options(
+            self.render_args.clone(),
+            &self.variation_args,
+            &libatm::MIDINoteVec::from(&self.note_set.note_set),
+            self.melody_length.melody_length,
+        );
+        match self.partition_args.partition_depth {
+            // Use partitioning scheme
+            Some(partition_depth) => {
+                // Create path generator
+                let path_generator = crate::storage::PartitionPathGenerator::new_with_variations(
+                    self.note_set.note_set.len() as u32,
+                    self.melody_length.melody_length,
+                    render.num_variations(),
+                    self.partition_args.max_files,
+                    partition_depth,
+                ).unwrap_or_else(|err| {
+                    eprintln!("::: ERROR: Failed to initialize partitioning scheme ({:?})", err);
+                    std::process::exit(1);
+                });
+                // Melody range must be aligned to partitions
+                let num_melodies_per_partition = path_generator.gen_num_melodies_per_partition();
+                self.write_melodies(render, path_generator, num_melodies_per_partition);
+            },
+            // Don't use partitioning scheme
+            None => self.write_melodies(render, crate::storage::MIDIHashPathGenerator, 1),
+        }
+    }
+}
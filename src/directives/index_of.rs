@@ -0,0 +1,44 @@
+// index_of.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::cli::CliDirective;
+
+/***************************
+***** IndexOfDirective *****
+***************************/
+
+/// Generate the index of a melody in the sequence of melodies generated from a set of notes.
+/// Indices are stable for a given set of notes and melody length, and can be used as IDs.
+#[derive(structopt::StructOpt)]
+pub struct IndexOfDirective {
+    #[structopt(flatten)]
+    pub note_set: crate::cli::NoteSetArg,
+    #[structopt(flatten)]
+    pub note_vec: crate::cli::NoteVecArg,
+}
+
+impl CliDirective for IndexOfDirective {
+    fn run(self) {
+        let notes = libatm::MIDINoteVec::from(self.note_set.note_set);
+        let melody = match self.note_vec.note_vec {
+            crate::chord::ChordVec { roots, chord_types: None } => roots,
+            crate::chord::ChordVec { chord_types: Some(_), .. } => {
+                println!("::: ERROR: Chords don't have an index in the melodies generated from a note set");
+                std::process::exit(1);
+            },
+        };
+
+        match crate::utils::gen_melody_index(&notes, &melody) {
+            Ok(melody_index) => println!("{}", melody_index),
+            Err(err) => {
+                println!("::: ERROR: Failed to generate index for melody ({})", err);
+                std::process::exit(1);
+            },
+        }
+    }
+}
@@ -0,0 +1,103 @@
+// ls.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use std::io::Write;
+
+use crate::{
+    cli::CliDirective,
+    directives::gen::gen_stdout_writer,
+    storage::{
+        archive_reader::gen_parent_path,
+        ArchiveEntry,
+        ArchiveReader,
+    },
+};
+
+/**********************
+***** LsDirective *****
+**********************/
+
+/// List the MIDI files in an archive generated by one of the `gen` directives (Tar, Gzip-
+/// or Zstandard-compressed Tar, or batch), and decode the melody of each. Doesn't require
+/// the note set or partitioning scheme the archive was generated with.
+///
+/// Prints one tab-separated line per file: mode (octal), size (bytes), partition, hash,
+/// melody (comma-separated NOTE:OCTAVE pairs) and, for files inside a batch, the batch entry
+/// and position in the batch (i.e., '6062/batch0.tar.gz:12'). Empty fields are printed as '-'.
+#[derive(structopt::StructOpt)]
+pub struct LsDirective {
+    /// Archive input path, or '-' to read from stdin.
+    #[structopt(parse(from_os_str))]
+    pub archive: std::path::PathBuf,
+    /// Only list files in partitions starting with prefix (i.e., '6062' or '6062/6465').
+    #[structopt(short="p", long)]
+    pub prefix: Option<String>,
+}
+
+impl LsDirective {
+    /// Whether partition matches prefix filter
+    fn matches_prefix(&self, partition: &str) -> bool {
+        match self.prefix {
+            Some(ref prefix) => partition.starts_with(prefix.trim_end_matches(std::path::MAIN_SEPARATOR)),
+            None => true,
+        }
+    }
+
+    /// Write line for entry
+    fn write_entry<W: std::io::Write>(target: &mut W, entry: &ArchiveEntry) -> std::io::Result<()> {
+        let melody = match crate::midi::decode_midi_file(&entry.data) {
+            Ok(decoded) => crate::utils::format_melody(&decoded.sequence),
+            Err(err) => {
+                eprintln!("::: WARNING: Failed to decode {} ({})", entry.path, err);
+                "-".to_string()
+            },
+        };
+        let batch = match entry.batch {
+            Some(ref batch) => format!("{}:{}", batch.path, batch.position),
+            None => "-".to_string(),
+        };
+        let partition = match entry.partition() {
+            "" => "-",
+            partition => partition,
+        };
+        writeln!(
+            target,
+            "{:o}\t{}\t{}\t{}\t{}\t{}",
+            entry.mode,
+            entry.data.len(),
+            partition,
+            entry.hash(),
+            melody,
+            batch,
+        )
+    }
+}
+
+impl CliDirective for LsDirective {
+    fn run(self) {
+        let reader = ArchiveReader::open(&self.archive).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to open archive {:?} ({})", &self.archive, err);
+            std::process::exit(1);
+        });
+        let mut target = gen_stdout_writer();
+        let result = reader.for_each_entry(
+            // Only decompress batches in matching partitions
+            |batch_path| self.matches_prefix(gen_parent_path(batch_path)),
+            |entry| {
+                if !entry.is_render_manifest() && self.matches_prefix(entry.partition()) {
+                    Self::write_entry(&mut target, &entry)?;
+                }
+                Ok(std::ops::ControlFlow::Continue(()))
+            },
+        ).and_then(|_| target.flush());
+        if let Err(err) = result {
+            eprintln!("::: ERROR: Failed to read archive {:?} ({})", &self.archive, err);
+            std::process::exit(1);
+        }
+    }
+}
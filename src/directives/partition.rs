@@ -6,48 +6,395 @@
 // To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
 // a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
 
+use std::io::Write;
+
 use crate::{
-    cli::{CliDirective, PartitionArgs},
-    storage::{PartitionPathGenerator, PathGenerator},
+    cli::{CliDirective, PartitionArgs, RenderManifestArgs, VariationArgs},
+    directives::{extract::read_melodies, gen::gen_stdout_writer},
+    storage::{
+        archive_reader::ArchiveReader,
+        pack_file::{PackReader, PACK_MAGIC},
+        PartitionPathGenerator,
+        PathGenerator,
+        PathGeneratorError,
+        PreparedMIDIFile,
+        RENDER_MANIFEST_PATH,
+    },
+    utils::MelodyIndexError,
+    variation::{MelodyVariation, VariationIndexError, VariationSet},
 };
 
+/***********************
+***** MelodyLookup *****
+***********************/
+
+/// Error type for looking up the location of a melody
+#[derive(Debug, thiserror::Error)]
+enum MelodyLookupError {
+    #[error("Melody has length {length} (expected {expected})")]
+    LengthMismatch { length: usize, expected: u32 },
+    #[error("Failed to generate path for melody ({0:?})")]
+    Path(#[from] PathGeneratorError),
+    #[error("Failed to calculate index of melody ({0})")]
+    Index(#[from] MelodyIndexError),
+    #[error(transparent)]
+    Variation(#[from] VariationIndexError),
+}
+
+/// Location of melody in a dataset generated by the `gen` directives
+struct MelodyLocation {
+    melody: String,
+    hash: String,
+    path: String,
+    /// Batch entry holding the melody, and position of the melody in the batch
+    batch: Option<(String, u64)>,
+}
+
+impl MelodyLocation {
+    /// Write location as tab-separated line: melody, hash, path and (if known)
+    /// batch entry and position in the batch (i.e., '6062/batch0.tar.gz:12')
+    fn write_tsv<W: std::io::Write>(&self, target: &mut W) -> std::io::Result<()> {
+        write!(target, "{}\t{}\t{}", self.melody, self.hash, self.path)?;
+        if let Some((ref batch_path, position)) = self.batch {
+            write!(target, "\t{}:{}", batch_path, position)?;
+        }
+        writeln!(target)
+    }
+
+    /// Write location as JSON object on a single line
+    fn write_json<W: std::io::Write>(&self, target: &mut W) -> std::io::Result<()> {
+        write!(
+            target,
+            "{{\"melody\":\"{}\",\"hash\":\"{}\",\"path\":\"{}\"",
+            self.melody,
+            self.hash,
+            escape_json(&self.path),
+        )?;
+        if let Some((ref batch_path, position)) = self.batch {
+            write!(target, ",\"batch\":\"{}\",\"position\":{}", escape_json(batch_path), position)?;
+        }
+        writeln!(target, "}}")
+    }
+}
+
+/// Escape backslashes and quotes in JSON string (paths use '\' as separator on Windows)
+fn escape_json(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
 /*****************************
 ***** PartitionDirective *****
 *****************************/
 
-/// Generate the partition(s) for a MIDI pitch sequence within a partitioning scheme.
-/// If no partition depth is provided, will default to a depth of 1.
+/// Generate the path of a MIDI pitch sequence within a partitioning scheme, as written by the
+/// `gen` directives with the same note set. If no partition depth is provided, will default
+/// to a depth of 1. With --batch-size, also generate the batch entry (and position in the batch)
+/// `gen batch` wrote the melody to.
+///
+/// For datasets generated with chord types, rhythms or dynamics, pass the same --chord-types,
+/// --durations (and --bars) or --velocities, and the chord, duration or velocity of each note
+/// of the melody (i.e., 'C:4+E:4+G:4@1/8~mf,D:4+F:4+A:4@1/4~ff').
+///
+/// With --dataset, chord types, durations, bars and velocities are read from the render manifest
+/// of the dataset (directory, archive or melody pack) instead.
+///
+/// With --input, melodies are read from a file (or stdin) instead, one per line, and one
+/// tab-separated line (or JSON object with --json) is printed per melody: melody, hash,
+/// path and, with --batch-size, batch entry and position in the batch. Invalid melodies are
+/// reported and skipped.
 #[derive(structopt::StructOpt)]
 pub struct PartitionDirective {
     #[structopt(flatten)]
-    pub note_vec: crate::cli::NoteVecArg,
+    pub note_set: crate::cli::NoteSetArg,
+    /// Melody, as comma-separated NOTE:OCTAVE pairs and 'R' for rests (i.e., 'C:4,R,D:4').
+    /// With --durations, each note is followed by '@' and its duration (i.e., 'C:4@1/8,R@1/8,D:4@1/4'),
+    /// and with --velocities, by '~' and its velocity, except rests (i.e., 'C:4~mf,R,D:4~ff'). With --chord-types,
+    /// notes played together are joined by '+' (i.e., 'C:4+E:4+G:4,A:3+C:4+E:4').
+    #[structopt(
+        value_name="melody",
+        required_unless="input",
+        conflicts_with="input",
+        parse(try_from_str = crate::variation::parse_annotated_melody))]
+    pub melody: Option<crate::variation::AnnotatedMelody>,
+    /// Read melodies from file (or stdin if '-'), one per line, as NOTE:OCTAVE pairs
+    /// or MIDI note numbers (i.e., '60,60,62'). All melodies must have the same length.
+    #[structopt(short="i", long, conflicts_with_all=&["chord-types", "durations", "velocities"], parse(from_os_str))]
+    pub input: Option<std::path::PathBuf>,
+    /// Print JSON object per melody instead of tab-separated lines.
+    #[structopt(long)]
+    pub json: bool,
     #[structopt(flatten)]
     pub partition: PartitionArgs,
+    #[structopt(flatten)]
+    pub variation_args: VariationArgs,
+    /// Read chord types, durations, bars and velocities from the render manifest of dataset
+    /// (directory, archive or melody pack) instead.
+    #[structopt(
+        long,
+        value_name="dataset",
+        conflicts_with_all=&["chord-types", "durations", "bars", "velocities"],
+        parse(from_os_str))]
+    pub dataset: Option<std::path::PathBuf>,
+    /// Number of melodies per batch the dataset was generated with (see: `gen batch`).
+    #[structopt(short="s", long, parse(try_from_str = crate::cli::try_batch_from_str))]
+    pub batch_size: Option<u32>,
+    /// Batches were compressed with Zstandard (batch<N>.tar.zst) instead of Gzip.
+    #[structopt(long, requires="batch-size")]
+    pub zstd: bool,
 }
 
-impl CliDirective for PartitionDirective {
-    fn run(self) {
-        let note_vec = self.note_vec.note_vec;
-        let melody_length = note_vec.len() as u32;
-        let max_files = self.partition.max_files;
-        let partition_depth = self.partition.partition_depth.unwrap_or(1);
-
-        let path_generator = PartitionPathGenerator::new(melody_length, melody_length, max_files, partition_depth);
-        match path_generator {
-            Ok(path_generator) => {
-                let mfile = libatm::MIDIFile::new(note_vec, libatm::MIDIFormat::Format0, 1, 1);
-                match path_generator.gen_path_for_file(&mfile) {
-                    Ok(path) => println!("{}", path),
-                    Err(err) => {
-                        println!("::: ERROR: Failed to generate path for melody ({})", err);
-                        std::process::exit(1);
-                    },
-                }
-            },
+/// Read render manifest of dataset at `path` (directory, archive or melody pack).
+/// Exits if the dataset can't be read, or has no render manifest.
+fn read_dataset_manifest(path: &std::path::Path) -> String {
+    let manifest = if path.is_dir() {
+        match std::fs::read_to_string(path.join(RENDER_MANIFEST_PATH)) {
+            Ok(manifest) => Some(manifest),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
             Err(err) => {
-                println!("::: ERROR: Failed to initialize partition generator ({})", err);
-                std::process::exit(2);
+                eprintln!("::: ERROR: Failed to read render manifest of dataset {:?} ({})", path, err);
+                std::process::exit(1);
             },
         }
+    } else {
+        let mut magic = [0u8; 8];
+        let is_pack = std::fs::File::open(path)
+            .and_then(|mut file| std::io::Read::read_exact(&mut file, &mut magic))
+            .is_ok_and(|_| &magic == PACK_MAGIC);
+        if is_pack {
+            match PackReader::open(path) {
+                Ok(pack_reader) => Some(pack_reader.header().render.gen_manifest()),
+                Err(err) => {
+                    eprintln!("::: ERROR: Failed to read melody pack {:?} ({})", path, err);
+                    std::process::exit(1);
+                },
+            }
+        } else {
+            // Render manifest is the first entry of the archive (batches are never read)
+            let mut manifest = None;
+            let result = ArchiveReader::open(path).and_then(|archive_reader| {
+                archive_reader.for_each_entry(|_| false, |entry| {
+                    if entry.is_render_manifest() {
+                        manifest = Some(String::from_utf8_lossy(&entry.data).into_owned());
+                    }
+                    Ok(std::ops::ControlFlow::Break(()))
+                })
+            });
+            if let Err(err) = result {
+                eprintln!("::: ERROR: Failed to read archive {:?} ({})", path, err);
+                std::process::exit(1);
+            }
+            manifest
+        }
+    };
+    manifest.unwrap_or_else(|| {
+        eprintln!("::: ERROR: Dataset {:?} has no render manifest (see: --chord-types, --durations, --velocities)", path);
+        std::process::exit(1);
+    })
+}
+
+/// Generate number of files written before melody `melody` (with pitch index `pitch_index`,
+/// generated from `notes`) with variation at index `variation_index` in its partition of a
+/// dataset generated with `path_generator`. Each partition holds a contiguous range of melodies,
+/// but melodies with rests aren't generated with every dynamics pattern (see: `DynamicsSet`).
+fn gen_position_in_partition(
+    notes: &libatm::MIDINoteVec,
+    pitch_index: u64,
+    melody: &[libatm::MIDINote],
+    variation_index: u64,
+    variations: &VariationSet,
+    path_generator: &PartitionPathGenerator,
+) -> u64 {
+    let num_variations = variations.num_variations();
+    let num_melodies_per_partition = path_generator.gen_num_melodies_per_partition() / num_variations;
+    let position_in_partition = pitch_index % num_melodies_per_partition;
+    if variations.dynamics.is_none() {
+        return position_in_partition * num_variations + variation_index;
+    }
+    // Count files of every melody from the start of the partition (range always valid)
+    let partition_start = pitch_index - position_in_partition;
+    crate::utils::gen_sequences_range(notes, melody.len() as u32, partition_start, pitch_index)
+        .unwrap()
+        .map(|sequence| {
+            let sequence = sequence.into_iter().copied().collect::<Vec<libatm::MIDINote>>();
+            variations.gen_num_melody_variations(&sequence)
+        })
+        .sum::<u64>()
+        + variations.gen_num_melody_variations_before(variation_index, melody)
+}
+
+impl PartitionDirective {
+    /// Create path generator for melodies of length `melody_length`,
+    /// generated with `num_variations` variations each
+    fn gen_path_generator(&self, melody_length: u32, num_variations: u64) -> PartitionPathGenerator {
+        PartitionPathGenerator::new_with_variations(
+            self.note_set.note_set.len() as u32,
+            melody_length,
+            num_variations,
+            self.partition.max_files,
+            self.partition.partition_depth.unwrap_or(1),
+        ).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to initialize partition generator ({})", err);
+            std::process::exit(2);
+        })
+    }
+
+    /// Generate batch entry path and position in batch for melody with position
+    /// `position_in_partition` in partition of path `path`, in a batch dataset (see: `BatchTarFile`)
+    fn gen_batch_location(&self, position_in_partition: u64, path: &str, batch_size: u32) -> (String, u64) {
+        // Each partition's files are split into batches in order
+        let batch_number = position_in_partition / batch_size as u64;
+        let extension = if self.zstd { "tar.zst" } else { "tar.gz" };
+        let partition = crate::storage::archive_reader::gen_parent_path(path);
+        let batch_path = crate::storage::batch_tar_file::gen_batch_path(partition, batch_number, extension);
+        (batch_path, position_in_partition % batch_size as u64)
+    }
+
+    /// Look up location of melody with variation `variation` (if the dataset was generated
+    /// with variations `variations`) in dataset generated with `path_generator`
+    fn lookup(
+        &self,
+        notes: &libatm::MIDINoteVec,
+        melody: libatm::MIDINoteVec,
+        variation: &MelodyVariation,
+        variations: &VariationSet,
+        melody_length: u32,
+        path_generator: &PartitionPathGenerator,
+    ) -> Result<MelodyLocation, MelodyLookupError> {
+        if melody.len() as u32 != melody_length {
+            return Err(MelodyLookupError::LengthMismatch { length: melody.len(), expected: melody_length });
+        }
+        let variation_index = variations.gen_variation_index(variation, &melody)?;
+        let formatted_melody = crate::variation::format_annotated_melody(&melody, variation);
+        let hash = variation.gen_hash(&melody);
+        let mfile = libatm::MIDIFile::new(melody, libatm::MIDIFormat::Format0, 1, 1);
+        let pfile = PreparedMIDIFile { mfile, hash, data: Vec::new() };
+        let path = path_generator.gen_path_for_prepared(&pfile)?;
+        let batch = match self.batch_size {
+            Some(batch_size) => {
+                let pitch_index = crate::utils::gen_melody_index(notes, &pfile.mfile.sequence)?;
+                let position_in_partition = gen_position_in_partition(
+                    notes,
+                    pitch_index,
+                    &pfile.mfile.sequence,
+                    variation_index,
+                    variations,
+                    path_generator,
+                );
+                Some(self.gen_batch_location(position_in_partition, &path, batch_size))
+            },
+            None => None,
+        };
+        Ok(MelodyLocation {
+            melody: formatted_melody,
+            hash: pfile.hash,
+            path,
+            batch,
+        })
+    }
+
+    /// Look up location of every melody in input, and write one line per melody to stdout.
+    /// Path generator is created for the length of the first melody.
+    fn lookup_input(&self, input: &std::path::Path) {
+        let notes = libatm::MIDINoteVec::from(&self.note_set.note_set);
+        let mut path_generator: Option<(u32, PartitionPathGenerator)> = None;
+        let mut target = gen_stdout_writer();
+        for (line_number, melody) in read_melodies(input) {
+            let melody = match melody {
+                Ok(melody) => melody,
+                Err(err) => {
+                    eprintln!("::: WARNING: Skipping invalid melody on line {} ({})", line_number, err);
+                    continue;
+                },
+            };
+            let (melody_length, path_generator) = path_generator.get_or_insert_with(|| {
+                let melody_length = melody.len() as u32;
+                (melody_length, self.gen_path_generator(melody_length, 1))
+            });
+            let location = match self.lookup(
+                &notes,
+                melody,
+                &MelodyVariation::default(),
+                &VariationSet::default(),
+                *melody_length,
+                path_generator,
+            ) {
+                Ok(location) => location,
+                Err(err) => {
+                    eprintln!("::: WARNING: Skipping melody on line {} ({})", line_number, err);
+                    continue;
+                },
+            };
+            let result = if self.json {
+                location.write_json(&mut target)
+            } else {
+                location.write_tsv(&mut target)
+            };
+            if let Err(err) = result {
+                eprintln!("::: ERROR: Failed to write to stdout ({})", err);
+                std::process::exit(1);
+            }
+        }
+        if let Err(err) = target.flush() {
+            eprintln!("::: ERROR: Failed to write to stdout ({})", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+impl CliDirective for PartitionDirective {
+    fn run(self) {
+        let manifest_args = self.dataset.as_ref().map(|path| {
+            let manifest = read_dataset_manifest(path);
+            RenderManifestArgs::from_manifest(&manifest).unwrap_or_else(|err| {
+                eprintln!("::: ERROR: Failed to parse render manifest of dataset {:?} ({})", path, err);
+                std::process::exit(1);
+            })
+        });
+        let variation_args = match manifest_args {
+            Some(ref manifest_args) => &manifest_args.variation_args,
+            None => &self.variation_args,
+        };
+
+        if let Some(ref input) = self.input {
+            if !(variation_args.chord_types.is_empty()
+                && variation_args.durations.is_empty()
+                && variation_args.velocities.is_empty())
+            {
+                eprintln!("::: ERROR: Dataset was generated with chord types, durations or velocities, which --input doesn't support");
+                std::process::exit(1);
+            }
+            return self.lookup_input(input);
+        }
+
+        let crate::variation::AnnotatedMelody { melody, mut variation } = self.melody.clone().unwrap();
+        let notes = libatm::MIDINoteVec::from(&self.note_set.note_set);
+        let melody_length = melody.len() as u32;
+        let variations = variation_args.gen_variation_set(melody_length).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to generate chord type, rhythm or dynamics patterns ({})", err);
+            std::process::exit(1);
+        });
+        // Melodies without chords are looked up as chords of one note
+        if variations.chords.is_some() && variation.chord_types.is_none() {
+            variation.chord_types = Some(vec![crate::chord::ChordType::unison(); melody.len()]);
+        }
+        let path_generator = self.gen_path_generator(melody_length, variations.num_variations());
+        let location = self.lookup(&notes, melody, &variation, &variations, melody_length, &path_generator)
+            .unwrap_or_else(|err| {
+                eprintln!("::: ERROR: {}", err);
+                std::process::exit(1);
+            });
+        let mut target = gen_stdout_writer();
+        let result = if self.json {
+            location.write_json(&mut target)
+        } else {
+            writeln!(target, "{}", location.path).and_then(|_| match location.batch {
+                Some((batch_path, position)) => writeln!(target, "{}:{}", batch_path, position),
+                None => Ok(()),
+            })
+        };
+        if let Err(err) = result.and_then(|_| target.flush()) {
+            eprintln!("::: ERROR: Failed to write to stdout ({})", err);
+            std::process::exit(1);
+        }
     }
 }
@@ -0,0 +1,334 @@
+// verify.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::{
+    cli::CliDirective,
+    directives::gen::{resolve_melody_range, resolve_render_options},
+    storage::{
+        ArchiveEntry,
+        ArchiveReader,
+        PathGenerator,
+    },
+};
+
+/*****************************
+***** VerificationReport *****
+*****************************/
+
+/// Maximum number of melodies verified at once (the set of melodies found takes 4GiB)
+const MAX_VERIFY_MELODIES: u64 = 1 << 35;
+
+/// Set of melodies (by index) found in archive, stored as one bit per melody
+struct MelodyBitSet {
+    /// Index of first melody in set
+    start: u64,
+    words: Vec<u64>,
+}
+
+impl MelodyBitSet {
+    fn new(range: &std::ops::Range<u64>) -> Self {
+        let num_words = (range.end - range.start).div_ceil(64) as usize;
+        Self { start: range.start, words: vec![0; num_words] }
+    }
+
+    /// Add melody to set, and return whether it wasn't already present
+    fn insert(&mut self, index: u64) -> bool {
+        let offset = index - self.start;
+        let word = &mut self.words[(offset / 64) as usize];
+        let mask = 1u64 << (offset % 64);
+        let is_new = *word & mask == 0;
+        *word |= mask;
+        is_new
+    }
+
+    fn contains(&self, index: u64) -> bool {
+        let offset = index - self.start;
+        self.words[(offset / 64) as usize] & (1u64 << (offset % 64)) != 0
+    }
+}
+
+/// Problems found while verifying archive
+#[derive(Default)]
+struct VerificationReport {
+    has_manifest: bool,
+    num_entries: u64,
+    num_missing: u64,
+    num_duplicate: u64,
+    num_misplaced: u64,
+    num_corrupt: u64,
+    num_unexpected: u64,
+}
+
+impl VerificationReport {
+    fn is_ok(&self) -> bool {
+        self.num_missing + self.num_duplicate + self.num_misplaced + self.num_corrupt + self.num_unexpected == 0
+    }
+}
+
+/// Melodies an archive should contain, and the options they're rendered with
+struct ExpectedDataset {
+    notes: libatm::MIDINoteVec,
+    render: crate::midi::MIDIRenderOptions,
+    melody_range: std::ops::Range<u64>,
+}
+
+/**************************
+***** VerifyDirective *****
+**************************/
+
+/// Verify that an archive generated by one of the `gen` directives (Tar, Gzip- or
+/// Zstandard-compressed Tar, or batch) contains exactly the melodies generated from a note
+/// set, each stored once at the path given by the partitioning scheme. The melody of each
+/// entry is recovered from the hash in its filename and the notes its MIDI data plays, and
+/// checked against the MIDI file rendered with the same options (see: --division, --tempo,
+/// etc.) and variation (see: --durations, etc.). Reports
+/// missing, duplicate, misplaced (wrong path) and corrupt entries, as well as entries
+/// with melodies that aren't in the dataset (unexpected), and exits with status 1 if
+/// any were found. Exits before verifying any entries if the render manifest of the archive
+/// doesn't match the rendering options.
+///
+/// The archive is read in a single pass, and only one bit per melody is kept in memory, so
+/// archives of more than 2^35 melodies must be verified in shards (see: --shard).
+#[derive(structopt::StructOpt)]
+pub struct VerifyDirective {
+    /// Archive input path, or '-' to read from stdin.
+    #[structopt(parse(from_os_str))]
+    pub archive: std::path::PathBuf,
+    #[structopt(flatten)]
+    pub note_set: crate::cli::NoteSetArg,
+    #[structopt(flatten)]
+    pub melody_length: crate::cli::MelodyLengthArg,
+    #[structopt(flatten)]
+    pub partition_args: crate::cli::PartitionArgs,
+    #[structopt(flatten)]
+    pub shard_args: crate::cli::ShardArgs,
+    #[structopt(flatten)]
+    pub render_args: crate::cli::MIDIRenderArgs,
+    #[structopt(flatten)]
+    pub variation_args: crate::cli::VariationArgs,
+    /// Maximum number of problems of each kind to print (all are counted).
+    #[structopt(long, default_value="100")]
+    pub max_report: u64,
+}
+
+impl VerifyDirective {
+    /// Verify archive entries against melodies generated from `notes` and rendered with `render`,
+    /// using provided path generator. Melody range is aligned to `num_melodies_per_partition` melodies.
+    fn verify<G: PathGenerator>(
+        &self,
+        path_generator: G,
+        num_melodies_per_partition: u64,
+        notes: libatm::MIDINoteVec,
+        render: crate::midi::MIDIRenderOptions,
+    ) {
+        let melody_length = self.melody_length.melody_length;
+
+        // Resolve range of melodies archive should contain
+        let melody_range = resolve_melody_range(
+            &self.shard_args,
+            render.gen_num_melodies(notes.len() as u32, melody_length),
+            num_melodies_per_partition,
+        );
+        let num_melodies = melody_range.end - melody_range.start;
+        if num_melodies > MAX_VERIFY_MELODIES {
+            eprintln!(
+                "::: ERROR: Can't verify {} melodies at once (at most {}), verify the archive in shards instead (see: --shard)",
+                num_melodies,
+                MAX_VERIFY_MELODIES,
+            );
+            std::process::exit(1);
+        }
+        let mut found = MelodyBitSet::new(&melody_range);
+        let dataset = ExpectedDataset { notes, render, melody_range };
+        let mut report = VerificationReport::default();
+
+        let reader = ArchiveReader::open(&self.archive).unwrap_or_else(|err| {
+            eprintln!("::: ERROR: Failed to open archive {:?} ({})", &self.archive, err);
+            std::process::exit(1);
+        });
+        let mut pb = pbr::ProgressBar::on(std::io::stderr(), dataset.melody_range.end - dataset.melody_range.start);
+        pb.set_max_refresh_rate(Some(std::time::Duration::from_millis(500)));
+        let result = reader.for_each_entry(
+            |_| true,
+            |entry| {
+                self.verify_entry(&entry, &dataset, &path_generator, &mut found, &mut report);
+                if !entry.is_render_manifest() {
+                    pb.inc();
+                }
+                Ok(std::ops::ControlFlow::Continue(()))
+            },
+        );
+        pb.finish_println("");
+        if let Err(err) = result {
+            eprintln!("::: ERROR: Failed to read archive {:?} ({})", &self.archive, err);
+            std::process::exit(1);
+        }
+
+        // Report melodies that weren't found (skipping melodies that aren't generated with their variation)
+        let num_variations = dataset.render.num_variations();
+        for index in dataset.melody_range.clone().filter(|index| !found.contains(*index)) {
+            let melody = crate::utils::gen_melody_at(&dataset.notes, melody_length, index / num_variations).unwrap();
+            let variation = match dataset.render.variations.gen_variation_at(index % num_variations, &melody) {
+                Some(variation) => variation,
+                None => continue,
+            };
+            report.num_missing += 1;
+            if report.num_missing <= self.max_report {
+                println!(
+                    "::: WARNING: Missing melody {} (index {})",
+                    crate::variation::format_annotated_melody(&melody, &variation),
+                    index,
+                );
+            }
+        }
+
+        if !report.has_manifest {
+            eprintln!("::: WARNING: Archive has no render manifest, rendering options weren't checked");
+        }
+        println!(
+            "::: INFO: Verified {} entries ({} missing, {} duplicate, {} misplaced, {} corrupt, {} unexpected)",
+            report.num_entries,
+            report.num_missing,
+            report.num_duplicate,
+            report.num_misplaced,
+            report.num_corrupt,
+            report.num_unexpected,
+        );
+        if !report.is_ok() {
+            std::process::exit(1);
+        }
+    }
+
+    /// Verify single archive entry, and record it in set of found melodies
+    fn verify_entry<G: PathGenerator>(
+        &self,
+        entry: &ArchiveEntry,
+        dataset: &ExpectedDataset,
+        path_generator: &G,
+        found: &mut MelodyBitSet,
+        report: &mut VerificationReport,
+    ) {
+        // Ensure archive was rendered with the same options
+        if entry.is_render_manifest() {
+            let manifest = String::from_utf8_lossy(&entry.data);
+            if manifest != dataset.render.gen_manifest() {
+                eprintln!(
+                    "::: ERROR: Archive was rendered with options {} (see: its render manifest), not {}",
+                    manifest.trim_end().replace('\n', ","),
+                    dataset.render,
+                );
+                std::process::exit(1);
+            }
+            report.has_manifest = true;
+            return;
+        }
+        report.num_entries += 1;
+        let max_report = self.max_report;
+
+        // Recover melody from hash and ensure MIDI data is what would be generated for it
+        let (melody, variation_index) = match dataset.render.recover_melody(entry.hash(), &entry.data) {
+            Ok(recovered) => recovered,
+            // Variation isn't generated from the provided chord types, durations or velocities
+            Err(crate::midi::RecoverMelodyError::VariationIndex(err)) => {
+                report.num_unexpected += 1;
+                if report.num_unexpected <= max_report {
+                    println!("::: WARNING: Unexpected entry {} ({})", entry.path, err);
+                }
+                return;
+            },
+            Err(err) => {
+                report.num_corrupt += 1;
+                if report.num_corrupt <= max_report {
+                    println!("::: WARNING: Corrupt entry {} ({})", entry.path, err);
+                }
+                return;
+            },
+        };
+        // Recovered variation index is always generated for the melody
+        let variation = dataset.render.variations.gen_variation_at(variation_index, &melody).unwrap();
+        let melody_str = crate::variation::format_annotated_melody(&melody, &variation);
+        let pfile = dataset.render.prepare_variation(melody, variation_index).unwrap();
+        if pfile.hash != entry.hash() || pfile.data != entry.data {
+            report.num_corrupt += 1;
+            if report.num_corrupt <= max_report {
+                println!("::: WARNING: Corrupt entry {} (MIDI data isn't melody {})", entry.path, melody_str);
+            }
+            return;
+        }
+
+        // Ensure melody is in dataset
+        let index = crate::utils::gen_melody_index(&dataset.notes, &pfile.mfile.sequence)
+            .ok()
+            .filter(|_| pfile.mfile.sequence.len() as u32 == self.melody_length.melody_length)
+            .and_then(|pitch_index| pitch_index.checked_mul(dataset.render.num_variations()))
+            .map(|index| index + variation_index);
+        let index = match index {
+            Some(index) if dataset.melody_range.contains(&index) => index,
+            _ => {
+                report.num_unexpected += 1;
+                if report.num_unexpected <= max_report {
+                    println!("::: WARNING: Unexpected entry {} (melody {})", entry.path, melody_str);
+                }
+                return;
+            },
+        };
+        if !found.insert(index) {
+            report.num_duplicate += 1;
+            if report.num_duplicate <= max_report {
+                println!("::: WARNING: Duplicate entry {} (melody {})", entry.path, melody_str);
+            }
+        }
+        // Ensure melody is stored at expected path
+        match path_generator.gen_path_for_prepared(&pfile) {
+            Ok(ref path) if *path == entry.path => (),
+            Ok(path) => {
+                report.num_misplaced += 1;
+                if report.num_misplaced <= max_report {
+                    println!("::: WARNING: Misplaced entry {} (expected at {})", entry.path, path);
+                }
+            },
+            Err(err) => {
+                eprintln!("::: ERROR: Failed to generate path for melody {} ({:?})", melody_str, err);
+                std::process::exit(1);
+            },
+        }
+    }
+}
+
+impl CliDirective for VerifyDirective {
+    fn run(self) {
+        let notes = libatm::MIDINoteVec::from(self.note_set.note_set.clone());
+        let render = resolve_render_options(
+            self.render_args.clone(),
+            &self.variation_args,
+            &notes,
+            self.melody_length.melody_length,
+        );
+        match self.partition_args.partition_depth {
+            // Use partitioning scheme
+            Some(partition_depth) => {
+                // Create path generator
+                let path_generator = crate::storage::PartitionPathGenerator::new_with_variations(
+                    notes.len() as u32,
+                    self.melody_length.melody_length,
+                    render.num_variations(),
+                    self.partition_args.max_files,
+                    partition_depth,
+                ).unwrap_or_else(|err| {
+                    eprintln!("::: ERROR: Failed to initialize partitioning scheme ({:?})", err);
+                    std::process::exit(1);
+                });
+                // Melody range must be aligned to partitions
+                let num_melodies_per_partition = path_generator.gen_num_melodies_per_partition();
+                self.verify(path_generator, num_melodies_per_partition, notes, render);
+            },
+            // Don't use partitioning scheme
+            None => self.verify(crate::storage::MIDIHashPathGenerator, 1, notes, render),
+        }
+    }
+}
@@ -0,0 +1,420 @@
+// drums.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::rhythm::NoteDuration;
+
+/// Named General MIDI percussion instruments, and their note numbers
+pub const NAMED_INSTRUMENTS: [(&str, u8); 14] = [
+    ("kick", 36),
+    ("rim", 37),
+    ("snare", 38),
+    ("clap", 39),
+    ("hat", 42),
+    ("pedal-hat", 44),
+    ("low-tom", 45),
+    ("open-hat", 46),
+    ("mid-tom", 47),
+    ("crash", 49),
+    ("high-tom", 50),
+    ("ride", 51),
+    ("tambourine", 54),
+    ("cowbell", 56),
+];
+
+/// Lowest and highest note numbers of General MIDI percussion instruments
+const PERCUSSION_RANGE: std::ops::RangeInclusive<u8> = 35..=81;
+
+/// MIDI channel drum patterns are rendered on (channel 10, reserved for percussion)
+pub const DRUM_CHANNEL: u8 = 9;
+
+/// Maximum number of steps in drum patterns
+pub const MAX_STEPS: u32 = 32;
+
+/// Separator between the parts of a drum pattern, in hashes and track names
+pub const DRUM_PART_SEPARATOR: char = '_';
+
+/*********************************
+***** Percussion Instruments *****
+*********************************/
+
+/// Error type for parsing [DrumInstrument](struct.DrumInstrument.html) and
+/// [DrumPart](struct.DrumPart.html) from `&str`
+#[derive(Debug, thiserror::Error)]
+pub enum ParseDrumPartError {
+    #[error("Invalid percussion instrument {input} (expected name, i.e., 'kick', 'snare', 'hat', \
+            or General MIDI percussion note number 35-81)")]
+    InvalidInstrument { input: String },
+    #[error("Invalid maximum number of hits in {input} (expected INSTRUMENT:HITS, i.e., 'kick:4')")]
+    InvalidMaxHits { input: String },
+}
+
+/// General MIDI percussion instrument (i.e., 'kick' for Bass Drum 1, note number 36)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DrumInstrument {
+    /// Note number of instrument on the percussion channel [35-81]
+    pub note: u8,
+}
+
+impl DrumInstrument {
+    /// Generate note of instrument
+    pub fn gen_note(&self) -> libatm::MIDINote {
+        // Percussion note numbers are always valid notes
+        crate::midi::note_from_number(self.note).unwrap()
+    }
+}
+
+impl std::str::FromStr for DrumInstrument {
+    type Err = ParseDrumPartError;
+
+    /// Parse instrument from name (see: [NAMED_INSTRUMENTS](constant.NAMED_INSTRUMENTS.html))
+    /// or General MIDI percussion note number
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim();
+        if let Some((_, note)) = NAMED_INSTRUMENTS.iter().find(|(name, _)| *name == input) {
+            return Ok(Self { note: *note });
+        }
+        match input.parse::<u8>() {
+            Ok(note) if PERCUSSION_RANGE.contains(&note) => Ok(Self { note }),
+            _ => Err(ParseDrumPartError::InvalidInstrument { input: input.to_string() }),
+        }
+    }
+}
+
+impl std::fmt::Display for DrumInstrument {
+    /// Format instrument as its name, or note number if it has none
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match NAMED_INSTRUMENTS.iter().find(|(_, note)| *note == self.note) {
+            Some((name, _)) => write!(f, "{}", name),
+            None => write!(f, "{}", self.note),
+        }
+    }
+}
+
+/// Instrument of a drum pattern, with an optional limit on the number of steps it's hit on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DrumPart {
+    pub instrument: DrumInstrument,
+    /// Maximum number of steps instrument is hit on (any number if not set)
+    pub max_hits: Option<u32>,
+}
+
+impl std::str::FromStr for DrumPart {
+    type Err = ParseDrumPartError;
+
+    /// Parse part from instrument, optionally followed by maximum number of hits (i.e., 'kick:4')
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.trim().split_once(':') {
+            Some((instrument, max_hits)) => Ok(Self {
+                instrument: instrument.parse::<DrumInstrument>()?,
+                max_hits: Some(max_hits.parse::<u32>().map_err(|_| ParseDrumPartError::InvalidMaxHits {
+                    input: input.to_string(),
+                })?),
+            }),
+            None => Ok(Self { instrument: input.parse::<DrumInstrument>()?, max_hits: None }),
+        }
+    }
+}
+
+impl std::fmt::Display for DrumPart {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.max_hits {
+            Some(max_hits) => write!(f, "{}:{}", self.instrument, max_hits),
+            None => write!(f, "{}", self.instrument),
+        }
+    }
+}
+
+/*************************
+***** DrumPatternSet *****
+*************************/
+
+/// Error type for [DrumPatternSet](struct.DrumPatternSet.html)
+#[derive(Debug, thiserror::Error)]
+pub enum DrumPatternSetError {
+    #[error("Must provide at least one percussion instrument")]
+    Empty,
+    #[error("Percussion instrument {instrument} provided more than once")]
+    DuplicateInstrument { instrument: DrumInstrument },
+    #[error("Number of steps must be between 1 and {} (found {steps})", MAX_STEPS)]
+    InvalidSteps { steps: u32 },
+    #[error("Step duration {step} isn't a whole number of ticks with division {division} \
+            (see: --division)")]
+    InvalidStep { step: NoteDuration, division: u16 },
+    #[error("Number of drum patterns of {steps} steps overflows a 64-bit index")]
+    TooManyPatterns { steps: u32 },
+}
+
+/// Set of drum patterns: every combination of on/off steps over a grid of `steps` steps for
+/// every instrument (part), where every part is hit on at most its maximum number of steps.
+/// The steps of every part are generated in lexicographic order (off before on, starting
+/// from the first step), and parts are generated in the order provided (the first part
+/// changing slowest), so every pattern has a stable index.
+///
+/// Patterns are represented by one bitmask per part, where the first step is the highest
+/// of `steps` bits (i.e., `0b1000_1000_1000_1000` for a kick on every beat of 16 steps).
+#[derive(Clone, Debug)]
+pub struct DrumPatternSet {
+    /// Parts patterns are generated for (in generation order)
+    parts: Vec<DrumPart>,
+    /// Number of steps of patterns
+    steps: u32,
+    /// Duration of each step
+    step: NoteDuration,
+    /// Number of step patterns of `n` steps with at most `k` hits, at `counts[n][k]`
+    counts: Vec<Vec<u64>>,
+    /// Total number of patterns
+    num_patterns: u64,
+}
+
+impl DrumPatternSet {
+    /// Create new `DrumPatternSet` instance for patterns of `steps` steps of duration `step`
+    pub fn new(parts: &[DrumPart], steps: u32, step: NoteDuration) -> Result<Self, DrumPatternSetError> {
+        if parts.is_empty() {
+            return Err(DrumPatternSetError::Empty);
+        }
+        for (position, part) in parts.iter().enumerate() {
+            if parts[..position].iter().any(|other| other.instrument == part.instrument) {
+                return Err(DrumPatternSetError::DuplicateInstrument { instrument: part.instrument });
+            }
+        }
+        if steps == 0 || steps > MAX_STEPS {
+            return Err(DrumPatternSetError::InvalidSteps { steps });
+        }
+        // Pascal's rule: a pattern of n steps with at most k hits has either no hit on its
+        // first step, or a hit followed by a pattern of n - 1 steps with at most k - 1 hits
+        let mut counts = vec![vec![1u64; steps as usize + 1]];
+        for n in 1..=steps as usize {
+            let row = (0..=steps as usize)
+                .map(|k| counts[n - 1][k] + if k > 0 { counts[n - 1][k - 1] } else { 0 })
+                .collect();
+            counts.push(row);
+        }
+        let parts = parts.to_vec();
+        let num_patterns = parts
+            .iter()
+            .try_fold(1u64, |num_patterns, part| {
+                let max_hits = part.max_hits.unwrap_or(steps).min(steps);
+                num_patterns.checked_mul(counts[steps as usize][max_hits as usize])
+            })
+            .ok_or(DrumPatternSetError::TooManyPatterns { steps })?;
+        Ok(Self { parts, steps, step, counts, num_patterns })
+    }
+
+    /// Number of patterns in set
+    pub fn num_patterns(&self) -> u64 {
+        self.num_patterns
+    }
+
+    /// Parts of patterns (in generation order)
+    pub fn parts(&self) -> &[DrumPart] {
+        &self.parts
+    }
+
+    /// Check that steps can be rendered with `division` ticks per quarter note
+    pub fn check_division(&self, division: u16) -> Result<(), DrumPatternSetError> {
+        match self.step.gen_ticks(division) {
+            Some(_) => Ok(()),
+            None => Err(DrumPatternSetError::InvalidStep { step: self.step, division }),
+        }
+    }
+
+    /// Smallest multiple of `division` (ticks per quarter note) steps can be rendered with
+    /// (see: [check_division](#method.check_division)). Divisions with the top bit set are
+    /// in SMPTE frames, so aren't considered.
+    pub fn gen_division(&self, division: u16) -> Result<u16, DrumPatternSetError> {
+        (1..=0x7FFF / division)
+            .map(|multiple| multiple * division)
+            .find(|multiple| self.check_division(*multiple).is_ok())
+            .ok_or(DrumPatternSetError::InvalidStep { step: self.step, division })
+    }
+
+    /// Maximum number of hits of `part`
+    fn gen_max_hits(&self, part: &DrumPart) -> u32 {
+        part.max_hits.unwrap_or(self.steps).min(self.steps)
+    }
+
+    /// Number of step patterns of `part`
+    fn gen_num_step_patterns(&self, part: &DrumPart) -> u64 {
+        self.counts[self.steps as usize][self.gen_max_hits(part) as usize]
+    }
+
+    /// Generate the step pattern at index `index` of patterns with at most `max_hits` hits
+    fn gen_steps_at(&self, mut index: u64, max_hits: u32) -> u32 {
+        let mut mask = 0;
+        let mut hits_left = max_hits as usize;
+        for step in 0..self.steps {
+            let steps_left = (self.steps - step - 1) as usize;
+            // Patterns without a hit on this step come first
+            let num_without_hit = self.counts[steps_left][hits_left];
+            if index >= num_without_hit {
+                index -= num_without_hit;
+                mask |= 1 << steps_left;
+                hits_left -= 1;
+            }
+        }
+        mask
+    }
+
+    /// Generate the pattern at index `index` (one bitmask per part),
+    /// or `None` if out of range
+    pub fn gen_pattern_at(&self, index: u64) -> Option<Vec<u32>> {
+        if index >= self.num_patterns {
+            return None;
+        }
+        let mut pattern = Vec::with_capacity(self.parts.len());
+        let mut remainder = index;
+        for part in self.parts.iter().rev() {
+            let num_step_patterns = self.gen_num_step_patterns(part);
+            pattern.push(self.gen_steps_at(remainder % num_step_patterns, self.gen_max_hits(part)));
+            remainder /= num_step_patterns;
+        }
+        pattern.reverse();
+        Some(pattern)
+    }
+
+    /// Generate pattern where every part is hit on as many steps as it can be, starting from
+    /// the first step (the pattern with the most notes, used to estimate file sizes)
+    pub fn gen_densest_pattern(&self) -> Vec<u32> {
+        self.parts
+            .iter()
+            .map(|part| {
+                let max_hits = self.gen_max_hits(part);
+                (((1u64 << max_hits) - 1) << (self.steps - max_hits)) as u32
+            })
+            .collect()
+    }
+
+    /// Generate the notes of every step of `pattern`, where steps without hits are empty
+    pub fn gen_steps(&self, pattern: &[u32]) -> Vec<Vec<libatm::MIDINote>> {
+        (0..self.steps)
+            .map(|step| {
+                let bit = 1 << (self.steps - step - 1);
+                self.parts
+                    .iter()
+                    .zip(pattern.iter())
+                    .filter(|(_, mask)| *mask & bit != 0)
+                    .map(|(part, _)| part.instrument.gen_note())
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Generate hash of pattern: the steps of every part as `<instrument>-<hex>`, joined by `_`,
+    /// where the hexadecimal digits read like the grid, starting from the first step
+    /// (i.e., `kick-8888_snare-0808_hat-aaaa`).
+    pub fn gen_hash(&self, pattern: &[u32]) -> String {
+        let num_digits = self.steps.div_ceil(4);
+        // Pad steps to whole hexadecimal digits
+        let shift = num_digits * 4 - self.steps;
+        self.parts
+            .iter()
+            .zip(pattern.iter())
+            .map(|(part, mask)| {
+                format!("{}-{:0width$x}", part.instrument, (*mask as u64) << shift, width = num_digits as usize)
+            })
+            .collect::<Vec<String>>()
+            .join(&DRUM_PART_SEPARATOR.to_string())
+    }
+
+    /// Render `pattern` as MIDI file with `render`, with track name
+    /// `hash` and melody index `index` in meta events (if written). The sequence of the MIDI
+    /// file is the lowest note of every step (or a rest).
+    fn prepare_pattern(
+        &self,
+        render: &crate::midi::MIDIRenderOptions,
+        pattern: &[u32],
+        hash: String,
+        index: u64,
+    ) -> crate::storage::PreparedMIDIFile {
+        let steps = self.gen_steps(pattern);
+        let variation = crate::variation::MelodyVariation {
+            rhythm: Some(vec![self.step; self.steps as usize]),
+            ..Default::default()
+        };
+        let data = render.render_chords(steps.iter().map(Vec::as_slice), &variation, &hash, Some(index));
+        let mfile = render.gen_mfile(
+            steps
+                .iter()
+                .map(|notes| {
+                    notes
+                        .iter()
+                        .min_by_key(|note| note.convert())
+                        .copied()
+                        .unwrap_or_else(|| libatm::MIDINote::new(libatm::MIDINoteType::Rest, 0))
+                })
+                .collect(),
+        );
+        crate::storage::PreparedMIDIFile { mfile, hash, data }
+    }
+
+    /// Render pattern at index `index` as MIDI file with `render`, and generate its hash (see:
+    /// [gen_hash](#method.gen_hash)). Patterns should be rendered on the percussion channel
+    /// (see: [DRUM_CHANNEL](constant.DRUM_CHANNEL.html)), and steps must be renderable with
+    /// the division of `render` (see: [check_division](#method.check_division)).
+    pub fn prepare(&self, render: &crate::midi::MIDIRenderOptions, index: u64) -> Option<crate::storage::PreparedMIDIFile> {
+        let pattern = self.gen_pattern_at(index)?;
+        let hash = self.gen_hash(&pattern);
+        Some(self.prepare_pattern(render, &pattern, hash, index))
+    }
+
+    /// Size of the largest MIDI file rendered with `render`, estimated by rendering the
+    /// densest pattern (see: [gen_densest_pattern](#method.gen_densest_pattern))
+    pub fn gen_max_file_size(&self, render: &crate::midi::MIDIRenderOptions) -> u64 {
+        let pattern = self.gen_densest_pattern();
+        let hash = self.gen_hash(&pattern);
+        self.prepare_pattern(render, &pattern, hash, self.num_patterns.saturating_sub(1)).data.len() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gen_parts(parts: &[&str]) -> Vec<DrumPart> {
+        parts.iter().map(|part| part.parse::<DrumPart>().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_drum_part_round_trip() {
+        for input in ["kick", "snare:2", "open-hat", "80:3"] {
+            assert_eq!(input, input.parse::<DrumPart>().unwrap().to_string());
+        }
+        assert_eq!("hat", "42".parse::<DrumPart>().unwrap().to_string());
+        assert!("34".parse::<DrumPart>().is_err());
+        assert!("kick:x".parse::<DrumPart>().is_err());
+    }
+
+    #[test]
+    fn test_drum_patterns() {
+        let step = "1/16".parse::<NoteDuration>().unwrap();
+        let patterns = DrumPatternSet::new(&gen_parts(&["kick", "snare"]), 4, step).unwrap();
+        assert_eq!(256, patterns.num_patterns());
+        // Snare changes fastest
+        assert_eq!(Some(vec![0b0000, 0b0001]), patterns.gen_pattern_at(1));
+        assert_eq!(Some(vec![0b0001, 0b0000]), patterns.gen_pattern_at(16));
+        assert_eq!(None, patterns.gen_pattern_at(256));
+        assert_eq!("kick-8_snare-2", patterns.gen_hash(&[0b1000, 0b0010]));
+
+        // Patterns with at most 1 hit: none, then the hit moving from the last to the first step
+        let patterns = DrumPatternSet::new(&gen_parts(&["kick:1"]), 4, step).unwrap();
+        assert_eq!(5, patterns.num_patterns());
+        let masks = (0..5).map(|index| patterns.gen_pattern_at(index).unwrap()[0]).collect::<Vec<u32>>();
+        assert_eq!(vec![0b0000, 0b0001, 0b0010, 0b0100, 0b1000], masks);
+        assert_eq!(vec![0b1000], patterns.gen_densest_pattern());
+
+        // Steps are padded to whole hexadecimal digits
+        let patterns = DrumPatternSet::new(&gen_parts(&["hat:2"]), 6, step).unwrap();
+        assert_eq!(1 + 6 + 15, patterns.num_patterns());
+        assert_eq!("hat-84", patterns.gen_hash(&[0b100001]));
+        assert!(patterns.check_division(1).is_err());
+        assert!(patterns.check_division(4).is_ok());
+        assert_eq!(4, patterns.gen_division(1).unwrap());
+        assert_eq!(12, patterns.gen_division(3).unwrap());
+        assert_eq!(480, patterns.gen_division(480).unwrap());
+    }
+}
@@ -0,0 +1,223 @@
+// dynamics.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::pattern::PatternSet;
+
+/// Dynamic markings and the velocities they're written with
+const DYNAMIC_MARKINGS: [(&str, u8); 8] = [
+    ("ppp", 16),
+    ("pp", 33),
+    ("p", 49),
+    ("mp", 64),
+    ("mf", 80),
+    ("f", 96),
+    ("ff", 112),
+    ("fff", 127),
+];
+
+/*********************
+***** Velocities *****
+*********************/
+
+/// Error type for [parse_velocity](fn.parse_velocity.html)
+#[derive(Debug, thiserror::Error)]
+pub enum ParseVelocityError {
+    #[error("Invalid velocity {input} (expected 1-127 or dynamic marking ppp-fff, i.e., 'mf')")]
+    InvalidFormat { input: String },
+}
+
+/// Parse velocity from number [1-127] or dynamic marking (`ppp`, `pp`, `p`, `mp`, `mf`,
+/// `f`, `ff` or `fff`).
+///
+/// # Examples
+///
+/// ```rust
+/// assert_eq!(80, atm::dynamics::parse_velocity("mf").unwrap());
+/// assert_eq!(100, atm::dynamics::parse_velocity("100").unwrap());
+/// assert!(atm::dynamics::parse_velocity("0").is_err());
+/// ```
+pub fn parse_velocity(input: &str) -> Result<u8, ParseVelocityError> {
+    let input = input.trim();
+    if let Some((_, velocity)) = DYNAMIC_MARKINGS.iter().find(|(marking, _)| *marking == input) {
+        return Ok(*velocity);
+    }
+    match input.parse::<u8>() {
+        Ok(velocity) if (1..=127).contains(&velocity) => Ok(velocity),
+        _ => Err(ParseVelocityError::InvalidFormat { input: input.to_string() }),
+    }
+}
+
+/// Format velocities as comma-separated numbers (i.e., '33,80,112')
+pub fn format_dynamics(velocities: &[u8]) -> String {
+    velocities.iter().map(|velocity| velocity.to_string()).collect::<Vec<String>>().join(",")
+}
+
+/// Generate hash of dynamics pattern, appended to the melody hash in the filename of MIDI
+/// files generated with dynamics: `v` followed by velocities (of sounding notes) joined by
+/// `.` (i.e., `v33.80.112`)
+pub fn gen_dynamics_hash(velocities: &[u8]) -> String {
+    format!(
+        "v{}",
+        velocities.iter().map(|velocity| velocity.to_string()).collect::<Vec<String>>().join("."),
+    )
+}
+
+/// Parse velocities from dynamics hash (see: [gen_dynamics_hash](fn.gen_dynamics_hash.html)),
+/// or `None` if it isn't a dynamics hash
+///
+/// # Examples
+///
+/// ```rust
+/// assert_eq!(Some(vec![33, 80, 112]), atm::dynamics::parse_dynamics_hash("v33.80.112"));
+/// assert_eq!(Some(vec![]), atm::dynamics::parse_dynamics_hash("v"));
+/// assert_eq!(None, atm::dynamics::parse_dynamics_hash("1-8.1-4"));
+/// ```
+pub fn parse_dynamics_hash(hash: &str) -> Option<Vec<u8>> {
+    match hash.strip_prefix('v')? {
+        "" => Some(Vec::new()),
+        velocities => velocities.split('.').map(|velocity| velocity.parse::<u8>().ok()).collect(),
+    }
+}
+
+/***********************
+***** Dynamics Set *****
+***********************/
+
+/// Error type for [DynamicsSet](struct.DynamicsSet.html)
+#[derive(Debug, thiserror::Error)]
+pub enum DynamicsSetError {
+    #[error("Must provide at least one velocity")]
+    Empty,
+    #[error("Number of dynamics patterns of {length} notes overflows a 64-bit index")]
+    TooManyPatterns { length: u32 },
+}
+
+/// Set of dynamics patterns melodies are generated with: every sequence of `length`
+/// velocities from a set of velocities. Patterns are generated in lexicographic order
+/// from the softest velocity (see: [PatternSet](../pattern/struct.PatternSet.html)).
+///
+/// Rests don't have a velocity, so a melody is only generated with the patterns that have
+/// the softest velocity at each of its rests, and the velocity of each rest is dropped from
+/// them (see: [gen_velocities_at](#method.gen_velocities_at)). A melody with `k` sounding
+/// notes is thus generated with `velocities^k` patterns, and the indices of the other
+/// patterns are skipped.
+///
+/// # Examples
+///
+/// ```rust
+/// let dynamics = atm::dynamics::DynamicsSet::new(&[112, 33], 3).unwrap();
+/// assert_eq!(8, dynamics.patterns().num_patterns());
+/// assert_eq!(Some(vec![33, 112, 112]), dynamics.patterns().gen_pattern_at(3));
+/// let melody = atm::utils::parse_note_vec("C:4,R,G:4").unwrap();
+/// assert_eq!(4, dynamics.gen_num_patterns(&melody));
+/// assert_eq!(Some(vec![33, 112]), dynamics.gen_velocities_at(1, &melody));
+/// assert_eq!(None, dynamics.gen_velocities_at(3, &melody));
+/// assert_eq!(Some(1), dynamics.gen_velocities_index(&[33, 112], &melody));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct DynamicsSet {
+    /// Dynamics patterns
+    patterns: PatternSet<u8>,
+}
+
+impl DynamicsSet {
+    /// Create new `DynamicsSet` instance for patterns of `length` notes.
+    /// Velocities are sorted, and duplicates removed.
+    pub fn new(velocities: &[u8], length: u32) -> Result<Self, DynamicsSetError> {
+        let mut velocities = velocities.to_vec();
+        velocities.sort_unstable();
+        velocities.dedup();
+        if velocities.is_empty() {
+            return Err(DynamicsSetError::Empty);
+        }
+        let patterns = PatternSet::new(velocities, length).ok_or(DynamicsSetError::TooManyPatterns { length })?;
+        Ok(Self { patterns })
+    }
+
+    /// Dynamics patterns in set
+    pub fn patterns(&self) -> &PatternSet<u8> {
+        &self.patterns
+    }
+
+    /// Velocities patterns are generated from (in generation order)
+    pub fn velocities(&self) -> &[u8] {
+        self.patterns.symbols()
+    }
+
+    /// Number of dynamics patterns melody `melody` is generated with (one per sequence
+    /// of velocities of its sounding notes)
+    pub fn gen_num_patterns(&self, melody: &[libatm::MIDINote]) -> u64 {
+        (self.velocities().len() as u64).pow(gen_num_sounding(melody))
+    }
+
+    /// Generate the velocities of the sounding notes of melody `melody` with the pattern at
+    /// index `index`, or `None` if out of range, or if melody isn't generated with the pattern
+    /// (because it doesn't have the softest velocity at every rest of the melody).
+    /// Inverse of [gen_velocities_index](#method.gen_velocities_index).
+    pub fn gen_velocities_at(&self, index: u64, melody: &[libatm::MIDINote]) -> Option<Vec<u8>> {
+        let pattern = self.patterns.gen_pattern_at(index)?;
+        if pattern.len() != melody.len() {
+            return None;
+        }
+        let softest = self.velocities()[0];
+        let mut velocities = Vec::with_capacity(pattern.len());
+        for (note, velocity) in melody.iter().zip(pattern) {
+            match note.note_type {
+                libatm::MIDINoteType::Rest if velocity != softest => return None,
+                libatm::MIDINoteType::Rest => (),
+                _ => velocities.push(velocity),
+            }
+        }
+        Some(velocities)
+    }
+
+    /// Calculate the index of the pattern melody `melody` is generated with for velocities
+    /// `velocities` of its sounding notes, or `None` if the velocities aren't generated from
+    /// the set. Inverse of [gen_velocities_at](#method.gen_velocities_at).
+    pub fn gen_velocities_index(&self, velocities: &[u8], melody: &[libatm::MIDINote]) -> Option<u64> {
+        if velocities.len() as u32 != gen_num_sounding(melody) {
+            return None;
+        }
+        let mut velocities = velocities.iter();
+        let pattern = melody
+            .iter()
+            .map(|note| match note.note_type {
+                libatm::MIDINoteType::Rest => self.velocities()[0],
+                // Number of velocities checked above
+                _ => *velocities.next().unwrap(),
+            })
+            .collect::<Vec<u8>>();
+        self.patterns.gen_pattern_index(&pattern)
+    }
+
+    /// Calculate the number of dynamics patterns melody `melody` is generated with before
+    /// the pattern at index `index` (which it must be generated with)
+    pub(crate) fn gen_num_patterns_before(&self, index: u64, melody: &[libatm::MIDINote]) -> u64 {
+        // Patterns a melody is generated with are ordered by the velocities of its sounding notes
+        let velocities = self.gen_velocities_at(index, melody).unwrap_or_default();
+        PatternSet::new(self.velocities().to_vec(), velocities.len() as u32)
+            .and_then(|patterns| patterns.gen_pattern_index(&velocities))
+            .unwrap_or(0)
+    }
+}
+
+/// Number of sounding notes (that aren't rests) of melody
+fn gen_num_sounding(melody: &[libatm::MIDINote]) -> u32 {
+    melody.iter().filter(|note| note.note_type != libatm::MIDINoteType::Rest).count() as u32
+}
+
+impl std::fmt::Display for DynamicsSet {
+    /// Format dynamics set as `velocities=<velocities>` (separated by `;`)
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "velocities={}",
+            self.velocities().iter().map(|v| v.to_string()).collect::<Vec<String>>().join(";"),
+        )
+    }
+}
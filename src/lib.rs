@@ -14,11 +14,29 @@
 //! [the `libatm` project](https://github.com/allthemusicllc/libatm), on which this tool relies. For
 //! more information on All the Music, check out [allthemusic.info](http://allthemusic.info).
 
+/// Chords (notes played together) melodies can be generated with
+pub mod chord;
 #[doc(hidden)]
 pub mod cli;
+/// Pairs of voices (melody and bass line) that can be generated
+pub mod counterpoint;
 #[doc(hidden)]
 pub mod directives;
+/// Drum patterns (on/off steps of percussion instruments) that can be generated
+pub mod drums;
+/// Dynamics (velocity patterns) melodies can be generated with
+pub mod dynamics;
+/// Rendering and decoding MIDI files
+pub mod midi;
+/// Enumeration of fixed-length patterns (chord types, rhythms, dynamics and progressions)
+pub mod pattern;
+/// Chord progressions (Roman numerals in a key) that can be generated
+pub mod progression;
+/// Rhythm patterns melodies can be generated with
+pub mod rhythm;
 /// MIDI file storage backends
 pub mod storage;
 /// Utilities for generating melodies
 pub mod utils;
+/// Combinations of chord type, rhythm and dynamics patterns melodies are generated with
+pub mod variation;
@@ -0,0 +1,967 @@
+// midi.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+/// Note number libatm writes for [rests](../../libatm/midi_note/enum.MIDINoteType.html#variant.Rest)
+/// (`u32::MAX` truncated to a byte)
+const REST_NOTE_NUMBER: u8 = 0xFF;
+
+/// Text of Copyright Notice meta event (see: [MIDIMetaEvents](struct.MIDIMetaEvents.html))
+pub const COPYRIGHT_NOTICE: &str =
+    "Dedicated to the public domain under CC0 1.0 (https://creativecommons.org/publicdomain/zero/1.0/)";
+
+/// Note types in order of MIDI note number (modulo 12)
+const NOTE_TYPES: [libatm::MIDINoteType; 12] = [
+    libatm::MIDINoteType::C,
+    libatm::MIDINoteType::CSharp,
+    libatm::MIDINoteType::D,
+    libatm::MIDINoteType::DSharp,
+    libatm::MIDINoteType::E,
+    libatm::MIDINoteType::F,
+    libatm::MIDINoteType::FSharp,
+    libatm::MIDINoteType::G,
+    libatm::MIDINoteType::GSharp,
+    libatm::MIDINoteType::A,
+    libatm::MIDINoteType::ASharp,
+    libatm::MIDINoteType::B,
+];
+
+/*************************
+***** MIDI Rendering *****
+*************************/
+
+/// Options for rendering melodies as MIDI files. Melodies are always written to a single
+/// track (and pairs of voices to one track per voice, see:
+/// [CounterpointSet](../counterpoint/struct.CounterpointSet.html)), with every note played
+/// for the same duration at the same velocity (unless rendered with rhythm or dynamics
+/// patterns). The default options produce the same files as
+/// [libatm::MIDIFile](../../libatm/midi_file/struct.MIDIFile.html) (format 0, 1 tick per
+/// quarter note, velocity 100 on channel 1, without tempo or program change events).
+///
+/// NOTE: File hashes (and thus paths) only depend on the melody, so the same options must
+/// be used to reproduce the files of a dataset (see: [PackHeader](../storage/pack_file/struct.PackHeader.html)).
+#[derive(Clone, Debug, PartialEq)]
+pub struct MIDIRenderOptions {
+    /// MIDI file format
+    pub format: libatm::MIDIFormat,
+    /// Number of ticks per quarter note
+    pub division: u16,
+    /// Tempo in beats (quarter notes) per minute, written as a Set Tempo meta event
+    /// (players default to 120 BPM if not set)
+    pub tempo: Option<u32>,
+    /// Duration of each note in ticks (one quarter note if not set)
+    pub duration: Option<u32>,
+    /// Velocity of each note [1-127]
+    pub velocity: u8,
+    /// MIDI channel [0-15]
+    pub channel: u8,
+    /// Program (instrument) [0-127], written as a Program Change event
+    pub program: Option<u8>,
+    /// Meta events identifying the dataset and melody, written at the start of the track
+    pub meta: Option<MIDIMetaEvents>,
+    /// Rhythm and dynamics patterns melodies are generated with. Each melody is rendered once
+    /// per variation, with the durations and velocities of its patterns instead of `duration`
+    /// and `velocity`.
+    pub variations: crate::variation::VariationSet,
+}
+
+/// Meta events written at the start of every MIDI file of a dataset, to dedicate the melody
+/// to the public domain and identify where it came from:
+///
+/// * Sequence/Track Name (`0x03`): melody as comma-separated NOTE:OCTAVE pairs
+/// * Copyright Notice (`0x02`): [COPYRIGHT_NOTICE](constant.COPYRIGHT_NOTICE.html)
+/// * Text (`0x01`): dataset ID and index of melody (i.e., `dataset=C4_D4_E4-8 index=42`)
+#[derive(Clone, Debug, PartialEq)]
+pub struct MIDIMetaEvents {
+    /// Dataset identifier
+    pub dataset_id: String,
+    /// Notes melodies are generated from (in generation order), to calculate melody index
+    pub notes: libatm::MIDINoteVec,
+}
+
+/// Error type for [recover_annotated_melody](fn.recover_annotated_melody.html) and
+/// [MIDIRenderOptions::recover_melody](struct.MIDIRenderOptions.html#method.recover_melody)
+#[derive(Debug, thiserror::Error)]
+pub enum RecoverMelodyError {
+    #[error(transparent)]
+    Decode(#[from] DecodeMIDIError),
+    #[error("Hash {hash} isn't the hash of a melody of the notes in the MIDI file")]
+    InvalidHash { hash: String },
+    #[error(transparent)]
+    VariationIndex(#[from] crate::variation::VariationIndexError),
+}
+
+impl Default for MIDIRenderOptions {
+    fn default() -> Self {
+        Self {
+            format: libatm::MIDIFormat::Format0,
+            division: 1,
+            tempo: None,
+            duration: None,
+            velocity: 0x64,
+            channel: 0,
+            program: None,
+            meta: None,
+            variations: crate::variation::VariationSet::default(),
+        }
+    }
+}
+
+impl std::fmt::Display for MIDIRenderOptions {
+    /// Format options as comma-separated `<option>=<value>` pairs (see:
+    /// [gen_option_pairs](#method.gen_option_pairs))
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let pairs = self
+            .gen_option_pairs()
+            .into_iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>();
+        write!(f, "{}", pairs.join(","))
+    }
+}
+
+impl MIDIRenderOptions {
+    /// Generate options as `(<option>, <value>)` pairs, with channel and program numbered
+    /// from 1 (as on the command line), `-` for options that aren't set, and lists of chord
+    /// types, durations or velocities separated by `;`. The dataset ID, and chord type,
+    /// rhythm and dynamics patterns are only included if set.
+    fn gen_option_pairs(&self) -> Vec<(&'static str, String)> {
+        let format_option = |value: Option<u32>| match value {
+            Some(value) => value.to_string(),
+            None => "-".to_string(),
+        };
+        let format_list = |values: Vec<String>| values.join(";");
+        let mut pairs = vec![
+            ("format", (self.format as u16).to_string()),
+            ("division", self.division.to_string()),
+            ("tempo", format_option(self.tempo)),
+            ("duration", format_option(self.duration)),
+            ("velocity", self.velocity.to_string()),
+            ("channel", (self.channel + 1).to_string()),
+            ("program", format_option(self.program.map(|program| program as u32 + 1))),
+        ];
+        if let Some(ref meta) = self.meta {
+            pairs.push(("dataset", meta.dataset_id.clone()));
+        }
+        if let Some(ref chords) = self.variations.chords {
+            pairs.push(("chords", format_list(chords.chord_types().iter().map(|t| t.to_string()).collect())));
+        }
+        if let Some(ref rhythms) = self.variations.rhythms {
+            pairs.push(("durations", format_list(rhythms.durations().iter().map(|d| d.to_string()).collect())));
+            if let Some(bars) = rhythms.bars() {
+                pairs.push(("bars", bars.to_string()));
+            }
+        }
+        if let Some(ref dynamics) = self.variations.dynamics {
+            pairs.push(("velocities", format_list(dynamics.velocities().iter().map(|v| v.to_string()).collect())));
+        }
+        pairs
+    }
+
+    /// Generate render manifest recording the options in a dataset (see:
+    /// [RENDER_MANIFEST_PATH](../storage/constant.RENDER_MANIFEST_PATH.html)): one
+    /// `<option>=<value>` pair per line, as formatted by [Display](#impl-Display), so
+    /// the dataset ID can't be confused with the options that follow it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// let render = atm::midi::MIDIRenderOptions { division: 480, ..Default::default() };
+    /// assert_eq!(
+    ///     "format=0\ndivision=480\ntempo=-\nduration=-\nvelocity=100\nchannel=1\nprogram=-\n",
+    ///     render.gen_manifest(),
+    /// );
+    /// ```
+    pub fn gen_manifest(&self) -> String {
+        self.gen_option_pairs()
+            .into_iter()
+            .map(|(name, value)| format!("{}={}\n", name, value))
+            .collect()
+    }
+
+    /// Duration of each note in ticks
+    pub fn note_duration(&self) -> u32 {
+        self.duration.unwrap_or(self.division as u32)
+    }
+
+    /// Number of variations each melody is rendered with (1 without chord types, rhythms or dynamics)
+    pub fn num_variations(&self) -> u64 {
+        self.variations.num_variations()
+    }
+
+    /// Number of melody indices for melodies of length `melody_length` generated from
+    /// `num_notes` notes (one per melody and variation), saturating at `u64::MAX`. Melodies
+    /// with rests aren't generated with every dynamics pattern, so there may be fewer files
+    /// (see: [gen_num_files](#method.gen_num_files)).
+    pub fn gen_num_melodies(&self, num_notes: u32, melody_length: u32) -> u64 {
+        (num_notes as u64)
+            .checked_pow(melody_length)
+            .unwrap_or(u64::MAX)
+            .saturating_mul(self.num_variations())
+    }
+
+    /// Number of MIDI files rendered for melodies of length `melody_length` generated from
+    /// `notes`, saturating at `u64::MAX`. With dynamics, each sounding note of a melody is
+    /// played at every velocity while rests aren't, so every position of a melody has
+    /// `velocities` choices per sounding note and one per rest.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// let notes = atm::utils::parse_note_vec("C:4,R,G:4").unwrap();
+    /// let variations = atm::variation::VariationSet::new(
+    ///     None,
+    ///     None,
+    ///     Some(atm::dynamics::DynamicsSet::new(&[33, 80], 2).unwrap()),
+    /// ).unwrap();
+    /// let render = atm::midi::MIDIRenderOptions { variations, ..Default::default() };
+    /// // (2 sounding notes * 2 velocities + 1 rest)^2
+    /// assert_eq!(25, render.gen_num_files(&notes, 2));
+    /// assert_eq!(36, render.gen_num_melodies(3, 2));
+    /// ```
+    pub fn gen_num_files(&self, notes: &[libatm::MIDINote], melody_length: u32) -> u64 {
+        let dynamics = match self.variations.dynamics {
+            Some(ref dynamics) => dynamics,
+            None => return self.gen_num_melodies(notes.len() as u32, melody_length),
+        };
+        let num_rests = notes.iter().filter(|note| note.note_type == libatm::MIDINoteType::Rest).count() as u64;
+        let num_choices = (notes.len() as u64 - num_rests)
+            .saturating_mul(dynamics.velocities().len() as u64)
+            .saturating_add(num_rests);
+        num_choices
+            .checked_pow(melody_length)
+            .unwrap_or(u64::MAX)
+            .saturating_mul(self.num_variations() / dynamics.patterns().num_patterns())
+    }
+
+    /// Create `libatm::MIDIFile` for melody with format and division of options
+    pub fn gen_mfile(&self, melody: libatm::MIDINoteVec) -> libatm::MIDIFile {
+        libatm::MIDIFile::new(melody, self.format, 1, self.division)
+    }
+
+    /// Render melody as MIDI file
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// let melody = "C:4,DSharp:4,G:4".parse::<libatm::MIDINoteVec>().unwrap();
+    /// let mfile = libatm::MIDIFile::new(melody.clone(), libatm::MIDIFormat::Format0, 1, 1);
+    /// let options = atm::midi::MIDIRenderOptions::default();
+    /// assert_eq!(mfile.gen_file().unwrap(), options.gen_file(&melody));
+    /// ```
+    pub fn gen_file(&self, melody: &[libatm::MIDINote]) -> Vec<u8> {
+        // Melodies that aren't in the dataset are written without index
+        let index = self.meta
+            .as_ref()
+            .and_then(|meta| crate::utils::gen_melody_index(&meta.notes, melody).ok());
+        self.render(melody, &crate::variation::MelodyVariation::default(), index)
+    }
+
+    /// Size in bytes of the largest MIDI file rendered for melodies of length `melody_length`
+    /// generated from `num_notes` notes. Exact unless meta events are written or melodies
+    /// are rendered with variations, in which case the longest possible melody name, index,
+    /// chords, durations and velocities are assumed.
+    pub fn gen_max_file_size(&self, num_notes: u32, melody_length: u32) -> u64 {
+        // Chord with the most notes, over the highest root it can be played over
+        let chord_type = self.variations.chords.as_ref().map(|chords| {
+            chords.chord_types().iter().max_by_key(|chord_type| chord_type.intervals.len()).unwrap().clone()
+        });
+        let root = match chord_type {
+            Some(ref chord_type) => note_from_number(127 - chord_type.intervals[chord_type.intervals.len() - 1]).unwrap(),
+            None => libatm::MIDINote::new(libatm::MIDINoteType::CSharp, 9),
+        };
+        let melody = vec![root; melody_length as usize];
+        let variation = crate::variation::MelodyVariation {
+            chord_types: chord_type.map(|chord_type| vec![chord_type; melody_length as usize]),
+            rhythm: self.variations.rhythms.as_ref().map(|rhythms| {
+                vec![rhythms.durations()[rhythms.durations().len() - 1]; melody_length as usize]
+            }),
+            velocities: self.variations.dynamics.as_ref().map(|dynamics| {
+                vec![dynamics.velocities()[dynamics.velocities().len() - 1]; melody_length as usize]
+            }),
+        };
+        let index = self.gen_num_melodies(num_notes, melody_length).saturating_sub(1);
+        self.render(&melody, &variation, Some(index)).len() as u64
+    }
+
+    /// Render melody as MIDI file with chords, durations and velocities of `variation`
+    /// (if provided), and melody index `index` in meta events (if written)
+    pub(crate) fn render(
+        &self,
+        melody: &[libatm::MIDINote],
+        variation: &crate::variation::MelodyVariation,
+        index: Option<u64>,
+    ) -> Vec<u8> {
+        // Track name is only written with meta events
+        let name = match self.meta {
+            Some(_) => crate::variation::format_annotated_melody(melody, variation),
+            None => String::new(),
+        };
+        match variation.chord_types {
+            Some(_) => {
+                let chords = variation.gen_chords(melody);
+                self.render_chords(chords.iter().map(Vec::as_slice), variation, &name, index)
+            },
+            // Every note is a chord of one note (and rests are empty chords)
+            None => self.render_chords(gen_note_chords(melody), variation, &name, index),
+        }
+    }
+
+    /// Render chords as MIDI file (where empty chords are rests), with durations and velocities
+    /// of `variation` (if provided), and track name `name` and melody index `index` in meta
+    /// events (if written)
+    pub(crate) fn render_chords<'a, I>(
+        &self,
+        chords: I,
+        variation: &crate::variation::MelodyVariation,
+        name: &str,
+        index: Option<u64>,
+    ) -> Vec<u8>
+    where
+        I: Iterator<Item = &'a [libatm::MIDINote]> + Clone,
+    {
+        let mut track = Vec::with_capacity(chords.clone().map(|chord| chord.len().max(1) * 6).sum::<usize>() + 16);
+        self.write_first_track_events(&mut track, name, index);
+        self.write_chords(&mut track, chords, variation);
+        gen_file_data(self.format, self.division, &[track])
+    }
+
+    /// Render voices as MIDI file with one track per voice (where rests are gaps), in format 1
+    /// regardless of the format of options. Track name `name` and index `index` are written in
+    /// meta events of the first track (if written), along with the tempo.
+    pub(crate) fn render_voices(&self, voices: &[&[libatm::MIDINote]], name: &str, index: Option<u64>) -> Vec<u8> {
+        let tracks = voices
+            .iter()
+            .enumerate()
+            .map(|(position, voice)| {
+                let mut track = Vec::with_capacity(voice.len() * 6 + 16);
+                if position == 0 {
+                    self.write_first_track_events(&mut track, name, index);
+                }
+                self.write_chords(&mut track, gen_note_chords(voice), &crate::variation::MelodyVariation::default());
+                track
+            })
+            .collect::<Vec<Vec<u8>>>();
+        gen_file_data(libatm::MIDIFormat::Format1, self.division, &tracks)
+    }
+
+    /// Write meta events with track name `name` and index `index` (if written), and tempo
+    /// (if set) to the start of track
+    fn write_first_track_events(&self, track: &mut Vec<u8>, name: &str, index: Option<u64>) {
+        if let Some(ref meta) = self.meta {
+            let text = match index {
+                Some(index) => format!("dataset={} index={}", meta.dataset_id, index),
+                None => format!("dataset={}", meta.dataset_id),
+            };
+            write_meta_event(track, 0x03, name.as_bytes());
+            write_meta_event(track, 0x02, COPYRIGHT_NOTICE.as_bytes());
+            write_meta_event(track, 0x01, text.as_bytes());
+        }
+        if let Some(tempo) = self.tempo {
+            // Set Tempo (microseconds per quarter note, 24-bit)
+            track.extend_from_slice(&[0x00, 0xFF, 0x51, 0x03]);
+            track.extend_from_slice(&(60_000_000 / tempo).to_be_bytes()[1..]);
+        }
+    }
+
+    /// Write program change (if set) and chords (where empty chords are rests) to track, with
+    /// durations and velocities of `variation` (if provided)
+    fn write_chords<'a, I>(&self, track: &mut Vec<u8>, chords: I, variation: &crate::variation::MelodyVariation)
+    where
+        I: Iterator<Item = &'a [libatm::MIDINote]> + Clone,
+    {
+        if let Some(program) = self.program {
+            track.extend_from_slice(&[0x00, 0xC0 | self.channel, program]);
+        }
+        let note_duration = self.note_duration();
+        // Rests are written as a gap before the next note, which is decoded using the duration
+        // of the first note. Melodies of only rests have no note to measure rests by, so each
+        // rest is written as a quarter note instead (see: decode_midi_file).
+        let rest_duration = if chords.clone().any(|chord| !chord.is_empty()) {
+            note_duration
+        } else {
+            self.division as u32
+        };
+        // Durations of rhythm pattern were validated for division when rhythm set was resolved
+        let ticks = variation.rhythm.as_ref().map(|rhythm| {
+            rhythm.iter().map(|duration| duration.gen_ticks(self.division).unwrap()).collect::<Vec<u32>>()
+        });
+        // Rests don't have a velocity, so velocities are taken in order of sounding notes
+        let mut velocities = variation.velocities.iter().flatten();
+        let mut rest_ticks = 0;
+        let mut is_first_note = true;
+        for (position, chord) in chords.enumerate() {
+            let note_duration = ticks.as_ref().map_or(note_duration, |ticks| ticks[position]);
+            if chord.is_empty() {
+                rest_ticks += ticks.as_ref().map_or(rest_duration, |ticks| ticks[position]);
+                continue;
+            }
+            // NoteOn for every note of chord, followed by NoteOn with velocity 0 (NoteOff)
+            // for every note of chord, with running status
+            write_vlq(track, rest_ticks);
+            if is_first_note {
+                track.push(0x90 | self.channel);
+                is_first_note = false;
+            }
+            let velocity = velocities.next().copied().unwrap_or(self.velocity);
+            for (chord_position, note) in chord.iter().enumerate() {
+                if chord_position > 0 {
+                    track.push(0x00);
+                }
+                track.extend_from_slice(&[note.convert() as u8, velocity]);
+            }
+            for (chord_position, note) in chord.iter().enumerate() {
+                write_vlq(track, if chord_position == 0 { note_duration } else { 0 });
+                track.extend_from_slice(&[note.convert() as u8, 0x00]);
+            }
+            rest_ticks = 0;
+        }
+        // Trailing rests are written as a gap before End of Track
+        if rest_ticks > 0 {
+            write_vlq(track, rest_ticks);
+            track.extend_from_slice(&[0xFF, 0x2F, 0x00]);
+        }
+    }
+
+    /// Render melody as MIDI file, and generate its hash
+    /// (see: [PreparedMIDIFile](../storage/struct.PreparedMIDIFile.html))
+    pub fn prepare(&self, melody: libatm::MIDINoteVec) -> crate::storage::PreparedMIDIFile {
+        let data = self.gen_file(&melody);
+        let mfile = self.gen_mfile(melody);
+        let hash = crate::utils::gen_melody_hash(&mfile.sequence);
+        crate::storage::PreparedMIDIFile { mfile, hash, data }
+    }
+
+    /// Render melody as MIDI file with the variation at index `variation_index` (see:
+    /// [VariationSet](../variation/struct.VariationSet.html)), and generate its hash (see:
+    /// [MelodyVariation::gen_hash](../variation/struct.MelodyVariation.html#method.gen_hash)),
+    /// or `None` if the melody isn't generated with the variation (i.e., dynamics patterns
+    /// with a velocity for one of its rests). Without variations, same as [prepare](#method.prepare).
+    pub fn prepare_variation(
+        &self,
+        melody: libatm::MIDINoteVec,
+        variation_index: u64,
+    ) -> Option<crate::storage::PreparedMIDIFile> {
+        if self.variations.is_empty() {
+            return Some(self.prepare(melody));
+        }
+        let variation = self.variations.gen_variation_at(variation_index, &melody)?;
+        let index = self.meta
+            .as_ref()
+            .and_then(|meta| crate::utils::gen_melody_index(&meta.notes, &melody).ok())
+            .map(|pitch_index| pitch_index * self.num_variations() + variation_index);
+        let data = self.render(&melody, &variation, index);
+        let mfile = self.gen_mfile(melody);
+        let hash = variation.gen_hash(&mfile.sequence);
+        Some(crate::storage::PreparedMIDIFile { mfile, hash, data })
+    }
+
+    /// Recover the melody of a MIDI file rendered with options, and the index of its variation
+    /// (see: [VariationSet](../variation/struct.VariationSet.html)), from its hash `hash` and
+    /// MIDI data `data` (see: [recover_annotated_melody](fn.recover_annotated_melody.html)).
+    /// The data isn't checked against the file rendered for the melody (see:
+    /// [prepare_variation](#method.prepare_variation)).
+    pub fn recover_melody(&self, hash: &str, data: &[u8]) -> Result<(libatm::MIDINoteVec, u64), RecoverMelodyError> {
+        let mut parsed = recover_annotated_melody(hash, data)?;
+        // Hash of a single chord of one note is the melody hash of the note
+        if self.variations.chords.is_some() && parsed.variation.chord_types.is_none() {
+            parsed.variation.chord_types = Some(vec![crate::chord::ChordType::unison(); parsed.melody.len()]);
+        }
+        let variation_index = self.variations.gen_variation_index(&parsed.variation, &parsed.melody)?;
+        Ok((parsed.melody, variation_index))
+    }
+}
+
+/// Recover the melody of a MIDI file, and the variation it's rendered with, from its hash
+/// `hash` and MIDI data `data`. The notes played in the file tell where each note number of
+/// the hash ends, and the hash gives the rests, chord types, rhythm and dynamics, so rests
+/// don't have to be decoded from timing (see: [parse_hash](../variation/fn.parse_hash.html)).
+pub fn recover_annotated_melody(hash: &str, data: &[u8]) -> Result<crate::variation::AnnotatedMelody, RecoverMelodyError> {
+    let notes = decode_midi_file(data)?
+        .sequence
+        .iter()
+        .filter(|note| note.note_type != libatm::MIDINoteType::Rest)
+        .copied()
+        .collect::<Vec<libatm::MIDINote>>();
+    crate::variation::parse_hash(hash, &notes)
+        .filter(|parsed| parsed.variation.gen_hash(&parsed.melody) == hash)
+        .ok_or_else(|| RecoverMelodyError::InvalidHash { hash: hash.to_string() })
+}
+
+/// Generate MIDI file data from header (with format `format` and division `division`)
+/// and track chunks
+fn gen_file_data(format: libatm::MIDIFormat, division: u16, tracks: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(tracks.iter().map(|track| track.len() + 8).sum::<usize>() + 14);
+    data.extend_from_slice(b"MThd");
+    data.extend_from_slice(&6u32.to_be_bytes());
+    data.extend_from_slice(&(format as u16).to_be_bytes());
+    data.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+    data.extend_from_slice(&division.to_be_bytes());
+    for track in tracks.iter() {
+        data.extend_from_slice(b"MTrk");
+        data.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        data.extend_from_slice(track);
+    }
+    data
+}
+
+/// Generate chord of one note for every note of melody (and empty chords for rests)
+fn gen_note_chords(melody: &[libatm::MIDINote]) -> impl Iterator<Item = &[libatm::MIDINote]> + Clone {
+    melody.iter().map(|note| match note.note_type {
+        libatm::MIDINoteType::Rest => &[][..],
+        _ => std::slice::from_ref(note),
+    })
+}
+
+/// Write meta event of type `meta_type` at delta time 0
+fn write_meta_event(target: &mut Vec<u8>, meta_type: u8, data: &[u8]) {
+    target.extend_from_slice(&[0x00, 0xFF, meta_type]);
+    write_vlq(target, data.len() as u32);
+    target.extend_from_slice(data);
+}
+
+/// Write variable-length quantity (i.e., delta time)
+fn write_vlq(target: &mut Vec<u8>, value: u32) {
+    let mut shift = 21;
+    while shift > 0 && value >> shift == 0 {
+        shift -= 7;
+    }
+    while shift > 0 {
+        target.push(0x80 | ((value >> shift) & 0x7F) as u8);
+        shift -= 7;
+    }
+    target.push((value & 0x7F) as u8);
+}
+
+/************************
+***** MIDI Decoding *****
+************************/
+
+/// Error type for [decode_midi_file](fn.decode_midi_file.html)
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeMIDIError {
+    /// Chunk doesn't start with expected type
+    #[error("Invalid {chunk} chunk (expected {expected:?})")]
+    InvalidChunkType { chunk: &'static str, expected: &'static str },
+    /// Data ends in the middle of a chunk or event
+    #[error("Unexpected end of data")]
+    UnexpectedEnd,
+    /// First event of track has no status byte
+    #[error("Data byte without status at offset {offset}")]
+    MissingStatus { offset: usize },
+    /// Note number below 12 (octave -1)
+    #[error("Note number {number} can't be represented (octave below 0)")]
+    UnsupportedNote { number: u8 },
+}
+
+/// Melody and header fields decoded from a MIDI file
+/// (see: [decode_midi_file](fn.decode_midi_file.html))
+#[derive(Clone, Debug)]
+pub struct DecodedMIDIFile {
+    /// MIDI format (0, 1 or 2)
+    pub format: u16,
+    /// Number of tracks (from header)
+    pub tracks: u16,
+    /// Ticks per quarter note
+    pub division: u16,
+    /// Notes played (in order of NoteOn events, across all tracks)
+    pub sequence: libatm::MIDINoteVec,
+}
+
+/// Convert MIDI note number to note, or `None` if the octave
+/// is below 0 (which `libatm::MIDINote` can't represent)
+///
+/// # Examples
+///
+/// ```rust
+/// let note = atm::midi::note_from_number(61).unwrap();
+/// assert_eq!(libatm::MIDINote::new(libatm::MIDINoteType::CSharp, 4), note);
+/// ```
+pub fn note_from_number(number: u8) -> Option<libatm::MIDINote> {
+    match number {
+        REST_NOTE_NUMBER => Some(libatm::MIDINote::new(libatm::MIDINoteType::Rest, 0)),
+        12..=127 => Some(libatm::MIDINote::new(
+            NOTE_TYPES[(number % 12) as usize],
+            (number / 12) as u32 - 1,
+        )),
+        _ => None,
+    }
+}
+
+/// Cursor over MIDI file data
+struct Cursor<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.offset).copied()
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], DecodeMIDIError> {
+        let end = self.offset.checked_add(len).filter(|end| *end <= self.data.len());
+        match end {
+            Some(end) => {
+                let bytes = &self.data[self.offset..end];
+                self.offset = end;
+                Ok(bytes)
+            },
+            None => Err(DecodeMIDIError::UnexpectedEnd),
+        }
+    }
+
+    fn read_u8(&mut self) -> Result<u8, DecodeMIDIError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, DecodeMIDIError> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, DecodeMIDIError> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Read variable-length quantity (i.e., delta time)
+    fn read_vlq(&mut self) -> Result<u32, DecodeMIDIError> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let byte = self.read_u8()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        Ok(value)
+    }
+}
+
+/// Decode the melody from the track(s) of a MIDI file. Notes are taken from NoteOn
+/// events with a non-zero velocity, in the order they appear. Gaps between notes (and
+/// before End of Track) are decoded as one
+/// [rest](../../libatm/midi_note/enum.MIDINoteType.html#variant.Rest) per note duration,
+/// where the note duration is that of the first note of the track (or one quarter note
+/// if the track only has rests), as written by
+/// [MIDIRenderOptions::gen_file](struct.MIDIRenderOptions.html#method.gen_file).
+/// Rests written by `libatm` (note number 255 with velocity 0, once for NoteOn and once
+/// for NoteOff) are decoded as well.
+///
+/// # Examples
+///
+/// ```rust
+/// let melody = "C:4,DSharp:4,G:4".parse::<libatm::MIDINoteVec>().unwrap();
+/// let mfile = libatm::MIDIFile::new(melody.clone(), libatm::MIDIFormat::Format0, 1, 1);
+/// let decoded = atm::midi::decode_midi_file(&mfile.gen_file().unwrap()).unwrap();
+/// assert_eq!(melody, decoded.sequence);
+/// ```
+pub fn decode_midi_file(data: &[u8]) -> Result<DecodedMIDIFile, DecodeMIDIError> {
+    let mut cursor = Cursor { data, offset: 0 };
+
+    // Header chunk
+    if cursor.read_bytes(4)? != b"MThd" {
+        return Err(DecodeMIDIError::InvalidChunkType { chunk: "header", expected: "MThd" });
+    }
+    let header_length = cursor.read_u32()? as usize;
+    let format = cursor.read_u16()?;
+    let tracks = cursor.read_u16()?;
+    let division = cursor.read_u16()?;
+    cursor.read_bytes(header_length.saturating_sub(6))?;
+
+    // Track chunks (other chunk types are skipped)
+    let mut sequence = Vec::new();
+    while cursor.peek().is_some() {
+        let chunk_type = cursor.read_bytes(4)?;
+        let chunk_length = cursor.read_u32()? as usize;
+        let chunk_start = cursor.offset;
+        let chunk = cursor.read_bytes(chunk_length)?;
+        if chunk_type == b"MTrk" {
+            decode_track(chunk, chunk_start, division, &mut sequence)?;
+        }
+    }
+
+    Ok(DecodedMIDIFile {
+        format,
+        tracks,
+        division,
+        sequence: libatm::MIDINoteVec(sequence),
+    })
+}
+
+/// Decode notes from track events, and append them to `sequence`
+fn decode_track(
+    data: &[u8],
+    chunk_start: usize,
+    division: u16,
+    sequence: &mut Vec<libatm::MIDINote>,
+) -> Result<(), DecodeMIDIError> {
+    let mut cursor = Cursor { data, offset: 0 };
+    let mut running_status: Option<u8> = None;
+    // Whether the last event was the NoteOn of a rest
+    let mut in_rest = false;
+    // Time of current event, start of first note and end of last note (in ticks)
+    let mut time = 0u64;
+    let mut first_note_start: Option<u64> = None;
+    let mut last_note_end = 0u64;
+    // Duration of first note, which gaps between notes are measured in
+    let mut note_duration: Option<u64> = None;
+    // Gaps (in ticks) before notes, as position in sequence and length
+    let mut gaps: Vec<(usize, u64)> = Vec::new();
+    let mut add_gap = |position: usize, time: u64, last_note_end: u64| {
+        if time > last_note_end {
+            gaps.push((position, time - last_note_end));
+        }
+    };
+    while cursor.peek().is_some() {
+        time += cursor.read_vlq()? as u64;
+        let offset = chunk_start + cursor.offset;
+        let byte = cursor.peek().ok_or(DecodeMIDIError::UnexpectedEnd)?;
+
+        // libatm writes rests with running status, so their note number (0xFF) would
+        // otherwise be read as the status byte of a meta event
+        let is_rest = byte == REST_NOTE_NUMBER
+            && running_status.is_some_and(|status| status & 0xF0 == 0x90)
+            && data.get(cursor.offset + 1) == Some(&0);
+        let status = if byte & 0x80 != 0 && !is_rest {
+            cursor.read_u8()?
+        } else {
+            running_status.ok_or(DecodeMIDIError::MissingStatus { offset })?
+        };
+
+        match status {
+            // Meta event (end of track ends decoding of this track)
+            0xFF => {
+                let meta_type = cursor.read_u8()?;
+                let length = cursor.read_vlq()? as usize;
+                cursor.read_bytes(length)?;
+                running_status = None;
+                if meta_type == 0x2F {
+                    add_gap(sequence.len(), time, last_note_end);
+                    break;
+                }
+            },
+            // System exclusive event
+            0xF0 | 0xF7 => {
+                let length = cursor.read_vlq()? as usize;
+                cursor.read_bytes(length)?;
+                running_status = None;
+            },
+            // NoteOn and NoteOff
+            0x80..=0x9F => {
+                let number = cursor.read_u8()?;
+                let velocity = cursor.read_u8()?;
+                if number == REST_NOTE_NUMBER {
+                    // Rests are written as NoteOn/NoteOff pairs with velocity 0
+                    if !in_rest {
+                        sequence.push(libatm::MIDINote::new(libatm::MIDINoteType::Rest, 0));
+                    }
+                    in_rest = !in_rest;
+                    last_note_end = time;
+                } else if status >= 0x90 && velocity > 0 {
+                    add_gap(sequence.len(), time, last_note_end);
+                    sequence.push(note_from_number(number).ok_or(DecodeMIDIError::UnsupportedNote { number })?);
+                    first_note_start.get_or_insert(time);
+                    in_rest = false;
+                } else {
+                    // NoteOff (or NoteOn with velocity 0)
+                    last_note_end = time;
+                    if let Some(start) = first_note_start {
+                        note_duration.get_or_insert(time - start);
+                    }
+                }
+                running_status = Some(status);
+            },
+            // Program change and channel pressure have one data byte
+            0xC0..=0xDF => {
+                cursor.read_bytes(1)?;
+                running_status = Some(status);
+            },
+            // Other channel voice messages have two data bytes
+            _ => {
+                cursor.read_bytes(2)?;
+                running_status = Some(status);
+            },
+        }
+    }
+
+    // Insert rests for gaps (from the end, so positions stay valid)
+    let note_duration = note_duration.filter(|duration| *duration > 0).unwrap_or(division.max(1) as u64);
+    for (position, length) in gaps.into_iter().rev() {
+        let num_rests = (length / note_duration) as usize;
+        let rest = libatm::MIDINote::new(libatm::MIDINoteType::Rest, 0);
+        sequence.splice(position..position, std::iter::repeat_n(rest, num_rests));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_midi_file_with_libatm_rests() {
+        let melody = "Rest:0,C:4,Rest:0,Rest:0,G:9,C:0,Rest:0"
+            .parse::<libatm::MIDINoteVec>()
+            .unwrap();
+        let mfile = libatm::MIDIFile::new(melody.clone(), libatm::MIDIFormat::Format0, 1, 1);
+        let decoded = decode_midi_file(&mfile.gen_file().unwrap()).unwrap();
+        assert_eq!(melody, decoded.sequence);
+        assert_eq!((0, 1, 1), (decoded.format, decoded.tracks, decoded.division));
+    }
+
+    #[test]
+    fn test_decode_midi_file_truncated() {
+        let melody = "C:4,D:4".parse::<libatm::MIDINoteVec>().unwrap();
+        let data = libatm::MIDIFile::new(melody, libatm::MIDIFormat::Format0, 1, 1).gen_file().unwrap();
+        assert!(matches!(
+            decode_midi_file(&data[..data.len() - 2]),
+            Err(DecodeMIDIError::UnexpectedEnd),
+        ));
+    }
+
+    #[test]
+    fn test_render_options_round_trip() {
+        let melody = crate::utils::parse_note_vec("R,C:4,R,R,G:5,A:3,R").unwrap();
+        let options = MIDIRenderOptions {
+            format: libatm::MIDIFormat::Format1,
+            division: 480,
+            tempo: Some(90),
+            duration: Some(240),
+            velocity: 80,
+            channel: 9,
+            program: Some(24),
+            meta: None,
+            variations: crate::variation::VariationSet::default(),
+        };
+        let data = options.gen_file(&melody);
+        let decoded = decode_midi_file(&data).unwrap();
+        assert_eq!(melody, decoded.sequence);
+        assert_eq!((1, 1, 480), (decoded.format, decoded.tracks, decoded.division));
+        // Default options match libatm (for melodies without rests)
+        let melody = "C:4,G:5,A:3".parse::<libatm::MIDINoteVec>().unwrap();
+        let mfile = libatm::MIDIFile::new(melody.clone(), libatm::MIDIFormat::Format0, 1, 1);
+        assert_eq!(mfile.gen_file().unwrap(), MIDIRenderOptions::default().gen_file(&melody));
+    }
+
+    #[test]
+    fn test_recover_melody_with_rhythms() {
+        let notes = crate::utils::parse_note_vec("C:4,R,G:4").unwrap();
+        let durations = ["1/8", "1/4"]
+            .iter()
+            .map(|duration| duration.parse::<crate::rhythm::NoteDuration>().unwrap())
+            .collect::<Vec<crate::rhythm::NoteDuration>>();
+        let options = MIDIRenderOptions {
+            division: 480,
+            variations: crate::variation::VariationSet::new(
+                None,
+                Some(crate::rhythm::RhythmSet::new(&durations, 3, None).unwrap()),
+                None,
+            ).unwrap(),
+            ..Default::default()
+        };
+        let num_variations = options.num_variations();
+        for index in 0..options.gen_num_melodies(notes.len() as u32, 3) {
+            let melody = crate::utils::gen_melody_at(&notes, 3, index / num_variations).unwrap();
+            let pfile = options.prepare_variation(melody.clone(), index % num_variations).unwrap();
+            let (recovered, variation_index) = options.recover_melody(&pfile.hash, &pfile.data).unwrap();
+            assert_eq!((melody, index % num_variations), (recovered.clone(), variation_index));
+            assert_eq!(pfile.data, options.prepare_variation(recovered, variation_index).unwrap().data);
+        }
+        // Hash must match the notes played in the file
+        let pfile = options.prepare_variation(crate::utils::parse_note_vec("C:4,R,G:4").unwrap(), 0).unwrap();
+        assert!(options.recover_melody("67R60_1-8.1-8.1-8", &pfile.data).is_err());
+        assert!(MIDIRenderOptions::default().recover_melody(&pfile.hash, &pfile.data).is_err());
+    }
+
+    #[test]
+    fn test_recover_melody_with_chords() {
+        let notes = crate::utils::parse_note_vec("C:4,G:4").unwrap();
+        let chord_types = ["0", "maj"]
+            .iter()
+            .map(|chord_type| chord_type.parse::<crate::chord::ChordType>().unwrap())
+            .collect::<Vec<crate::chord::ChordType>>();
+        // Melodies of one note include a chord of one note, whose hash is the melody hash
+        for melody_length in 1..=2 {
+            let options = MIDIRenderOptions {
+                variations: crate::variation::VariationSet::new(
+                    Some(crate::chord::ChordTypeSet::new(&chord_types, melody_length).unwrap()),
+                    None,
+                    Some(crate::dynamics::DynamicsSet::new(&[33, 80], melody_length).unwrap()),
+                ).unwrap(),
+                ..Default::default()
+            };
+            let num_variations = options.num_variations();
+            for index in 0..options.gen_num_melodies(notes.len() as u32, melody_length) {
+                let melody = crate::utils::gen_melody_at(&notes, melody_length, index / num_variations).unwrap();
+                let pfile = options.prepare_variation(melody.clone(), index % num_variations).unwrap();
+                let (recovered, variation_index) = options.recover_melody(&pfile.hash, &pfile.data).unwrap();
+                assert_eq!((melody, index % num_variations), (recovered, variation_index));
+            }
+        }
+        // Hash must match every note of the chords played in the file
+        let options = MIDIRenderOptions {
+            variations: crate::variation::VariationSet::new(
+                Some(crate::chord::ChordTypeSet::new(&chord_types, 2).unwrap()),
+                None,
+                None,
+            ).unwrap(),
+            ..Default::default()
+        };
+        let pfile = options.prepare_variation(notes.clone(), 3).unwrap();
+        assert_eq!("60+64+67.67+71+74", pfile.hash);
+        assert!(options.recover_melody("60+64+67.67", &pfile.data).is_err());
+        assert!(options.recover_melody("60+63+67.67+71+74", &pfile.data).is_err());
+    }
+
+    #[test]
+    fn test_render_dynamics_without_rest_velocities() {
+        let notes = crate::utils::parse_note_vec("C:4,R,G:4").unwrap();
+        let options = MIDIRenderOptions {
+            variations: crate::variation::VariationSet::new(
+                None,
+                None,
+                Some(crate::dynamics::DynamicsSet::new(&[33, 80, 112], 3).unwrap()),
+            ).unwrap(),
+            ..Default::default()
+        };
+        let num_variations = options.num_variations();
+        let mut files = std::collections::HashSet::new();
+        for index in 0..options.gen_num_melodies(notes.len() as u32, 3) {
+            let melody = crate::utils::gen_melody_at(&notes, 3, index / num_variations).unwrap();
+            let pfile = match options.prepare_variation(melody.clone(), index % num_variations) {
+                Some(pfile) => pfile,
+                None => continue,
+            };
+            let (recovered, variation_index) = options.recover_melody(&pfile.hash, &pfile.data).unwrap();
+            assert_eq!((melody, index % num_variations), (recovered, variation_index));
+            // No two files are the same, even if they only differ in the velocity of a rest
+            assert!(files.insert(pfile.data));
+        }
+        assert_eq!(options.gen_num_files(&notes, 3), files.len() as u64);
+    }
+
+    #[test]
+    fn test_render_meta_events() {
+        let notes = "C:4,D:4,E:4".parse::<libatm::MIDINoteVec>().unwrap();
+        let options = MIDIRenderOptions {
+            meta: Some(MIDIMetaEvents { dataset_id: "C4_D4_E4-3".to_string(), notes: notes.clone() }),
+            ..Default::default()
+        };
+        let melody = "E:4,C:4,D:4".parse::<libatm::MIDINoteVec>().unwrap();
+        let data = options.gen_file(&melody);
+        assert_eq!(melody, decode_midi_file(&data).unwrap().sequence);
+        // Track name, copyright notice and text (index 2 * 9 + 0 * 3 + 1)
+        let mut expected = vec![0x00, 0xFF, 0x03, 11];
+        expected.extend_from_slice(b"E:4,C:4,D:4");
+        assert_eq!(&expected[..], &data[22..37]);
+        let text = b"dataset=C4_D4_E4-3 index=19";
+        assert!(data.windows(text.len()).any(|window| window == text));
+        assert!(options.gen_max_file_size(3, 3) >= data.len() as u64);
+    }
+}
@@ -0,0 +1,196 @@
+// pattern.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+/**********************
+***** Pattern Set *****
+**********************/
+
+/// Set of patterns: every sequence of `length` symbols from a set of symbols, or only those
+/// whose symbol weights add up to a total (i.e., rhythms that fill a number of bars). Like
+/// melodies, patterns are generated in lexicographic order (of symbols, in the order they're
+/// provided), so every pattern has a stable index.
+///
+/// # Examples
+///
+/// ```rust
+/// let patterns = atm::pattern::PatternSet::new(vec!['a', 'b', 'c'], 2).unwrap();
+/// assert_eq!(9, patterns.num_patterns());
+/// assert_eq!(Some(vec!['b', 'a']), patterns.gen_pattern_at(3));
+/// assert_eq!(Some(3), patterns.gen_pattern_index(&['b', 'a']));
+/// // Patterns of 3 symbols with weights adding up to 4: a,a,b and a,b,a and b,a,a
+/// let patterns = atm::pattern::PatternSet::with_total_weight(vec!['a', 'b'], vec![1, 2], 3, 4).unwrap();
+/// assert_eq!(3, patterns.num_patterns());
+/// assert_eq!(Some(vec!['a', 'b', 'a']), patterns.gen_pattern_at(1));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct PatternSet<T> {
+    /// Symbols patterns are generated from (in generation order)
+    symbols: Vec<T>,
+    /// Number of symbols per pattern
+    length: u32,
+    /// Weight of each symbol, if patterns are restricted to a total weight
+    weights: Vec<u64>,
+    /// Number of patterns of `k` symbols with weights adding up to `t` (`counts[k][t]`),
+    /// if patterns are restricted to a total weight
+    counts: Vec<Vec<u64>>,
+    /// Total number of patterns
+    num_patterns: u64,
+}
+
+impl<T: Clone + PartialEq> PatternSet<T> {
+    /// Create new `PatternSet` instance for every pattern of `length` symbols, or `None`
+    /// if the number of patterns overflows a 64-bit index
+    pub fn new(symbols: Vec<T>, length: u32) -> Option<Self> {
+        let num_patterns = (symbols.len() as u64).checked_pow(length)?;
+        Some(Self { symbols, length, weights: Vec::new(), counts: Vec::new(), num_patterns })
+    }
+
+    /// Create new `PatternSet` instance for patterns of `length` symbols whose weights
+    /// (`weights[i]` for `symbols[i]`) add up to `total`, or `None` if the number of
+    /// patterns overflows a 64-bit index. There may be no such pattern.
+    pub fn with_total_weight(symbols: Vec<T>, weights: Vec<u64>, length: u32, total: u64) -> Option<Self> {
+        let counts = Self::gen_counts(&weights, length, total)?;
+        let num_patterns = counts[length as usize][total as usize];
+        Some(Self { symbols, length, weights, counts, num_patterns })
+    }
+
+    /// Generate number of patterns of `k` symbols (for `k` up to `length`) with weights
+    /// adding up to `t` (for `t` up to `total`)
+    fn gen_counts(weights: &[u64], length: u32, total: u64) -> Option<Vec<Vec<u64>>> {
+        let mut counts = vec![vec![0u64; total as usize + 1]; length as usize + 1];
+        counts[0][0] = 1;
+        for k in 1..=length as usize {
+            for t in 0..=total as usize {
+                let mut count = 0u64;
+                for weight in weights.iter().map(|weight| *weight as usize).filter(|weight| *weight <= t) {
+                    count = count.checked_add(counts[k - 1][t - weight])?;
+                }
+                counts[k][t] = count;
+            }
+        }
+        Some(counts)
+    }
+
+    /// Number of patterns in set
+    pub fn num_patterns(&self) -> u64 {
+        self.num_patterns
+    }
+
+    /// Symbols patterns are generated from (in generation order)
+    pub fn symbols(&self) -> &[T] {
+        &self.symbols
+    }
+
+    /// Whether patterns are restricted to a total weight
+    fn is_weighted(&self) -> bool {
+        !self.counts.is_empty()
+    }
+
+    /// Weight left to fill at the start of a pattern (0 if patterns aren't restricted)
+    fn gen_total(&self) -> u64 {
+        self.counts.first().map_or(0, |counts| (counts.len() - 1) as u64)
+    }
+
+    /// Weight of symbol at position `digit` in the set (0 if patterns aren't restricted)
+    fn gen_weight(&self, digit: usize) -> u64 {
+        self.weights.get(digit).copied().unwrap_or(0)
+    }
+
+    /// Number of patterns of `k` symbols that fill the remaining weight `t`
+    fn gen_num_completions(&self, k: u32, t: u64) -> u64 {
+        if self.is_weighted() {
+            self.counts[k as usize][t as usize]
+        } else {
+            (self.symbols.len() as u64).pow(k)
+        }
+    }
+
+    /// Generate the pattern at index `index`, or `None` if out of range.
+    /// Inverse of [gen_pattern_index](#method.gen_pattern_index).
+    pub fn gen_pattern_at(&self, index: u64) -> Option<Vec<T>> {
+        if index >= self.num_patterns {
+            return None;
+        }
+        let mut remainder = index;
+        let mut remaining = self.gen_total();
+        let mut pattern = Vec::with_capacity(self.length as usize);
+        for position in 0..self.length {
+            let num_left = self.length - position - 1;
+            for (digit, symbol) in self.symbols.iter().enumerate() {
+                let weight = self.gen_weight(digit);
+                if weight > remaining {
+                    continue;
+                }
+                let num_completions = self.gen_num_completions(num_left, remaining - weight);
+                if remainder < num_completions {
+                    pattern.push(symbol.clone());
+                    remaining -= weight;
+                    break;
+                }
+                remainder -= num_completions;
+            }
+        }
+        Some(pattern)
+    }
+
+    /// Calculate the index of pattern `pattern`, or `None` if it isn't in the set.
+    /// Inverse of [gen_pattern_at](#method.gen_pattern_at).
+    pub fn gen_pattern_index(&self, pattern: &[T]) -> Option<u64> {
+        if pattern.len() != self.length as usize {
+            return None;
+        }
+        let mut index = 0u64;
+        let mut remaining = self.gen_total();
+        for (position, symbol) in pattern.iter().enumerate() {
+            let num_left = self.length - position as u32 - 1;
+            let digit = self.symbols.iter().position(|s| s == symbol)?;
+            if self.gen_weight(digit) > remaining {
+                return None;
+            }
+            // Add patterns starting with earlier symbols at this position
+            for weight in (0..digit).map(|earlier| self.gen_weight(earlier)).filter(|weight| *weight <= remaining) {
+                index += self.gen_num_completions(num_left, remaining - weight);
+            }
+            remaining -= self.gen_weight(digit);
+        }
+        match remaining {
+            0 => Some(index),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_index_round_trip() {
+        let unrestricted = PatternSet::new(vec![16u8, 80, 127], 4).unwrap();
+        assert_eq!(81, unrestricted.num_patterns());
+        let weighted = PatternSet::with_total_weight(vec![1u8, 2, 4], vec![1, 2, 4], 4, 8).unwrap();
+        assert_eq!(13, weighted.num_patterns());
+        for (patterns, total) in [(unrestricted, None), (weighted, Some(8))] {
+            let mut previous: Option<Vec<u8>> = None;
+            for index in 0..patterns.num_patterns() {
+                let pattern = patterns.gen_pattern_at(index).unwrap();
+                assert_eq!(Some(index), patterns.gen_pattern_index(&pattern));
+                if let Some(total) = total {
+                    assert_eq!(total, pattern.iter().map(|symbol| *symbol as u32).sum::<u32>());
+                }
+                // Patterns are generated in lexicographic order
+                if let Some(previous) = previous {
+                    assert!(previous < pattern);
+                }
+                previous = Some(pattern);
+            }
+            assert!(patterns.gen_pattern_at(patterns.num_patterns()).is_none());
+            assert!(patterns.gen_pattern_index(&[16, 80, 100, 127]).is_none());
+        }
+    }
+}
@@ -0,0 +1,389 @@
+// progression.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::{chord::ChordType, pattern::PatternSet};
+
+/// Roman numerals of the scale degrees (uppercase)
+const NUMERALS: [&str; 7] = ["I", "II", "III", "IV", "V", "VI", "VII"];
+
+/// Semitones above the tonic of each scale degree in major keys
+const MAJOR_SCALE: [u8; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+/// Semitones above the tonic of each scale degree in (natural) minor keys
+const MINOR_SCALE: [u8; 7] = [0, 2, 3, 5, 7, 8, 10];
+
+/// Separator between the numerals of a progression, in hashes and track names
+pub const PROGRESSION_SEPARATOR: char = '-';
+
+/*************************
+***** Roman Numerals *****
+*************************/
+
+/// Error type for parsing [RomanNumeral](struct.RomanNumeral.html) from `&str`
+#[derive(Debug, thiserror::Error)]
+pub enum ParseRomanNumeralError {
+    #[error("Invalid Roman numeral {input} (expected scale degree I-VII, i.e., 'I', 'ii', 'bVII', 'V7', 'viio')")]
+    InvalidFormat { input: String },
+}
+
+/// Quality of the triad a Roman numeral is built on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TriadQuality {
+    /// Uppercase numeral (i.e., 'IV')
+    Major,
+    /// Lowercase numeral (i.e., 'ii')
+    Minor,
+    /// Numeral followed by 'o' or '°' (i.e., 'viio')
+    Diminished,
+    /// Numeral followed by '+' (i.e., 'III+')
+    Augmented,
+}
+
+/// Seventh added on top of the triad of a Roman numeral
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Seventh {
+    /// '7': minor seventh (diminished seventh over diminished triads)
+    Seventh,
+    /// 'maj7': major seventh
+    MajorSeventh,
+}
+
+/// Chord on a degree of the scale of a key, as written in Roman numeral analysis
+/// (i.e., 'I', 'ii', 'V7', 'viio', 'bVII')
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RomanNumeral {
+    /// Scale degree (0 for I, 6 for VII)
+    pub degree: u8,
+    /// Semitones the root is raised ('#') or lowered ('b') from the scale degree
+    pub accidental: i8,
+    /// Quality of triad
+    pub quality: TriadQuality,
+    /// Seventh (if any)
+    pub seventh: Option<Seventh>,
+}
+
+impl RomanNumeral {
+    /// Generate chord type of numeral
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// let numeral = "V7".parse::<atm::progression::RomanNumeral>().unwrap();
+    /// assert_eq!("7".parse::<atm::chord::ChordType>().unwrap(), numeral.gen_chord_type());
+    /// ```
+    pub fn gen_chord_type(&self) -> ChordType {
+        let mut intervals = match self.quality {
+            TriadQuality::Major => vec![0, 4, 7],
+            TriadQuality::Minor => vec![0, 3, 7],
+            TriadQuality::Diminished => vec![0, 3, 6],
+            TriadQuality::Augmented => vec![0, 4, 8],
+        };
+        match (self.seventh, self.quality) {
+            (Some(Seventh::Seventh), TriadQuality::Diminished) => intervals.push(9),
+            (Some(Seventh::Seventh), _) => intervals.push(10),
+            (Some(Seventh::MajorSeventh), _) => intervals.push(11),
+            (None, _) => (),
+        }
+        ChordType { intervals }
+    }
+}
+
+impl std::str::FromStr for RomanNumeral {
+    type Err = ParseRomanNumeralError;
+
+    /// Parse Roman numeral from optional accidental ('b' or '#'), scale degree (uppercase
+    /// for major and lowercase for minor triads), optional quality ('o' or '°' for diminished,
+    /// '+' for augmented) and optional seventh ('7' or 'maj7')
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseRomanNumeralError::InvalidFormat { input: input.to_string() };
+        let (accidental, rest) = match input.trim() {
+            numeral if numeral.starts_with('b') => (-1, &numeral[1..]),
+            numeral if numeral.starts_with('#') => (1, &numeral[1..]),
+            numeral => (0, numeral),
+        };
+        let numeral_end = rest.find(|c: char| !"IViv".contains(c)).unwrap_or(rest.len());
+        let (numeral, mut suffix) = rest.split_at(numeral_end);
+        let degree = NUMERALS
+            .iter()
+            .position(|candidate| candidate.eq_ignore_ascii_case(numeral))
+            .ok_or_else(invalid)?;
+        let mut quality = if numeral.chars().all(|c| c.is_ascii_uppercase()) {
+            TriadQuality::Major
+        } else if numeral.chars().all(|c| c.is_ascii_lowercase()) {
+            TriadQuality::Minor
+        } else {
+            return Err(invalid());
+        };
+        for (symbol, symbol_quality) in [("o", TriadQuality::Diminished), ("°", TriadQuality::Diminished), ("+", TriadQuality::Augmented)] {
+            if let Some(remainder) = suffix.strip_prefix(symbol) {
+                quality = symbol_quality;
+                suffix = remainder;
+            }
+        }
+        let seventh = match suffix {
+            "" => None,
+            "7" => Some(Seventh::Seventh),
+            "maj7" => Some(Seventh::MajorSeventh),
+            _ => return Err(invalid()),
+        };
+        Ok(Self { degree: degree as u8, accidental, quality, seventh })
+    }
+}
+
+impl std::fmt::Display for RomanNumeral {
+    /// Format Roman numeral in canonical form (i.e., 'bVII', 'viio7', 'IVmaj7'),
+    /// where diminished triads are lowercase and augmented triads uppercase
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.accidental {
+            accidental if accidental < 0 => write!(f, "b")?,
+            accidental if accidental > 0 => write!(f, "#")?,
+            _ => (),
+        }
+        let numeral = NUMERALS[self.degree as usize];
+        match self.quality {
+            TriadQuality::Major => write!(f, "{}", numeral)?,
+            TriadQuality::Minor => write!(f, "{}", numeral.to_ascii_lowercase())?,
+            TriadQuality::Diminished => write!(f, "{}o", numeral.to_ascii_lowercase())?,
+            TriadQuality::Augmented => write!(f, "{}+", numeral)?,
+        }
+        match self.seventh {
+            Some(Seventh::Seventh) => write!(f, "7"),
+            Some(Seventh::MajorSeventh) => write!(f, "maj7"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Format progression as numerals joined by '-' (i.e., 'I-vi-IV-V7'), used as its hash
+pub fn format_progression(progression: &[RomanNumeral]) -> String {
+    progression
+        .iter()
+        .map(|numeral| numeral.to_string())
+        .collect::<Vec<String>>()
+        .join(&PROGRESSION_SEPARATOR.to_string())
+}
+
+/*******************
+***** Voicings *****
+*******************/
+
+/// Error type for parsing [Voicing](enum.Voicing.html) from `&str`
+#[derive(Debug, thiserror::Error)]
+pub enum ParseVoicingError {
+    #[error("Invalid voicing {input} (expected 'close', 'open', 'first' or 'second')")]
+    InvalidFormat { input: String },
+}
+
+/// How the notes of a chord are arranged above its root
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Voicing {
+    /// Root position, with every note within an octave of the root
+    Close,
+    /// Root position, with every other note above the root raised an octave
+    Open,
+    /// First inversion (the root raised an octave)
+    First,
+    /// Second inversion (the root and the next note raised an octave)
+    Second,
+}
+
+impl Voicing {
+    /// Generate the notes of chord type `chord_type` in voicing,
+    /// as semitones above the root (in ascending order)
+    pub fn gen_offsets(&self, chord_type: &ChordType) -> Vec<u8> {
+        let intervals = &chord_type.intervals;
+        let mut offsets = match self {
+            Self::Close => intervals.clone(),
+            Self::Open => intervals
+                .iter()
+                .enumerate()
+                .map(|(position, interval)| if position % 2 == 1 { interval + 12 } else { *interval })
+                .collect(),
+            Self::First | Self::Second => {
+                let num_inverted = if *self == Self::First { 1 } else { 2 };
+                intervals
+                    .iter()
+                    .enumerate()
+                    .map(|(position, interval)| if position < num_inverted { interval + 12 } else { *interval })
+                    .collect()
+            },
+        };
+        offsets.sort_unstable();
+        offsets
+    }
+}
+
+impl std::str::FromStr for Voicing {
+    type Err = ParseVoicingError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.trim() {
+            "close" => Ok(Self::Close),
+            "open" => Ok(Self::Open),
+            "first" => Ok(Self::First),
+            "second" => Ok(Self::Second),
+            _ => Err(ParseVoicingError::InvalidFormat { input: input.to_string() }),
+        }
+    }
+}
+
+impl std::fmt::Display for Voicing {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Close => write!(f, "close"),
+            Self::Open => write!(f, "open"),
+            Self::First => write!(f, "first"),
+            Self::Second => write!(f, "second"),
+        }
+    }
+}
+
+/*************************
+***** ProgressionSet *****
+*************************/
+
+/// Error type for [ProgressionSet](struct.ProgressionSet.html)
+#[derive(Debug, thiserror::Error)]
+pub enum ProgressionSetError {
+    #[error("Must provide at least one Roman numeral")]
+    Empty,
+    #[error("Key must be a note (found rest)")]
+    RestKey,
+    #[error("Number of progressions of {length} chords overflows a 64-bit index")]
+    TooManyProgressions { length: u32 },
+    #[error("Chord {numeral} in key of {key} is outside of MIDI note numbers 12-127")]
+    OutOfRange { numeral: RomanNumeral, key: String },
+}
+
+/// Set of chord progressions: every sequence of `length` Roman numerals from a set of
+/// numerals, played as chords in a key with a voicing. Progressions are generated in
+/// lexicographic order of numerals, ordered by scale degree (see:
+/// [PatternSet](../pattern/struct.PatternSet.html)).
+#[derive(Clone, Debug)]
+pub struct ProgressionSet {
+    /// Progressions, as patterns of Roman numerals
+    patterns: PatternSet<RomanNumeral>,
+    /// Tonic of key
+    tonic: libatm::MIDINote,
+    /// Scale of key (see: [MAJOR_SCALE](constant.MAJOR_SCALE.html))
+    scale: [u8; 7],
+    /// Voicing chords are played in
+    voicing: Voicing,
+}
+
+impl ProgressionSet {
+    /// Create new `ProgressionSet` instance for progressions of `length` chords in the major
+    /// (or minor, if `minor`) key of `tonic`. Numerals are sorted, and duplicates removed.
+    pub fn new(
+        numerals: &[RomanNumeral],
+        length: u32,
+        tonic: libatm::MIDINote,
+        minor: bool,
+        voicing: Voicing,
+    ) -> Result<Self, ProgressionSetError> {
+        let mut numerals = numerals.to_vec();
+        numerals.sort();
+        numerals.dedup();
+        if numerals.is_empty() {
+            return Err(ProgressionSetError::Empty);
+        }
+        if tonic.note_type == libatm::MIDINoteType::Rest {
+            return Err(ProgressionSetError::RestKey);
+        }
+        let patterns = PatternSet::new(numerals, length).ok_or(ProgressionSetError::TooManyProgressions { length })?;
+        let scale = if minor { MINOR_SCALE } else { MAJOR_SCALE };
+        let progressions = Self { patterns, tonic, scale, voicing };
+        for numeral in progressions.patterns.symbols().iter() {
+            if progressions.gen_chord(numeral).is_none() {
+                return Err(ProgressionSetError::OutOfRange {
+                    numeral: *numeral,
+                    key: crate::utils::format_note(&tonic),
+                });
+            }
+        }
+        Ok(progressions)
+    }
+
+    /// Progressions in set, as patterns of Roman numerals
+    pub fn patterns(&self) -> &PatternSet<RomanNumeral> {
+        &self.patterns
+    }
+
+    /// Generate the notes of the chord of `numeral` (lowest first), or `None`
+    /// if any note is outside of MIDI note numbers 12-127
+    fn gen_chord(&self, numeral: &RomanNumeral) -> Option<Vec<libatm::MIDINote>> {
+        let root = self.tonic.convert() as i32 + self.scale[numeral.degree as usize] as i32 + numeral.accidental as i32;
+        self.voicing
+            .gen_offsets(&numeral.gen_chord_type())
+            .iter()
+            .map(|offset| {
+                let number = root + *offset as i32;
+                if (12..=127).contains(&number) {
+                    crate::midi::note_from_number(number as u8)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Generate the notes of every chord of progression `progression`
+    pub fn gen_chords(&self, progression: &[RomanNumeral]) -> Vec<Vec<libatm::MIDINote>> {
+        // Chords of every numeral were validated when the set was created
+        progression.iter().map(|numeral| self.gen_chord(numeral).unwrap()).collect()
+    }
+
+    /// Render progression at index `index` as MIDI file of block chords with `render`, and
+    /// generate its hash (see: [format_progression](fn.format_progression.html)). The
+    /// sequence of the MIDI file is the lowest note of every chord.
+    pub fn prepare(&self, render: &crate::midi::MIDIRenderOptions, index: u64) -> Option<crate::storage::PreparedMIDIFile> {
+        let progression = self.patterns.gen_pattern_at(index)?;
+        let chords = self.gen_chords(&progression);
+        let hash = format_progression(&progression);
+        let data = render.render_chords(
+            chords.iter().map(Vec::as_slice),
+            &crate::variation::MelodyVariation::default(),
+            &hash,
+            Some(index),
+        );
+        let mfile = render.gen_mfile(chords.iter().map(|chord| chord[0]).collect());
+        Some(crate::storage::PreparedMIDIFile { mfile, hash, data })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numeral_round_trip() {
+        for input in ["I", "ii", "iii", "IV", "V7", "vi", "viio", "viio7", "bVII", "#iv", "III+", "IVmaj7"] {
+            let numeral = input.parse::<RomanNumeral>().unwrap();
+            assert_eq!(input, numeral.to_string());
+        }
+        assert_eq!("viio", "vii°".parse::<RomanNumeral>().unwrap().to_string());
+        assert!("Vi".parse::<RomanNumeral>().is_err());
+        assert!("VIII".parse::<RomanNumeral>().is_err());
+    }
+
+    #[test]
+    fn test_progression_chords() {
+        let numerals = ["I", "vi", "IV", "V7"]
+            .iter()
+            .map(|numeral| numeral.parse::<RomanNumeral>().unwrap())
+            .collect::<Vec<RomanNumeral>>();
+        let tonic = libatm::MIDINote::new(libatm::MIDINoteType::C, 4);
+        let progressions = ProgressionSet::new(&numerals, 4, tonic, false, Voicing::Close).unwrap();
+        assert_eq!(256, progressions.patterns().num_patterns());
+        let chords = progressions.gen_chords(&numerals);
+        let formatted = chords.iter().map(|chord| crate::chord::format_chord(chord)).collect::<Vec<String>>();
+        assert_eq!(vec!["C:4+E:4+G:4", "A:4+C:5+E:5", "F:4+A:4+C:5", "G:4+B:4+D:5+F:5"], formatted);
+        // Numerals are generated in sorted order (by scale degree)
+        assert_eq!("I-I-IV-V7", format_progression(&progressions.patterns().gen_pattern_at(6).unwrap()));
+    }
+}
@@ -0,0 +1,305 @@
+// rhythm.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::pattern::PatternSet;
+
+/// Largest delta time that can be written as a variable-length quantity
+const MAX_TICKS: u64 = 0x0FFF_FFFF;
+
+/*************************
+***** Note Durations *****
+*************************/
+
+/// Error type for parsing [NoteDuration](struct.NoteDuration.html) from `&str`
+#[derive(Debug, thiserror::Error)]
+pub enum ParseNoteDurationError {
+    #[error("Invalid note duration {input} (expected fraction of a whole note, i.e., '1/8')")]
+    InvalidFormat { input: String },
+    #[error("Note duration must be greater than 0 (found {input})")]
+    Zero { input: String },
+}
+
+/// Duration of a note as a fraction of a whole note (i.e., `1/8` for an eighth note),
+/// always in lowest terms
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NoteDuration {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+impl NoteDuration {
+    /// Length of duration in ticks, for MIDI files with `division` ticks per quarter note.
+    /// Returns `None` if the duration isn't a whole number of ticks, or is too long to be
+    /// written as a delta time.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// let eighth = "1/8".parse::<atm::rhythm::NoteDuration>().unwrap();
+    /// assert_eq!(Some(240), eighth.gen_ticks(480));
+    /// assert_eq!(None, eighth.gen_ticks(1));
+    /// ```
+    pub fn gen_ticks(&self, division: u16) -> Option<u32> {
+        let whole_note_ticks = 4 * division as u64 * self.numerator as u64;
+        if !whole_note_ticks.is_multiple_of(self.denominator as u64) {
+            return None;
+        }
+        let ticks = whole_note_ticks / self.denominator as u64;
+        if ticks > MAX_TICKS {
+            return None;
+        }
+        Some(ticks as u32)
+    }
+}
+
+impl Ord for NoteDuration {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.numerator as u64 * other.denominator as u64)
+            .cmp(&(other.numerator as u64 * self.denominator as u64))
+    }
+}
+
+impl PartialOrd for NoteDuration {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::str::FromStr for NoteDuration {
+    type Err = ParseNoteDurationError;
+
+    /// Parse duration from fraction (i.e., '1/8', '3/16') or whole number of whole notes
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let invalid_format = || ParseNoteDurationError::InvalidFormat { input: input.to_string() };
+        let (numerator, denominator) = match input.trim().split_once('/') {
+            Some((numerator, denominator)) => (numerator, denominator),
+            None => (input.trim(), "1"),
+        };
+        let numerator = numerator.parse::<u32>().map_err(|_| invalid_format())?;
+        let denominator = denominator.parse::<u32>().map_err(|_| invalid_format())?;
+        if numerator == 0 || denominator == 0 {
+            return Err(ParseNoteDurationError::Zero { input: input.to_string() });
+        }
+        let divisor = gcd(numerator as u64, denominator as u64) as u32;
+        Ok(Self { numerator: numerator / divisor, denominator: denominator / divisor })
+    }
+}
+
+impl std::fmt::Display for NoteDuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.denominator {
+            1 => write!(f, "{}", self.numerator),
+            _ => write!(f, "{}/{}", self.numerator, self.denominator),
+        }
+    }
+}
+
+/// Generate hash of rhythm pattern, appended to the melody hash in the filename of MIDI files
+/// generated with rhythms: durations joined by `.`, each written as `<numerator>-<denominator>`.
+///
+/// # Examples
+///
+/// ```rust
+/// let rhythm = ["1/8", "1/4", "1"]
+///     .iter()
+///     .map(|duration| duration.parse::<atm::rhythm::NoteDuration>().unwrap())
+///     .collect::<Vec<_>>();
+/// assert_eq!("1-8.1-4.1-1", atm::rhythm::gen_rhythm_hash(&rhythm));
+/// ```
+pub fn gen_rhythm_hash(rhythm: &[NoteDuration]) -> String {
+    rhythm
+        .iter()
+        .map(|duration| format!("{}-{}", duration.numerator, duration.denominator))
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+/// Parse rhythm pattern from its hash (see: [gen_rhythm_hash](fn.gen_rhythm_hash.html)),
+/// or `None` if it isn't a rhythm hash
+///
+/// # Examples
+///
+/// ```rust
+/// let rhythm = atm::rhythm::parse_rhythm_hash("1-8.1-4.1-1").unwrap();
+/// assert_eq!("1/8,1/4,1", atm::rhythm::format_rhythm(&rhythm));
+/// assert!(atm::rhythm::parse_rhythm_hash("v33.80").is_none());
+/// ```
+pub fn parse_rhythm_hash(hash: &str) -> Option<Vec<NoteDuration>> {
+    hash
+        .split('.')
+        .map(|duration| {
+            let (numerator, denominator) = duration.split_once('-')?;
+            format!("{}/{}", numerator, denominator).parse::<NoteDuration>().ok()
+        })
+        .collect()
+}
+
+/// Format rhythm pattern as comma-separated durations (i.e., '1/8,1/4,1/2')
+pub fn format_rhythm(rhythm: &[NoteDuration]) -> String {
+    rhythm.iter().map(|duration| duration.to_string()).collect::<Vec<String>>().join(",")
+}
+
+/*********************
+***** Rhythm Set *****
+*********************/
+
+/// Error type for [RhythmSet](struct.RhythmSet.html)
+#[derive(Debug, thiserror::Error)]
+pub enum RhythmSetError {
+    #[error("Must provide at least one note duration")]
+    Empty,
+    #[error("Durations have no common subdivision that fits in a 64-bit integer")]
+    NoCommonUnit,
+    #[error("Duration {duration} isn't a whole number of ticks with division {division}, \
+            or is too long to be written as a delta time (see: --division)")]
+    InvalidTicks { duration: NoteDuration, division: u16 },
+    #[error("No rhythm pattern of {length} notes fills {bars} bars")]
+    NoPatterns { length: u32, bars: u32 },
+    #[error("Number of rhythm patterns of {length} notes overflows a 64-bit index")]
+    TooManyPatterns { length: u32 },
+}
+
+/// Set of rhythm patterns melodies are generated with: every sequence of `length` durations
+/// from a set of note durations, or only those that fill a number of bars (of 4/4) exactly.
+/// Patterns are generated in lexicographic order from the shortest duration (see:
+/// [PatternSet](../pattern/struct.PatternSet.html)).
+///
+/// # Examples
+///
+/// ```rust
+/// let durations = [
+///     "1/4".parse::<atm::rhythm::NoteDuration>().unwrap(),
+///     "1/2".parse::<atm::rhythm::NoteDuration>().unwrap(),
+/// ];
+/// // Patterns of 3 notes that fill one bar: 1/4,1/4,1/2 and 1/4,1/2,1/4 and 1/2,1/4,1/4
+/// let rhythms = atm::rhythm::RhythmSet::new(&durations, 3, Some(1)).unwrap();
+/// assert_eq!(3, rhythms.patterns().num_patterns());
+/// let rhythm = rhythms.patterns().gen_pattern_at(1).unwrap();
+/// assert_eq!("1/4,1/2,1/4", atm::rhythm::format_rhythm(&rhythm));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct RhythmSet {
+    /// Rhythm patterns, with durations weighted by their length if restricted to bars
+    patterns: PatternSet<NoteDuration>,
+    /// Number of bars patterns must fill (if restricted)
+    bars: Option<u32>,
+}
+
+impl RhythmSet {
+    /// Create new `RhythmSet` instance for patterns of `length` notes (that fill `bars`
+    /// bars, if provided). Durations are sorted, and duplicates removed.
+    pub fn new(durations: &[NoteDuration], length: u32, bars: Option<u32>) -> Result<Self, RhythmSetError> {
+        let mut durations = durations.to_vec();
+        durations.sort();
+        durations.dedup();
+        if durations.is_empty() {
+            return Err(RhythmSetError::Empty);
+        }
+
+        let patterns = match bars {
+            Some(bars) => {
+                // Convert durations to multiples of the smallest common unit, by way of the
+                // least common multiple of their denominators (the number of subdivisions
+                // of a whole note)
+                let subdivisions = durations.iter().try_fold(1u64, |subdivisions, duration| {
+                    let denominator = duration.denominator as u64;
+                    (subdivisions / gcd(subdivisions, denominator)).checked_mul(denominator)
+                }).ok_or(RhythmSetError::NoCommonUnit)?;
+                let subdivided = durations
+                    .iter()
+                    .map(|duration| (duration.numerator as u64).checked_mul(subdivisions / duration.denominator as u64))
+                    .collect::<Option<Vec<u64>>>()
+                    .ok_or(RhythmSetError::NoCommonUnit)?;
+                let unit = subdivided.iter().fold(0, |unit, subdivided| gcd(unit, *subdivided));
+                let units = subdivided.iter().map(|subdivided| subdivided / unit).collect::<Vec<u64>>();
+
+                let bar_subdivisions = (bars as u64).checked_mul(subdivisions).ok_or(RhythmSetError::NoCommonUnit)?;
+                let target = bar_subdivisions / unit;
+                let min_units = units[0] * length as u64;
+                let max_units = units[units.len() - 1] * length as u64;
+                if !bar_subdivisions.is_multiple_of(unit) || target < min_units || target > max_units {
+                    return Err(RhythmSetError::NoPatterns { length, bars });
+                }
+                let patterns = PatternSet::with_total_weight(durations, units, length, target)
+                    .ok_or(RhythmSetError::TooManyPatterns { length })?;
+                if patterns.num_patterns() == 0 {
+                    return Err(RhythmSetError::NoPatterns { length, bars });
+                }
+                patterns
+            },
+            None => PatternSet::new(durations, length).ok_or(RhythmSetError::TooManyPatterns { length })?,
+        };
+
+        Ok(Self { patterns, bars })
+    }
+
+    /// Rhythm patterns in set
+    pub fn patterns(&self) -> &PatternSet<NoteDuration> {
+        &self.patterns
+    }
+
+    /// Durations patterns are generated from (in generation order)
+    pub fn durations(&self) -> &[NoteDuration] {
+        self.patterns.symbols()
+    }
+
+    /// Number of bars patterns fill (if restricted to bars)
+    pub fn bars(&self) -> Option<u32> {
+        self.bars
+    }
+
+    /// Ensure every duration can be rendered in MIDI files with `division` ticks per quarter
+    /// note (see: [NoteDuration::gen_ticks](struct.NoteDuration.html#method.gen_ticks))
+    pub fn check_division(&self, division: u16) -> Result<(), RhythmSetError> {
+        match self.durations().iter().find(|duration| duration.gen_ticks(division).is_none()) {
+            Some(duration) => Err(RhythmSetError::InvalidTicks { duration: *duration, division }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Display for RhythmSet {
+    /// Format rhythm set as `durations=<durations>` (separated by `;`),
+    /// followed by `,bars=<bars>` if patterns are restricted to bars
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "durations={}",
+            self.durations().iter().map(|d| d.to_string()).collect::<Vec<String>>().join(";"),
+        )?;
+        match self.bars {
+            Some(bars) => write!(f, ",bars={}", bars),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_durations(input: &str) -> Vec<NoteDuration> {
+        input.split(',').map(|duration| duration.parse::<NoteDuration>().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_num_patterns_in_bars() {
+        // Compositions of 8 eighth notes into 4 parts of 1, 2 or 4 eighth notes
+        let durations = parse_durations("1/8,1/4,1/2");
+        let rhythms = RhythmSet::new(&durations, 4, Some(1)).unwrap();
+        assert_eq!(13, rhythms.patterns().num_patterns());
+        assert!(RhythmSet::new(&durations, 4, Some(3)).is_err());
+        // Eighth notes can't be written with one tick per quarter note
+        assert!(rhythms.check_division(1).is_err());
+        assert!(rhythms.check_division(2).is_ok());
+    }
+}
@@ -7,17 +7,66 @@
 // a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
 
 pub(crate) mod tar_archive;
+/// Reader for Tar-based archives
+pub mod archive_reader;
 /// Batch archive storage backend
 pub mod batch_tar_file;
+/// Checkpoints for resuming interrupted runs
+pub mod checkpoint;
+/// Directory tree storage backend
+pub mod directory;
+/// Bit-packed melody storage backend
+pub mod pack_file;
 /// Tar archive storage backend
 pub mod tar_file;
 /// Gzip-compressed Tar archive storage backend
 pub mod tar_gz_file;
+/// Zstandard-compressed Tar archive storage backend
+pub mod tar_zst_file;
+/// ZIP archive storage backend
+pub mod zip_file;
 
 pub use tar_archive::*;
-pub use batch_tar_file::BatchTarFile;
+pub use archive_reader::{ArchiveEntry, ArchiveFormat, ArchiveReader};
+pub use batch_tar_file::{BatchCompression, BatchOptions, BatchTarFile};
+pub use checkpoint::{BackendCheckpoint, Checkpoint, PositionWriter};
+pub use directory::DirectoryBackend;
+pub use pack_file::{PackFile, PackHeader, PackPartition, PackReader};
 pub use tar_file::TarFile;
 pub use tar_gz_file::TarGzFile;
+pub use tar_zst_file::{TarZstFile, ZstdOptions};
+pub use zip_file::{ZipFile, ZipMethod};
+
+/// Path of the render manifest of a dataset: an entry at the root of archives (or a file in
+/// the root directory of directory trees) with the options its MIDI files were rendered with
+/// (see: [MIDIRenderOptions::gen_manifest](../midi/struct.MIDIRenderOptions.html#method.gen_manifest))
+pub const RENDER_MANIFEST_PATH: &str = "atm-render.txt";
+
+/***************************
+***** PreparedMIDIFile *****
+***************************/
+
+/// MIDI file with its hash and serialized data already generated. Allows the expensive
+/// work of appending a file to a storage backend to happen on a different thread than
+/// the one that owns the backend.
+pub struct PreparedMIDIFile {
+    /// Source MIDI file
+    pub mfile: libatm::MIDIFile,
+    /// MIDI file hash (see:
+    /// [gen_melody_hash](../utils/fn.gen_melody_hash.html))
+    pub hash: String,
+    /// MIDI file data as written to disk
+    pub data: Vec<u8>,
+}
+
+impl PreparedMIDIFile {
+    /// Create new `PreparedMIDIFile` instance from MIDI file
+    pub fn new(mfile: libatm::MIDIFile) -> std::io::Result<Self> {
+        let hash = crate::utils::gen_melody_hash(&mfile.sequence);
+        let data = mfile.gen_file()?;
+        Ok(Self { mfile, hash, data })
+    }
+}
 
 /*****************
 ***** Traits *****
@@ -32,11 +81,38 @@ pub trait StorageBackend : Sized {
     /// Append MIDI file to storage backend
     fn append_file(&mut self, mfile: libatm::MIDIFile, mode: Option<u32>) -> Result<(), Self::Error>;
 
-    /// Convert melody to MIDI file and append to storage backend
-    fn append_melody(&mut self, melody: libatm::MIDINoteVec, mode: Option<u32>) -> Result<(), Self::Error> {
-        // Create libatm::MIDIFile instance from melody
-        let mfile = libatm::MIDIFile::new(melody, libatm::MIDIFormat::Format0, 1, 1);
-        self.append_file(mfile, mode)
+    /// Render melody as MIDI file with provided options and append to storage backend
+    fn append_melody(
+        &mut self,
+        melody: libatm::MIDINoteVec,
+        render: &crate::midi::MIDIRenderOptions,
+        mode: Option<u32>,
+    ) -> Result<(), Self::Error> {
+        self.append_prepared(render.prepare(melody), mode)
+    }
+
+    /// Append MIDI file with precomputed hash and data to storage backend
+    /// (see: [PreparedMIDIFile](struct.PreparedMIDIFile.html)).
+    ///
+    /// NOTE: The default implementation discards the precomputed values and falls back
+    /// to [append_file](#tymethod.append_file). Backends that can reuse them should
+    /// override this method.
+    fn append_prepared(&mut self, pfile: PreparedMIDIFile, mode: Option<u32>) -> Result<(), Self::Error> {
+        self.append_file(pfile.mfile, mode)
+    }
+
+    /// Record options MIDI files are rendered with in storage backend, where `manifest` is the
+    /// render manifest of the dataset (see: [RENDER_MANIFEST_PATH](constant.RENDER_MANIFEST_PATH.html)).
+    /// Should be called before the first MIDI file is appended, and not when resuming.
+    fn append_manifest(&mut self, manifest: &str) -> Result<(), Self::Error>;
+
+    /// Write all data appended so far to the target (as far as possible), and return the state
+    /// needed to resume writing from that point (see:
+    /// [BackendCheckpoint](checkpoint/struct.BackendCheckpoint.html)).
+    ///
+    /// NOTE: Returns `None` by default, for backends that don't support checkpoints.
+    fn checkpoint(&mut self) -> Result<Option<BackendCheckpoint>, Self::Error> {
+        Ok(None)
     }
 
     /// Conduct cleanup of storage backend and close for writing
@@ -59,15 +135,30 @@ pub trait IntoInner : StorageBackend {
 /// Error type for [PathGenerator](trait.PathGenerator.html)
 #[derive(Debug, thiserror::Error)]
 pub enum PathGeneratorError {
+    /// [CounterpointPathGenerator](struct.CounterpointPathGenerator.html) error
+    #[error(transparent)]
+    CounterpointPathGenerator(#[from] CounterpointPathGeneratorError),
+    /// [DrumPathGenerator](struct.DrumPathGenerator.html) error
+    #[error(transparent)]
+    DrumPathGenerator(#[from] DrumPathGeneratorError),
     /// [PartitionPathGenerator](struct.PartitionPathGenerator.html) error
     #[error(transparent)]
     PartitionPathGenerator(#[from] PartitionPathGeneratorError),
+    /// [ProgressionPathGenerator](struct.ProgressionPathGenerator.html) error
+    #[error(transparent)]
+    ProgressionPathGenerator(#[from] ProgressionPathGeneratorError),
 }
 
 /// Trait to generate storage path for MIDI files in storage backends
 pub trait PathGenerator {
     /// Generate storage path for MIDI file
     fn gen_path_for_file(&self, mfile: &libatm::MIDIFile) -> Result<String, PathGeneratorError>;
+
+    /// Generate storage path for prepared MIDI file. Path generators that use the
+    /// MIDI file hash should override this method to reuse the precomputed hash.
+    fn gen_path_for_prepared(&self, pfile: &PreparedMIDIFile) -> Result<String, PathGeneratorError> {
+        self.gen_path_for_file(&pfile.mfile)
+    }
 }
 
 /********************************
@@ -76,13 +167,17 @@ pub trait PathGenerator {
 
 /// Path generator that produces the hash of a MIDI file as the filename
 /// without any parent directories (see:
-/// [MIDIFile::gen_hash](../../libatm/midi_file/struct.MIDIFile.html#method.gen_hash)).
+/// [gen_melody_hash](../utils/fn.gen_melody_hash.html)).
 /// This path generator is useful for smaller datasets.
 pub struct MIDIHashPathGenerator;
 
 impl PathGenerator for MIDIHashPathGenerator {
     fn gen_path_for_file(&self, mfile: &libatm::MIDIFile) -> Result<String, PathGeneratorError> {
-        Ok(format!("{}.mid", mfile.gen_hash()))
+        Ok(format!("{}.mid", crate::utils::gen_melody_hash(&mfile.sequence)))
+    }
+
+    fn gen_path_for_prepared(&self, pfile: &PreparedMIDIFile) -> Result<String, PathGeneratorError> {
+        Ok(format!("{}.mid", pfile.hash))
     }
 }
 
@@ -112,8 +207,12 @@ pub enum PartitionPathGeneratorError {
 /// ensure no more than some threshold files are written to a directory
 /// (see: [gen_partition_length](struct.PartitionPathGenerator.html#method.gen_partition_length)).
 pub struct PartitionPathGenerator {
+    /// Number of distinct notes melodies are generated from
+    num_notes: u32,
     /// Length of melodies to generate partitions for
     melody_length: u32,
+    /// Number of variations (rhythm and dynamics patterns) each melody is generated with
+    num_variations: u64,
     /// Partition depth (i.e., number of partitions to generate)
     partition_depth: u32,
     /// Number of MIDI notes per partition
@@ -152,6 +251,20 @@ impl PartitionPathGenerator {
         melody_length: u32,
         max_files: u32,
         partition_depth: u32
+    ) -> Result<Self, PartitionPathGeneratorError> {
+        Self::new_with_variations(num_notes, melody_length, 1, max_files, partition_depth)
+    }
+
+    /// Create new `PartitionPathGenerator` instance for melodies generated with `num_variations`
+    /// variations each (see: [VariationSet](../variation/struct.VariationSet.html)). Partitions
+    /// only depend on the pitches of melodies, so every variation of a melody is stored in the
+    /// same partition.
+    pub fn new_with_variations(
+        num_notes: u32,
+        melody_length: u32,
+        num_variations: u64,
+        max_files: u32,
+        partition_depth: u32
     ) -> Result<Self, PartitionPathGeneratorError> {
         // Ensure partition depth is less than length of generated melodies
         if partition_depth > melody_length as u32 {
@@ -161,8 +274,8 @@ impl PartitionPathGenerator {
             });
         }
 
-        // Generate total number of melodies
-        let num_melodies = crate::utils::gen_num_melodies(num_notes, melody_length);
+        // Generate total number of melodies (including variations)
+        let num_melodies = crate::utils::gen_num_melodies(num_notes, melody_length).saturating_mul(num_variations);
         // If number of notes is 1, or total number of generated melodies is
         // less than max files per directory, then partition depth should be 1
         // and partition length should be 0
@@ -181,12 +294,36 @@ impl PartitionPathGenerator {
         }
 
         Ok(Self {
+            num_notes,
             melody_length: melody_length as u32,
+            num_variations,
             partition_depth: calc_partition_depth,
             partition_length: calc_partition_length,
         })
     }
 
+    /// Generate number of melodies per partition. Melodies are generated in lexicographic
+    /// order, and the partition path is a prefix of the melody, so each partition holds a
+    /// contiguous range of this many melodies (each with every variation).
+    pub fn gen_num_melodies_per_partition(&self) -> u64 {
+        crate::utils::gen_num_melodies(
+            self.num_notes,
+            self.melody_length - (self.partition_depth * self.partition_length),
+        ) * self.num_variations
+    }
+
+    /// Generate number of partition directories (excluding the root directory).
+    /// Each of the `partition_depth` levels has `num_notes^partition_length` times
+    /// as many directories as the level above it.
+    pub fn gen_num_directories(&self) -> u64 {
+        if self.partition_length == 0 {
+            return 0;
+        }
+        (1..=self.partition_depth)
+            .map(|depth| crate::utils::gen_num_melodies(self.num_notes, depth * self.partition_length))
+            .sum()
+    }
+
     /// Generate basename (parent directory/directories) for filepath
     fn gen_basename_for_file(&self, mfile: &libatm::MIDIFile) -> Result<String, PathGeneratorError> {
         // Ensure melody is expected length
@@ -207,7 +344,7 @@ impl PartitionPathGenerator {
             //  1) Generating self.partition_depth slices of size self.partition_length over the input
             //     melody by using a sliding window method
             //  2) Converting each slice into a string of integer representations of each note in the
-            //     slice
+            //     slice (the same as the MIDI file hash, so rests are written as 'R')
             //  3) Joining the slices together using the OS path separator
             _ => Ok((0..self.partition_depth)
                 .map(|p| {
@@ -215,11 +352,23 @@ impl PartitionPathGenerator {
                         ( (self.partition_length * p) as usize )..( (self.partition_length * (p + 1)) as usize )
                     ]
                 })
-                .map(|p| p.iter().map(|n| n.convert().to_string()).collect::<Vec<String>>().join(""))
+                .map(crate::utils::gen_melody_hash)
                 .collect::<Vec<String>>()
                 .join(&std::path::MAIN_SEPARATOR.to_string()))
         }
     }
+
+    /// Join basename and filename generated from MIDI file hash
+    fn join_basename_and_hash(basename: &str, hash: &str) -> String {
+        let filename = format!("{}.mid", hash);
+        format!(
+            "{}",
+            std::path::Path::new(basename)
+                .join(&filename)
+                .as_path()
+                .to_string_lossy(),
+        )
+    }
 }
 
 impl PathGenerator for PartitionPathGenerator {
@@ -227,14 +376,191 @@ impl PathGenerator for PartitionPathGenerator {
         // Generate basename (could be "")
         let basename = self.gen_basename_for_file(mfile)?;
         // Generate filename from MIDI file hash
-        let filename = format!("{}.mid", mfile.gen_hash());
-        Ok(format!(
-            "{}",
-            std::path::Path::new(&basename)
-                .join(&filename)
-                .as_path()
-                .to_string_lossy(),
-        ))
+        Ok(Self::join_basename_and_hash(&basename, &crate::utils::gen_melody_hash(&mfile.sequence)))
+    }
+
+    fn gen_path_for_prepared(&self, pfile: &PreparedMIDIFile) -> Result<String, PathGeneratorError> {
+        let basename = self.gen_basename_for_file(&pfile.mfile)?;
+        Ok(Self::join_basename_and_hash(&basename, &pfile.hash))
+    }
+}
+
+/***********************************
+***** ProgressionPathGenerator *****
+***********************************/
+
+/// Error type for [ProgressionPathGenerator](struct.ProgressionPathGenerator.html)
+#[derive(Debug, thiserror::Error)]
+pub enum ProgressionPathGeneratorError {
+    #[error("Partition depth must be less than the length of generated progressions \
+            ({partition_depth} > {progression_length})")]
+    PartitionDepthLongerThanProgression { partition_depth: u32, progression_length: u32, },
+    #[error("Path of progression can only be generated from its hash (expected prepared MIDI file)")]
+    MissingHash,
+}
+
+/// Path generator for chord progressions (see:
+/// [ProgressionSet](../progression/struct.ProgressionSet.html)), which names files by their
+/// numeral sequence (i.e., `I-vi-IV-V7.mid`). With partition depth N, files are partitioned by
+/// their first N numerals (i.e., `I/vi/I-vi-IV-V7.mid` with depth 2).
+pub struct ProgressionPathGenerator {
+    /// Number of numerals to partition by
+    partition_depth: u32,
+}
+
+impl ProgressionPathGenerator {
+    /// Create new `ProgressionPathGenerator` instance for progressions of length `progression_length`
+    pub fn new(progression_length: u32, partition_depth: u32) -> Result<Self, ProgressionPathGeneratorError> {
+        if partition_depth >= progression_length {
+            return Err(ProgressionPathGeneratorError::PartitionDepthLongerThanProgression {
+                partition_depth,
+                progression_length,
+            });
+        }
+        Ok(Self { partition_depth })
+    }
+}
+
+impl PathGenerator for ProgressionPathGenerator {
+    fn gen_path_for_file(&self, _mfile: &libatm::MIDIFile) -> Result<String, PathGeneratorError> {
+        // Numerals can't be recovered from the notes of the MIDI file
+        Err(ProgressionPathGeneratorError::MissingHash.into())
+    }
+
+    fn gen_path_for_prepared(&self, pfile: &PreparedMIDIFile) -> Result<String, PathGeneratorError> {
+        let mut path = pfile
+            .hash
+            .split(crate::progression::PROGRESSION_SEPARATOR)
+            .take(self.partition_depth as usize)
+            .collect::<std::path::PathBuf>();
+        path.push(format!("{}.mid", pfile.hash));
+        Ok(path.to_string_lossy().to_string())
+    }
+}
+
+/************************************
+***** CounterpointPathGenerator *****
+************************************/
+
+/// Error type for [CounterpointPathGenerator](struct.CounterpointPathGenerator.html)
+#[derive(Debug, thiserror::Error)]
+pub enum CounterpointPathGeneratorError {
+    #[error("Partition depth must be less than the length of generated voices \
+            ({partition_depth} > {length})")]
+    PartitionDepthLongerThanVoices { partition_depth: u32, length: u32, },
+    #[error("Expected two voices of length {expected}, found {observed} notes")]
+    LengthMismatch { expected: u32, observed: u32, },
+}
+
+/// Path generator for pairs of voices (see:
+/// [CounterpointSet](../counterpoint/struct.CounterpointSet.html)), which names files by the
+/// hash of both voices (i.e., `606467_485543.mid`). With partition depth N, files are
+/// partitioned by the notes of both voices at their first N positions (i.e.,
+/// `60_48/606467_485543.mid` with depth 1).
+pub struct CounterpointPathGenerator {
+    /// Number of notes per voice
+    length: u32,
+    /// Number of positions to partition by
+    partition_depth: u32,
+}
+
+impl CounterpointPathGenerator {
+    /// Create new `CounterpointPathGenerator` instance for voices of `length` notes
+    pub fn new(length: u32, partition_depth: u32) -> Result<Self, CounterpointPathGeneratorError> {
+        if partition_depth >= length {
+            return Err(CounterpointPathGeneratorError::PartitionDepthLongerThanVoices {
+                partition_depth,
+                length,
+            });
+        }
+        Ok(Self { length, partition_depth })
+    }
+
+    /// Generate basename (parent directories) for MIDI file, whose sequence is the
+    /// melody followed by the bass
+    fn gen_basename_for_file(&self, mfile: &libatm::MIDIFile) -> Result<std::path::PathBuf, PathGeneratorError> {
+        let num_notes = mfile.sequence.len() as u32;
+        if num_notes != self.length * crate::counterpoint::NUM_VOICES as u32 {
+            return Err(CounterpointPathGeneratorError::LengthMismatch {
+                expected: self.length,
+                observed: num_notes,
+            }.into());
+        }
+        let (melody, bass) = mfile.sequence.split_at(self.length as usize);
+        Ok((0..self.partition_depth as usize)
+            .map(|position| {
+                crate::counterpoint::gen_counterpoint_hash(&melody[position..=position], &bass[position..=position])
+            })
+            .collect())
+    }
+}
+
+impl PathGenerator for CounterpointPathGenerator {
+    fn gen_path_for_file(&self, mfile: &libatm::MIDIFile) -> Result<String, PathGeneratorError> {
+        let mut path = self.gen_basename_for_file(mfile)?;
+        let (melody, bass) = mfile.sequence.split_at(self.length as usize);
+        path.push(format!("{}.mid", crate::counterpoint::gen_counterpoint_hash(melody, bass)));
+        Ok(path.to_string_lossy().to_string())
+    }
+
+    fn gen_path_for_prepared(&self, pfile: &PreparedMIDIFile) -> Result<String, PathGeneratorError> {
+        let mut path = self.gen_basename_for_file(&pfile.mfile)?;
+        path.push(format!("{}.mid", pfile.hash));
+        Ok(path.to_string_lossy().to_string())
+    }
+}
+
+/****************************
+***** DrumPathGenerator *****
+****************************/
+
+/// Error type for [DrumPathGenerator](struct.DrumPathGenerator.html)
+#[derive(Debug, thiserror::Error)]
+pub enum DrumPathGeneratorError {
+    #[error("Partition depth must be less than the number of instruments of generated drum patterns \
+            ({partition_depth} > {num_parts})")]
+    PartitionDepthLongerThanPattern { partition_depth: u32, num_parts: u32, },
+    #[error("Path of drum pattern can only be generated from its hash (expected prepared MIDI file)")]
+    MissingHash,
+}
+
+/// Path generator for drum patterns (see:
+/// [DrumPatternSet](../drums/struct.DrumPatternSet.html)), which names files by the steps of
+/// every instrument (i.e., `kick-8888_snare-0808_hat-aaaa.mid`). With partition depth N, files
+/// are partitioned by the steps of their first N instruments (i.e.,
+/// `kick-8888/kick-8888_snare-0808_hat-aaaa.mid` with depth 1).
+pub struct DrumPathGenerator {
+    /// Number of instruments to partition by
+    partition_depth: u32,
+}
+
+impl DrumPathGenerator {
+    /// Create new `DrumPathGenerator` instance for drum patterns of `num_parts` instruments
+    pub fn new(num_parts: u32, partition_depth: u32) -> Result<Self, DrumPathGeneratorError> {
+        if partition_depth >= num_parts {
+            return Err(DrumPathGeneratorError::PartitionDepthLongerThanPattern {
+                partition_depth,
+                num_parts,
+            });
+        }
+        Ok(Self { partition_depth })
+    }
+}
+
+impl PathGenerator for DrumPathGenerator {
+    fn gen_path_for_file(&self, _mfile: &libatm::MIDIFile) -> Result<String, PathGeneratorError> {
+        // Instruments hit together can't be told apart in the sequence of the MIDI file
+        Err(DrumPathGeneratorError::MissingHash.into())
+    }
+
+    fn gen_path_for_prepared(&self, pfile: &PreparedMIDIFile) -> Result<String, PathGeneratorError> {
+        let mut path = pfile
+            .hash
+            .split(crate::drums::DRUM_PART_SEPARATOR)
+            .take(self.partition_depth as usize)
+            .collect::<std::path::PathBuf>();
+        path.push(format!("{}.mid", pfile.hash));
+        Ok(path.to_string_lossy().to_string())
     }
 }
 
@@ -270,6 +596,26 @@ mod tests {
         path_generator.gen_path_for_file(&mfile).unwrap();
     }
 
+    /************************************
+    ***** CounterpointPathGenerator *****
+    ************************************/
+
+    #[test]
+    fn test_counterpoint_path() {
+        let path_generator = CounterpointPathGenerator::new(3, 1).unwrap();
+        let mfile = libatm::MIDIFile::new(
+            crate::utils::parse_note_vec("C:4,E:4,G:4,C:3,R,G:2").unwrap(),
+            libatm::MIDIFormat::Format1,
+            2,
+            1,
+        );
+        let expected = std::path::Path::new("60_48").join("606467_48R43.mid");
+        assert_eq!(expected.to_string_lossy(), path_generator.gen_path_for_file(&mfile).unwrap());
+        // Fails because sequence isn't two voices of 3 notes
+        let mfile = libatm::MIDIFile::new(mfile.sequence[..4].iter().copied().collect(), libatm::MIDIFormat::Format1, 2, 1);
+        assert!(path_generator.gen_path_for_file(&mfile).is_err());
+    }
+
     macro_rules! check_num_files_partition {
         ($test_name:ident, $note_set:expr, $melody_length:expr, $max_files:expr, $partition_depth:expr) => {
             #[test]
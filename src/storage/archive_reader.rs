@@ -0,0 +1,244 @@
+// archive_reader.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use std::io::{BufRead, Read};
+
+use crate::storage::{batch_tar_file::DICTIONARY_PATH, RENDER_MANIFEST_PATH};
+
+/// Gzip magic bytes
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// Zstandard frame magic bytes
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Maximum window size (as power of 2) of Zstandard frames
+/// (see: [ZstdOptions](../tar_zst_file/struct.ZstdOptions.html))
+const ZSTD_WINDOW_LOG_MAX: u32 = 31;
+
+/************************
+***** ArchiveFormat *****
+************************/
+
+/// Compression of a Tar archive, detected from its first bytes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArchiveFormat {
+    /// Uncompressed Tar archive (see: [TarFile](../tar_file/struct.TarFile.html)
+    /// and [BatchTarFile](../batch_tar_file/struct.BatchTarFile.html))
+    Tar,
+    /// Gzip-compressed Tar archive (see: [TarGzFile](../tar_gz_file/struct.TarGzFile.html))
+    TarGz,
+    /// Zstandard-compressed Tar archive (see: [TarZstFile](../tar_zst_file/struct.TarZstFile.html))
+    TarZst,
+}
+
+impl ArchiveFormat {
+    /// Detect archive format from magic bytes at the start of `data`
+    pub fn detect(data: &[u8]) -> Self {
+        if data.starts_with(GZIP_MAGIC) {
+            Self::TarGz
+        } else if data.starts_with(ZSTD_MAGIC) {
+            Self::TarZst
+        } else {
+            Self::Tar
+        }
+    }
+}
+
+/***********************
+***** ArchiveEntry *****
+***********************/
+
+/// Location of a MIDI file inside a batch of a
+/// [BatchTarFile](../batch_tar_file/struct.BatchTarFile.html)
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchLocation {
+    /// Path of batch entry in top-level archive (i.e., `<partition>/batch<N>.tar.gz`)
+    pub path: String,
+    /// Position of MIDI file in batch (starting from 0)
+    pub position: u32,
+}
+
+/// MIDI file read from an archive
+#[derive(Clone, Debug)]
+pub struct ArchiveEntry {
+    /// Path of entry, as generated by the archive's path generator. For files inside a batch,
+    /// this is the path the file would have in a non-batched archive (`<partition>/<hash>.mid`).
+    pub path: String,
+    /// File permissions
+    pub mode: u32,
+    /// Entry data
+    pub data: Vec<u8>,
+    /// Location in batch (if read from a batch)
+    pub batch: Option<BatchLocation>,
+}
+
+impl ArchiveEntry {
+    /// Partition (parent directory) of entry, or empty string if not partitioned
+    pub fn partition(&self) -> &str {
+        gen_parent_path(&self.path)
+    }
+
+    /// Whether entry is the render manifest of the dataset rather than a MIDI file
+    /// (see: [RENDER_MANIFEST_PATH](../constant.RENDER_MANIFEST_PATH.html))
+    pub fn is_render_manifest(&self) -> bool {
+        self.batch.is_none() && self.path == RENDER_MANIFEST_PATH
+    }
+
+    /// Hash of MIDI file, taken from the entry filename
+    pub fn hash(&self) -> &str {
+        let filename = &self.path[self.path.rfind(std::path::MAIN_SEPARATOR).map_or(0, |idx| idx + 1)..];
+        filename.strip_suffix(".mid").unwrap_or(filename)
+    }
+}
+
+/// Parent directory of entry path, or empty string if none (i.e., the partition
+/// of a MIDI file or batch)
+pub fn gen_parent_path(path: &str) -> &str {
+    match path.rfind(std::path::MAIN_SEPARATOR) {
+        Some(idx) => &path[..idx],
+        None => "",
+    }
+}
+
+/************************
+***** ArchiveReader *****
+************************/
+
+/// Reader for archives written by the Tar-based storage backends (plain, Gzip- or
+/// Zstandard-compressed, and nested batch archives). Entries are read in the order they
+/// were written, in a single pass and without extracting anything to disk. Batches
+/// are decompressed in memory, and their MIDI files read as if they were entries of
+/// the top-level archive (see: [ArchiveEntry](struct.ArchiveEntry.html)).
+///
+/// NOTE: Reading continues past end-of-archive markers, so shards concatenated with
+/// `cat` are read as a single archive.
+pub struct ArchiveReader {
+    /// Underlying reader (buffered to detect format)
+    reader: std::io::BufReader<Box<dyn std::io::Read>>,
+    /// Archive format
+    format: ArchiveFormat,
+}
+
+impl ArchiveReader {
+    /// Open archive at `path` (or stdin if `path` is `-`) and detect its format
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let reader: Box<dyn std::io::Read> = if path.as_os_str() == "-" {
+            Box::new(std::io::stdin())
+        } else {
+            Box::new(std::fs::File::open(path)?)
+        };
+        Self::from_reader(reader)
+    }
+
+    /// Create new `ArchiveReader` instance from reader, and detect archive format
+    pub fn from_reader(reader: Box<dyn std::io::Read>) -> std::io::Result<Self> {
+        let mut reader = std::io::BufReader::new(reader);
+        let format = ArchiveFormat::detect(reader.fill_buf()?);
+        Ok(Self { reader, format })
+    }
+
+    /// Archive format
+    pub fn format(&self) -> ArchiveFormat {
+        self.format
+    }
+
+    /// Call `callback` with every MIDI file in the archive (and the render manifest, see:
+    /// [ArchiveEntry::is_render_manifest](struct.ArchiveEntry.html#method.is_render_manifest)),
+    /// until it returns `ControlFlow::Break`. Batches are only decompressed (and their MIDI files read)
+    /// if `read_batch` returns true for the path of the batch entry.
+    pub fn for_each_entry<B, F>(self, mut read_batch: B, mut callback: F) -> std::io::Result<()>
+    where
+        B: FnMut(&str) -> bool,
+        F: FnMut(ArchiveEntry) -> std::io::Result<std::ops::ControlFlow<()>>,
+    {
+        let decoder: Box<dyn std::io::Read> = match self.format {
+            ArchiveFormat::Tar => Box::new(self.reader),
+            // Archives resumed from a checkpoint have multiple gzip members
+            ArchiveFormat::TarGz => Box::new(flate2::read::MultiGzDecoder::new(self.reader)),
+            ArchiveFormat::TarZst => {
+                let mut decoder = zstd::Decoder::with_buffer(self.reader)?;
+                decoder.window_log_max(ZSTD_WINDOW_LOG_MAX)?;
+                Box::new(decoder)
+            },
+        };
+        let mut archive = tar::Archive::new(decoder);
+        archive.set_ignore_zeros(true);
+
+        // Zstd dictionary for batches (if any)
+        let mut dictionary = Vec::new();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.to_string_lossy().into_owned();
+            let mode = entry.header().mode()?;
+            let mut data = Vec::with_capacity(entry.size() as usize);
+
+            // Batch dictionary
+            if path == DICTIONARY_PATH {
+                entry.read_to_end(&mut dictionary)?;
+                continue;
+            }
+            // MIDI file
+            let is_batch = path.ends_with(".tar.gz") || path.ends_with(".tar.zst");
+            if !is_batch {
+                entry.read_to_end(&mut data)?;
+                if callback(ArchiveEntry { path, mode, data, batch: None })?.is_break() {
+                    break;
+                }
+                continue;
+            }
+
+            // Batch of MIDI files
+            if !read_batch(&path) {
+                continue;
+            }
+            entry.read_to_end(&mut data)?;
+            let batch_decoder: Box<dyn std::io::Read + '_> = if path.ends_with(".tar.gz") {
+                Box::new(flate2::read::GzDecoder::new(data.as_slice()))
+            } else {
+                Box::new(zstd::Decoder::with_dictionary(data.as_slice(), &dictionary)?)
+            };
+            let flow = Self::for_each_batch_entry(batch_decoder, &path, &mut callback)?;
+            if flow.is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Call `callback` with every MIDI file in batch
+    fn for_each_batch_entry<F>(
+        batch_decoder: Box<dyn std::io::Read + '_>,
+        batch_path: &str,
+        callback: &mut F,
+    ) -> std::io::Result<std::ops::ControlFlow<()>>
+    where
+        F: FnMut(ArchiveEntry) -> std::io::Result<std::ops::ControlFlow<()>>,
+    {
+        let partition = gen_parent_path(batch_path);
+        let mut batch = tar::Archive::new(batch_decoder);
+        for (position, entry) in batch.entries()?.enumerate() {
+            let mut entry = entry?;
+            let filename = entry.path()?.to_string_lossy().into_owned();
+            let mode = entry.header().mode()?;
+            let mut data = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut data)?;
+            // Files in batches are named by hash only, so prepend partition
+            let path = if partition.is_empty() {
+                filename
+            } else {
+                format!("{}{}{}", partition, std::path::MAIN_SEPARATOR, filename)
+            };
+            let batch = Some(BatchLocation { path: batch_path.to_string(), position: position as u32 });
+            if callback(ArchiveEntry { path, mode, data, batch })?.is_break() {
+                return Ok(std::ops::ControlFlow::Break(()));
+            }
+        }
+        Ok(std::ops::ControlFlow::Continue(()))
+    }
+}
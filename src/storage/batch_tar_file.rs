@@ -6,45 +6,166 @@
 // To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
 // a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
 
+use std::io::Write;
+
 use flate2::{
     Compression,
     write::GzEncoder,
 };
 
 use crate::storage::{
+    checkpoint,
+    BackendCheckpoint,
     IntoInner,
     MIDIHashPathGenerator,
     PartitionPathGenerator,
-    PathGeneratorError,
+    PositionWriter,
+    PreparedMIDIFile,
     StorageBackend,
     StorageState,
     TarArchive,
     TarArchiveError,
+    RENDER_MANIFEST_PATH,
 };
 
-/// Type alias for `BatchTarFile` archive inner object
-type ArchiveInnerObject = std::io::BufWriter<std::fs::File>;
+/// Type alias for `BatchTarFile` default writer
+type FileWriter = std::io::BufWriter<std::fs::File>;
+
+/// Type alias for `BatchTarFile` batch inner object (batches are compressed when flushed)
+type BatchInnerObject = Vec<u8>;
+
+/// Path of zstd dictionary entry in top-level archive
+pub const DICTIONARY_PATH: &str = "batch.dict";
+
+/// Generate path of batch archive in top-level archive: `<partition>/batch<batch_number>.<extension>`,
+/// or `batch<batch_number>.<extension>` if melodies aren't partitioned (entry paths must be relative)
+pub fn gen_batch_path(partition: &str, batch_number: u64, extension: &str) -> String {
+    std::path::Path::new(partition)
+        .join(format!("batch{}.{}", batch_number, extension))
+        .to_string_lossy()
+        .to_string()
+}
+
+/***************************
+***** BatchCompression *****
+***************************/
+
+/// Compression to use for batch archives in a [BatchTarFile](struct.BatchTarFile.html)
+pub enum BatchCompression {
+    /// [Gzip](https://en.wikipedia.org/wiki/Gzip) with compression level
+    /// (batches are named `batch<N>.tar.gz`)
+    Gzip(Compression),
+    /// [Zstandard](https://facebook.github.io/zstd/) with compression level and dictionary
+    /// (batches are named `batch<N>.tar.zst`). If the dictionary isn't empty, it's stored as
+    /// the first entry of the top-level archive (see: [DICTIONARY_PATH](constant.DICTIONARY_PATH.html)),
+    /// and is required to decompress the batches (i.e., `zstd -D batch.dict -d batch0.tar.zst`).
+    Zstd { level: i32, dictionary: Vec<u8> },
+}
 
-/// Type alias for `BatchTarFile` batch inner object
-type BatchInnerObject = GzEncoder<std::io::BufWriter<Vec<u8>>>;
+impl Default for BatchCompression {
+    fn default() -> Self {
+        Self::Gzip(Compression::default())
+    }
+}
+
+impl BatchCompression {
+    /// Create zstd batch compression with dictionary of at most `dictionary_size` bytes,
+    /// trained from sample MIDI files. Each sample is written as it would appear in a batch
+    /// archive. If there isn't enough sample data to train a dictionary, the dictionary is empty.
+    pub fn zstd_from_samples(
+        level: i32,
+        samples: Vec<PreparedMIDIFile>,
+        dictionary_size: usize,
+    ) -> Result<Self, TarArchiveError> {
+        let samples = samples
+            .into_iter()
+            .map(|pfile| {
+                let mut sample = TarArchive::new(Vec::with_capacity(1024), MIDIHashPathGenerator);
+                sample.append_prepared(pfile, None)?;
+                Ok(sample.get_ref().clone())
+            })
+            .collect::<Result<Vec<Vec<u8>>, TarArchiveError>>()?;
+        let dictionary = zstd::dict::from_samples(&samples, dictionary_size).unwrap_or_default();
+        Ok(Self::Zstd { level, dictionary })
+    }
+}
+
+/// Compressor for batch archives
+enum BatchCompressor {
+    Gzip(Compression),
+    Zstd(zstd::bulk::Compressor<'static>),
+}
+
+impl BatchCompressor {
+    /// Create new `BatchCompressor` instance
+    fn new(batch_compression: &BatchCompression) -> std::io::Result<Self> {
+        match batch_compression {
+            BatchCompression::Gzip(level) => Ok(Self::Gzip(*level)),
+            BatchCompression::Zstd { level, dictionary } => Ok(Self::Zstd(
+                zstd::bulk::Compressor::with_dictionary(*level, dictionary)?
+            )),
+        }
+    }
+
+    /// Compress batch archive
+    fn compress(&mut self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip(level) => {
+                let mut encoder = GzEncoder::new(Vec::with_capacity(512), *level);
+                encoder.write_all(data)?;
+                encoder.finish()
+            },
+            Self::Zstd(compressor) => compressor.compress(data),
+        }
+    }
+
+    /// File extension of compressed batch archives
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Gzip(_) => "tar.gz",
+            Self::Zstd(_) => "tar.zst",
+        }
+    }
+}
+
+/***********************
+***** BatchOptions *****
+***********************/
+
+/// Options for batches of a [BatchTarFile](struct.BatchTarFile.html)
+pub struct BatchOptions {
+    /// Maximum number of files per batch
+    pub batch_size: u32,
+    /// Compression to use for batch archives
+    pub batch_compression: BatchCompression,
+    /// Permissions to use for entries in top-level archive file (644 if not set)
+    pub batch_mode: Option<u32>,
+}
+
+/***********************
+***** BatchTarFile *****
+***********************/
 
 /// Nested [tar archive](https://en.wikipedia.org/wiki/Tar_(computing)) storage backend,
 /// where each entry in the archive is a 
-/// [gzip](https://en.wikipedia.org/wiki/Gzip)-compressed tar archive containing MIDI files.
+/// [gzip](https://en.wikipedia.org/wiki/Gzip)-compressed (or
+/// [zstd](https://facebook.github.io/zstd/)-compressed, see:
+/// [BatchCompression](enum.BatchCompression.html)) tar archive containing MIDI files.
 /// Use for the largest datasets where compression, or output file size, is of the utmost
 /// importance. Choosing a batch size (and compression level) such that each compressed tar
 /// archive aligns with 512 bytes will ensure that no space is wasted in the top-level archive.
 /// For example, if a batch size of 25 compresses to 515 bytes, then each entry will take `1,536`
 /// bytes (512 for header plus 1024 for data). However, if a batch compresses to 510 bytes,
 /// then each entry will take 1024 bytes, with only 2 bytes extra. Keep in mind that higher
-/// compression levels will reduce throughput of the program.
-pub struct BatchTarFile {
+/// compression levels will reduce throughput of the program. Writes to a file by default,
+/// but can write to any `std::io::Write` (i.e., stdout, see: [from_writer](#method.from_writer)).
+pub struct BatchTarFile<W: std::io::Write = FileWriter> {
     /// Top-level archive file
-    archive: tar::Builder<ArchiveInnerObject>,
+    archive: tar::Builder<PositionWriter<W>>,
     /// Batch archive buffer
     batch_archive: TarArchive<BatchInnerObject, MIDIHashPathGenerator>,
-    /// Compression level to use for batch archive
-    batch_compression: Compression,
+    /// Compressor to use for batch archive
+    batch_compressor: BatchCompressor,
     /// Number of files in current batch
     batch_count: u32,
     /// Maximum number of files per batch
@@ -61,107 +182,106 @@ pub struct BatchTarFile {
     state: StorageState,
 }
 
-impl BatchTarFile {
+impl<W: std::io::Write> BatchTarFile<W> {
     /// Generate new batch archive
-    fn gen_batch_archive(compression_level: Compression) -> TarArchive<BatchInnerObject, MIDIHashPathGenerator> {
-        TarArchive::new(
-            GzEncoder::new(
-                std::io::BufWriter::new(Vec::with_capacity(512)),
-                compression_level,
-            ),
-            MIDIHashPathGenerator,
-        )
+    fn gen_batch_archive() -> TarArchive<BatchInnerObject, MIDIHashPathGenerator> {
+        TarArchive::new(Vec::with_capacity(512), MIDIHashPathGenerator)
     }
 
-    /// Create new `BatchTarFile` instance
-    pub fn new<P: AsRef<std::path::Path>>(
-        target_path: P,
-        batch_size: u32,
-        num_notes: u32,
-        melody_length: u32,
-        max_files: u32,
-        partition_depth: u32,
-        batch_compression: Option<Compression>,
-        batch_mode: Option<u32>,
+    /// Generate header for entry in top-level archive
+    fn gen_header(&self, size: u64) -> tar::Header {
+        let mut header = tar::Header::new_old();
+        header.set_size(size);
+        // Set file permissions to provided value,
+        // or 644 (rw-r-r) by default
+        match self.batch_mode {
+            Some(mode) => header.set_mode(mode),
+            None => header.set_mode(644),
+        }
+        header
+    }
+
+    /// Create new `BatchTarFile` instance from position writer
+    fn from_position_writer(
+        archive: PositionWriter<W>,
+        path_generator: PartitionPathGenerator,
+        options: &BatchOptions,
     ) -> Result<Self, TarArchiveError> {
         // Validate batch entries mode (must be integer <= 777)
-        if let Some(mode) = batch_mode {
+        if let Some(mode) = options.batch_mode {
             if mode > 777 {
-                return Err(TarArchiveError::IOError(std::io::Error::new(
-                    std::io::ErrorKind::Other,
+                return Err(TarArchiveError::IOError(std::io::Error::other(
                     format!("Invalid file mode {}", mode),
                 )));
             }
         }
 
-        // Open target file and initialize tar builder
-        let archive = tar::Builder::new(std::io::BufWriter::new(
-            std::fs::File::create(target_path)?
-        ));
-
-        // Generate partition manager
-        let path_generator = PartitionPathGenerator::new(
-            num_notes,
-            melody_length,
-            max_files,
-            partition_depth
-        ).map_err(|e| TarArchiveError::PathGenerator(
-            PathGeneratorError::PartitionPathGenerator(e)
-        ))?;
-
-        // Resolve batch compression
-        let batch_compression = match batch_compression {
-            Some(compression) => compression,
-            None => Compression::default(),
-        };
+        // Initialize tar builder
+        let archive = tar::Builder::new(archive);
 
         Ok(Self {
             archive,
-            batch_archive: Self::gen_batch_archive(batch_compression),
-            batch_compression,
-            batch_mode,
+            batch_archive: Self::gen_batch_archive(),
+            batch_compressor: BatchCompressor::new(&options.batch_compression)?,
+            batch_mode: options.batch_mode,
             batch_count: 0,
-            batch_size,
+            batch_size: options.batch_size,
             batch_number: 0,
             partition: String::new(),
             path_generator,
             state: StorageState::Open,
         })
     }
-    
+
+    /// Create new `BatchTarFile` instance from writer (see: [new](#method.new)).
+    /// Writer should be buffered, as entries are written in many small writes.
+    pub fn from_writer(
+        writer: W,
+        path_generator: PartitionPathGenerator,
+        options: &BatchOptions,
+    ) -> Result<Self, TarArchiveError> {
+        let mut batch_tar_file = Self::from_position_writer(
+            PositionWriter::new(writer, 0),
+            path_generator,
+            options,
+        )?;
+        // Write zstd dictionary as first entry (if exists)
+        if let BatchCompression::Zstd { ref dictionary, .. } = options.batch_compression {
+            if !dictionary.is_empty() {
+                let mut header = batch_tar_file.gen_header(dictionary.len() as u64);
+                batch_tar_file.archive.append_data(&mut header, DICTIONARY_PATH, dictionary.as_slice())?;
+            }
+        }
+        Ok(batch_tar_file)
+    }
+
+    /// Generate number of melodies per partition
+    /// (see: [PartitionPathGenerator::gen_num_melodies_per_partition](../struct.PartitionPathGenerator.html#method.gen_num_melodies_per_partition))
+    pub fn gen_num_melodies_per_partition(&self) -> u64 {
+        self.path_generator.gen_num_melodies_per_partition()
+    }
+
     /// Flush current batch archive to disk (if exists)
     fn flush_batch(&mut self) -> Result<(), TarArchiveError> {
-        // If batch archive is open
-        if self.batch_archive.state == StorageState::Open {
-            // Finish batch archive
+        // If batch archive is open and isn't empty (there's no batch before the first file)
+        if self.batch_archive.state == StorageState::Open && self.batch_count > 0 {
+            // Finish batch archive and compress it
             self.batch_archive.finish()?;
-            // Get Gzip encoder and finish writing data
-            let encoder = self.batch_archive.get_mut();
-            encoder.try_finish()?;
-            // Get underlying BufWriter
-            let buf_writer = encoder.get_mut();
-            // Get underlying buffer (Vec<u8>)
-            let raw_buffer = buf_writer.get_mut();
-
-            // Construct path: `<partition>/batch<batch_number>.tar.gz`
-            let path = format!(
-                "{partition}{separator}batch{batch_number}.tar.gz",
-                partition=self.partition,
-                separator=&std::path::MAIN_SEPARATOR.to_string(),
-                batch_number=self.batch_number,
+            let raw_buffer = self.batch_compressor.compress(self.batch_archive.get_ref())?;
+
+            // Construct path: `<partition>/batch<batch_number>.<extension>`
+            let path = gen_batch_path(
+                &self.partition,
+                self.batch_number as u64,
+                self.batch_compressor.extension(),
             );
 
             // Construct tar header and write raw buffer data to top-level archive
-            let mut header = tar::Header::new_old();
-            header.set_size(raw_buffer.len() as u64);
-            match self.batch_mode {
-                Some(mode) => header.set_mode(mode),
-                None => header.set_mode(644),
-            }
+            let mut header = self.gen_header(raw_buffer.len() as u64);
             self
                 .archive
                 .append_data(&mut header, &path, raw_buffer.as_slice())
-                .map_err(|e| TarArchiveError::IOError(e))?;
+                .map_err(TarArchiveError::IOError)?;
         }
         Ok(())
     }
@@ -173,7 +293,7 @@ impl BatchTarFile {
         self.flush_batch()?;
 
         // Initialize new batch archive
-        self.batch_archive = Self::gen_batch_archive(self.batch_compression);
+        self.batch_archive = Self::gen_batch_archive();
 
         // Reset batch count and:
         // If partition boundary, reset batch_number
@@ -188,10 +308,49 @@ impl BatchTarFile {
     }
 }
 
-impl StorageBackend for BatchTarFile {
+impl BatchTarFile {
+    /// Create new `BatchTarFile` instance, where batches are partitioned by `path_generator`
+    pub fn new<P: AsRef<std::path::Path>>(
+        target_path: P,
+        path_generator: PartitionPathGenerator,
+        options: &BatchOptions,
+    ) -> Result<Self, TarArchiveError> {
+        // Open target file and wrap in BufWriter, optimized for many small writes
+        let archive = std::io::BufWriter::new(std::fs::File::create(target_path)?);
+        Self::from_writer(archive, path_generator, options)
+    }
+
+    /// Open existing `BatchTarFile` and resume writing from checkpoint
+    /// (see: [StorageBackend::checkpoint](../trait.StorageBackend.html#method.checkpoint)).
+    /// Writing resumes at the start of the batch that was pending when the checkpoint was written.
+    /// If using zstd compression, the dictionary must be the one already stored in the archive.
+    pub fn resume<P: AsRef<std::path::Path>>(
+        target_path: P,
+        path_generator: PartitionPathGenerator,
+        options: &BatchOptions,
+        checkpoint: &BackendCheckpoint,
+    ) -> Result<Self, TarArchiveError> {
+        // Open target file and truncate to checkpoint
+        let archive = checkpoint::open_for_resume(target_path, checkpoint)?;
+        let archive = PositionWriter::new(std::io::BufWriter::new(archive), checkpoint.archive_offset);
+        let mut batch_tar_file = Self::from_position_writer(archive, path_generator, options)?;
+        // Restore partition and batch number of pending batch
+        batch_tar_file.batch_number = checkpoint.batch_number;
+        batch_tar_file.partition = checkpoint.partition.clone();
+        Ok(batch_tar_file)
+    }
+}
+
+impl<W: std::io::Write> StorageBackend for BatchTarFile<W> {
     type Error = TarArchiveError;
 
     fn append_file(&mut self, mfile: libatm::MIDIFile, mode: Option<u32>) -> Result<(), Self::Error> {
+        // Generate hash and data for MIDI file
+        let pfile = PreparedMIDIFile::new(mfile)?;
+        self.append_prepared(pfile, mode)
+    }
+
+    fn append_prepared(&mut self, pfile: PreparedMIDIFile, mode: Option<u32>) -> Result<(), Self::Error> {
         // Ensure archive is still open
         if self.state == StorageState::Closed {
             return Err(TarArchiveError::IOError(std::io::Error::new(
@@ -201,7 +360,7 @@ impl StorageBackend for BatchTarFile {
         }
 
         // Generate partition for MIDI file
-        let partition = self.path_generator.gen_basename_for_file(&mfile)?;
+        let partition = self.path_generator.gen_basename_for_file(&pfile.mfile)?;
 
         // If first MIDI file or reached partition_boundary
         if self.partition != partition {
@@ -217,31 +376,94 @@ impl StorageBackend for BatchTarFile {
         }
 
         // Add file to batch archive and increment batch_count
-        self.batch_archive.append_file(mfile, mode)?;
+        self.batch_archive.append_prepared(pfile, mode)?;
         self.batch_count = self.batch_count + 1;
         Ok(())
     }
 
+    fn append_manifest(&mut self, manifest: &str) -> Result<(), Self::Error> {
+        // Render manifest is written to the top-level archive, next to the partitions
+        let mut header = self.gen_header(manifest.len() as u64);
+        self
+            .archive
+            .append_data(&mut header, RENDER_MANIFEST_PATH, manifest.as_bytes())
+            .map_err(TarArchiveError::IOError)
+    }
+
+    fn checkpoint(&mut self) -> Result<Option<BackendCheckpoint>, Self::Error> {
+        // Files in the current batch are only written to the top-level archive once the
+        // batch is flushed, so the checkpoint points to the start of the current batch
+        let archive_offset = self.archive.get_mut().gen_offset()?;
+        Ok(Some(BackendCheckpoint {
+            archive_offset,
+            num_pending: self.batch_count as u64,
+            batch_number: self.batch_number,
+            partition: self.partition.clone(),
+        }))
+    }
+
     fn finish(&mut self) -> Result<(), Self::Error> {
         match self.state {
             // If archive is still "open"
             StorageState::Open => {
+                self.state = StorageState::Closed;
                 // Flush remaining batch to disk
                 self.flush_batch()?;
                 // Write footer sections to top-level archive and
                 // close for writing
-                self.archive.finish().map_err(|e| TarArchiveError::IOError(e))
+                self.archive.finish().map_err(TarArchiveError::IOError)?;
+                // Write buffered data to disk
+                self.archive.get_mut().flush().map_err(TarArchiveError::IOError)
             },
             _ => Ok(()),
         }
     }
 }
 
-impl IntoInner for BatchTarFile {
-    type Inner = ArchiveInnerObject;
+impl<W: std::io::Write> IntoInner for BatchTarFile<W> {
+    type Inner = W;
 
     fn into_inner(mut self) -> Result<Self::Inner, <Self as StorageBackend>::Error> {
         self.finish()?;
-        self.archive.into_inner().map_err(|e| TarArchiveError::IOError(e))
+        self.archive.into_inner().map(PositionWriter::into_inner).map_err(TarArchiveError::IOError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write first `num_melodies` melodies of `notes` to batch archive in batches of 2,
+    /// and return paths of entries in top-level archive
+    fn gen_batch_paths(notes: &str, num_melodies: u64) -> Vec<std::path::PathBuf> {
+        let notes = crate::utils::parse_note_vec(notes).unwrap();
+        let options = BatchOptions { batch_size: 2, batch_compression: BatchCompression::default(), batch_mode: None };
+        let path_generator = PartitionPathGenerator::new(notes.len() as u32, 3, 4096, 1).unwrap();
+        let mut batch_tar_file = BatchTarFile::from_writer(Vec::new(), path_generator, &options).unwrap();
+        let render = crate::midi::MIDIRenderOptions::default();
+        for index in 0..num_melodies {
+            let melody = crate::utils::gen_melody_at(&notes, 3, index).unwrap();
+            batch_tar_file.append_melody(melody, &render, None).unwrap();
+        }
+        let data = batch_tar_file.into_inner().unwrap();
+        let mut archive = tar::Archive::new(data.as_slice());
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().to_path_buf())
+            .collect()
+    }
+
+    #[test]
+    fn test_no_empty_batches() {
+        // All melodies fit in max files, so they're written to a single (root) partition
+        // as three batches, without an empty batch before the first one
+        let paths = gen_batch_paths("C:4,D:4,E:4", 5);
+        let expected = ["batch0.tar.gz", "batch1.tar.gz", "batch2.tar.gz"];
+        assert_eq!(expected.iter().map(std::path::PathBuf::from).collect::<Vec<_>>(), paths);
+
+        // Melodies of a single note are never partitioned
+        let paths = gen_batch_paths("C:4", 1);
+        assert_eq!(vec![std::path::PathBuf::from("batch0.tar.gz")], paths);
     }
 }
@@ -0,0 +1,390 @@
+// checkpoint.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use std::io::{BufRead, Write};
+
+/****************************
+***** BackendCheckpoint *****
+****************************/
+
+/// State of a storage backend at a consistent point in its target file, such that
+/// truncating the target file to `archive_offset` bytes and appending the files from
+/// that point on produces the same output as an uninterrupted run
+/// (see: [StorageBackend::checkpoint](../trait.StorageBackend.html#method.checkpoint)).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BackendCheckpoint {
+    /// Size of target file at consistent point (in bytes)
+    pub archive_offset: u64,
+    /// Number of files appended to the backend that haven't been written to the target
+    /// file yet (i.e., files in the current batch of a
+    /// [BatchTarFile](../batch_tar_file/struct.BatchTarFile.html))
+    pub num_pending: u64,
+    /// Batch number within partition of the first pending file
+    /// ([BatchTarFile](../batch_tar_file/struct.BatchTarFile.html) only)
+    pub batch_number: u32,
+    /// Partition of the first pending file
+    /// ([BatchTarFile](../batch_tar_file/struct.BatchTarFile.html) only)
+    pub partition: String,
+}
+
+/*********************
+***** Checkpoint *****
+*********************/
+
+/// Error type for reading and writing checkpoint files
+#[derive(Debug, thiserror::Error)]
+pub enum CheckpointError {
+    /// IO error
+    #[error(transparent)]
+    IOError(#[from] std::io::Error),
+    #[error("Invalid checkpoint file line {line}")]
+    InvalidLine { line: String },
+    #[error("Invalid value for checkpoint field {field}")]
+    InvalidValue { field: String },
+    #[error("Missing checkpoint field {field}")]
+    MissingField { field: String },
+    #[error("Checkpoint was written by a run with different {field}")]
+    Mismatch { field: String },
+}
+
+/// Checkpoint of a generation run, written periodically next to the target file
+/// so that an interrupted run can be resumed. Stored as `<field>=<value>` lines.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Checkpoint {
+    /// Note set melodies are generated from (see:
+    /// [format_melody](../../utils/fn.format_melody.html))
+    pub notes: String,
+    /// Length of generated melodies
+    pub melody_length: u32,
+    /// Options melodies are rendered as MIDI files with (see:
+    /// [MIDIRenderOptions](../../midi/struct.MIDIRenderOptions.html))
+    pub render: String,
+    /// Layout of the target file: partitioning scheme and backend options that
+    /// determine where melodies are written (i.e., `partitions=2,max_files=4096,compress=6`)
+    pub layout: String,
+    /// Index after last melody to generate
+    pub end_index: u64,
+    /// Index of first melody not written to the target file yet
+    pub melody_index: u64,
+    /// Storage backend state
+    pub backend: BackendCheckpoint,
+}
+
+impl Checkpoint {
+    /// Create checkpoint template for a run generating melodies of length `melody_length`
+    /// from `notes` up to `end_index`, rendered with `render` and written with `layout`.
+    /// Melody index and backend state are set each time the checkpoint is written.
+    pub fn new(
+        notes: &libatm::MIDINoteVec,
+        melody_length: u32,
+        render: &crate::midi::MIDIRenderOptions,
+        layout: String,
+        end_index: u64,
+    ) -> Self {
+        Self {
+            notes: crate::utils::format_melody(notes),
+            melody_length,
+            render: render.to_string(),
+            layout,
+            end_index,
+            ..Default::default()
+        }
+    }
+
+    /// Generate path of checkpoint file for target file (`<target>.checkpoint`)
+    pub fn gen_path<P: AsRef<std::path::Path>>(target_path: P) -> std::path::PathBuf {
+        let mut path = target_path.as_ref().as_os_str().to_owned();
+        path.push(".checkpoint");
+        std::path::PathBuf::from(path)
+    }
+
+    /// Read checkpoint from file
+    pub fn read<P: AsRef<std::path::Path>>(path: P) -> Result<Self, CheckpointError> {
+        let file = std::io::BufReader::new(std::fs::File::open(path)?);
+        let mut fields = std::collections::HashMap::new();
+        for line in file.lines() {
+            let line = line?;
+            match line.find('=') {
+                Some(idx) => fields.insert(line[..idx].to_string(), line[idx + 1..].to_string()),
+                None => return Err(CheckpointError::InvalidLine { line }),
+            };
+        }
+
+        fn get_field<T: std::str::FromStr>(
+            fields: &std::collections::HashMap<String, String>,
+            field: &str,
+        ) -> Result<T, CheckpointError> {
+            fields
+                .get(field)
+                .ok_or(CheckpointError::MissingField { field: field.to_string() })?
+                .parse::<T>()
+                .map_err(|_| CheckpointError::InvalidValue { field: field.to_string() })
+        }
+
+        Ok(Self {
+            notes: get_field(&fields, "notes")?,
+            melody_length: get_field(&fields, "melody_length")?,
+            // Checkpoints written before rendering options were configurable used the defaults
+            render: match fields.get("render") {
+                Some(render) => render.clone(),
+                None => crate::midi::MIDIRenderOptions::default().to_string(),
+            },
+            layout: get_field(&fields, "layout")?,
+            end_index: get_field(&fields, "end_index")?,
+            melody_index: get_field(&fields, "melody_index")?,
+            backend: BackendCheckpoint {
+                archive_offset: get_field(&fields, "archive_offset")?,
+                num_pending: get_field(&fields, "num_pending")?,
+                batch_number: get_field(&fields, "batch_number")?,
+                partition: get_field(&fields, "partition")?,
+            },
+        })
+    }
+
+    /// Write checkpoint to file. Checkpoint is written to a temporary file first
+    /// and then renamed, so the checkpoint file is never partially written.
+    pub fn write<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), CheckpointError> {
+        let mut tmp_path = path.as_ref().as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        {
+            let mut file = std::io::BufWriter::new(std::fs::File::create(&tmp_path)?);
+            write!(
+                file,
+                concat!("notes={notes}\n",
+                        "melody_length={melody_length}\n",
+                        "render={render}\n",
+                        "layout={layout}\n",
+                        "end_index={end_index}\n",
+                        "melody_index={melody_index}\n",
+                        "archive_offset={archive_offset}\n",
+                        "num_pending={num_pending}\n",
+                        "batch_number={batch_number}\n",
+                        "partition={partition}\n"),
+                notes=self.notes,
+                melody_length=self.melody_length,
+                render=self.render,
+                layout=self.layout,
+                end_index=self.end_index,
+                melody_index=self.melody_index,
+                archive_offset=self.backend.archive_offset,
+                num_pending=self.backend.num_pending,
+                batch_number=self.backend.batch_number,
+                partition=self.backend.partition,
+            )?;
+            file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        }
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Ensure checkpoint was written by a run with the same arguments as `template`
+    /// (see: [new](#method.new)) generating melodies in `melody_range`, and return
+    /// the range of melodies left to generate (melodies before the checkpoint
+    /// are already in the target file).
+    pub fn gen_resume_range(
+        &self,
+        template: &Checkpoint,
+        melody_range: std::ops::Range<u64>,
+    ) -> Result<std::ops::Range<u64>, CheckpointError> {
+        let mismatch = |field: &str| Err(CheckpointError::Mismatch { field: field.to_string() });
+        if self.notes != template.notes {
+            return mismatch("notes");
+        }
+        if self.melody_length != template.melody_length {
+            return mismatch("melody length");
+        }
+        if self.render != template.render {
+            return mismatch("rendering options");
+        }
+        if self.layout != template.layout {
+            return mismatch("layout (partitioning or backend options)");
+        }
+        if self.end_index != melody_range.end
+            || self.melody_index < melody_range.start
+            || self.melody_index > melody_range.end
+        {
+            return mismatch("melody range");
+        }
+        Ok(self.melody_index..self.end_index)
+    }
+}
+
+/// Open target file to resume writing from checkpoint. Truncates the file to the
+/// last consistent point and seeks to the end.
+pub(crate) fn open_for_resume<P: AsRef<std::path::Path>>(
+    target_path: P,
+    checkpoint: &BackendCheckpoint,
+) -> std::io::Result<std::fs::File> {
+    use std::io::Seek;
+
+    let mut file = std::fs::OpenOptions::new().write(true).open(target_path)?;
+    if file.metadata()?.len() < checkpoint.archive_offset {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "Target file is shorter than checkpoint offset",
+        ));
+    }
+    file.set_len(checkpoint.archive_offset)?;
+    file.seek(std::io::SeekFrom::End(0))?;
+    Ok(file)
+}
+
+/*************************
+***** PositionWriter *****
+*************************/
+
+/// Writer that tracks its position in the target (the number of bytes written to it,
+/// plus the offset it started at), so that backends can write checkpoints for any
+/// `std::io::Write`, not just seekable files.
+pub struct PositionWriter<W: std::io::Write> {
+    /// Underlying writer
+    writer: W,
+    /// Position in target
+    position: u64,
+}
+
+impl<W: std::io::Write> PositionWriter<W> {
+    /// Create new `PositionWriter` instance starting at `position`
+    /// (i.e., `0` for a new target, or the checkpoint offset when resuming)
+    pub fn new(writer: W, position: u64) -> Self {
+        Self { writer, position }
+    }
+
+    /// Flush underlying writer and return current position in target
+    pub fn gen_offset(&mut self) -> std::io::Result<u64> {
+        self.writer.flush()?;
+        Ok(self.position)
+    }
+
+    /// Acquires a mutable reference to the underlying writer
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Unwraps this `PositionWriter`, returning the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for PositionWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let num_bytes = self.writer.write(buf)?;
+        self.position += num_bytes as u64;
+        Ok(num_bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_write_read() {
+        let path = std::env::temp_dir().join(format!("atm-checkpoint-{}", std::process::id()));
+        let checkpoint = Checkpoint {
+            notes: "C:4,D:4,E:4".to_string(),
+            melody_length: 8,
+            render: crate::midi::MIDIRenderOptions::default().to_string(),
+            layout: "partitions=2,max_files=4096".to_string(),
+            end_index: 6561,
+            melody_index: 1000,
+            backend: BackendCheckpoint {
+                archive_offset: 1024000,
+                num_pending: 3,
+                batch_number: 4,
+                partition: "60/62".to_string(),
+            },
+        };
+        checkpoint.write(&path).unwrap();
+        let observed = Checkpoint::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(checkpoint, observed);
+    }
+
+    /// Write melodies at indices in `melody_range` (of melodies of length 3 from 3 notes)
+    /// to backend
+    fn write_melodies<B: crate::storage::StorageBackend>(backend: &mut B, melody_range: std::ops::Range<u64>) {
+        let notes = crate::utils::parse_note_vec("C:4,D:4,E:4").unwrap();
+        let render = crate::midi::MIDIRenderOptions::default();
+        for index in melody_range {
+            let melody = crate::utils::gen_melody_at(&notes, 3, index).unwrap();
+            backend.append_melody(melody, &render, None).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_resume_truncates_and_skips_written() {
+        use crate::storage::StorageBackend;
+
+        let dir = std::env::temp_dir().join(format!("atm-resume-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (expected_path, target_path) = (dir.join("expected.tar"), dir.join("target.tar"));
+
+        // Uninterrupted run
+        let mut backend = crate::storage::TarFile::new(&expected_path, crate::storage::MIDIHashPathGenerator).unwrap();
+        write_melodies(&mut backend, 0..27);
+        backend.finish().unwrap();
+
+        // Interrupted run: checkpoint after 10 melodies, then more melodies are written
+        // (and the archive is finished) before the run stops
+        let mut backend = crate::storage::TarFile::new(&target_path, crate::storage::MIDIHashPathGenerator).unwrap();
+        write_melodies(&mut backend, 0..10);
+        let backend_checkpoint = backend.checkpoint().unwrap().unwrap();
+        write_melodies(&mut backend, 10..15);
+        backend.finish().unwrap();
+        let notes = crate::utils::parse_note_vec("C:4,D:4,E:4").unwrap();
+        let render = crate::midi::MIDIRenderOptions::default();
+        let template = Checkpoint::new(&notes, 3, &render, "partitions=none".to_string(), 27);
+        let checkpoint = Checkpoint { melody_index: 10, backend: backend_checkpoint.clone(), ..template.clone() };
+
+        // Resuming truncates the target to the checkpoint offset, and only the melodies
+        // after the checkpoint are written again
+        let melody_range = checkpoint.gen_resume_range(&template, 0..27).unwrap();
+        assert_eq!(10..27, melody_range);
+        let mut backend = crate::storage::TarFile::resume(
+            &target_path,
+            crate::storage::MIDIHashPathGenerator,
+            &backend_checkpoint,
+        ).unwrap();
+        assert_eq!(backend_checkpoint.archive_offset, std::fs::metadata(&target_path).unwrap().len());
+        write_melodies(&mut backend, melody_range);
+        backend.finish().unwrap();
+
+        let (expected, observed) = (std::fs::read(&expected_path).unwrap(), std::fs::read(&target_path).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(expected == observed, "Resumed archive differs from uninterrupted archive");
+    }
+
+    #[test]
+    fn test_resume_mismatched_args() {
+        let notes = crate::utils::parse_note_vec("C:4,D:4,E:4").unwrap();
+        let render = crate::midi::MIDIRenderOptions::default();
+        let template = Checkpoint::new(&notes, 3, &render, "partitions=2,max_files=4096".to_string(), 27);
+        let checkpoint = Checkpoint { melody_index: 9, ..template.clone() };
+        assert_eq!(9..27, checkpoint.gen_resume_range(&template, 0..27).unwrap());
+
+        // Any argument that changes the layout of the target file prevents resuming
+        let resumed = |template: &Checkpoint, melody_range| checkpoint.gen_resume_range(template, melody_range);
+        let other_notes = crate::utils::parse_note_vec("C:4,D:4,F:4").unwrap();
+        let layout = "partitions=2,max_files=4096".to_string();
+        assert!(resumed(&Checkpoint::new(&other_notes, 3, &render, layout.clone(), 27), 0..27).is_err());
+        assert!(resumed(&Checkpoint::new(&notes, 4, &render, layout.clone(), 27), 0..27).is_err());
+        let other_render = crate::midi::MIDIRenderOptions { velocity: 64, ..Default::default() };
+        assert!(resumed(&Checkpoint::new(&notes, 3, &other_render, layout, 27), 0..27).is_err());
+        let other_layout = "partitions=1,max_files=4096".to_string();
+        assert!(resumed(&Checkpoint::new(&notes, 3, &render, other_layout, 27), 0..27).is_err());
+        // Checkpoint outside of melody range (i.e., different shard)
+        assert!(resumed(&template, 0..18).is_err());
+        assert!(resumed(&template, 18..27).is_err());
+    }
+}
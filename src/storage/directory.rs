@@ -0,0 +1,185 @@
+// directory.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use std::io::Write;
+
+use crate::storage::{
+    PathGenerator,
+    PathGeneratorError,
+    PreparedMIDIFile,
+    StorageBackend,
+    StorageState,
+    RENDER_MANIFEST_PATH,
+};
+
+/***************************
+***** DirectoryBackend *****
+***************************/
+
+/// Error type for [DirectoryBackend](struct.DirectoryBackend.html) (wrapping around
+/// `std::io::Error` and [PathGeneratorError](../enum.PathGeneratorError.html))
+#[derive(Debug, thiserror::Error)]
+pub enum DirectoryBackendError {
+    /// IO error
+    #[error(transparent)]
+    IOError(#[from] std::io::Error),
+    /// [PathGenerator](../trait.PathGenerator.html) error
+    #[error(transparent)]
+    PathGenerator(#[from] PathGeneratorError),
+}
+
+/// Directory tree storage backend, which writes each MIDI file to `<root>/<path>`, where
+/// `<path>` is generated by the path generator (i.e., `<root>/<partition>/<hash>.mid`).
+/// Use when the files are needed on disk as-is (i.e., to be served over a network file system).
+/// Partition directories are created on demand. Keep in mind that every file and directory takes
+/// at least one filesystem block and one inode (see: `estimate dir`).
+pub struct DirectoryBackend<G: PathGenerator> {
+    /// Root directory
+    root: std::path::PathBuf,
+    /// Path generator
+    path_generator: G,
+    /// Directory of last file written, which is known to exist
+    current_dir: Option<std::path::PathBuf>,
+    /// Number of files to write between syncing directories (if set)
+    sync_interval: Option<u32>,
+    /// Number of files written (and synced) since directories were last synced
+    num_pending: u32,
+    /// Directories created or written to since they were last synced
+    pending_dirs: Vec<std::path::PathBuf>,
+    /// Backend state
+    state: StorageState,
+}
+
+impl<G: PathGenerator> DirectoryBackend<G> {
+    /// Create new `DirectoryBackend` instance. Creates the root directory if it doesn't exist.
+    /// If `sync_interval` is set, each file is synced to disk as it's written, and their
+    /// directories are synced every `sync_interval` files. Files are closed once synced,
+    /// so no more than one file is open at a time.
+    pub fn new<P: AsRef<std::path::Path>>(
+        root: P,
+        path_generator: G,
+        sync_interval: Option<u32>,
+    ) -> std::io::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        std::fs::create_dir_all(&root)?;
+        Ok(Self {
+            root,
+            path_generator,
+            current_dir: None,
+            sync_interval,
+            num_pending: 0,
+            pending_dirs: Vec::new(),
+            state: StorageState::Open,
+        })
+    }
+
+    /// Create directory (and parent directories) if not the same as the directory
+    /// of the last file written
+    fn ensure_dir(&mut self, dir: &std::path::Path) -> std::io::Result<()> {
+        if self.current_dir.as_deref() != Some(dir) {
+            std::fs::create_dir_all(dir)?;
+            if self.sync_interval.is_some() {
+                self.pending_dirs.push(dir.to_path_buf());
+            }
+            self.current_dir = Some(dir.to_path_buf());
+        }
+        Ok(())
+    }
+
+    /// Sync pending directories to disk (files are synced as they're written)
+    fn sync_pending(&mut self) -> std::io::Result<()> {
+        self.num_pending = 0;
+        // Sync directories so new entries are persisted
+        for dir in self.pending_dirs.drain(..) {
+            std::fs::File::open(&dir)?.sync_all()?;
+        }
+        // Directory of next file must be synced as well
+        if let Some(ref dir) = self.current_dir {
+            self.pending_dirs.push(dir.clone());
+        }
+        Ok(())
+    }
+}
+
+/// Set permissions of file to `mode` (ignoring umask)
+#[cfg(unix)]
+fn set_mode(file: &std::fs::File, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(std::fs::Permissions::from_mode(mode))
+}
+
+/// Permissions aren't supported on this platform
+#[cfg(not(unix))]
+fn set_mode(_file: &std::fs::File, _mode: u32) -> std::io::Result<()> {
+    Ok(())
+}
+
+impl<G: PathGenerator> StorageBackend for DirectoryBackend<G> {
+    type Error = DirectoryBackendError;
+
+    fn append_file(&mut self, mfile: libatm::MIDIFile, mode: Option<u32>) -> Result<(), Self::Error> {
+        // Generate hash and data for MIDI file
+        let pfile = PreparedMIDIFile::new(mfile)?;
+        self.append_prepared(pfile, mode)
+    }
+
+    fn append_prepared(&mut self, pfile: PreparedMIDIFile, mode: Option<u32>) -> Result<(), Self::Error> {
+        // Ensure backend is still open
+        if self.state == StorageState::Closed {
+            return Err(DirectoryBackendError::IOError(std::io::Error::other(
+                "Directory is closed for writing, cannot append file",
+            )));
+        }
+
+        // Generate path from melody hash and ensure parent directory exists
+        let path = self.root.join(self.path_generator.gen_path_for_prepared(&pfile)?);
+        if let Some(dir) = path.parent() {
+            self.ensure_dir(dir)?;
+        }
+
+        // Write file and set permissions if provided
+        let mut file = std::fs::File::create(&path)?;
+        file.write_all(&pfile.data)?;
+        if let Some(mode) = mode {
+            set_mode(&file, mode)?;
+        }
+
+        // Sync file to disk before closing it, and directories in batches (if enabled)
+        if let Some(sync_interval) = self.sync_interval {
+            file.sync_all()?;
+            self.num_pending += 1;
+            if self.num_pending >= sync_interval {
+                self.sync_pending()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn append_manifest(&mut self, manifest: &str) -> Result<(), Self::Error> {
+        let mut file = std::fs::File::create(self.root.join(RENDER_MANIFEST_PATH))?;
+        file.write_all(manifest.as_bytes())?;
+        if self.sync_interval.is_some() {
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Self::Error> {
+        match self.state {
+            StorageState::Open => {
+                self.state = StorageState::Closed;
+                // Sync remaining directories to disk (if enabled)
+                if self.sync_interval.is_some() {
+                    self.sync_pending()?;
+                }
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,661 @@
+// pack_file.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::{
+    midi::{MIDIMetaEvents, MIDIRenderOptions},
+    storage::{
+        PartitionPathGenerator,
+        PartitionPathGeneratorError,
+        PreparedMIDIFile,
+        StorageBackend,
+        StorageState,
+    },
+};
+
+/// Magic bytes at the start of every melody pack
+pub const PACK_MAGIC: &[u8; 8] = b"ATMPACK\0";
+
+/// Current melody pack format version
+pub const PACK_VERSION: u16 = 3;
+
+/// Offset of the melody count in the header (after the magic bytes and version)
+const NUM_MELODIES_OFFSET: u64 = 10;
+
+/*********************
+***** PackHeader *****
+*********************/
+
+/// Error type for [PackFile](struct.PackFile.html) and [PackReader](struct.PackReader.html)
+#[derive(Debug, thiserror::Error)]
+pub enum PackFileError {
+    /// IO error
+    #[error(transparent)]
+    IOError(#[from] std::io::Error),
+    #[error("Not a melody pack (invalid magic bytes)")]
+    InvalidMagic,
+    #[error("Unsupported melody pack version {version} (expected {expected})")]
+    UnsupportedVersion { version: u16, expected: u16 },
+    #[error("Invalid melody pack header ({reason})")]
+    InvalidHeader { reason: String },
+    #[error(transparent)]
+    MelodyIndex(#[from] crate::utils::MelodyIndexError),
+    #[error("Expected melody of length {expected}, found length {observed}")]
+    MelodyLengthMismatch { expected: u32, observed: u32 },
+    #[error("MIDI file doesn't match rendering options in the melody pack header")]
+    ParameterMismatch,
+    #[error("Melody index {index} out of range for pack with {num_melodies} melodies")]
+    IndexOutOfRange { index: u64, num_melodies: u64 },
+}
+
+/// Partitioning scheme recorded in a melody pack, used to regenerate the paths
+/// the melodies would have in a partitioned archive (see:
+/// [PartitionPathGenerator](../struct.PartitionPathGenerator.html))
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PackPartition {
+    /// Maximum number of files per directory
+    pub max_files: u32,
+    /// Partition depth
+    pub partition_depth: u32,
+}
+
+/// Melody pack header, which holds everything needed to regenerate the MIDI files
+/// of the melodies in the pack.
+///
+/// On disk, the header is laid out as follows (integers are little-endian):
+///
+/// | Field              | Size     | Notes                                         |
+/// |--------------------|----------|-----------------------------------------------|
+/// | Magic              | 8        | `ATMPACK\0`                                   |
+/// | Version            | 2        | Currently `3`                                 |
+/// | Number of melodies | 8        | Written when the pack is finished             |
+/// | Note set length    | 2        | Length of note set in bytes                   |
+/// | Note set           | variable | NOTE:OCTAVE pairs in generation order (UTF-8) |
+/// | Melody length      | 4        |                                               |
+/// | MIDI format        | 2        | `0`, `1` or `2`                               |
+/// | MIDI tracks        | 2        | Always `1`                                    |
+/// | MIDI division      | 2        |                                               |
+/// | Max files          | 4        | `0` if melodies aren't partitioned            |
+/// | Partition depth    | 4        | `0` if melodies aren't partitioned            |
+/// | Tempo              | 4        | Beats per minute, `0` if not set              |
+/// | Note duration      | 4        | Ticks, `0` if not set (one quarter note)      |
+/// | Velocity           | 1        |                                               |
+/// | Channel            | 1        | `0`-`15`                                      |
+/// | Program            | 1        | `0`-`127`, `255` if not set                   |
+/// | Dataset ID length  | 2        | `0` if meta events aren't written             |
+/// | Dataset ID         | variable | UTF-8                                         |
+#[derive(Clone, Debug)]
+pub struct PackHeader {
+    /// Notes melodies are generated from (in generation order)
+    pub notes: libatm::MIDINoteVec,
+    /// Length of melodies
+    pub melody_length: u32,
+    /// Options melodies are rendered as MIDI files with
+    pub render: MIDIRenderOptions,
+    /// Partitioning scheme (if partitioned)
+    pub partition: Option<PackPartition>,
+    /// Number of melodies in the pack
+    pub num_melodies: u64,
+}
+
+impl PackHeader {
+    /// Create new `PackHeader` instance
+    pub fn new(
+        notes: libatm::MIDINoteVec,
+        melody_length: u32,
+        render: MIDIRenderOptions,
+        partition: Option<PackPartition>,
+    ) -> Self {
+        Self {
+            notes,
+            melody_length,
+            render,
+            partition,
+            num_melodies: 0,
+        }
+    }
+
+    /// Number of bits used to store each note (`ceil(log2(num_notes))`)
+    pub fn bits_per_note(&self) -> u32 {
+        let num_notes = self.notes.len() as u32;
+        if num_notes <= 1 {
+            0
+        } else {
+            32 - (num_notes - 1).leading_zeros()
+        }
+    }
+
+    /// Number of bits used to store each melody
+    pub fn bits_per_melody(&self) -> u64 {
+        self.bits_per_note() as u64 * self.melody_length as u64
+    }
+
+    /// Size of header in bytes
+    pub fn gen_header_size(&self) -> u64 {
+        // Fixed-size fields plus note set and dataset ID
+        let dataset_id_size = self.render.meta.as_ref().map_or(0, |meta| meta.dataset_id.len());
+        51 + (crate::utils::format_melody(&self.notes).len() + dataset_id_size) as u64
+    }
+
+    /// Size of melody pack in bytes with `num_melodies` melodies
+    pub fn gen_size(&self, num_melodies: u64) -> u64 {
+        self.gen_header_size() + (num_melodies * self.bits_per_melody()).div_ceil(8)
+    }
+
+    /// Render MIDI file for melody using the rendering options in the header
+    pub fn gen_file(&self, melody: libatm::MIDINoteVec) -> PreparedMIDIFile {
+        self.render.prepare(melody)
+    }
+
+    /// Create partition path generator from partitioning scheme. Returns `None` if
+    /// melodies aren't partitioned (use [MIDIHashPathGenerator](../struct.MIDIHashPathGenerator.html)).
+    pub fn gen_path_generator(&self) -> Option<Result<PartitionPathGenerator, PartitionPathGeneratorError>> {
+        self.partition.map(|partition| PartitionPathGenerator::new(
+            self.notes.len() as u32,
+            self.melody_length,
+            partition.max_files,
+            partition.partition_depth,
+        ))
+    }
+
+    /// Write header to `target`
+    pub fn write<W: std::io::Write>(&self, target: &mut W) -> std::io::Result<()> {
+        let notes = crate::utils::format_melody(&self.notes);
+        let partition = self.partition.unwrap_or(PackPartition { max_files: 0, partition_depth: 0 });
+        target.write_all(PACK_MAGIC)?;
+        target.write_all(&PACK_VERSION.to_le_bytes())?;
+        target.write_all(&self.num_melodies.to_le_bytes())?;
+        target.write_all(&(notes.len() as u16).to_le_bytes())?;
+        target.write_all(notes.as_bytes())?;
+        target.write_all(&self.melody_length.to_le_bytes())?;
+        target.write_all(&format_to_u16(self.render.format).to_le_bytes())?;
+        target.write_all(&1u16.to_le_bytes())?;
+        target.write_all(&self.render.division.to_le_bytes())?;
+        target.write_all(&partition.max_files.to_le_bytes())?;
+        target.write_all(&partition.partition_depth.to_le_bytes())?;
+        target.write_all(&self.render.tempo.unwrap_or(0).to_le_bytes())?;
+        target.write_all(&self.render.duration.unwrap_or(0).to_le_bytes())?;
+        target.write_all(&[
+            self.render.velocity,
+            self.render.channel,
+            self.render.program.unwrap_or(0xFF),
+        ])?;
+        let dataset_id = self.render.meta.as_ref().map_or("", |meta| &meta.dataset_id);
+        target.write_all(&(dataset_id.len() as u16).to_le_bytes())?;
+        target.write_all(dataset_id.as_bytes())?;
+        Ok(())
+    }
+
+    /// Read header from `source`
+    pub fn read<R: std::io::Read>(source: &mut R) -> Result<Self, PackFileError> {
+        let mut magic = [0u8; 8];
+        source.read_exact(&mut magic)?;
+        if &magic != PACK_MAGIC {
+            return Err(PackFileError::InvalidMagic);
+        }
+        let version = read_u16(source)?;
+        if version != PACK_VERSION {
+            return Err(PackFileError::UnsupportedVersion { version, expected: PACK_VERSION });
+        }
+        let num_melodies = read_u64(source)?;
+
+        // Parse note set
+        let mut notes = vec![0u8; read_u16(source)? as usize];
+        source.read_exact(&mut notes)?;
+        let notes = std::str::from_utf8(&notes)
+            .map_err(|err| PackFileError::InvalidHeader { reason: err.to_string() })?;
+        let notes = crate::utils::parse_note_vec(notes)
+            .map_err(|err| PackFileError::InvalidHeader { reason: err.to_string() })?;
+
+        let melody_length = read_u32(source)?;
+        let format = format_from_u16(read_u16(source)?)?;
+        let tracks = read_u16(source)?;
+        if tracks != 1 {
+            return Err(PackFileError::InvalidHeader { reason: format!("unsupported number of tracks {}", tracks) });
+        }
+        let division = read_u16(source)?;
+        let max_files = read_u32(source)?;
+        let partition_depth = read_u32(source)?;
+        let partition = match partition_depth {
+            0 => None,
+            _ => Some(PackPartition { max_files, partition_depth }),
+        };
+        let tempo = read_u32(source)?;
+        let duration = read_u32(source)?;
+        let mut note_options = [0u8; 3];
+        source.read_exact(&mut note_options)?;
+        let mut dataset_id = vec![0u8; read_u16(source)? as usize];
+        source.read_exact(&mut dataset_id)?;
+        let dataset_id = String::from_utf8(dataset_id)
+            .map_err(|err| PackFileError::InvalidHeader { reason: err.to_string() })?;
+        let meta = if dataset_id.is_empty() {
+            None
+        } else {
+            Some(MIDIMetaEvents { dataset_id, notes: notes.clone() })
+        };
+        let render = MIDIRenderOptions {
+            format,
+            division,
+            tempo: Some(tempo).filter(|tempo| *tempo != 0),
+            duration: Some(duration).filter(|duration| *duration != 0),
+            velocity: note_options[0],
+            channel: note_options[1],
+            program: Some(note_options[2]).filter(|program| *program != 0xFF),
+            meta,
+            // Packs don't support rhythms or dynamics (see: GenPackDirective)
+            variations: crate::variation::VariationSet::default(),
+        };
+        Ok(Self { notes, melody_length, render, partition, num_melodies })
+    }
+}
+
+/// Convert MIDI format to its value in the MIDI file header
+fn format_to_u16(format: libatm::MIDIFormat) -> u16 {
+    match format {
+        libatm::MIDIFormat::Format0 => 0,
+        libatm::MIDIFormat::Format1 => 1,
+        libatm::MIDIFormat::Format2 => 2,
+    }
+}
+
+/// Convert value in MIDI file header to MIDI format
+fn format_from_u16(format: u16) -> Result<libatm::MIDIFormat, PackFileError> {
+    match format {
+        0 => Ok(libatm::MIDIFormat::Format0),
+        1 => Ok(libatm::MIDIFormat::Format1),
+        2 => Ok(libatm::MIDIFormat::Format2),
+        _ => Err(PackFileError::InvalidHeader { reason: format!("unknown MIDI format {}", format) }),
+    }
+}
+
+fn read_u16<R: std::io::Read>(source: &mut R) -> std::io::Result<u16> {
+    let mut buf = [0u8; 2];
+    source.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32<R: std::io::Read>(source: &mut R) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    source.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: std::io::Read>(source: &mut R) -> std::io::Result<u64> {
+    let mut buf = [0u8; 8];
+    source.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/*******************
+***** PackFile *****
+*******************/
+
+/// Melody pack storage backend, which stores melodies bit-packed in a single file
+/// rather than as MIDI files. Each note is stored as its index in the note set
+/// using `ceil(log2(num_notes))` bits (most significant bit first), and melodies are
+/// stored back to back in the order they're appended (see: [PackHeader](struct.PackHeader.html)).
+/// Use for archiving datasets as compactly as possible. MIDI files (and their hashes) can be
+/// regenerated from the pack with [PackReader](struct.PackReader.html), or converted to a Tar
+/// file with `atm convert`.
+///
+/// NOTE: The number of melodies is written to the header when the pack is finished,
+/// so the target must be seekable.
+pub struct PackFile<W: std::io::Write + std::io::Seek> {
+    /// Underlying writer
+    writer: W,
+    /// Pack header
+    header: PackHeader,
+    /// Bits not yet written to the writer
+    bit_buffer: u64,
+    /// Number of bits in the bit buffer (always less than 8 between appends)
+    num_buffered_bits: u32,
+    /// Backend state
+    state: StorageState,
+}
+
+impl PackFile<std::io::BufWriter<std::fs::File>> {
+    /// Create new `PackFile` instance
+    pub fn new<P: AsRef<std::path::Path>>(target_path: P, header: PackHeader) -> std::io::Result<Self> {
+        // Open filepath and wrap in BufWriter, optimized for many small writes
+        let writer = std::io::BufWriter::new(std::fs::File::create(target_path)?);
+        Self::from_writer(writer, header)
+    }
+}
+
+impl<W: std::io::Write + std::io::Seek> PackFile<W> {
+    /// Create new `PackFile` instance from writer, and write header
+    pub fn from_writer(mut writer: W, mut header: PackHeader) -> std::io::Result<Self> {
+        header.num_melodies = 0;
+        header.write(&mut writer)?;
+        Ok(Self {
+            writer,
+            header,
+            bit_buffer: 0,
+            num_buffered_bits: 0,
+            state: StorageState::Open,
+        })
+    }
+
+    /// Append `num_bits` lowest bits of `value` to the pack
+    fn write_bits(&mut self, value: u64, num_bits: u32) -> std::io::Result<()> {
+        if num_bits == 0 {
+            return Ok(());
+        }
+        self.bit_buffer = (self.bit_buffer << num_bits) | value;
+        self.num_buffered_bits += num_bits;
+        while self.num_buffered_bits >= 8 {
+            self.num_buffered_bits -= 8;
+            self.writer.write_all(&[(self.bit_buffer >> self.num_buffered_bits) as u8])?;
+        }
+        self.bit_buffer &= (1 << self.num_buffered_bits) - 1;
+        Ok(())
+    }
+
+    /// Write remaining bits (padded with zeros), and number of melodies to the header
+    fn write_num_melodies(&mut self) -> std::io::Result<()> {
+        if self.num_buffered_bits > 0 {
+            let padding = 8 - self.num_buffered_bits;
+            self.write_bits(0, padding)?;
+        }
+        let end = self.writer.stream_position()?;
+        self.writer.seek(std::io::SeekFrom::Start(NUM_MELODIES_OFFSET))?;
+        self.writer.write_all(&self.header.num_melodies.to_le_bytes())?;
+        self.writer.seek(std::io::SeekFrom::Start(end))?;
+        self.writer.flush()
+    }
+
+    /// Append melody to the pack (as indices in note set)
+    fn append_sequence(&mut self, sequence: &[libatm::MIDINote]) -> Result<(), PackFileError> {
+        // Ensure pack is still open
+        if self.state == StorageState::Closed {
+            return Err(PackFileError::IOError(std::io::Error::other(
+                "Pack is closed for writing, cannot append file",
+            )));
+        }
+
+        // Ensure melody can be read back with header
+        let melody_length = sequence.len() as u32;
+        if melody_length != self.header.melody_length {
+            return Err(PackFileError::MelodyLengthMismatch {
+                expected: self.header.melody_length,
+                observed: melody_length,
+            });
+        }
+
+        // Map each note to its index in note set before writing any bits,
+        // so a failed append doesn't leave a partial melody behind
+        let digits = sequence
+            .iter()
+            .enumerate()
+            .map(|(position, note)| match self.header.notes.iter().position(|n| n == note) {
+                Some(digit) => Ok(digit as u64),
+                None => Err(crate::utils::MelodyIndexError::NoteNotInSet { note: crate::utils::format_note(note), position }),
+            })
+            .collect::<Result<Vec<u64>, crate::utils::MelodyIndexError>>()?;
+        let bits_per_note = self.header.bits_per_note();
+        for digit in digits {
+            self.write_bits(digit, bits_per_note)?;
+        }
+        self.header.num_melodies += 1;
+        Ok(())
+    }
+
+    /// Finish writing pack and return the underlying writer
+    pub fn into_inner(mut self) -> Result<W, PackFileError> {
+        self.finish()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: std::io::Write + std::io::Seek> StorageBackend for PackFile<W> {
+    type Error = PackFileError;
+
+    fn append_file(&mut self, mfile: libatm::MIDIFile, mode: Option<u32>) -> Result<(), Self::Error> {
+        // Generate hash and data for MIDI file
+        let pfile = PreparedMIDIFile::new(mfile)?;
+        self.append_prepared(pfile, mode)
+    }
+
+    fn append_melody(
+        &mut self,
+        melody: libatm::MIDINoteVec,
+        render: &MIDIRenderOptions,
+        _mode: Option<u32>,
+    ) -> Result<(), Self::Error> {
+        // MIDI file can only be regenerated if it's rendered with the options in the header
+        if *render != self.header.render {
+            return Err(PackFileError::ParameterMismatch);
+        }
+        self.append_sequence(&melody)
+    }
+
+    fn append_prepared(&mut self, pfile: PreparedMIDIFile, _mode: Option<u32>) -> Result<(), Self::Error> {
+        // Ensure MIDI file can be regenerated from header
+        if pfile.data != self.header.render.gen_file(&pfile.mfile.sequence) {
+            return Err(PackFileError::ParameterMismatch);
+        }
+        self.append_sequence(&pfile.mfile.sequence)
+    }
+
+    fn append_manifest(&mut self, manifest: &str) -> Result<(), Self::Error> {
+        // Rendering options are recorded in the header
+        if manifest != self.header.render.gen_manifest() {
+            return Err(PackFileError::ParameterMismatch);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Self::Error> {
+        match self.state {
+            StorageState::Open => {
+                self.state = StorageState::Closed;
+                self.write_num_melodies()?;
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/*********************
+***** PackReader *****
+*********************/
+
+/// Reader for melody packs written by [PackFile](struct.PackFile.html). Melodies can be
+/// read in order (see: [into_melodies](#method.into_melodies)) or, if the source is seekable,
+/// by their position in the pack (see: [read_melody](#method.read_melody)).
+pub struct PackReader<R: std::io::Read> {
+    /// Underlying reader
+    reader: R,
+    /// Pack header
+    header: PackHeader,
+    /// Offset of first melody in the pack
+    body_offset: u64,
+}
+
+impl PackReader<std::io::BufReader<std::fs::File>> {
+    /// Open melody pack at `path` and read header
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, PackFileError> {
+        let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        Self::from_reader(reader)
+    }
+}
+
+impl<R: std::io::Read> PackReader<R> {
+    /// Create new `PackReader` instance from reader, and read header
+    pub fn from_reader(mut reader: R) -> Result<Self, PackFileError> {
+        let header = PackHeader::read(&mut reader)?;
+        let body_offset = header.gen_header_size();
+        Ok(Self { reader, header, body_offset })
+    }
+
+    /// Pack header
+    pub fn header(&self) -> &PackHeader {
+        &self.header
+    }
+
+    /// Iterate over melodies in the pack, in the order they were written.
+    /// Reader must still be positioned at the first melody (i.e., before
+    /// reading any melodies by position).
+    pub fn into_melodies(self) -> PackMelodies<R> {
+        let remaining = self.header.num_melodies;
+        PackMelodies {
+            reader: self.reader,
+            header: self.header,
+            bit_buffer: 0,
+            num_buffered_bits: 0,
+            remaining,
+        }
+    }
+}
+
+impl<R: std::io::Read + std::io::Seek> PackReader<R> {
+    /// Read melody at position `index` in the pack
+    pub fn read_melody(&mut self, index: u64) -> Result<libatm::MIDINoteVec, PackFileError> {
+        if index >= self.header.num_melodies {
+            return Err(PackFileError::IndexOutOfRange { index, num_melodies: self.header.num_melodies });
+        }
+        let bits_per_melody = self.header.bits_per_melody();
+        let first_bit = index * bits_per_melody;
+        let last_bit = first_bit + bits_per_melody;
+        // Read bytes spanning the melody
+        let mut buf = vec![0u8; (last_bit.div_ceil(8) - first_bit / 8) as usize];
+        self.reader.seek(std::io::SeekFrom::Start(self.body_offset + first_bit / 8))?;
+        self.reader.read_exact(&mut buf)?;
+
+        let bits_per_note = self.header.bits_per_note();
+        let mut bit_offset = first_bit % 8;
+        let melody = (0..self.header.melody_length)
+            .map(|_| {
+                let mut digit = 0usize;
+                for _ in 0..bits_per_note {
+                    let bit = (buf[(bit_offset / 8) as usize] >> (7 - bit_offset % 8)) & 1;
+                    digit = (digit << 1) | bit as usize;
+                    bit_offset += 1;
+                }
+                self.header.notes.get(digit).copied()
+            })
+            .collect::<Option<libatm::MIDINoteVec>>()
+            .ok_or(PackFileError::InvalidHeader { reason: "note index out of range".to_string() })?;
+        Ok(melody)
+    }
+
+    /// Regenerate MIDI file (and its hash) for melody at position `index` in the pack
+    /// (see: [PackHeader::gen_file](struct.PackHeader.html#method.gen_file))
+    pub fn read_file(&mut self, index: u64) -> Result<PreparedMIDIFile, PackFileError> {
+        let melody = self.read_melody(index)?;
+        Ok(self.header.gen_file(melody))
+    }
+}
+
+/// Iterator over the melodies in a melody pack, in the order they were written
+/// (see: [PackReader::into_melodies](struct.PackReader.html#method.into_melodies))
+pub struct PackMelodies<R: std::io::Read> {
+    /// Underlying reader, positioned after the last byte read
+    reader: R,
+    /// Pack header
+    header: PackHeader,
+    /// Bits read but not yet consumed
+    bit_buffer: u64,
+    /// Number of bits in the bit buffer
+    num_buffered_bits: u32,
+    /// Number of melodies left to read
+    remaining: u64,
+}
+
+impl<R: std::io::Read> PackMelodies<R> {
+    /// Pack header
+    pub fn header(&self) -> &PackHeader {
+        &self.header
+    }
+
+    /// Read next `num_bits` bits from the pack
+    fn read_bits(&mut self, num_bits: u32) -> std::io::Result<u64> {
+        while self.num_buffered_bits < num_bits {
+            let mut byte = [0u8; 1];
+            self.reader.read_exact(&mut byte)?;
+            self.bit_buffer = (self.bit_buffer << 8) | byte[0] as u64;
+            self.num_buffered_bits += 8;
+        }
+        self.num_buffered_bits -= num_bits;
+        let value = self.bit_buffer >> self.num_buffered_bits;
+        self.bit_buffer &= (1 << self.num_buffered_bits) - 1;
+        Ok(value)
+    }
+
+    /// Read next melody from the pack
+    fn read_melody(&mut self) -> Result<libatm::MIDINoteVec, PackFileError> {
+        let bits_per_note = self.header.bits_per_note();
+        let mut melody = Vec::with_capacity(self.header.melody_length as usize);
+        for _ in 0..self.header.melody_length {
+            let digit = self.read_bits(bits_per_note)? as usize;
+            match self.header.notes.get(digit) {
+                Some(note) => melody.push(*note),
+                None => return Err(PackFileError::InvalidHeader {
+                    reason: "note index out of range".to_string(),
+                }),
+            }
+        }
+        Ok(melody.into_iter().collect::<libatm::MIDINoteVec>())
+    }
+}
+
+impl<R: std::io::Read> Iterator for PackMelodies<R> {
+    type Item = Result<libatm::MIDINoteVec, PackFileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        Some(self.read_melody())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_round_trip() {
+        let notes = "C:4,D:4,E:4,F:4,G:4".parse::<libatm::MIDINoteVec>().unwrap();
+        let render = MIDIRenderOptions {
+            division: 96,
+            tempo: Some(100),
+            program: Some(0),
+            meta: Some(MIDIMetaEvents { dataset_id: "test".to_string(), notes: notes.clone() }),
+            ..Default::default()
+        };
+        let header = PackHeader::new(notes.clone(), 3, render.clone(), None);
+        let mut pack = PackFile::from_writer(std::io::Cursor::new(Vec::new()), header.clone()).unwrap();
+        for melody_ref in crate::utils::gen_sequences(&notes, 3) {
+            let melody = melody_ref.iter().map(|n| **n).collect::<libatm::MIDINoteVec>();
+            pack.append_melody(melody, &render, None).unwrap();
+        }
+        // Melodies rendered with different options can't be regenerated from the header
+        let melody = "C:4,D:4,E:4".parse::<libatm::MIDINoteVec>().unwrap();
+        assert!(pack.append_melody(melody, &MIDIRenderOptions::default(), None).is_err());
+        let data = pack.into_inner().unwrap().into_inner();
+        // 125 melodies of 3 notes, 3 bits per note
+        assert_eq!(header.gen_size(125), data.len() as u64);
+
+        // Read melodies in order
+        let reader = PackReader::from_reader(std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(125, reader.header().num_melodies);
+        assert_eq!(render, reader.header().render);
+        for (melody, melody_ref) in reader.into_melodies().zip(crate::utils::gen_sequences(&notes, 3)) {
+            let expected = melody_ref.iter().map(|n| **n).collect::<libatm::MIDINoteVec>();
+            assert_eq!(expected, melody.unwrap());
+        }
+
+        // Read melody by position
+        let mut reader = PackReader::from_reader(std::io::Cursor::new(&data)).unwrap();
+        let expected = crate::utils::gen_melody_at(&notes, 3, 97).unwrap();
+        assert_eq!(expected, reader.read_melody(97).unwrap());
+        assert!(reader.read_melody(125).is_err());
+    }
+}
@@ -10,7 +10,9 @@ use crate::storage::{
     IntoInner,
     PathGenerator,
     PathGeneratorError,
+    PreparedMIDIFile,
     StorageBackend,
+    RENDER_MANIFEST_PATH,
 };
 
 /***********************
@@ -73,16 +75,9 @@ where
     pub fn get_ref(&self) -> &W {
         self.archive.get_ref()
     }
-}
 
-impl<W, G> StorageBackend for TarArchive<W, G>
-where
-    W: std::io::Write,
-    G: PathGenerator,
-{
-    type Error = TarArchiveError;
-
-    fn append_file(&mut self, mfile: libatm::MIDIFile, mode: Option<u32>) -> Result<(), Self::Error> {
+    /// Append entry with `data` at `path`, with permissions `mode` (or 644 by default)
+    fn append_data(&mut self, path: &str, data: &[u8], mode: Option<u32>) -> Result<(), TarArchiveError> {
         // Ensure archive is stil open
         if self.state == StorageState::Closed {
             return Err(TarArchiveError::IOError(std::io::Error::new(
@@ -91,25 +86,45 @@ where
             )));
         }
 
-        // Generate path from melody hash
-        let path = self.path_generator.gen_path_for_file(&mfile)?;
         // Generate header for entry
         let mut header = tar::Header::new_old();
         // Set size field in header
-        header.set_size(mfile.gen_size() as u64);
+        header.set_size(data.len() as u64);
         // Set file permissions to provided value,
         // or 644 (rw-r-r) by default
         match mode {
             Some(mode) => header.set_mode(mode),
             None => header.set_mode(644),
         }
-        // Generate buffer containing MIDI file data
-        let data = mfile.gen_file()?;
         self
             .archive
-            .append_data(&mut header, &path, data.as_slice())
+            .append_data(&mut header, path, data)
             .map_err(|e| TarArchiveError::IOError(e))
     }
+}
+
+impl<W, G> StorageBackend for TarArchive<W, G>
+where
+    W: std::io::Write,
+    G: PathGenerator,
+{
+    type Error = TarArchiveError;
+
+    fn append_file(&mut self, mfile: libatm::MIDIFile, mode: Option<u32>) -> Result<(), Self::Error> {
+        // Generate hash and data for MIDI file
+        let pfile = PreparedMIDIFile::new(mfile)?;
+        self.append_prepared(pfile, mode)
+    }
+
+    fn append_prepared(&mut self, pfile: PreparedMIDIFile, mode: Option<u32>) -> Result<(), Self::Error> {
+        // Generate path from melody hash
+        let path = self.path_generator.gen_path_for_prepared(&pfile)?;
+        self.append_data(&path, &pfile.data, mode)
+    }
+
+    fn append_manifest(&mut self, manifest: &str) -> Result<(), Self::Error> {
+        self.append_data(RENDER_MANIFEST_PATH, manifest.as_bytes(), None)
+    }
 
     fn finish(&mut self) -> Result<(), Self::Error> {
         // If archive is still "open" call tar.finish() and set state
@@ -6,23 +6,30 @@
 // To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
 // a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
 
+use std::io::Write;
+
 use crate::storage::{
+    checkpoint,
+    BackendCheckpoint,
     IntoInner,
     PathGenerator,
+    PositionWriter,
+    PreparedMIDIFile,
     StorageBackend,
     TarArchive,
     TarArchiveError,
 };
 
-/// Type alias for `TarFile` inner object
-type InnerObject = std::io::BufWriter<std::fs::File>;
+/// Type alias for `TarFile` default writer
+type FileWriter = std::io::BufWriter<std::fs::File>;
 
 /// [Tar archive](https://en.wikipedia.org/wiki/Tar_(computing)) storage backend.
 /// Use for datasets where compression isn't necessary. For reference, a tar archive
 /// with 4096 entries will take up ~4.19MB on disk, as each entry requires a header of 512
-/// bytes and at least 512 bytes of data.
-pub struct TarFile<G: PathGenerator> {
-    archive: TarArchive<InnerObject, G>,
+/// bytes and at least 512 bytes of data. Writes to a file by default, but can write to
+/// any `std::io::Write` (i.e., stdout, see: [from_writer](#method.from_writer)).
+pub struct TarFile<G: PathGenerator, W: std::io::Write = FileWriter> {
+    archive: TarArchive<PositionWriter<W>, G>,
 }
 
 impl<G: PathGenerator> TarFile<G> {
@@ -33,28 +40,68 @@ impl<G: PathGenerator> TarFile<G> {
         // Wrap in BufWriter, optimized for many small writes
         // (see: https://doc.rust-lang.org/std/io/struct.BufWriter.html)
         let archive = std::io::BufWriter::new(archive);
+        Ok(Self::from_writer(archive, path_generator))
+    }
+
+    /// Open existing `TarFile` and resume writing from checkpoint
+    /// (see: [StorageBackend::checkpoint](../trait.StorageBackend.html#method.checkpoint))
+    pub fn resume<P: AsRef<std::path::Path>>(
+        target_path: P,
+        path_generator: G,
+        checkpoint: &BackendCheckpoint,
+    ) -> Result<Self, TarArchiveError> {
+        // Open filepath and truncate to checkpoint
+        let archive = checkpoint::open_for_resume(target_path, checkpoint)?;
+        let archive = PositionWriter::new(std::io::BufWriter::new(archive), checkpoint.archive_offset);
         Ok(Self {
             archive: TarArchive::new(archive, path_generator),
         })
     }
 }
 
-impl<G: PathGenerator> StorageBackend for TarFile<G> {
-    type Error = <TarArchive<InnerObject, G> as StorageBackend>::Error;
+impl<G: PathGenerator, W: std::io::Write> TarFile<G, W> {
+    /// Create new `TarFile` instance from writer. Writer should be
+    /// buffered, as entries are written in many small writes.
+    pub fn from_writer(writer: W, path_generator: G) -> Self {
+        Self {
+            archive: TarArchive::new(PositionWriter::new(writer, 0), path_generator),
+        }
+    }
+}
+
+impl<G: PathGenerator, W: std::io::Write> StorageBackend for TarFile<G, W> {
+    type Error = <TarArchive<PositionWriter<W>, G> as StorageBackend>::Error;
 
     fn append_file(&mut self, mfile: libatm::MIDIFile, mode: Option<u32>) -> Result<(), Self::Error> {
         self.archive.append_file(mfile, mode)
     }
 
+    fn append_prepared(&mut self, pfile: PreparedMIDIFile, mode: Option<u32>) -> Result<(), Self::Error> {
+        self.archive.append_prepared(pfile, mode)
+    }
+
+    fn append_manifest(&mut self, manifest: &str) -> Result<(), Self::Error> {
+        self.archive.append_manifest(manifest)
+    }
+
+    fn checkpoint(&mut self) -> Result<Option<BackendCheckpoint>, Self::Error> {
+        // Each entry is written to the archive in full, so the archive is
+        // consistent as soon as buffered data has been written to disk
+        let archive_offset = self.archive.get_mut().gen_offset()?;
+        Ok(Some(BackendCheckpoint { archive_offset, ..Default::default() }))
+    }
+
     fn finish(&mut self) -> Result<(), Self::Error> {
-        self.archive.finish()
+        self.archive.finish()?;
+        // Write buffered data to disk
+        self.archive.get_mut().flush().map_err(TarArchiveError::IOError)
     }
 }
 
-impl<G: PathGenerator> IntoInner for TarFile<G> {
-    type Inner = InnerObject;
+impl<G: PathGenerator, W: std::io::Write> IntoInner for TarFile<G, W> {
+    type Inner = W;
 
     fn into_inner(self) -> Result<Self::Inner, <Self as StorageBackend>::Error> {
-        self.archive.into_inner()
+        Ok(self.archive.into_inner()?.into_inner())
     }
 }
@@ -6,25 +6,112 @@
 // To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
 // a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
 
-use flate2::write::GzEncoder;
+use std::io::Write;
+
+use flate2::{
+    Compression,
+    write::GzEncoder,
+};
 
 use crate::storage::{
+    checkpoint,
+    BackendCheckpoint,
     IntoInner,
     PathGenerator,
+    PositionWriter,
+    PreparedMIDIFile,
     StorageBackend,
     TarArchive,
+    TarArchiveError,
 };
 
+/**************************
+***** GzMemberEncoder *****
+**************************/
+
+/// Gzip encoder that can finish the current gzip member and start a new one, producing a
+/// [multi-member](https://tools.ietf.org/html/rfc1952#page-5) gzip file. Decompressing a
+/// multi-member file produces the concatenated contents of all members, so the boundary
+/// between two members is a point at which the file can be truncated and appended to.
+pub struct GzMemberEncoder<W: std::io::Write> {
+    /// Encoder for current member
+    encoder: Option<GzEncoder<W>>,
+    /// Compression level to use for each member
+    compression_level: Compression,
+    /// Number of (uncompressed) bytes written to current member
+    member_size: u64,
+}
+
+impl<W: std::io::Write> GzMemberEncoder<W> {
+    /// Create new `GzMemberEncoder` instance
+    pub fn new(writer: W, compression_level: Compression) -> Self {
+        Self {
+            encoder: Some(GzEncoder::new(writer, compression_level)),
+            compression_level,
+            member_size: 0,
+        }
+    }
+
+    /// Finish current member (if any data has been written to it) and start a new one.
+    /// Returns a mutable reference to the underlying writer, which is positioned
+    /// at the boundary between the two members.
+    pub fn finish_member(&mut self) -> std::io::Result<&mut W> {
+        if self.member_size > 0 {
+            self.encoder.as_mut().expect("encoder is always present").try_finish()?;
+            let writer = self.encoder.take().expect("encoder is always present").finish()?;
+            self.encoder = Some(GzEncoder::new(writer, self.compression_level));
+            self.member_size = 0;
+        }
+        Ok(self.get_mut())
+    }
+
+    /// Finish current member without consuming the encoder. Data should not be
+    /// written to the encoder after calling this method.
+    pub fn try_finish(&mut self) -> std::io::Result<()> {
+        self.encoder.as_mut().expect("encoder is always present").try_finish()
+    }
+
+    /// Finish current member and return the underlying writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.encoder.take().expect("encoder is always present").finish()
+    }
+
+    /// Acquires a mutable reference to the underlying writer
+    pub fn get_mut(&mut self) -> &mut W {
+        self.encoder.as_mut().expect("encoder is always present").get_mut()
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for GzMemberEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let num_bytes = self.encoder.as_mut().expect("encoder is always present").write(buf)?;
+        self.member_size += num_bytes as u64;
+        Ok(num_bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.encoder.as_mut().expect("encoder is always present").flush()
+    }
+}
+
+/********************
+***** TarGzFile *****
+********************/
+
+/// Type alias for `TarGzFile` default writer
+type FileWriter = std::io::BufWriter<std::fs::File>;
+
 /// Type alias for `TarGzFile` inner object
-type InnerObject = GzEncoder<std::io::BufWriter<std::fs::File>>;
+type InnerObject<W> = GzMemberEncoder<PositionWriter<W>>;
 
 /// [Gzip](https://en.wikipedia.org/wiki/Gzip)-compressed
 /// [tar archive](https://en.wikipedia.org/wiki/Tar_(computing)) storage backend.
 /// Use for larger datasets where smaller output file is desired (i.e., for sharing via email or
 /// messaging apps). Realized compression ratio will depend on the `compression_level` used,
-/// as well as the compressibility of the input data.
-pub struct TarGzFile<G: PathGenerator> {
-    archive: TarArchive<InnerObject, G>,
+/// as well as the compressibility of the input data. Writes to a file by default, but can write
+/// to any `std::io::Write` (i.e., stdout, see: [from_writer](#method.from_writer)).
+pub struct TarGzFile<G: PathGenerator, W: std::io::Write = FileWriter> {
+    archive: TarArchive<InnerObject<W>, G>,
 }
 
 impl<G: PathGenerator> TarGzFile<G> {
@@ -34,45 +121,99 @@ impl<G: PathGenerator> TarGzFile<G> {
     pub fn new<P: AsRef<std::path::Path>>(
         target_path: P,
         path_generator: G,
-        compression_level: Option<flate2::Compression>,
+        compression_level: Option<Compression>,
     ) -> std::io::Result<Self> {
-        // Open filepath
-        let archive = std::fs::File::create(target_path)?;
-        // Wrap in BufWriter, optimized for many small writes
+        // Open filepath and wrap in BufWriter, optimized for many small writes
         // (see: https://doc.rust-lang.org/std/io/struct.BufWriter.html)
-        let archive = std::io::BufWriter::new(archive);
-        // Create Gzip encoder with file as underlying buffer
+        let archive = std::io::BufWriter::new(std::fs::File::create(target_path)?);
+        Ok(Self::from_writer(archive, path_generator, compression_level))
+    }
+
+    /// Open existing `TarGzFile` and resume writing from checkpoint
+    /// (see: [StorageBackend::checkpoint](../trait.StorageBackend.html#method.checkpoint)).
+    /// Writing resumes in a new gzip member.
+    pub fn resume<P: AsRef<std::path::Path>>(
+        target_path: P,
+        path_generator: G,
+        compression_level: Option<Compression>,
+        checkpoint: &BackendCheckpoint,
+    ) -> std::io::Result<Self> {
+        // Open filepath and truncate to checkpoint
+        let archive = checkpoint::open_for_resume(target_path, checkpoint)?;
+        let archive = PositionWriter::new(std::io::BufWriter::new(archive), checkpoint.archive_offset);
+        Ok(Self::from_position_writer(archive, path_generator, compression_level))
+    }
+}
+
+impl<G: PathGenerator, W: std::io::Write> TarGzFile<G, W> {
+    /// Create new `TarGzFile` instance from position writer
+    fn from_position_writer(
+        archive: PositionWriter<W>,
+        path_generator: G,
+        compression_level: Option<Compression>,
+    ) -> Self {
+        // Create Gzip encoder with writer as underlying buffer
         // If no compression level provided, use default compression level
         // as implemented in flate2::Compression::default
-        let archive = flate2::write::GzEncoder::new(
+        let archive = GzMemberEncoder::new(
             archive,
             match compression_level {
                 Some(level) => level,
-                None => flate2::Compression::default(),
+                None => Compression::default(),
             },
         );
-        Ok(Self {
+        Self {
             archive: TarArchive::new(archive, path_generator),
-        })
+        }
+    }
+
+    /// Create new `TarGzFile` instance from writer (see: [new](#method.new)).
+    /// Writer should be buffered, as compressed data is written in many small writes.
+    pub fn from_writer(
+        writer: W,
+        path_generator: G,
+        compression_level: Option<Compression>,
+    ) -> Self {
+        Self::from_position_writer(PositionWriter::new(writer, 0), path_generator, compression_level)
     }
 }
 
-impl<G: PathGenerator> StorageBackend for TarGzFile<G> {
-    type Error = <TarArchive<InnerObject, G> as StorageBackend>::Error;
+impl<G: PathGenerator, W: std::io::Write> StorageBackend for TarGzFile<G, W> {
+    type Error = <TarArchive<InnerObject<W>, G> as StorageBackend>::Error;
 
     fn append_file(&mut self, mfile: libatm::MIDIFile, mode: Option<u32>) -> Result<(), Self::Error> {
         self.archive.append_file(mfile, mode)
     }
 
+    fn append_prepared(&mut self, pfile: PreparedMIDIFile, mode: Option<u32>) -> Result<(), Self::Error> {
+        self.archive.append_prepared(pfile, mode)
+    }
+
+    fn append_manifest(&mut self, manifest: &str) -> Result<(), Self::Error> {
+        self.archive.append_manifest(manifest)
+    }
+
+    fn checkpoint(&mut self) -> Result<Option<BackendCheckpoint>, Self::Error> {
+        // Finish current gzip member, so the compressed data written so far
+        // can be decompressed on its own
+        let archive = self.archive.get_mut().finish_member().map_err(TarArchiveError::IOError)?;
+        let archive_offset = archive.gen_offset()?;
+        Ok(Some(BackendCheckpoint { archive_offset, ..Default::default() }))
+    }
+
     fn finish(&mut self) -> Result<(), Self::Error> {
+        self.archive.finish()?;
         // NOTE: The underlying flate2::write::GzEncoder implements std::ops::Drop,
-        // and thus will finish itself when it goes out of scope
-        self.archive.finish()
+        // and thus would finish itself when it goes out of scope, but any errors
+        // would be ignored. Finish it explicitly and write buffered data to disk.
+        let encoder = self.archive.get_mut();
+        encoder.try_finish().map_err(TarArchiveError::IOError)?;
+        encoder.get_mut().flush().map_err(TarArchiveError::IOError)
     }
 }
 
-impl<G: PathGenerator> IntoInner for TarGzFile<G> {
-    type Inner = InnerObject;
+impl<G: PathGenerator, W: std::io::Write> IntoInner for TarGzFile<G, W> {
+    type Inner = InnerObject<W>;
 
     fn into_inner(self) -> Result<Self::Inner, <Self as StorageBackend>::Error> {
         self.archive.into_inner()
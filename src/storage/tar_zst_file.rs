@@ -0,0 +1,132 @@
+// tar_zst_file.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use std::io::Write;
+
+use zstd::stream::write::Encoder;
+
+use crate::storage::{
+    IntoInner,
+    PathGenerator,
+    PreparedMIDIFile,
+    StorageBackend,
+    TarArchive,
+    TarArchiveError,
+};
+
+/**********************
+***** ZstdOptions *****
+**********************/
+
+/// [Zstandard](https://facebook.github.io/zstd/) compression options
+#[derive(Clone, Debug)]
+pub struct ZstdOptions {
+    /// Compression level (1-22)
+    pub level: i32,
+    /// Base 2 log of the long-distance matching window size (if enabled).
+    /// Decompressing with windows larger than 2^27 bytes (128MB) requires passing
+    /// `--long=<window_log>` to the zstd CLI.
+    pub window_log: Option<u32>,
+    /// Number of worker threads (0 compresses on the calling thread)
+    pub num_workers: u32,
+}
+
+impl Default for ZstdOptions {
+    fn default() -> Self {
+        Self {
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            window_log: None,
+            num_workers: 0,
+        }
+    }
+}
+
+impl ZstdOptions {
+    /// Create new zstd stream encoder with options
+    pub fn gen_encoder<W: std::io::Write>(&self, writer: W) -> std::io::Result<Encoder<'static, W>> {
+        let mut encoder = Encoder::new(writer, self.level)?;
+        encoder.include_checksum(true)?;
+        if let Some(window_log) = self.window_log {
+            encoder.long_distance_matching(true)?;
+            encoder.window_log(window_log)?;
+        }
+        if self.num_workers > 0 {
+            encoder.multithread(self.num_workers)?;
+        }
+        Ok(encoder)
+    }
+}
+
+/*********************
+***** TarZstFile *****
+*********************/
+
+/// Type alias for `TarZstFile` inner object
+type InnerObject = Encoder<'static, std::io::BufWriter<std::fs::File>>;
+
+/// [Zstandard](https://facebook.github.io/zstd/)-compressed
+/// [tar archive](https://en.wikipedia.org/wiki/Tar_(computing)) storage backend.
+/// Compresses faster than [TarGzFile](../tar_gz_file/struct.TarGzFile.html) at comparable
+/// compression ratios, and with a long-distance matching window can exploit the similarity
+/// between the many small (and nearly identical) MIDI files in a dataset.
+pub struct TarZstFile<G: PathGenerator> {
+    archive: TarArchive<InnerObject, G>,
+}
+
+impl<G: PathGenerator> TarZstFile<G> {
+    /// Create new `TarZstFile` instance
+    pub fn new<P: AsRef<std::path::Path>>(
+        target_path: P,
+        path_generator: G,
+        options: &ZstdOptions,
+    ) -> std::io::Result<Self> {
+        // Open filepath and wrap in BufWriter, optimized for many small writes
+        let archive = std::io::BufWriter::new(std::fs::File::create(target_path)?);
+        // Create zstd encoder with file as underlying buffer
+        let archive = options.gen_encoder(archive)?;
+        Ok(Self {
+            archive: TarArchive::new(archive, path_generator),
+        })
+    }
+}
+
+impl<G: PathGenerator> StorageBackend for TarZstFile<G> {
+    type Error = <TarArchive<InnerObject, G> as StorageBackend>::Error;
+
+    fn append_file(&mut self, mfile: libatm::MIDIFile, mode: Option<u32>) -> Result<(), Self::Error> {
+        self.archive.append_file(mfile, mode)
+    }
+
+    fn append_prepared(&mut self, pfile: PreparedMIDIFile, mode: Option<u32>) -> Result<(), Self::Error> {
+        self.archive.append_prepared(pfile, mode)
+    }
+
+    fn append_manifest(&mut self, manifest: &str) -> Result<(), Self::Error> {
+        self.archive.append_manifest(manifest)
+    }
+
+    fn finish(&mut self) -> Result<(), Self::Error> {
+        self.archive.finish()?;
+        // Unlike flate2::write::GzEncoder, zstd::stream::write::Encoder doesn't finish
+        // itself when dropped, so finish it explicitly and write buffered data to disk
+        let encoder = self.archive.get_mut();
+        encoder.do_finish().map_err(TarArchiveError::IOError)?;
+        encoder.get_mut().flush().map_err(TarArchiveError::IOError)
+    }
+}
+
+impl<G: PathGenerator> IntoInner for TarZstFile<G> {
+    type Inner = InnerObject;
+
+    fn into_inner(mut self) -> Result<Self::Inner, <Self as StorageBackend>::Error> {
+        // Finish zstd encoder (and flush), otherwise the returned encoder holds an
+        // incomplete frame
+        self.finish()?;
+        self.archive.into_inner()
+    }
+}
@@ -0,0 +1,190 @@
+// zip_file.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use std::io::Write;
+
+use crate::storage::{
+    IntoInner,
+    PathGenerator,
+    PathGeneratorError,
+    PreparedMIDIFile,
+    StorageBackend,
+    StorageState,
+    RENDER_MANIFEST_PATH,
+};
+
+/********************
+***** ZipMethod *****
+********************/
+
+/// Compression method for entries in a [ZipFile](struct.ZipFile.html)
+#[derive(Clone, Copy, Debug)]
+pub enum ZipMethod {
+    /// Deflate each entry with compression level (0-9, or default level if not provided)
+    Deflate(Option<i32>),
+    /// Store each entry without compression
+    Store,
+}
+
+impl Default for ZipMethod {
+    fn default() -> Self {
+        Self::Deflate(None)
+    }
+}
+
+/******************
+***** ZipFile *****
+******************/
+
+/// Error type for [ZipFile](struct.ZipFile.html) (wrapping around `std::io::Error`,
+/// `zip::result::ZipError` and [PathGeneratorError](../enum.PathGeneratorError.html))
+#[derive(Debug, thiserror::Error)]
+pub enum ZipFileError {
+    /// IO error
+    #[error(transparent)]
+    IOError(#[from] std::io::Error),
+    /// [PathGenerator](../trait.PathGenerator.html) error
+    #[error(transparent)]
+    PathGenerator(#[from] PathGeneratorError),
+    /// Zip error
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+}
+
+/// [ZIP archive](https://en.wikipedia.org/wiki/ZIP_(file_format)) storage backend.
+/// Use for datasets that will be shared with users who can't easily open tar archives
+/// (i.e., on Windows). Unlike gzip-compressed tar archives, each entry is compressed
+/// separately, so compression ratios are worse, but entries can be extracted individually.
+/// [ZIP64](https://en.wikipedia.org/wiki/ZIP_(file_format)#ZIP64) records are written once
+/// the archive has more than 65,535 entries (or is larger than 4GB).
+///
+/// NOTE: The central directory is written when the archive is finished, so an entry
+/// for each file is kept in memory until then.
+pub struct ZipFile<W: std::io::Write + std::io::Seek, G: PathGenerator> {
+    archive: zip::ZipWriter<W>,
+    path_generator: G,
+    method: ZipMethod,
+    state: StorageState,
+}
+
+impl<G: PathGenerator> ZipFile<std::io::BufWriter<std::fs::File>, G> {
+    /// Create new `ZipFile` instance
+    pub fn new<P: AsRef<std::path::Path>>(
+        target_path: P,
+        path_generator: G,
+        method: ZipMethod,
+    ) -> std::io::Result<Self> {
+        // Open filepath and wrap in BufWriter, optimized for many small writes
+        let archive = std::io::BufWriter::new(std::fs::File::create(target_path)?);
+        Ok(Self::from_writer(archive, path_generator, method))
+    }
+}
+
+impl<W, G> ZipFile<W, G>
+where
+    W: std::io::Write + std::io::Seek,
+    G: PathGenerator,
+{
+    /// Create new `ZipFile` instance from writer
+    pub fn from_writer(writer: W, path_generator: G, method: ZipMethod) -> Self {
+        Self {
+            archive: zip::ZipWriter::new(writer),
+            path_generator,
+            method,
+            state: StorageState::Open,
+        }
+    }
+
+    /// Generate options for entry
+    fn gen_file_options(&self, mode: Option<u32>) -> zip::write::FileOptions {
+        let options = match self.method {
+            ZipMethod::Deflate(level) => zip::write::FileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated)
+                .compression_level(level),
+            ZipMethod::Store => zip::write::FileOptions::default()
+                .compression_method(zip::CompressionMethod::Stored),
+        };
+        // Set file permissions to provided value,
+        // or 644 (rw-r-r) by default
+        options.unix_permissions(mode.unwrap_or(0o644))
+    }
+}
+
+impl<W, G> StorageBackend for ZipFile<W, G>
+where
+    W: std::io::Write + std::io::Seek,
+    G: PathGenerator,
+{
+    type Error = ZipFileError;
+
+    fn append_file(&mut self, mfile: libatm::MIDIFile, mode: Option<u32>) -> Result<(), Self::Error> {
+        // Generate hash and data for MIDI file
+        let pfile = PreparedMIDIFile::new(mfile)?;
+        self.append_prepared(pfile, mode)
+    }
+
+    fn append_prepared(&mut self, pfile: PreparedMIDIFile, mode: Option<u32>) -> Result<(), Self::Error> {
+        // Ensure archive is still open
+        if self.state == StorageState::Closed {
+            return Err(ZipFileError::IOError(std::io::Error::other(
+                "Archive is closed for writing, cannot append file",
+            )));
+        }
+
+        // Generate path from melody hash
+        let path = self.path_generator.gen_path_for_prepared(&pfile)?;
+        // ZIP entry names always use forward slashes
+        let path = path.replace(std::path::MAIN_SEPARATOR, "/");
+        let options = self.gen_file_options(mode);
+        self.archive.start_file(path, options)?;
+        self.archive.write_all(&pfile.data)?;
+        Ok(())
+    }
+
+    fn append_manifest(&mut self, manifest: &str) -> Result<(), Self::Error> {
+        let options = self.gen_file_options(None);
+        self.archive.start_file(RENDER_MANIFEST_PATH, options)?;
+        self.archive.write_all(manifest.as_bytes())?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Self::Error> {
+        match self.state {
+            StorageState::Open => {
+                self.state = StorageState::Closed;
+                // Write central directory and flush buffered data
+                let mut writer = self.archive.finish()?;
+                writer.flush()?;
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<W, G> IntoInner for ZipFile<W, G>
+where
+    W: std::io::Write + std::io::Seek,
+    G: PathGenerator,
+{
+    type Inner = W;
+
+    fn into_inner(mut self) -> Result<Self::Inner, <Self as StorageBackend>::Error> {
+        match self.state {
+            StorageState::Open => {
+                self.state = StorageState::Closed;
+                // Write central directory and return underlying writer
+                Ok(self.archive.finish()?)
+            },
+            // Underlying writer is released when finishing archive
+            _ => Err(ZipFileError::IOError(std::io::Error::other(
+                "Archive already finished, cannot return underlying writer",
+            ))),
+        }
+    }
+}
@@ -59,3 +59,432 @@ pub fn gen_sequences(
         .map(|_| notes.iter())
         .multi_cartesian_product()
 }
+
+/// Error type for mapping melodies to and from their index in the sequence
+/// generated by [gen_sequences](fn.gen_sequences.html)
+#[derive(Debug, thiserror::Error)]
+pub enum MelodyIndexError {
+    #[error("Note {note} at position {position} is not in the note set")]
+    NoteNotInSet { note: String, position: usize },
+    #[error("Melody index {index} out of range for {num_melodies} melodies")]
+    IndexOutOfRange { index: u64, num_melodies: u64 },
+    #[error("Number of melodies with {num_notes} notes and length {melody_length} overflows a 64-bit index")]
+    IndexOverflow { num_notes: u32, melody_length: u32 },
+}
+
+/// Ensure number of melodies with `num_notes` notes and length `melody_length`
+/// fits in a `u64` and return it
+fn checked_num_melodies(num_notes: u32, melody_length: u32) -> Result<u64, MelodyIndexError> {
+    (num_notes as u64)
+        .checked_pow(melody_length)
+        .ok_or(MelodyIndexError::IndexOverflow { num_notes, melody_length })
+}
+
+/// Calculate the index of `melody` in the sequence of melodies generated by
+/// [gen_sequences](fn.gen_sequences.html) with the same notes and length.
+/// Melodies are generated in lexicographic order, so the index is calculated
+/// by treating the melody as a number in base `notes.len()` (in `O(melody.len())` time).
+///
+/// # Arguments
+///
+/// * `notes`: set of MIDI notes melodies are generated from (in generation order)
+/// * `melody`: melody to calculate index of
+///
+/// # Examples
+///
+/// ```rust
+/// let note_set = "C:4,D:4,E:4".parse::<libatm::MIDINoteSet>().unwrap();
+/// let notes = libatm::MIDINoteVec::from(note_set);
+/// let melody = "E:4,C:4,D:4".parse::<libatm::MIDINoteVec>().unwrap();
+/// // (2 * 3^2) + (0 * 3^1) + (1 * 3^0) = 19
+/// assert_eq!(19, atm::utils::gen_melody_index(&notes, &melody).unwrap());
+/// ```
+pub fn gen_melody_index(
+    notes: &libatm::MIDINoteVec,
+    melody: &[libatm::MIDINote],
+) -> Result<u64, MelodyIndexError> {
+    checked_num_melodies(notes.len() as u32, melody.len() as u32)?;
+    melody
+        .iter()
+        .enumerate()
+        .try_fold(0u64, |index, (position, note)| {
+            match notes.iter().position(|n| n == note) {
+                Some(digit) => Ok(index * (notes.len() as u64) + digit as u64),
+                None => Err(MelodyIndexError::NoteNotInSet { note: format_note(note), position }),
+            }
+        })
+}
+
+/// Generate the melody at index `index` in the sequence of melodies generated by
+/// [gen_sequences](fn.gen_sequences.html), without generating any of the melodies
+/// before it (in `O(length)` time). Inverse of [gen_melody_index](fn.gen_melody_index.html).
+///
+/// # Arguments
+///
+/// * `notes`: set of MIDI notes melodies are generated from (in generation order)
+/// * `length`: length of melodies
+/// * `index`: index of melody to generate
+///
+/// # Examples
+///
+/// ```rust
+/// let note_set = "C:4,D:4,E:4".parse::<libatm::MIDINoteSet>().unwrap();
+/// let notes = libatm::MIDINoteVec::from(note_set);
+/// let melody = atm::utils::gen_melody_at(&notes, 3, 19).unwrap();
+/// assert_eq!("E:4,C:4,D:4".parse::<libatm::MIDINoteVec>().unwrap(), melody);
+/// ```
+pub fn gen_melody_at(
+    notes: &libatm::MIDINoteVec,
+    length: u32,
+    index: u64,
+) -> Result<libatm::MIDINoteVec, MelodyIndexError> {
+    let num_melodies = checked_num_melodies(notes.len() as u32, length)?;
+    if index >= num_melodies {
+        return Err(MelodyIndexError::IndexOutOfRange { index, num_melodies });
+    }
+
+    let melody = gen_digits(notes.len() as u64, length, index)
+        .into_iter()
+        .map(|digit| notes[digit])
+        .collect::<libatm::MIDINoteVec>();
+    Ok(melody)
+}
+
+/// Generate digits (positions in note set) of melody at `index`, from most
+/// to least significant
+fn gen_digits(num_notes: u64, length: u32, index: u64) -> Vec<usize> {
+    let mut remainder = index;
+    let mut digits = (0..length)
+        .map(|_| {
+            let digit = (remainder % num_notes) as usize;
+            remainder /= num_notes;
+            digit
+        })
+        .collect::<Vec<usize>>();
+    digits.reverse();
+    digits
+}
+
+/// Iterator over a range of the melodies generated by [gen_sequences](fn.gen_sequences.html)
+/// (see: [gen_sequences_range](fn.gen_sequences_range.html))
+pub struct SequenceRange<'a> {
+    /// Set of MIDI notes to generate melodies from
+    notes: &'a libatm::MIDINoteVec,
+    /// Positions in note set of the next melody
+    digits: Vec<usize>,
+    /// Number of melodies left in range
+    remaining: u64,
+}
+
+impl<'a> Iterator for SequenceRange<'a> {
+    type Item = Vec<&'a libatm::MIDINote>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let melody = self.digits.iter().map(|digit| &self.notes[*digit]).collect::<Self::Item>();
+        self.remaining -= 1;
+        // Increment digits, carrying from least significant
+        for digit in self.digits.iter_mut().rev() {
+            *digit += 1;
+            if *digit < self.notes.len() {
+                break;
+            }
+            *digit = 0;
+        }
+        Some(melody)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+}
+
+/// Generate melodies with indices in `start..end` of the melodies generated by
+/// [gen_sequences](fn.gen_sequences.html), in the same order. Seeks directly to `start`
+/// instead of generating the melodies before it.
+///
+/// # Arguments:
+///
+/// * `notes`: set of MIDI notes (see: [libatm::MIDINote](../../libatm/midi_note/struct.MIDINote.html))
+/// * `length`: length of sequences to generate
+/// * `start`: index of first melody (inclusive)
+/// * `end`: index of last melody (exclusive)
+///
+/// # Examples
+///
+/// ```rust
+/// let note_set = "C:4,D:4,E:4,F:4,G:5".parse::<libatm::MIDINoteVec>().unwrap();
+/// // Generate all melodies past the first 1,000
+/// let melodies = atm::utils::gen_sequences_range(&note_set, 6, 1000, 15625).unwrap();
+/// let expected = atm::utils::gen_sequences(&note_set, 6).skip(1000);
+/// assert!(melodies.eq(expected));
+/// ```
+pub fn gen_sequences_range(
+    notes: &libatm::MIDINoteVec,
+    length: u32,
+    start: u64,
+    end: u64,
+) -> Result<SequenceRange<'_>, MelodyIndexError> {
+    let num_melodies = checked_num_melodies(notes.len() as u32, length)?;
+    if end > num_melodies {
+        return Err(MelodyIndexError::IndexOutOfRange { index: end, num_melodies });
+    }
+    Ok(SequenceRange {
+        notes,
+        digits: gen_digits(notes.len() as u64, length, start),
+        remaining: end.saturating_sub(start),
+    })
+}
+
+/// Generate range of melody indices for shard `shard_index` (starting from 0) of `num_shards`.
+/// Shard boundaries are aligned to multiples of `block_size` (i.e., the number of melodies
+/// per partition), so no block is split between shards. Concatenating the ranges of all shards
+/// in order produces `0..num_melodies`.
+///
+/// # Examples
+///
+/// ```rust
+/// // 10 blocks of 100 melodies split into 3 shards
+/// assert_eq!(0..300, atm::utils::gen_shard_range(1000, 100, 0, 3));
+/// assert_eq!(300..600, atm::utils::gen_shard_range(1000, 100, 1, 3));
+/// assert_eq!(600..1000, atm::utils::gen_shard_range(1000, 100, 2, 3));
+/// ```
+pub fn gen_shard_range(
+    num_melodies: u64,
+    block_size: u64,
+    shard_index: u32,
+    num_shards: u32,
+) -> std::ops::Range<u64> {
+    let num_blocks = num_melodies.div_ceil(block_size);
+    let gen_boundary = |shard: u32| {
+        let block = ((num_blocks as u128 * shard as u128) / num_shards as u128) as u64;
+        std::cmp::min(block * block_size, num_melodies)
+    };
+    gen_boundary(shard_index)..gen_boundary(shard_index + 1)
+}
+
+/// Token for rests in note sets and melodies (i.e., 'C:4,R,G:4')
+pub const REST_TOKEN: &str = "R";
+
+/// Format note as NOTE:OCTAVE pair, or [REST_TOKEN](constant.REST_TOKEN.html) for rests
+pub(crate) fn format_note(note: &libatm::MIDINote) -> String {
+    match note.note_type {
+        libatm::MIDINoteType::Rest => REST_TOKEN.to_string(),
+        note_type => format!("{:?}:{}", note_type, note.octave),
+    }
+}
+
+/// Format melody as comma-separated set of NOTE:OCTAVE pairs (and rests), the same
+/// format accepted on the command line (i.e., 'C:4,DSharp:4,R,G:4').
+///
+/// # Examples
+///
+/// ```rust
+/// let melody = "C:4,D#:4,Rest:0,G:4".parse::<libatm::MIDINoteVec>().unwrap();
+/// assert_eq!("C:4,DSharp:4,R,G:4", atm::utils::format_melody(&melody));
+/// ```
+pub fn format_melody(melody: &[libatm::MIDINote]) -> String {
+    melody
+        .iter()
+        .map(format_note)
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Generate hash of melody, used as the filename of its MIDI file: MIDI note numbers
+/// concatenated, with [REST_TOKEN](constant.REST_TOKEN.html) for rests. Matches
+/// [MIDIFile::gen_hash](../../libatm/midi_file/struct.MIDIFile.html#method.gen_hash)
+/// for melodies without rests (which libatm hashes as `u32::MAX`).
+///
+/// # Examples
+///
+/// ```rust
+/// let melody = atm::utils::parse_note_vec("C:4,R,G:4").unwrap();
+/// assert_eq!("60R67", atm::utils::gen_melody_hash(&melody));
+/// ```
+pub fn gen_melody_hash(melody: &[libatm::MIDINote]) -> String {
+    melody
+        .iter()
+        .map(|note| match note.note_type {
+            libatm::MIDINoteType::Rest => REST_TOKEN.to_string(),
+            _ => note.convert().to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join("")
+}
+
+/// Recover melody from its hash (see: [gen_melody_hash](fn.gen_melody_hash.html)) and the
+/// notes (without rests) played in its MIDI file, which tell where each note number of the
+/// hash ends. Rests (including trailing rests) are taken from the hash. Returns `None` if
+/// the hash isn't the hash of a melody of those notes.
+///
+/// # Examples
+///
+/// ```rust
+/// let melody = atm::utils::parse_note_vec("C:4,R,G:4").unwrap();
+/// let notes = [melody[0], melody[2]];
+/// assert_eq!(Some(melody), atm::utils::parse_melody_hash("60R67", &notes));
+/// let melody = atm::utils::parse_note_vec("C:4,R,G:4,R").unwrap();
+/// assert_eq!(Some(melody), atm::utils::parse_melody_hash("60R67R", &notes));
+/// assert_eq!(None, atm::utils::parse_melody_hash("60R68", &notes));
+/// ```
+pub fn parse_melody_hash(hash: &str, notes: &[libatm::MIDINote]) -> Option<libatm::MIDINoteVec> {
+    let mut notes = notes.iter();
+    let mut remainder = hash;
+    let mut melody = Vec::new();
+    while !remainder.is_empty() {
+        if let Some(rest) = remainder.strip_prefix(REST_TOKEN) {
+            melody.push(libatm::MIDINote::new(libatm::MIDINoteType::Rest, 0));
+            remainder = rest;
+            continue;
+        }
+        let note = notes.next()?;
+        remainder = remainder.strip_prefix(note.convert().to_string().as_str())?;
+        melody.push(*note);
+    }
+    match notes.next() {
+        Some(_) => None,
+        None => Some(libatm::MIDINoteVec(melody)),
+    }
+}
+
+/// Parse note from NOTE:OCTAVE pair or [REST_TOKEN](constant.REST_TOKEN.html).
+/// Rests are always parsed with octave 0, so that all rests are equal.
+pub(crate) fn parse_note(input: &str) -> Result<libatm::MIDINote, libatm::ParseMIDINoteError> {
+    if input.eq_ignore_ascii_case(REST_TOKEN) {
+        return Ok(libatm::MIDINote::new(libatm::MIDINoteType::Rest, 0));
+    }
+    let note = input.parse::<libatm::MIDINote>()?;
+    match note.note_type {
+        libatm::MIDINoteType::Rest => Ok(libatm::MIDINote::new(libatm::MIDINoteType::Rest, 0)),
+        _ => Ok(note),
+    }
+}
+
+/// Parse melody from comma-separated NOTE:OCTAVE pairs, where rests are written as
+/// [REST_TOKEN](constant.REST_TOKEN.html) (i.e., 'C:4,R,G:4').
+pub fn parse_note_vec(input: &str) -> Result<libatm::MIDINoteVec, libatm::ParseMIDINoteSequenceError> {
+    input
+        .split(',')
+        .enumerate()
+        .map(|(idx, note)| parse_note(note).map_err(|err| libatm::ParseMIDINoteSequenceError::ParseMIDINote(idx, err)))
+        .collect()
+}
+
+/// Parse note set from comma-separated NOTE:OCTAVE pairs, where a rest (written as
+/// [REST_TOKEN](constant.REST_TOKEN.html)) is one more symbol melodies are generated from.
+///
+/// # Examples
+///
+/// ```rust
+/// let note_set = atm::utils::parse_note_set("C:4,R,D:4").unwrap();
+/// let notes = libatm::MIDINoteVec::from(note_set);
+/// assert_eq!("C:4,D:4,R", atm::utils::format_melody(&notes));
+/// ```
+pub fn parse_note_set(input: &str) -> Result<libatm::MIDINoteSet, libatm::ParseMIDINoteSequenceError> {
+    Ok(libatm::MIDINoteSet(parse_note_vec(input)?.0.into_iter().collect()))
+}
+
+/// Error type for [parse_melody](fn.parse_melody.html)
+#[derive(Debug, thiserror::Error)]
+pub enum ParseMelodyError {
+    #[error(transparent)]
+    ParseNotes(#[from] libatm::ParseMIDINoteSequenceError),
+    #[error("Invalid MIDI note number {input} at index {index} (expected 12-127)")]
+    InvalidNoteNumber { input: String, index: usize },
+}
+
+/// Parse melody from comma-separated NOTE:OCTAVE pairs (i.e., 'C:4,DSharp:4,G:4'),
+/// or from MIDI note numbers separated by commas and/or whitespace (i.e., '60,63,67').
+/// Rests are written as [REST_TOKEN](constant.REST_TOKEN.html) in either format.
+///
+/// # Examples
+///
+/// ```rust
+/// let melody = "C:4,D#:4,Rest:0,G:4".parse::<libatm::MIDINoteVec>().unwrap();
+/// assert_eq!(melody, atm::utils::parse_melody("C:4,DSharp:4,R,G:4").unwrap());
+/// assert_eq!(melody, atm::utils::parse_melody("60 63 R 67").unwrap());
+/// ```
+pub fn parse_melody(input: &str) -> Result<libatm::MIDINoteVec, ParseMelodyError> {
+    let input = input.trim();
+    if input.contains(':') {
+        return Ok(parse_note_vec(input)?);
+    }
+    input
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|number| !number.is_empty())
+        .enumerate()
+        .map(|(index, number)| {
+            if number.eq_ignore_ascii_case(REST_TOKEN) {
+                return Ok(libatm::MIDINote::new(libatm::MIDINoteType::Rest, 0));
+            }
+            number
+                .parse::<u8>()
+                .ok()
+                .filter(|number| *number <= 127)
+                .and_then(crate::midi::note_from_number)
+                .ok_or_else(|| ParseMelodyError::InvalidNoteNumber { input: number.to_string(), index })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_melody_index_matches_gen_sequences() {
+        let notes = libatm::MIDINoteVec::from(
+            "C:4,D:4,E:4,G:5".parse::<libatm::MIDINoteSet>().unwrap()
+        );
+        for (index, melody_ref) in gen_sequences(&notes, 5).enumerate() {
+            let melody = melody_ref.iter().map(|n| **n).collect::<libatm::MIDINoteVec>();
+            assert_eq!(index as u64, gen_melody_index(&notes, &melody).unwrap());
+            assert_eq!(melody, gen_melody_at(&notes, 5, index as u64).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_shard_ranges_cover_melodies() {
+        for num_shards in 1..12 {
+            let ranges = (0..num_shards)
+                .map(|shard| gen_shard_range(7 * 7 * 7, 7, shard, num_shards))
+                .collect::<Vec<std::ops::Range<u64>>>();
+            assert_eq!(0, ranges[0].start);
+            assert_eq!(7 * 7 * 7, ranges[ranges.len() - 1].end);
+            for (range, next_range) in ranges.iter().zip(ranges.iter().skip(1)) {
+                assert_eq!(range.end, next_range.start);
+                assert_eq!(0, range.end % 7);
+            }
+        }
+    }
+
+    #[test]
+    fn test_melody_index_note_not_in_set() {
+        let notes = libatm::MIDINoteVec::from("C:4,D:4".parse::<libatm::MIDINoteSet>().unwrap());
+        let melody = "C:4,E:4".parse::<libatm::MIDINoteVec>().unwrap();
+        assert!(gen_melody_index(&notes, &melody).is_err());
+    }
+
+    #[test]
+    fn test_melody_at_out_of_range() {
+        let notes = libatm::MIDINoteVec::from("C:4,D:4".parse::<libatm::MIDINoteSet>().unwrap());
+        assert!(gen_melody_at(&notes, 3, 8).is_err());
+    }
+
+    #[test]
+    fn test_parse_melody_note_numbers() {
+        let melody = parse_melody("C:4,E:4,G:4,C:5").unwrap();
+        assert_eq!(melody, parse_melody("60,64,67,72").unwrap());
+        assert_eq!(melody, parse_melody(" 60, 64\t67 72 ").unwrap());
+        assert!(parse_melody("60,64,128").is_err());
+        assert!(parse_melody("60,11").is_err());
+        // Rests in either format
+        let melody = parse_melody("R,C:4,rest:3").unwrap();
+        assert_eq!("R,C:4,R", format_melody(&melody));
+        assert_eq!(melody, parse_melody("r 60,R").unwrap());
+        assert_eq!("R60R", gen_melody_hash(&melody));
+    }
+}
@@ -0,0 +1,480 @@
+// variation.rs
+//
+// Copyright (c) 2020 All The Music, LLC
+//
+// This work is licensed under the Creative Commons Attribution 4.0 International License.
+// To view a copy of this license, visit http://creativecommons.org/licenses/by/4.0/ or send
+// a letter to Creative Commons, PO Box 1866, Mountain View, CA 94042, USA.
+
+use crate::{
+    chord::{ChordType, ChordTypeSet, ChordTypeSetError},
+    dynamics::{DynamicsSet, DynamicsSetError},
+    rhythm::{NoteDuration, RhythmSet, RhythmSetError},
+};
+
+/*************************
+***** Variation Sets *****
+*************************/
+
+/// Error type for [VariationSet](struct.VariationSet.html)
+#[derive(Debug, thiserror::Error)]
+pub enum VariationSetError {
+    #[error(transparent)]
+    Chords(#[from] ChordTypeSetError),
+    #[error(transparent)]
+    Rhythms(#[from] RhythmSetError),
+    #[error(transparent)]
+    Dynamics(#[from] DynamicsSetError),
+    #[error("Number of chord type, rhythm and dynamics patterns overflows a 64-bit index")]
+    TooManyVariations,
+}
+
+/// Error type for [VariationSet::gen_variation_index](struct.VariationSet.html#method.gen_variation_index)
+#[derive(Debug, thiserror::Error)]
+pub enum VariationIndexError {
+    #[error("Chord types {chord_types} aren't generated from the provided chord types")]
+    ChordsNotInSet { chord_types: String },
+    #[error("Rhythm {rhythm} isn't generated from the provided durations")]
+    RhythmNotInSet { rhythm: String },
+    #[error("Dynamics {velocities} aren't generated from the provided velocities")]
+    DynamicsNotInSet { velocities: String },
+    #[error("Melody must be a sequence of chords (i.e., 'C:4+E:4+G:4') if chord types are provided")]
+    MissingChords,
+    #[error("Melody must have a duration per note (i.e., 'C:4@1/8') if durations are provided")]
+    MissingRhythm,
+    #[error("Melody must have a velocity per note except rests (i.e., 'C:4~mf,R') if velocities are provided")]
+    MissingDynamics,
+    #[error("Must provide the chord types the dataset was generated with (see: --chord-types)")]
+    MissingChordTypes,
+    #[error("Must provide the durations the dataset was generated with (see: --durations)")]
+    MissingDurations,
+    #[error("Must provide the velocities the dataset was generated with (see: --velocities)")]
+    MissingVelocities,
+}
+
+/// Chord type, rhythm and dynamics pattern a melody is rendered with
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MelodyVariation {
+    /// Type of the chord played over each note (see: [ChordTypeSet](../chord/struct.ChordTypeSet.html))
+    pub chord_types: Option<Vec<ChordType>>,
+    /// Duration of each note (see: [RhythmSet](../rhythm/struct.RhythmSet.html))
+    pub rhythm: Option<Vec<NoteDuration>>,
+    /// Velocity of each sounding note, as rests don't have a velocity (see:
+    /// [DynamicsSet](../dynamics/struct.DynamicsSet.html))
+    pub velocities: Option<Vec<u8>>,
+}
+
+impl MelodyVariation {
+    /// Generate chords played for melody `melody` (a chord of one note per note without
+    /// chord types), where rests are empty chords
+    pub fn gen_chords(&self, melody: &[libatm::MIDINote]) -> Vec<Vec<libatm::MIDINote>> {
+        melody
+            .iter()
+            .enumerate()
+            .map(|(position, note)| match self.chord_types {
+                // Chord types were validated for note set when chord type set was resolved
+                Some(ref chord_types) => chord_types[position].gen_chord(note).unwrap_or_default(),
+                None if note.note_type == libatm::MIDINoteType::Rest => Vec::new(),
+                None => vec![*note],
+            })
+            .collect()
+    }
+
+    /// Generate hash of melody `melody` rendered with variation: the melody hash (with chord
+    /// hashes joined by `.` in place of the melody hash with chord types), followed by `_` and
+    /// the rhythm hash, and `_` and the dynamics hash (i.e., `60+64+67.62+65+69_1-8.1-4_v33.80`).
+    /// Without chord types, rhythm or dynamics, same as
+    /// [gen_melody_hash](../utils/fn.gen_melody_hash.html).
+    pub fn gen_hash(&self, melody: &[libatm::MIDINote]) -> String {
+        let mut hash = match self.chord_types {
+            Some(_) => self
+                .gen_chords(melody)
+                .iter()
+                .map(|chord| crate::chord::gen_chord_hash(chord))
+                .collect::<Vec<String>>()
+                .join("."),
+            None => crate::utils::gen_melody_hash(melody),
+        };
+        if let Some(ref rhythm) = self.rhythm {
+            hash.push('_');
+            hash.push_str(&crate::rhythm::gen_rhythm_hash(rhythm));
+        }
+        if let Some(ref velocities) = self.velocities {
+            hash.push('_');
+            hash.push_str(&crate::dynamics::gen_dynamics_hash(velocities));
+        }
+        hash
+    }
+}
+
+/// Chord type, rhythm and dynamics patterns melodies are generated with. Each pitch sequence
+/// is generated once per combination of chord type pattern, rhythm pattern and dynamics
+/// pattern (variation), with variations ordered by chord type pattern, then rhythm pattern,
+/// then dynamics pattern.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VariationSet {
+    pub chords: Option<ChordTypeSet>,
+    pub rhythms: Option<RhythmSet>,
+    pub dynamics: Option<DynamicsSet>,
+}
+
+impl VariationSet {
+    /// Create new `VariationSet` instance, ensuring the number of variations fits in 64 bits
+    pub fn new(
+        chords: Option<ChordTypeSet>,
+        rhythms: Option<RhythmSet>,
+        dynamics: Option<DynamicsSet>,
+    ) -> Result<Self, VariationSetError> {
+        let variations = Self { chords, rhythms, dynamics };
+        variations
+            .num_chords()
+            .checked_mul(variations.num_rhythms())
+            .and_then(|num_variations| num_variations.checked_mul(variations.num_dynamics()))
+            .ok_or(VariationSetError::TooManyVariations)?;
+        Ok(variations)
+    }
+
+    /// Whether melodies are generated without chord type, rhythm or dynamics patterns
+    pub fn is_empty(&self) -> bool {
+        self.chords.is_none() && self.rhythms.is_none() && self.dynamics.is_none()
+    }
+
+    /// Number of chord type patterns (1 without chord types)
+    fn num_chords(&self) -> u64 {
+        self.chords.as_ref().map_or(1, |chords| chords.patterns().num_patterns())
+    }
+
+    /// Number of rhythm patterns (1 without rhythms)
+    fn num_rhythms(&self) -> u64 {
+        self.rhythms.as_ref().map_or(1, |rhythms| rhythms.patterns().num_patterns())
+    }
+
+    /// Number of dynamics patterns (1 without dynamics)
+    fn num_dynamics(&self) -> u64 {
+        self.dynamics.as_ref().map_or(1, |dynamics| dynamics.patterns().num_patterns())
+    }
+
+    /// Number of variations each pitch sequence is generated with (1 without any patterns)
+    pub fn num_variations(&self) -> u64 {
+        self.num_chords() * self.num_rhythms() * self.num_dynamics()
+    }
+
+    /// Number of variations melody `melody` is generated with: every variation, except that
+    /// only the dynamics patterns of its sounding notes are generated (see:
+    /// [DynamicsSet](../dynamics/struct.DynamicsSet.html))
+    pub fn gen_num_melody_variations(&self, melody: &[libatm::MIDINote]) -> u64 {
+        match self.dynamics {
+            Some(ref dynamics) => self.num_chords() * self.num_rhythms() * dynamics.gen_num_patterns(melody),
+            None => self.num_variations(),
+        }
+    }
+
+    /// Calculate the number of variations melody `melody` is generated with before the
+    /// variation at index `index` (which it must be generated with)
+    pub fn gen_num_melody_variations_before(&self, index: u64, melody: &[libatm::MIDINote]) -> u64 {
+        match self.dynamics {
+            Some(ref dynamics) => {
+                let num_dynamics = self.num_dynamics();
+                index / num_dynamics * dynamics.gen_num_patterns(melody)
+                    + dynamics.gen_num_patterns_before(index % num_dynamics, melody)
+            },
+            None => index,
+        }
+    }
+
+    /// Generate the variation at index `index` for melody `melody`, or `None` if out of range
+    /// or the melody isn't generated with it (see: [DynamicsSet](../dynamics/struct.DynamicsSet.html)).
+    /// Inverse of [gen_variation_index](#method.gen_variation_index).
+    pub fn gen_variation_at(&self, index: u64, melody: &[libatm::MIDINote]) -> Option<MelodyVariation> {
+        if index >= self.num_variations() {
+            return None;
+        }
+        let num_dynamics = self.num_dynamics();
+        let num_rhythms = self.num_rhythms();
+        Some(MelodyVariation {
+            chord_types: match self.chords {
+                Some(ref chords) => Some(chords.patterns().gen_pattern_at(index / num_dynamics / num_rhythms)?),
+                None => None,
+            },
+            rhythm: match self.rhythms {
+                Some(ref rhythms) => Some(rhythms.patterns().gen_pattern_at(index / num_dynamics % num_rhythms)?),
+                None => None,
+            },
+            velocities: match self.dynamics {
+                Some(ref dynamics) => Some(dynamics.gen_velocities_at(index % num_dynamics, melody)?),
+                None => None,
+            },
+        })
+    }
+
+    /// Calculate the index of variation `variation` of melody `melody`.
+    /// Inverse of [gen_variation_at](#method.gen_variation_at).
+    pub fn gen_variation_index(
+        &self,
+        variation: &MelodyVariation,
+        melody: &[libatm::MIDINote],
+    ) -> Result<u64, VariationIndexError> {
+        let chords_index = match (self.chords.as_ref(), variation.chord_types.as_ref()) {
+            (Some(chords), Some(chord_types)) => chords.patterns().gen_pattern_index(chord_types).ok_or_else(|| {
+                VariationIndexError::ChordsNotInSet {
+                    chord_types: chord_types.iter().map(|chord_type| chord_type.to_string()).collect::<Vec<String>>().join(","),
+                }
+            })?,
+            (Some(_), None) => return Err(VariationIndexError::MissingChords),
+            (None, Some(_)) => return Err(VariationIndexError::MissingChordTypes),
+            (None, None) => 0,
+        };
+        let rhythm_index = match (self.rhythms.as_ref(), variation.rhythm.as_ref()) {
+            (Some(rhythms), Some(rhythm)) => rhythms.patterns().gen_pattern_index(rhythm).ok_or_else(|| {
+                VariationIndexError::RhythmNotInSet { rhythm: crate::rhythm::format_rhythm(rhythm) }
+            })?,
+            (Some(_), None) => return Err(VariationIndexError::MissingRhythm),
+            (None, Some(_)) => return Err(VariationIndexError::MissingDurations),
+            (None, None) => 0,
+        };
+        let dynamics_index = match (self.dynamics.as_ref(), variation.velocities.as_ref()) {
+            (Some(dynamics), Some(velocities)) => dynamics.gen_velocities_index(velocities, melody).ok_or_else(|| {
+                VariationIndexError::DynamicsNotInSet { velocities: crate::dynamics::format_dynamics(velocities) }
+            })?,
+            (Some(_), None) => return Err(VariationIndexError::MissingDynamics),
+            (None, Some(_)) => return Err(VariationIndexError::MissingVelocities),
+            (None, None) => 0,
+        };
+        Ok((chords_index * self.num_rhythms() + rhythm_index) * self.num_dynamics() + dynamics_index)
+    }
+}
+
+/*****************************
+***** Annotated Melodies *****
+*****************************/
+
+/// Error type for [parse_annotated_melody](fn.parse_annotated_melody.html)
+#[derive(Debug, thiserror::Error)]
+pub enum ParseAnnotatedMelodyError {
+    #[error(transparent)]
+    ParseChords(#[from] crate::chord::ParseChordVecError),
+    #[error(transparent)]
+    ParseDuration(#[from] crate::rhythm::ParseNoteDurationError),
+    #[error(transparent)]
+    ParseVelocity(#[from] crate::dynamics::ParseVelocityError),
+    #[error("Either every note or no note must have a duration (i.e., 'C:4@1/8,D:4@1/4')")]
+    MissingDuration,
+    #[error("Either every note (except rests) or no note must have a velocity (i.e., 'C:4~mf,R,D:4~ff')")]
+    MissingVelocity,
+}
+
+/// Melody with the chord type, rhythm and dynamics pattern it's rendered with (if provided)
+#[derive(Clone, Debug)]
+pub struct AnnotatedMelody {
+    pub melody: libatm::MIDINoteVec,
+    pub variation: MelodyVariation,
+}
+
+/// Format melody with variation as comma-separated NOTE:OCTAVE pairs (or chords, see:
+/// [format_chord](../chord/fn.format_chord.html)), each followed by `@` and its duration
+/// and `~` and its velocity (except rests) if provided (i.e., 'C:4+E:4+G:4@1/8~80,R@1/8').
+/// Without chord types, rhythm or dynamics, same as [format_melody](../utils/fn.format_melody.html).
+pub fn format_annotated_melody(melody: &[libatm::MIDINote], variation: &MelodyVariation) -> String {
+    let chords = variation.gen_chords(melody);
+    let mut velocities = variation.velocities.iter().flatten();
+    melody
+        .iter()
+        .enumerate()
+        .map(|(position, note)| {
+            let mut token = match variation.chord_types {
+                Some(_) if !chords[position].is_empty() => crate::chord::format_chord(&chords[position]),
+                _ => crate::utils::format_note(note),
+            };
+            if let Some(ref rhythm) = variation.rhythm {
+                token.push_str(&format!("@{}", rhythm[position]));
+            }
+            if note.note_type != libatm::MIDINoteType::Rest {
+                if let Some(velocity) = velocities.next() {
+                    token.push_str(&format!("~{}", velocity));
+                }
+            }
+            token
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Parse melody from comma-separated NOTE:OCTAVE pairs (or chords, see:
+/// [parse_chord](../chord/fn.parse_chord.html)), each optionally followed by `@` and its
+/// duration, and `~` and its velocity except for rests (i.e., 'C:4+E:4+G:4@1/8~mf,R@1/8,G:4@1/4~ff').
+///
+/// # Examples
+///
+/// ```rust
+/// let parsed = atm::variation::parse_annotated_melody("C:4@1/8~mf,R@1/8,G:4@1/4~ff").unwrap();
+/// assert_eq!("C:4,R,G:4", atm::utils::format_melody(&parsed.melody));
+/// assert_eq!("1/8,1/8,1/4", atm::rhythm::format_rhythm(&parsed.variation.rhythm.unwrap()));
+/// assert_eq!(Some(vec![80, 112]), parsed.variation.velocities);
+/// assert!(atm::variation::parse_annotated_melody("C:4~mf,R~mf,G:4~ff").is_err());
+/// let parsed = atm::variation::parse_annotated_melody("C:4+E:4+G:4,G:3").unwrap();
+/// assert_eq!("C:4,G:3", atm::utils::format_melody(&parsed.melody));
+/// assert_eq!("C:4+E:4+G:4,G:3", atm::variation::format_annotated_melody(&parsed.melody, &parsed.variation));
+/// assert_eq!(
+///     atm::variation::MelodyVariation::default(),
+///     atm::variation::parse_annotated_melody("C:4,R,G:4").unwrap().variation,
+/// );
+/// ```
+pub fn parse_annotated_melody(input: &str) -> Result<AnnotatedMelody, ParseAnnotatedMelodyError> {
+    let mut notes = Vec::new();
+    let mut rhythm = Vec::new();
+    let mut velocities = Vec::new();
+    for token in input.split(',') {
+        let token = match token.split_once('~') {
+            Some((token, velocity)) => {
+                velocities.push(Some(crate::dynamics::parse_velocity(velocity)?));
+                token
+            },
+            None => {
+                velocities.push(None);
+                token
+            },
+        };
+        match token.split_once('@') {
+            Some((note, duration)) => {
+                notes.push(note);
+                rhythm.push(duration.parse::<NoteDuration>()?);
+            },
+            None => notes.push(token),
+        }
+    }
+    let chords = crate::chord::parse_chord_vec(&notes.join(","))?;
+    let rhythm = match rhythm.len() {
+        0 => None,
+        num_durations if num_durations == notes.len() => Some(rhythm),
+        _ => return Err(ParseAnnotatedMelodyError::MissingDuration),
+    };
+    // Rests don't have a velocity
+    let velocities = if velocities.iter().all(Option::is_none) {
+        None
+    } else {
+        let mut sounding = Vec::with_capacity(velocities.len());
+        for (note, velocity) in chords.roots.iter().zip(velocities) {
+            match (note.note_type == libatm::MIDINoteType::Rest, velocity) {
+                (true, None) => (),
+                (false, Some(velocity)) => sounding.push(velocity),
+                _ => return Err(ParseAnnotatedMelodyError::MissingVelocity),
+            }
+        }
+        Some(sounding)
+    };
+    Ok(AnnotatedMelody {
+        melody: chords.roots,
+        variation: MelodyVariation { chord_types: chords.chord_types, rhythm, velocities },
+    })
+}
+
+/// Parse melody and variation from the hash of a melody rendered with variation (see:
+/// [MelodyVariation::gen_hash](struct.MelodyVariation.html#method.gen_hash)) and the notes
+/// (without rests) played in its MIDI file, including every note of each chord. Returns `None`
+/// if the hash isn't the hash of a melody of those notes, or its patterns don't have one symbol
+/// per note.
+///
+/// # Examples
+///
+/// ```rust
+/// let melody = atm::utils::parse_note_vec("C:4,R,G:4").unwrap();
+/// let parsed = atm::variation::parse_hash("60R67_1-8.1-8.1-4_v33.80", &[melody[0], melody[2]]).unwrap();
+/// assert_eq!(melody, parsed.melody);
+/// assert_eq!("1/8,1/8,1/4", atm::rhythm::format_rhythm(&parsed.variation.rhythm.unwrap()));
+/// assert_eq!(Some(vec![33, 80]), parsed.variation.velocities);
+/// let chords = atm::utils::parse_note_vec("C:4,E:4,G:4,G:3").unwrap();
+/// let parsed = atm::variation::parse_hash("60+64+67.55_v33.80", &chords).unwrap();
+/// assert_eq!("C:4+E:4+G:4~33,G:3~80", atm::variation::format_annotated_melody(&parsed.melody, &parsed.variation));
+/// ```
+pub fn parse_hash(hash: &str, notes: &[libatm::MIDINote]) -> Option<AnnotatedMelody> {
+    let mut components = hash.split('_');
+    let melody_hash = components.next()?;
+    let mut variation = MelodyVariation::default();
+    // Chord hashes are joined by '.', and the notes of each chord by '+'
+    let melody = if melody_hash.contains(['.', crate::chord::CHORD_SEPARATOR]) {
+        let (roots, chord_types): (Vec<libatm::MIDINote>, Vec<ChordType>) = melody_hash
+            .split('.')
+            .map(crate::chord::parse_chord_hash)
+            .collect::<Option<Vec<(libatm::MIDINote, ChordType)>>>()?
+            .into_iter()
+            .unzip();
+        variation.chord_types = Some(chord_types);
+        if variation.gen_chords(&roots).concat() != notes {
+            return None;
+        }
+        libatm::MIDINoteVec(roots)
+    } else {
+        crate::utils::parse_melody_hash(melody_hash, notes)?
+    };
+    let num_sounding = melody.iter().filter(|note| note.note_type != libatm::MIDINoteType::Rest).count();
+    for component in components {
+        // Dynamics come last, and only have a velocity per sounding note
+        if let Some(velocities) = crate::dynamics::parse_dynamics_hash(component) {
+            match variation.velocities {
+                None if velocities.len() == num_sounding => variation.velocities = Some(velocities),
+                _ => return None,
+            }
+            continue;
+        }
+        match crate::rhythm::parse_rhythm_hash(component) {
+            Some(rhythm) if variation.rhythm.is_none()
+                && variation.velocities.is_none()
+                && rhythm.len() == melody.len() => variation.rhythm = Some(rhythm),
+            _ => return None,
+        }
+    }
+    Some(AnnotatedMelody { melody, variation })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variation_index_round_trip() {
+        let durations = ["1/8", "1/4"]
+            .iter()
+            .map(|duration| duration.parse::<NoteDuration>().unwrap())
+            .collect::<Vec<NoteDuration>>();
+        let chord_types = ["maj", "min"]
+            .iter()
+            .map(|chord_type| chord_type.parse::<ChordType>().unwrap())
+            .collect::<Vec<ChordType>>();
+        let variations = VariationSet::new(
+            Some(ChordTypeSet::new(&chord_types, 3).unwrap()),
+            Some(RhythmSet::new(&durations, 3, None).unwrap()),
+            Some(DynamicsSet::new(&[33, 80, 112], 3).unwrap()),
+        ).unwrap();
+        assert_eq!(8 * 8 * 27, variations.num_variations());
+        let melody = crate::utils::parse_note_vec("C:4,D:4,E:4").unwrap();
+        for index in 0..variations.num_variations() {
+            let variation = variations.gen_variation_at(index, &melody).unwrap();
+            assert_eq!(index, variations.gen_variation_index(&variation, &melody).unwrap());
+        }
+        // Variations are ordered by chord type pattern, then rhythm pattern, then dynamics pattern
+        let variation = variations.gen_variation_at(8 * 27 + 28, &melody).unwrap();
+        assert_eq!(Some(vec![chord_types[1].clone(), chord_types[1].clone(), chord_types[0].clone()]), variation.chord_types);
+        assert_eq!("1/8,1/8,1/4", crate::rhythm::format_rhythm(&variation.rhythm.unwrap()));
+        assert_eq!(Some(vec![33, 33, 80]), variation.velocities);
+        assert!(variations.gen_variation_at(variations.num_variations(), &melody).is_none());
+
+        // Rests don't have a velocity, so melodies with rests are generated with fewer variations
+        let variations = VariationSet::new(
+            None,
+            Some(RhythmSet::new(&durations, 3, None).unwrap()),
+            Some(DynamicsSet::new(&[33, 80, 112], 3).unwrap()),
+        ).unwrap();
+        let melody = crate::utils::parse_note_vec("C:4,R,G:4").unwrap();
+        assert_eq!(8 * 9, variations.gen_num_melody_variations(&melody));
+        let mut num_generated = 0;
+        for index in 0..variations.num_variations() {
+            if let Some(variation) = variations.gen_variation_at(index, &melody) {
+                assert_eq!(2, variation.velocities.as_ref().unwrap().len());
+                assert_eq!(index, variations.gen_variation_index(&variation, &melody).unwrap());
+                assert_eq!(num_generated, variations.gen_num_melody_variations_before(index, &melody));
+                num_generated += 1;
+            }
+        }
+        assert_eq!(8 * 9, num_generated);
+    }
+}